@@ -0,0 +1,6 @@
+// The browser build: a `cdylib` exporting `wasm::run` and `wasm::evaluate`
+// through `wasm-bindgen`. Kept out of the library's own crate types so the
+// core still builds as a plain `no_std` rlib.
+//
+//   cargo build --release --example wasm --features wasm --target wasm32-unknown-unknown
+pub use stack_machine_bez::wasm::{evaluate, run};
@@ -0,0 +1,177 @@
+// Tones, loaded with `--plugin audio`. Notes are collected while the program
+// runs and written to `audio.wav` once it ends.
+use crate::checker::StackEffect;
+use crate::common::Error;
+use crate::plugin::{Native, Pack, PackState};
+use crate::stack::Stack;
+use crate::words::Fault;
+
+pub const OUTPUT: &str = "audio.wav";
+pub const SAMPLE_RATE: u32 = 22050;
+// Keeps a runaway loop from filling the disk.
+const MAX_MILLIS: u64 = 10 * 60 * 1000;
+const VOLUME: f64 = 0.3;
+
+pub static PACK: Pack = Pack {
+    name: "audio",
+    description: "plays tones into audio.wav",
+    words: &[
+        Native {
+            name: "tone",
+            effect: StackEffect {
+                inputs: 2,
+                outputs: 0,
+            },
+            description: "plays the second value in Hz for the top value in milliseconds",
+        },
+        Native {
+            name: "rest",
+            effect: StackEffect {
+                inputs: 1,
+                outputs: 0,
+            },
+            description: "stays silent for the top value in milliseconds",
+        },
+    ],
+    writes_files: true,
+    start: || Box::new(Song::default()),
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Note {
+    // 0 for silence.
+    hertz: u32,
+    millis: u32,
+}
+
+#[derive(Default)]
+pub struct Song {
+    notes: Vec<Note>,
+    millis: u64,
+}
+
+impl Song {
+    fn add(&mut self, hertz: i32, millis: i32) -> Result<(), Fault> {
+        if hertz < 0 || millis < 0 {
+            return Err(Fault::Failed(
+                "tones and rests can't be negative".to_string(),
+            ));
+        }
+        if self.millis + millis as u64 > MAX_MILLIS {
+            return Err(Fault::Failed(
+                "the audio is longer than 10 minutes".to_string(),
+            ));
+        }
+        self.millis += millis as u64;
+        self.notes.push(Note {
+            hertz: hertz as u32,
+            millis: millis as u32,
+        });
+        Ok(())
+    }
+
+    fn samples(&self) -> Vec<i16> {
+        let mut samples = vec![];
+        for note in &self.notes {
+            let count = (note.millis as u64 * SAMPLE_RATE as u64 / 1000) as usize;
+            samples.extend((0..count).map(|i| {
+                let t = i as f64 / SAMPLE_RATE as f64;
+                let wave = (2.0 * std::f64::consts::PI * note.hertz as f64 * t).sin();
+                (wave * VOLUME * i16::MAX as f64).round() as i16
+            }));
+        }
+        samples
+    }
+
+    // 16 bit mono PCM.
+    pub fn wav(&self) -> Vec<u8> {
+        let samples = self.samples();
+        let data_len = (samples.len() * 2) as u32;
+        let mut out = Vec::with_capacity(44 + data_len as usize);
+        out.extend(b"RIFF");
+        out.extend((36 + data_len).to_le_bytes());
+        out.extend(b"WAVEfmt ");
+        out.extend(16u32.to_le_bytes());
+        // PCM, one channel.
+        out.extend(1u16.to_le_bytes());
+        out.extend(1u16.to_le_bytes());
+        out.extend(SAMPLE_RATE.to_le_bytes());
+        out.extend((SAMPLE_RATE * 2).to_le_bytes());
+        out.extend(2u16.to_le_bytes());
+        out.extend(16u16.to_le_bytes());
+        out.extend(b"data");
+        out.extend(data_len.to_le_bytes());
+        for sample in samples {
+            out.extend(sample.to_le_bytes());
+        }
+        out
+    }
+}
+
+impl PackState for Song {
+    fn call(&mut self, word: usize, stack: &mut dyn Stack<i32>) -> Result<(), Fault> {
+        let millis = stack.pop().unwrap_or_default();
+        match word {
+            0 => {
+                let hertz = stack.pop().unwrap_or_default();
+                self.add(hertz, millis)
+            }
+            _ => self.add(0, millis),
+        }
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        std::fs::write(OUTPUT, self.wav()).map_err(|e| Error::Io {
+            path: OUTPUT.to_string(),
+            comment: e.to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod audio_tests {
+    use super::*;
+    use crate::stack::VecStack;
+
+    fn play(words: &[(usize, &[i32])]) -> (Song, Result<(), Fault>) {
+        let mut song = Song::default();
+        let mut stack = VecStack::new();
+        for (word, values) in words {
+            for value in *values {
+                stack.push(*value);
+            }
+            if let Err(fault) = song.call(*word, &mut stack) {
+                return (song, Err(fault));
+            }
+        }
+        (song, Ok(()))
+    }
+
+    #[test]
+    fn tones_and_rests() {
+        let (song, result) = play(&[(0, &[440, 10]), (1, &[20])]);
+        assert_eq!(result, Ok(()));
+        let wav = song.wav();
+        // 10 ms and 20 ms at 22050 Hz, two bytes each.
+        assert_eq!(wav.len(), 44 + 2 * (220 + 441));
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        assert_eq!(&wav[36..40], b"data");
+        assert_eq!(
+            u32::from_le_bytes([wav[40], wav[41], wav[42], wav[43]]),
+            1322
+        );
+        let samples = song.samples();
+        assert!(samples[..220].iter().any(|s| *s > 9000));
+        assert!(samples[220..].iter().all(|s| *s == 0));
+    }
+
+    #[test]
+    fn refuses_negative_and_endless_audio() {
+        assert!(play(&[(1, &[-1])]).1.is_err());
+        assert!(play(&[(0, &[-440, 10])]).1.is_err());
+        let (song, result) = play(&[(1, &[600_000]), (1, &[1])]);
+        assert!(result.is_err());
+        assert_eq!(song.notes.len(), 1);
+    }
+}
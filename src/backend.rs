@@ -0,0 +1,131 @@
+// What `rorth build --target` picks between. Each backend turns a parsed
+// program into the bytes of one output file, or says which instruction it
+// can't build. The targets without a backend are known by name so that
+// asking for one says there is no backend for it yet, instead of that the
+// target doesn't exist.
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::{
+    bytecode,
+    common::Error,
+    stack_machine::{interpretable, Program},
+    x86_64,
+};
+
+pub trait CodegenBackend: Sync {
+    // What `--target` calls it.
+    fn name(&self) -> &'static str;
+    // Of the file `rorth build` writes when no `--output` is given.
+    fn extension(&self) -> &'static str;
+    fn emit(&self, program: &Program) -> Result<Vec<u8>, Error>;
+}
+
+// `.rorthc` files, run by the interpreter on any host.
+pub struct Bytecode;
+
+impl CodegenBackend for Bytecode {
+    fn name(&self) -> &'static str {
+        "bytecode"
+    }
+
+    fn extension(&self) -> &'static str {
+        bytecode::EXTENSION
+    }
+
+    // Refuses what the interpreter would, rather than build a file that
+    // can't run.
+    fn emit(&self, program: &Program) -> Result<Vec<u8>, Error> {
+        interpretable(program)?;
+        Ok(bytecode::encode(program))
+    }
+}
+
+// Assembly for the GNU assembler, linked into a program that runs without
+// rorth. See `x86_64`.
+pub struct X86_64Linux;
+
+impl CodegenBackend for X86_64Linux {
+    fn name(&self) -> &'static str {
+        "x86_64-linux"
+    }
+
+    fn extension(&self) -> &'static str {
+        x86_64::EXTENSION
+    }
+
+    fn emit(&self, program: &Program) -> Result<Vec<u8>, Error> {
+        x86_64::assembly(program).map(String::into_bytes)
+    }
+}
+
+pub static BACKENDS: &[&dyn CodegenBackend] = &[&Bytecode, &X86_64Linux];
+
+// Targets rorth knows of but can't build for.
+const UNSUPPORTED: &[&str] = &["aarch64-macos", "wasm32"];
+
+pub fn default() -> &'static dyn CodegenBackend {
+    BACKENDS[0]
+}
+
+pub fn find(target: &str) -> Result<&'static dyn CodegenBackend, Error> {
+    if let Some(backend) = BACKENDS.iter().find(|backend| backend.name() == target) {
+        return Ok(*backend);
+    }
+    let known: Vec<&str> = BACKENDS.iter().map(|backend| backend.name()).collect();
+    let problem = if UNSUPPORTED.contains(&target) {
+        format!("there is no backend for `{}` yet", target)
+    } else {
+        format!("unknown target `{}`", target)
+    };
+    Err(Error::Usage {
+        comment: format!("{}; rorth builds {}", problem, known.join(", ")),
+    })
+}
+
+#[cfg(test)]
+mod backend_tests {
+    use super::*;
+    use crate::parser::parse;
+    use crate::tokenizer::tokenize;
+
+    #[test]
+    fn bytecode_is_the_default() {
+        let program = parse(tokenize("fun main 1 print ret").unwrap()).unwrap();
+        let backend = find("bytecode").unwrap();
+        assert_eq!(backend.name(), default().name());
+        assert_eq!(backend.extension(), "rorthc");
+        assert_eq!(backend.emit(&program), Ok(bytecode::encode(&program)));
+    }
+
+    #[test]
+    fn only_native_targets_take_asm() {
+        let program = parse(tokenize("fun main\n  asm \"nop\" end ret").unwrap()).unwrap();
+        assert_eq!(
+            find("bytecode").unwrap().emit(&program),
+            Err(Error::UnsupportedInstruction {
+                word: "asm".to_string(),
+                pos: 3,
+                line: 2
+            })
+        );
+        assert!(find("x86_64-linux").unwrap().emit(&program).is_ok());
+    }
+
+    #[test]
+    fn other_targets_are_refused() {
+        let comment = |target| match find(target) {
+            Err(Error::Usage { comment }) => comment,
+            _ => panic!("{} was found", target),
+        };
+        assert_eq!(
+            comment("wasm32"),
+            "there is no backend for `wasm32` yet; rorth builds bytecode, x86_64-linux"
+        );
+        assert_eq!(
+            comment("riscv"),
+            "unknown target `riscv`; rorth builds bytecode, x86_64-linux"
+        );
+    }
+}
@@ -0,0 +1,197 @@
+// `StackMachine::builder()`: the stack, limits, sinks and callbacks of a
+// machine in one expression, for embedders that set more than `new` and
+// `with_config` take.
+//
+//     let mut machine = StackMachine::builder()
+//         .fuel(1_000_000)
+//         .output(|line| println!("{}", line))
+//         .build();
+use alloc::boxed::Box;
+
+#[cfg(debug_assertions)]
+use crate::stack::CheckedStack;
+use crate::stack::{Stack, VecStack};
+use crate::stack_machine::{
+    ErrorSink, Heartbeat, OutputSink, StackMachine, Status, Step, Trace, VmConfig,
+};
+
+// The stack a `Builder` runs on: wrapped in a `CheckedStack` in debug
+// builds, so an embedder's broken `Stack` fails their tests at the operation
+// that went wrong, and as it was given otherwise.
+#[cfg(debug_assertions)]
+pub type Checked<S> = CheckedStack<S, i32>;
+#[cfg(not(debug_assertions))]
+pub type Checked<S> = S;
+
+#[cfg(debug_assertions)]
+fn checked<S: Stack<i32>>(stack: S) -> Checked<S> {
+    CheckedStack::new(stack)
+}
+
+#[cfg(not(debug_assertions))]
+fn checked<S: Stack<i32>>(stack: S) -> Checked<S> {
+    stack
+}
+
+pub struct Builder<T> {
+    stack: T,
+    config: VmConfig,
+    output: Option<OutputSink>,
+    error_sink: Option<ErrorSink>,
+    trace: Option<Trace>,
+    heartbeat: Option<Heartbeat>,
+}
+
+impl Builder<Checked<VecStack<i32>>> {
+    pub fn new() -> Self {
+        Self {
+            stack: checked(VecStack::new()),
+            config: VmConfig::default(),
+            output: None,
+            error_sink: None,
+            trace: None,
+            heartbeat: None,
+        }
+    }
+}
+
+impl Default for Builder<Checked<VecStack<i32>>> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Stack<i32>> Builder<T> {
+    pub fn stack<S: Stack<i32>>(self, stack: S) -> Builder<Checked<S>> {
+        Builder {
+            stack: checked(stack),
+            config: self.config,
+            output: self.output,
+            error_sink: self.error_sink,
+            trace: self.trace,
+            heartbeat: self.heartbeat,
+        }
+    }
+
+    // Replaces the whole config, so it goes before `fuel` and `heartbeat`.
+    pub fn config(mut self, config: VmConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    // Steps the machine may take over its life, as `VmConfig::max_steps`.
+    pub fn fuel(mut self, steps: usize) -> Self {
+        self.config.max_steps = Some(steps);
+        self
+    }
+
+    // See `StackMachine::set_output`.
+    pub fn output(mut self, callback: impl FnMut(&str) + 'static) -> Self {
+        self.output = Some(Box::new(callback));
+        self
+    }
+
+    // See `StackMachine::set_error_sink`.
+    pub fn error_sink(mut self, callback: impl FnMut(&str) + 'static) -> Self {
+        self.error_sink = Some(Box::new(callback));
+        self
+    }
+
+    // See `StackMachine::set_trace`.
+    pub fn trace(mut self, callback: impl FnMut(&Step) + 'static) -> Self {
+        self.trace = Some(Box::new(callback));
+        self
+    }
+
+    // Calls `callback` every `steps` steps, see `StackMachine::set_heartbeat`.
+    pub fn heartbeat(mut self, steps: usize, callback: impl FnMut(&Status) + 'static) -> Self {
+        self.config.heartbeat = Some(steps);
+        self.heartbeat = Some(Box::new(callback));
+        self
+    }
+
+    pub fn build(self) -> StackMachine<T> {
+        let mut machine = StackMachine::with_config(self.stack, self.config);
+        if let Some(callback) = self.output {
+            machine.set_output(callback);
+        }
+        if let Some(callback) = self.error_sink {
+            machine.set_error_sink(callback);
+        }
+        if let Some(callback) = self.trace {
+            machine.set_trace(callback);
+        }
+        if let Some(callback) = self.heartbeat {
+            machine.set_heartbeat(callback);
+        }
+        machine
+    }
+}
+
+#[cfg(test)]
+mod builder_tests {
+    use super::*;
+    use crate::common::Error;
+    use crate::parser::parse;
+    use crate::tokenizer::tokenize;
+    use alloc::rc::Rc;
+    use alloc::string::{String, ToString};
+    use alloc::vec::Vec;
+    use core::cell::RefCell;
+
+    #[test]
+    fn sinks_and_limits_are_set() {
+        let program =
+            parse(tokenize("fun main 2 print 3 eprint 0 while end ret").unwrap()).unwrap();
+        let printed: Rc<RefCell<Vec<String>>> = Rc::default();
+        let (out, err) = (printed.clone(), printed.clone());
+        let mut machine = StackMachine::builder()
+            .stack(VecStack::new())
+            .config(VmConfig::sandboxed())
+            .fuel(4)
+            .output(move |line| out.borrow_mut().push(line.to_string()))
+            .error_sink(move |line| err.borrow_mut().push(["!", line].concat()))
+            .build();
+        let result = machine.run_main(&program);
+        assert_eq!(*printed.borrow(), ["2", "!3"]);
+        assert!(result.output.is_empty());
+        assert!(matches!(
+            result.error,
+            Some(Error::StepLimitExceeded { .. })
+        ));
+    }
+
+    // Drops everything pushed onto it.
+    struct Forgetful;
+
+    impl Stack<i32> for Forgetful {
+        fn push(&mut self, _: i32) {}
+
+        fn pop(&mut self) -> Option<i32> {
+            None
+        }
+
+        fn peek(&self) -> Option<&i32> {
+            None
+        }
+
+        fn is_empty(&self) -> bool {
+            true
+        }
+
+        fn size(&self) -> usize {
+            0
+        }
+
+        fn as_slice(&self) -> &[i32] {
+            &[]
+        }
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "after new")]
+    fn debug_builds_check_the_stack() {
+        let _ = StackMachine::builder().stack(Forgetful).build();
+    }
+}
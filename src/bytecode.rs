@@ -0,0 +1,594 @@
+// Compiled programs, as written by `rorth build` into `.rorthc` files.
+//
+// Layout, little endian:
+//   magic     4 bytes  "RRTC"
+//   version   u16      VERSION
+//   checksum  u32      FNV-1a of everything after the header
+//   length    u32      bytes after the header
+// followed by the instructions, the functions, their doc comments, the
+// names of the plugin words used, those of the variables `env` reads and
+// the text of the `asm` blocks.
+// Plugin words are stored by name, so a file runs whatever order the packs
+// were loaded in.
+use crate::checker::validate_program;
+use crate::common::Error;
+use crate::parser::{Instruction, InstructionType};
+use crate::plugin;
+use crate::stack_machine::Program;
+use crate::words;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+
+pub const EXTENSION: &str = "rorthc";
+pub const MAGIC: [u8; 4] = *b"RRTC";
+// Bump whenever the layout or the meaning of an opcode changes.
+pub const VERSION: u16 = 5;
+const HEADER_LEN: usize = 14;
+
+fn bytecode_error(comment: impl Into<String>) -> Error {
+    Error::Bytecode {
+        comment: comment.into(),
+    }
+}
+
+fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c9dc5, |hash, byte| {
+        (hash ^ *byte as u32).wrapping_mul(0x01000193)
+    })
+}
+
+// One past the highest opcode. Words take their numbers from
+// `words::BUILTINS`; control flow and plugin words keep their own.
+pub const OPCODES: usize = 57;
+const NATIVE: u8 = 29;
+const CALL: u8 = 19;
+const ADDRESS: u8 = 53;
+const ENV: u8 = 55;
+const ASM: u8 = 56;
+// Set on a jump's opcode in files: its operand is then the signed distance
+// from the jump to its target, so a function's code is the same bytes
+// wherever it sits and code generators can move blocks around without
+// patching them. Calls and `&` go to functions by where they start, and
+// stay absolute.
+pub const RELATIVE: u8 = 0x80;
+
+const CONTROL: [(u8, &str); 17] = [
+    (0, "push"),
+    (7, "while"),
+    (8, "end while"),
+    (9, "if"),
+    (10, "else"),
+    (11, "end if"),
+    (CALL, "call"),
+    (26, "try"),
+    (27, "recover"),
+    (28, "end try"),
+    (NATIVE, "native"),
+    (35, "loop"),
+    (36, "do"),
+    (37, "end do"),
+    (ADDRESS, "address"),
+    (ENV, "env"),
+    (ASM, "asm"),
+];
+
+pub fn mnemonic(op: u8) -> Option<&'static str> {
+    CONTROL
+        .iter()
+        .find(|(number, _)| *number == op)
+        .map(|(_, name)| *name)
+        .or_else(|| words::by_opcode(op).map(|(name, _)| name))
+}
+
+pub fn opcode(instruction: &InstructionType) -> (u8, Option<u32>) {
+    use InstructionType::*;
+    match instruction {
+        Push(n) => (0, Some(*n as u32)),
+        While(j) => (7, Some(*j as u32)),
+        EndWhile(j) => (8, Some(*j as u32)),
+        If(j) => (9, Some(*j as u32)),
+        Else(j) => (10, Some(*j as u32)),
+        EndIf => (11, None),
+        Call(j) => (CALL, Some(*j as u32)),
+        Address(j) => (ADDRESS, Some(*j as u32)),
+        Try(j) => (26, Some(*j as u32)),
+        Recover(j) => (27, Some(*j as u32)),
+        EndTry => (28, None),
+        Loop(j) => (35, Some(*j as u32)),
+        Do(j) => (36, Some(*j as u32)),
+        EndDo(j) => (37, Some(*j as u32)),
+        // The loaded id; `encode` swaps in the file's own numbering.
+        Native(id) => (NATIVE, Some(*id as u32)),
+        Env(index) => (ENV, Some(*index as u32)),
+        Asm(index) => (ASM, Some(*index as u32)),
+        word => match words::op(word) {
+            Some((_, op)) => (op.opcode, None),
+            None => unreachable!("{:?} is missing from the word table", word),
+        },
+    }
+}
+
+// The opcode and operand as a file holds them: `opcode`, with jumps made
+// relative to `index`, the instruction's own place.
+pub fn encoded(index: usize, instruction: &InstructionType) -> (u8, Option<u32>) {
+    let (op, operand) = opcode(instruction);
+    match operand {
+        Some(target) if is_jump(op) => (
+            op | RELATIVE,
+            Some((target as i64 - index as i64) as i32 as u32),
+        ),
+        operand => (op, operand),
+    }
+}
+
+fn is_jump(op: u8) -> bool {
+    matches!(op, 7..=10 | 26 | 27 | 35..=37)
+}
+
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u32(&mut self, n: usize) {
+        self.0.extend((n as u32).to_le_bytes());
+    }
+
+    fn str(&mut self, s: &str) {
+        self.u32(s.len());
+        self.0.extend(s.as_bytes());
+    }
+}
+
+pub fn encode(program: &Program) -> Vec<u8> {
+    let mut natives: Vec<usize> = vec![];
+    let mut body = Writer(vec![]);
+    body.u32(program.instructions.len());
+    for (index, instruction) in program.instructions.iter().enumerate() {
+        let (op, mut operand) = encoded(index, &instruction.instruction_type);
+        if let InstructionType::Native(id) = instruction.instruction_type {
+            let index = natives.iter().position(|n| *n == id).unwrap_or_else(|| {
+                natives.push(id);
+                natives.len() - 1
+            });
+            operand = Some(index as u32);
+        }
+        body.0.push(op);
+        if let Some(operand) = operand {
+            body.0.extend(operand.to_le_bytes());
+        }
+        body.u32(instruction.pos);
+        body.u32(instruction.line);
+    }
+    let mut functions: Vec<(&String, &usize)> = program.functions.iter().collect();
+    functions.sort();
+    body.u32(functions.len());
+    for (name, start) in functions {
+        body.str(name);
+        body.u32(*start);
+        body.0.push(program.private.contains(name) as u8);
+    }
+    let mut docs: Vec<(&String, &String)> = program.docs.iter().collect();
+    docs.sort();
+    body.u32(docs.len());
+    for (name, doc) in docs {
+        body.str(name);
+        body.str(doc);
+    }
+    body.u32(natives.len());
+    for id in natives {
+        let name = plugin::word(id).map_or("?", |loaded| loaded.native().name);
+        body.str(name);
+    }
+    body.u32(program.env.len());
+    for name in &program.env {
+        body.str(name);
+    }
+    body.u32(program.asm.len());
+    for text in &program.asm {
+        body.str(text);
+    }
+
+    let mut out = Vec::with_capacity(HEADER_LEN + body.0.len());
+    out.extend(MAGIC);
+    out.extend(VERSION.to_le_bytes());
+    out.extend(checksum(&body.0).to_le_bytes());
+    out.extend((body.0.len() as u32).to_le_bytes());
+    out.extend(body.0);
+    out
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    at: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, n: usize) -> Result<&[u8], Error> {
+        let bytes = self
+            .bytes
+            .get(self.at..self.at + n)
+            .ok_or_else(|| bytecode_error("the file ends too early"))?;
+        self.at += n;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn usize(&mut self) -> Result<usize, Error> {
+        Ok(self.u32()? as usize)
+    }
+
+    fn str(&mut self) -> Result<String, Error> {
+        let len = self.usize()?;
+        String::from_utf8(self.take(len)?.to_vec())
+            .map_err(|_| bytecode_error("a name is not valid UTF-8"))
+    }
+
+    // The instruction at `index` of `count`.
+    fn instruction(&mut self, index: usize, count: usize) -> Result<Instruction, Error> {
+        use InstructionType::*;
+        let op = match self.u8()? {
+            op if op & RELATIVE != 0 && is_jump(op & !RELATIVE) => op & !RELATIVE,
+            op if op & RELATIVE != 0 => {
+                return Err(bytecode_error(format!("unknown opcode {}", op)))
+            }
+            op if is_jump(op) => {
+                return Err(bytecode_error(format!(
+                    "the jump at {} is stored absolute, which bytecode version {} doesn't allow",
+                    index, VERSION
+                )))
+            }
+            op => return self.absolute(op),
+        };
+        let by = self.u32()? as i32;
+        let target = usize::try_from(index as i64 + by as i64)
+            .ok()
+            .filter(|target| *target < count)
+            .ok_or_else(|| {
+                bytecode_error(format!(
+                    "the jump at {} goes {:+}, outside the program's {} instructions",
+                    index, by, count
+                ))
+            })?;
+        let instruction_type = match op {
+            7 => While(target),
+            8 => EndWhile(target),
+            9 => If(target),
+            10 => Else(target),
+            26 => Try(target),
+            27 => Recover(target),
+            35 => Loop(target),
+            36 => Do(target),
+            _ => EndDo(target),
+        };
+        Ok(Instruction {
+            instruction_type,
+            pos: self.usize()?,
+            line: self.usize()?,
+        })
+    }
+
+    fn absolute(&mut self, op: u8) -> Result<Instruction, Error> {
+        use InstructionType::*;
+        let instruction_type = match op {
+            0 => Push(self.u32()? as i32),
+            11 => EndIf,
+            19 => Call(self.usize()?),
+            28 => EndTry,
+            NATIVE => Native(self.usize()?),
+            ADDRESS => Address(self.usize()?),
+            ENV => Env(self.usize()?),
+            ASM => Asm(self.usize()?),
+            op => match words::by_opcode(op) {
+                Some((_, word)) => word.instruction,
+                None => return Err(bytecode_error(format!("unknown opcode {}", op))),
+            },
+        };
+        Ok(Instruction {
+            instruction_type,
+            pos: self.usize()?,
+            line: self.usize()?,
+        })
+    }
+}
+
+// Checks the header and the checksum before reading anything, and the jumps
+// afterwards, so a damaged file fails here instead of misbehaving at run time.
+pub fn decode(bytes: &[u8]) -> Result<Program, Error> {
+    if bytes.len() < HEADER_LEN || bytes[..4] != MAGIC {
+        return Err(bytecode_error("not a compiled rorth program"));
+    }
+    let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+    if version != VERSION {
+        return Err(bytecode_error(format!(
+            "compiled for bytecode version {}, but this rorth reads version {}; rebuild it from source",
+            version, VERSION
+        )));
+    }
+    let mut header = Reader { bytes, at: 6 };
+    let expected = header.u32()?;
+    let len = header.usize()?;
+    let body = &bytes[HEADER_LEN..];
+    if body.len() != len {
+        return Err(bytecode_error(format!(
+            "expected {} bytes after the header, found {}",
+            len,
+            body.len()
+        )));
+    }
+    if checksum(body) != expected {
+        return Err(bytecode_error("checksum mismatch, the file is corrupted"));
+    }
+
+    let mut reader = Reader { bytes: body, at: 0 };
+    let mut program = Program::default();
+    let count = reader.usize()?;
+    for index in 0..count {
+        program.instructions.push(reader.instruction(index, count)?);
+    }
+    for _ in 0..reader.usize()? {
+        let name = reader.str()?;
+        let start = reader.usize()?;
+        if reader.u8()? != 0 {
+            program.private.insert(name.clone());
+        }
+        program.functions.insert(name, start);
+    }
+    program.index_symbols();
+    for _ in 0..reader.usize()? {
+        let name = reader.str()?;
+        program.docs.insert(name, reader.str()?);
+    }
+    let mut natives = vec![];
+    for _ in 0..reader.usize()? {
+        let name = reader.str()?;
+        natives.push(plugin::native(&name).ok_or_else(|| {
+            bytecode_error(format!("uses `{}`, from a plugin that is not loaded", name))
+        })?);
+    }
+    for instruction in &mut program.instructions {
+        if let InstructionType::Native(index) = &mut instruction.instruction_type {
+            *index = *natives
+                .get(*index)
+                .ok_or_else(|| bytecode_error("a plugin word is missing from the table"))?;
+        }
+    }
+    for _ in 0..reader.usize()? {
+        program.env.push(reader.str()?);
+    }
+    for _ in 0..reader.usize()? {
+        program.asm.push(reader.str()?);
+    }
+    if reader.at != body.len() {
+        return Err(bytecode_error("unexpected bytes after the program"));
+    }
+    validate_program(&program)?;
+    Ok(program)
+}
+
+// One line per instruction: its index, opcode as the file holds it and
+// mnemonic, with function names as labels. Jump targets are instruction
+// indexes, with the relative distance stored in the file after them.
+pub fn disassemble(program: &Program) -> String {
+    let mut labels: Vec<(usize, &str)> = program
+        .functions
+        .iter()
+        .map(|(name, start)| (*start, name.as_str()))
+        .collect();
+    labels.sort();
+    let width = program
+        .instructions
+        .len()
+        .saturating_sub(1)
+        .to_string()
+        .len();
+    let mut out = String::new();
+    for (i, instruction) in program.instructions.iter().enumerate() {
+        for (_, name) in labels.iter().filter(|(start, _)| *start == i) {
+            out.push_str(&format!("{}:\n", name));
+        }
+        let (op, operand) = opcode(&instruction.instruction_type);
+        let operand = match operand {
+            Some(n) if op == 0 => format!(" {}", n as i32),
+            Some(_) if op == NATIVE => format!(" {}", instruction.instruction_type),
+            Some(index) if op == ENV => match program.env.get(index as usize) {
+                Some(name) => format!(" \"{}\"", name),
+                None => format!(" {}", index),
+            },
+            Some(index) if op == ASM => match program.asm.get(index as usize) {
+                Some(text) => format!(" {:?}", text),
+                None => format!(" {}", index),
+            },
+            Some(target) if op == CALL || op == ADDRESS => match program.symbol(target as usize) {
+                Some(name) => format!(" {}", name),
+                None => format!(" -> {}", target),
+            },
+            Some(target) => format!(" -> {} ({:+})", target, target as i64 - i as i64),
+            None => String::new(),
+        };
+        out.push_str(&format!(
+            "  {:>width$}  {:02x}  {}{}\n",
+            i,
+            encoded(i, &instruction.instruction_type).0,
+            mnemonic(op).unwrap_or("?"),
+            operand,
+            width = width
+        ));
+    }
+    out
+}
+
+#[cfg(test)]
+mod bytecode_tests {
+    use super::*;
+    use crate::parser::parse;
+    use crate::tokenizer::tokenize;
+
+    fn compiled() -> Vec<u8> {
+        let source = "module m
+  ## Squares.
+  pub fun sq dup * ret
+  fun unused ret
+end
+fun main 3 m.sq while 1 - end if 1 else 2 end print 2 &m.sq invoke pop ret";
+        let mut program = parse(tokenize(source).unwrap()).unwrap();
+        let main = program.functions["main"];
+        program.instructions[main].instruction_type = InstructionType::Push(-3);
+        encode(&program)
+    }
+
+    fn message(bytes: &[u8]) -> String {
+        decode(bytes).err().unwrap().to_string()
+    }
+
+    #[test]
+    fn round_trip() {
+        let bytes = compiled();
+        let program = decode(&bytes).unwrap();
+        assert_eq!(encode(&program), bytes);
+        assert_eq!(
+            program.instructions[program.functions["main"]].instruction_type,
+            InstructionType::Push(-3)
+        );
+        assert!(program.private.contains("m.unused"));
+        assert_eq!(program.docs["m.sq"], "Squares.");
+        assert_eq!(program.symbol(program.functions["m.sq"]), Some("m.sq"));
+    }
+
+    #[test]
+    fn every_opcode_has_a_mnemonic() {
+        for op in 0..OPCODES as u8 {
+            assert!(mnemonic(op).is_some(), "opcode {}", op);
+        }
+        assert_eq!(mnemonic(OPCODES as u8), None);
+    }
+
+    #[test]
+    fn disassembly() {
+        let program =
+            parse(tokenize("fun sq dup * ret fun main 3 sq while 1 - end print ret").unwrap())
+                .unwrap();
+        assert_eq!(
+            disassemble(&program),
+            "sq:
+   0  0c  dup
+   1  04  *
+   2  14  ret
+main:
+   3  00  push 3
+   4  13  call sq
+   5  87  while -> 8 (+3)
+   6  00  push 1
+   7  03  -
+   8  88  end while -> 5 (-3)
+   9  06  print
+  10  14  ret
+"
+        );
+    }
+
+    #[test]
+    fn header_mismatches() {
+        let bytes = compiled();
+        assert_eq!(
+            message(b"fun main ret"),
+            "bytecode: not a compiled rorth program"
+        );
+
+        let mut newer = bytes.clone();
+        newer[4] = 9;
+        assert_eq!(
+            message(&newer),
+            "bytecode: compiled for bytecode version 9, but this rorth reads version 5; rebuild it from source"
+        );
+
+        let mut flipped = bytes.clone();
+        *flipped.last_mut().unwrap() ^= 1;
+        assert_eq!(
+            message(&flipped),
+            "bytecode: checksum mismatch, the file is corrupted"
+        );
+
+        assert_eq!(
+            message(&bytes[..bytes.len() - 1]),
+            format!(
+                "bytecode: expected {} bytes after the header, found {}",
+                bytes.len() - HEADER_LEN,
+                bytes.len() - HEADER_LEN - 1
+            )
+        );
+    }
+
+    #[test]
+    fn bad_jumps_are_caught_even_with_a_valid_checksum() {
+        let mut program = parse(tokenize("fun main 1 while end ret").unwrap()).unwrap();
+        program.instructions[1].instruction_type = InstructionType::While(3);
+        assert_eq!(
+            decode(&encode(&program)).err().map(|e| e.message()),
+            Some("`while` at 1 should jump to 2, not 3".to_string())
+        );
+        program.instructions[1].instruction_type = InstructionType::While(40);
+        assert_eq!(
+            decode(&encode(&program)).err().map(|e| e.message()),
+            Some(
+                "bytecode: the jump at 1 goes +39, outside the program's 4 instructions"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn absolute_jumps_are_refused() {
+        let program = parse(tokenize("fun main 1 while end ret").unwrap()).unwrap();
+        let mut bytes = encode(&program);
+        // The `while`, written the way older versions stored it.
+        let at = HEADER_LEN + bytes[HEADER_LEN..].iter().position(|b| *b == 0x87).unwrap();
+        bytes[at] = 7;
+        let sum = checksum(&bytes[HEADER_LEN..]);
+        bytes[6..10].copy_from_slice(&sum.to_le_bytes());
+        assert_eq!(
+            decode(&bytes).err().map(|e| e.message()),
+            Some(
+                "bytecode: the jump at 1 is stored absolute, which bytecode version 5 doesn't allow"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn jumps_are_stored_relative() {
+        let program = parse(tokenize("fun main 1 if 2 else 3 end pop ret").unwrap()).unwrap();
+        assert_eq!(
+            encoded(1, &program.instructions[1].instruction_type),
+            (0x89, Some(2))
+        );
+        assert_eq!(
+            encoded(3, &program.instructions[3].instruction_type),
+            (0x8a, Some(2))
+        );
+        // The same function a few instructions further on is the same bytes.
+        let later =
+            parse(tokenize("fun pad 0 0 pop pop ret fun main 1 if 2 else 3 end pop ret").unwrap())
+                .unwrap();
+        let bytes = |program: &Program, start: usize| -> Vec<(u8, Option<u32>)> {
+            program.instructions[start..]
+                .iter()
+                .enumerate()
+                .map(|(i, instruction)| encoded(start + i, &instruction.instruction_type))
+                .collect()
+        };
+        assert_eq!(bytes(&program, 0), bytes(&later, later.functions["main"]));
+        assert_eq!(
+            decode(&encode(&later)).unwrap().instructions,
+            later.instructions
+        );
+    }
+}
@@ -0,0 +1,527 @@
+// On-disk cache of tokenized files and parsed programs, so running a program
+// whose includes haven't changed skips straight to the VM.
+//
+// Entries are named after an FNV-1a hash of what they were made from: the
+// source of one file for its tokens, the whole token stream after includes
+// for the program. The rorth version, `FORMAT` and the bytecode version are
+// part of the hash, so an upgrade or a change to what is cached starts from
+// an empty cache, and an edited file simply hashes to a new entry. The hash
+// only picks the file: each entry also holds what it was made from, and is
+// used only when that is the same byte for byte, so two sources that happen
+// to hash alike never get each other's tokens. Every entry carries a
+// checksum; one that doesn't match, or a program that no longer decodes (say
+// a plugin isn't loaded), is rebuilt and overwritten.
+//
+// Writing an entry also throws out the ones not used for `max_age`, then the
+// least recently used until the rest fit in `max_bytes`. Writing is best
+// effort: a cache that can't be written to just makes rorth as slow as
+// without one.
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::bytecode;
+use crate::common::Error;
+use crate::parser::parse_recovering;
+use crate::stack_machine::Program;
+use crate::tokenizer::{tokenize_recovering, Token, TokenType};
+use crate::words;
+
+const TOKENS_MAGIC: [u8; 4] = *b"RRTK";
+const PROGRAM_MAGIC: [u8; 4] = *b"RRPG";
+
+// Goes up whenever the tokenizer, parser or checker would make something
+// else of the same source, or the token or entry encoding changes, since the
+// package version stays the same between releases.
+pub const FORMAT: u32 = 4;
+
+pub const MAX_BYTES: u64 = 64 << 20;
+pub const MAX_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+pub struct Cache {
+    dir: PathBuf,
+    format: u32,
+    max_bytes: u64,
+    max_age: Duration,
+}
+
+// `$XDG_CACHE_HOME/rorth`, or `~/.cache/rorth`.
+pub fn default_dir() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CACHE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+    Some(base.join("rorth"))
+}
+
+fn hash(format: u32, kind: &str, bytes: &[u8]) -> u64 {
+    let versions = [&format.to_le_bytes()[..], &bytecode::VERSION.to_le_bytes()].concat();
+    [
+        env!("CARGO_PKG_VERSION").as_bytes(),
+        &versions,
+        kind.as_bytes(),
+        bytes,
+    ]
+    .iter()
+    .flat_map(|part| part.iter())
+    .fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c9dc5, |hash, byte| {
+        (hash ^ *byte as u32).wrapping_mul(0x01000193)
+    })
+}
+
+fn put_u32(out: &mut Vec<u8>, n: usize) {
+    out.extend((n as u32).to_le_bytes());
+}
+
+fn put_str(out: &mut Vec<u8>, s: &str) {
+    put_u32(out, s.len());
+    out.extend(s.as_bytes());
+}
+
+// Words are stored by name, like plugin words in bytecode. None for a token
+// with no name to store it by, which then isn't cached.
+pub fn encode_tokens(tokens: &[Token]) -> Option<Vec<u8>> {
+    let mut out = vec![];
+    put_u32(&mut out, tokens.len());
+    for token in tokens {
+        match &token.token_type {
+            TokenType::Num(n) => {
+                out.push(1);
+                out.extend(n.to_le_bytes());
+            }
+            TokenType::Doc(s) => {
+                out.push(2);
+                put_str(&mut out, s);
+            }
+            TokenType::Str(s) => {
+                out.push(3);
+                put_str(&mut out, s);
+            }
+            TokenType::Identifier(s) => {
+                out.push(4);
+                put_str(&mut out, s);
+            }
+            TokenType::Edition(year) => {
+                out.push(5);
+                put_u32(&mut out, *year as usize);
+            }
+            TokenType::Address(s) => {
+                out.push(6);
+                put_str(&mut out, s);
+            }
+            word => {
+                out.push(0);
+                put_str(&mut out, words::by_token(word)?.name);
+            }
+        }
+        put_u32(&mut out, token.pos);
+        put_u32(&mut out, token.line);
+    }
+    Some(out)
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    at: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, n: usize) -> Option<&[u8]> {
+        let bytes = self.bytes.get(self.at..self.at.checked_add(n)?)?;
+        self.at += n;
+        Some(bytes)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    fn str(&mut self) -> Option<String> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).ok()
+    }
+}
+
+pub fn decode_tokens(bytes: &[u8]) -> Option<Vec<Token<'static>>> {
+    let mut reader = Reader { bytes, at: 0 };
+    let count = reader.u32()? as usize;
+    // Every token takes at least nine bytes, so a bad count can't make us
+    // allocate more than the entry could hold.
+    let mut tokens = Vec::with_capacity(count.min(bytes.len() / 9));
+    for _ in 0..count {
+        let token_type = match reader.take(1)?[0] {
+            0 => words::by_name(&reader.str()?)?.token.clone(),
+            1 => TokenType::Num(reader.u32()? as i32),
+            2 => TokenType::Doc(reader.str()?.into()),
+            3 => TokenType::Str(reader.str()?.into()),
+            4 => TokenType::Identifier(reader.str()?.into()),
+            5 => TokenType::Edition(reader.u32()?.try_into().ok()?),
+            6 => TokenType::Address(reader.str()?.into()),
+            _ => return None,
+        };
+        tokens.push(Token {
+            token_type,
+            pos: reader.u32()? as usize,
+            line: reader.u32()? as usize,
+        });
+    }
+    (reader.at == bytes.len()).then_some(tokens)
+}
+
+// `magic`, the checksum of the rest, then `made_from` with its length in
+// front, then `value`.
+fn entry_bytes(magic: [u8; 4], made_from: &[u8], value: &[u8]) -> Vec<u8> {
+    let mut body = vec![];
+    put_u32(&mut body, made_from.len());
+    body.extend(made_from);
+    body.extend(value);
+    let mut bytes = magic.to_vec();
+    bytes.extend(checksum(&body).to_le_bytes());
+    bytes.extend(body);
+    bytes
+}
+
+// The value of an entry, if it is whole and was made from `made_from`.
+fn entry_value<'a>(magic: [u8; 4], made_from: &[u8], bytes: &'a [u8]) -> Option<&'a [u8]> {
+    let (header, body) = bytes.split_at_checked(8)?;
+    if header[..4] != magic || header[4..] != checksum(body).to_le_bytes() {
+        return None;
+    }
+    let mut reader = Reader { bytes: body, at: 0 };
+    let len = reader.u32()? as usize;
+    (reader.take(len)? == made_from).then(|| &body[reader.at..])
+}
+
+impl Cache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            format: FORMAT,
+            max_bytes: MAX_BYTES,
+            max_age: MAX_AGE,
+        }
+    }
+
+    pub fn with_limits(self, max_bytes: u64, max_age: Duration) -> Self {
+        Self {
+            max_bytes,
+            max_age,
+            ..self
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn entry(&self, kind: &str, key: u64) -> PathBuf {
+        self.dir.join(format!("{:016x}.{}", key, kind))
+    }
+
+    // The entry at `path`, marked as used just now so eviction keeps it.
+    fn load(&self, path: &Path) -> Option<Vec<u8>> {
+        let bytes = std::fs::read(path).ok()?;
+        let _ = std::fs::File::options()
+            .write(true)
+            .open(path)
+            .and_then(|file| file.set_modified(SystemTime::now()));
+        Some(bytes)
+    }
+
+    // Through a temporary file, so a run that reads the entry at the same
+    // time never sees half of it.
+    fn store(&self, path: &Path, bytes: &[u8]) {
+        let temporary = path.with_extension(format!("tmp{}", std::process::id()));
+        let written = std::fs::create_dir_all(&self.dir)
+            .and_then(|()| std::fs::write(&temporary, bytes))
+            .and_then(|()| std::fs::rename(&temporary, path));
+        if written.is_err() {
+            let _ = std::fs::remove_file(&temporary);
+        }
+        self.evict(path);
+    }
+
+    // Entries past `max_age`, then the least recently used ones until the
+    // rest fit in `max_bytes`. `keep`, just written, always stays.
+    fn evict(&self, keep: &Path) {
+        let Ok(dir) = std::fs::read_dir(&self.dir) else {
+            return;
+        };
+        let now = SystemTime::now();
+        let mut entries: Vec<(SystemTime, u64, PathBuf)> = dir
+            .filter_map(|entry| {
+                let entry = entry.ok()?;
+                let meta = entry.metadata().ok()?;
+                meta.is_file()
+                    .then(|| (meta.modified().unwrap_or(now), meta.len(), entry.path()))
+            })
+            .filter(|(_, _, path)| path != keep)
+            .collect();
+        entries.sort();
+        let mut total: u64 = std::fs::metadata(keep).map_or(0, |meta| meta.len())
+            + entries.iter().map(|(_, len, _)| len).sum::<u64>();
+        for (used, len, path) in entries {
+            let old = now.duration_since(used).is_ok_and(|age| age > self.max_age);
+            if !old && total <= self.max_bytes {
+                break;
+            }
+            if std::fs::remove_file(&path).is_ok() {
+                total -= len;
+            }
+        }
+    }
+
+    // Tokens of one file. Files that don't tokenize aren't cached, so their
+    // errors are always reported fresh.
+    pub fn tokens<'src>(&self, source: &'src str) -> Result<Vec<Token<'src>>, Error> {
+        let path = self.entry("tokens", hash(self.format, "tokens", source.as_bytes()));
+        if let Some(tokens) = self
+            .load(&path)
+            .and_then(|bytes| decode_tokens(entry_value(TOKENS_MAGIC, source.as_bytes(), &bytes)?))
+        {
+            return Ok(tokens);
+        }
+        let tokens = tokenize_recovering(source).map_err(Error::from_vec)?;
+        if let Some(value) = encode_tokens(&tokens) {
+            self.store(&path, &entry_bytes(TOKENS_MAGIC, source.as_bytes(), &value));
+        }
+        Ok(tokens)
+    }
+
+    // The parsed program, kept as bytecode, which also checks its jumps when
+    // read back.
+    pub fn program(&self, tokens: Vec<Token>) -> Result<Program, Error> {
+        let Some(made_from) = encode_tokens(&tokens) else {
+            return parse_recovering(tokens).map_err(Error::from_vec);
+        };
+        let path = self.entry(
+            bytecode::EXTENSION,
+            hash(self.format, "program", &made_from),
+        );
+        if let Some(program) = self.load(&path).and_then(|bytes| {
+            bytecode::decode(entry_value(PROGRAM_MAGIC, &made_from, &bytes)?).ok()
+        }) {
+            return Ok(program);
+        }
+        let program = parse_recovering(tokens).map_err(Error::from_vec)?;
+        let value = bytecode::encode(&program);
+        self.store(&path, &entry_bytes(PROGRAM_MAGIC, &made_from, &value));
+        Ok(program)
+    }
+}
+
+#[cfg(test)]
+mod cache_tests {
+    use super::*;
+    use crate::parser::parse;
+
+    // A fresh directory per test, so tests running at once don't share one.
+    fn scratch(name: &str) -> Cache {
+        let dir = std::env::temp_dir().join(format!("rorth-cache-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        Cache::new(dir)
+    }
+
+    fn entries(cache: &Cache, kind: &str) -> Vec<PathBuf> {
+        let mut paths: Vec<PathBuf> = std::fs::read_dir(cache.dir())
+            .map(|dir| dir.map(|entry| entry.unwrap().path()).collect())
+            .unwrap_or_default();
+        paths.retain(|path| path.extension().is_some_and(|ext| ext == kind));
+        paths
+    }
+
+    const SOURCE: &str =
+        "#pragma edition 2026\n## Squares.\nfun sq dup * ret\nfun main 3 sq print \"hi\" -2 ret";
+
+    #[test]
+    fn tokens_round_trip() {
+        let tokens = tokenize_recovering(SOURCE).unwrap();
+        let bytes = encode_tokens(&tokens).unwrap();
+        assert_eq!(decode_tokens(&bytes), Some(tokens));
+        assert_eq!(decode_tokens(&bytes[..bytes.len() - 1]), None);
+        assert_eq!(decode_tokens(&[255, 255, 255, 255]), None);
+    }
+
+    #[test]
+    fn address_tokens_round_trip() {
+        let source = "fun inc 1 + ret\nfun main 1 &inc invoke print ret";
+        let tokens = tokenize_recovering(source).unwrap();
+        assert!(tokens
+            .iter()
+            .any(|token| token.token_type == TokenType::Address("inc".into())));
+        let bytes = encode_tokens(&tokens).unwrap();
+        assert_eq!(decode_tokens(&bytes), Some(tokens));
+        let cache = scratch("address");
+        cache.tokens(source).unwrap();
+        assert_eq!(entries(&cache, "tokens").len(), 1);
+        std::fs::remove_dir_all(cache.dir()).unwrap();
+    }
+
+    #[test]
+    fn second_read_comes_from_disk() {
+        let cache = scratch("tokens");
+        let fresh = cache.tokens(SOURCE).unwrap();
+        assert_eq!(fresh, tokenize_recovering(SOURCE).unwrap());
+        let entries = entries(&cache, "tokens");
+        let [entry] = &entries[..] else {
+            panic!("expected one entry, found {:?}", entries);
+        };
+        // Swap in the tokens of another program under the same key to see
+        // that they are what gets read.
+        let other = encode_tokens(&tokenize_recovering("42").unwrap()).unwrap();
+        std::fs::write(entry, entry_bytes(TOKENS_MAGIC, SOURCE.as_bytes(), &other)).unwrap();
+        assert_eq!(
+            cache.tokens(SOURCE),
+            tokenize_recovering("42").map_err(Error::from_vec)
+        );
+        std::fs::remove_dir_all(cache.dir()).unwrap();
+    }
+
+    #[test]
+    fn entries_made_from_other_sources_miss() {
+        let cache = scratch("collision");
+        let fresh = cache.tokens(SOURCE).unwrap();
+        let written = entries(&cache, "tokens");
+        let [entry] = &written[..] else {
+            panic!("expected one entry, found {:?}", written);
+        };
+        // As if another source had hashed to the same name.
+        let other = encode_tokens(&tokenize_recovering("42").unwrap()).unwrap();
+        std::fs::write(entry, entry_bytes(TOKENS_MAGIC, b"42", &other)).unwrap();
+        assert_eq!(cache.tokens(SOURCE), Ok(fresh));
+
+        let source = SOURCE.replace(" \"hi\"", "");
+        let tokens = || tokenize_recovering(&source).unwrap();
+        let expected = bytecode::encode(&parse(tokens()).unwrap());
+        cache.program(tokens()).unwrap();
+        let written = entries(&cache, bytecode::EXTENSION);
+        let other = bytecode::encode(&parse(tokenize_recovering("fun main ret").unwrap()).unwrap());
+        std::fs::write(&written[0], entry_bytes(PROGRAM_MAGIC, b"", &other)).unwrap();
+        assert_eq!(
+            cache.program(tokens()).map(|p| bytecode::encode(&p)),
+            Ok(expected)
+        );
+        std::fs::remove_dir_all(cache.dir()).unwrap();
+    }
+
+    #[test]
+    fn old_and_least_recently_used_entries_are_evicted() {
+        let cache = scratch("evict");
+        let sources: Vec<String> = (0..4)
+            .map(|n| SOURCE.replace('3', &n.to_string()))
+            .collect();
+        for source in &sources {
+            cache.tokens(source).unwrap();
+        }
+        let size = std::fs::metadata(&entries(&cache, "tokens")[0])
+            .unwrap()
+            .len();
+        // Age the first two; reading the first makes it recent again.
+        let long_ago = SystemTime::now() - Duration::from_secs(3600);
+        for source in &sources[..2] {
+            let path = cache.entry("tokens", hash(cache.format, "tokens", source.as_bytes()));
+            let file = std::fs::File::options().write(true).open(path).unwrap();
+            file.set_modified(long_ago).unwrap();
+        }
+        cache.tokens(&sources[0]).unwrap();
+
+        let small = cache.with_limits(3 * size, MAX_AGE);
+        small.tokens(&SOURCE.replace('3', "9")).unwrap();
+        let left = entries(&small, "tokens");
+        assert_eq!(left.len(), 3);
+        let second = small.entry(
+            "tokens",
+            hash(small.format, "tokens", sources[1].as_bytes()),
+        );
+        assert!(!left.contains(&second));
+
+        let young = small.with_limits(MAX_BYTES, Duration::from_secs(60));
+        let entry = young.entry(
+            "tokens",
+            hash(young.format, "tokens", sources[3].as_bytes()),
+        );
+        std::fs::File::options()
+            .write(true)
+            .open(&entry)
+            .unwrap()
+            .set_modified(long_ago)
+            .unwrap();
+        young.tokens(&SOURCE.replace('3', "8")).unwrap();
+        assert!(!entry.exists());
+        assert_eq!(entries(&young, "tokens").len(), 3);
+        std::fs::remove_dir_all(young.dir()).unwrap();
+    }
+
+    #[test]
+    fn damaged_entries_are_rebuilt() {
+        let cache = scratch("damaged");
+        let fresh = cache.tokens(SOURCE).unwrap();
+        let entries = entries(&cache, "tokens");
+        let [entry] = &entries[..] else {
+            panic!("expected one entry, found {:?}", entries);
+        };
+        let mut bytes = std::fs::read(entry).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        std::fs::write(entry, &bytes).unwrap();
+        assert_eq!(cache.tokens(SOURCE), Ok(fresh));
+        assert_ne!(std::fs::read(entry).unwrap(), bytes);
+        std::fs::remove_dir_all(cache.dir()).unwrap();
+    }
+
+    #[test]
+    fn edits_make_new_entries_and_errors_make_none() {
+        let cache = scratch("edits");
+        cache.tokens(SOURCE).unwrap();
+        cache.tokens(&SOURCE.replace('3', "4")).unwrap();
+        assert_eq!(entries(&cache, "tokens").len(), 2);
+        assert!(cache.tokens("fun main \"oops").is_err());
+        assert_eq!(entries(&cache, "tokens").len(), 2);
+        std::fs::remove_dir_all(cache.dir()).unwrap();
+    }
+
+    #[test]
+    fn programs_are_cached_as_bytecode() {
+        let cache = scratch("programs");
+        let source = SOURCE.replace(" \"hi\"", "");
+        let tokens = || tokenize_recovering(&source).unwrap();
+        let expected = bytecode::encode(&parse(tokens()).unwrap());
+        assert_eq!(
+            cache.program(tokens()).map(|p| bytecode::encode(&p)),
+            Ok(expected.clone())
+        );
+        assert_eq!(entries(&cache, bytecode::EXTENSION).len(), 1);
+        assert_eq!(
+            cache.program(tokens()).map(|p| bytecode::encode(&p)),
+            Ok(expected)
+        );
+
+        let broken = tokenize_recovering("fun main nope ret").unwrap();
+        assert!(cache.program(broken).is_err());
+        assert_eq!(entries(&cache, bytecode::EXTENSION).len(), 1);
+        std::fs::remove_dir_all(cache.dir()).unwrap();
+    }
+
+    #[test]
+    fn another_format_misses() {
+        let cache = scratch("format");
+        let source = SOURCE.replace(" \"hi\"", "");
+        let tokens = || tokenize_recovering(&source).unwrap();
+        cache.tokens(SOURCE).unwrap();
+        cache.program(tokens()).unwrap();
+        let newer = Cache {
+            format: FORMAT + 1,
+            ..Cache::new(cache.dir())
+        };
+        newer.tokens(SOURCE).unwrap();
+        newer.program(tokens()).unwrap();
+        assert_eq!(entries(&cache, "tokens").len(), 2);
+        assert_eq!(entries(&cache, bytecode::EXTENSION).len(), 2);
+        std::fs::remove_dir_all(cache.dir()).unwrap();
+    }
+}
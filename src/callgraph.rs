@@ -0,0 +1,211 @@
+// `rorth callgraph`: which function calls which, from the `Call`s the parser
+// resolved, with the functions that recurse and those nothing reachable from
+// `main` or `on_error` calls. Taking a function's `&` counts as calling it,
+// since whatever `invoke`s it can't be known.
+use alloc::collections::BTreeSet;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::parser::InstructionType;
+use crate::stack_machine::{Program, ERROR_HANDLER};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Tree,
+    Dot,
+}
+
+impl Format {
+    pub fn parse(value: &str) -> Option<Format> {
+        match value {
+            "tree" => Some(Format::Tree),
+            "dot" => Some(Format::Dot),
+            _ => None,
+        }
+    }
+}
+
+pub struct Graph {
+    // In the order they start in.
+    pub names: Vec<String>,
+    // What each function calls, by its place in `names`.
+    pub calls: Vec<BTreeSet<usize>>,
+}
+
+pub fn build(program: &Program) -> Graph {
+    let starts: Vec<usize> = program.symbols.keys().copied().collect();
+    let names = program.symbols.values().cloned().collect();
+    let mut calls = vec![BTreeSet::new(); starts.len()];
+    for (index, instruction) in program.instructions.iter().enumerate() {
+        let (InstructionType::Call(target) | InstructionType::Address(target)) =
+            instruction.instruction_type
+        else {
+            continue;
+        };
+        // Code before the first function isn't in one.
+        let Some(caller) = starts.iter().rposition(|start| *start <= index) else {
+            continue;
+        };
+        if let Ok(callee) = starts.binary_search(&target) {
+            calls[caller].insert(callee);
+        }
+    }
+    Graph { names, calls }
+}
+
+impl Graph {
+    fn find(&self, name: &str) -> Option<usize> {
+        self.names.iter().position(|n| n == name)
+    }
+
+    // The functions that call themselves. A name is only resolved once it
+    // has been declared, so that's the only cycle a program can have.
+    pub fn recursive(&self) -> Vec<usize> {
+        (0..self.names.len())
+            .filter(|f| self.calls[*f].contains(f))
+            .collect()
+    }
+
+    // Functions a run can never get to: nothing `main` or `on_error` calls,
+    // directly or not, calls them.
+    pub fn unreachable(&self) -> Vec<usize> {
+        let mut seen = vec![false; self.names.len()];
+        let mut todo: Vec<usize> = ["main", ERROR_HANDLER]
+            .iter()
+            .filter_map(|name| self.find(name))
+            .collect();
+        while let Some(function) = todo.pop() {
+            if !core::mem::replace(&mut seen[function], true) {
+                todo.extend(&self.calls[function]);
+            }
+        }
+        (0..self.names.len()).filter(|f| !seen[*f]).collect()
+    }
+
+    // `main`'s calls as an indented tree, then the recursive functions and the functions
+    // that can't be reached.
+    pub fn tree(&self) -> String {
+        let mut out = String::new();
+        let mut shown = vec![false; self.names.len()];
+        let mut path = vec![];
+        for root in ["main", ERROR_HANDLER] {
+            if let Some(root) = self.find(root) {
+                self.branch(root, &mut path, &mut shown, &mut out);
+            }
+        }
+        for (label, functions) in [
+            ("recursive", self.recursive()),
+            ("unreachable", self.unreachable()),
+        ] {
+            if !functions.is_empty() {
+                let names: Vec<&str> = functions.iter().map(|f| self.names[*f].as_str()).collect();
+                out.push_str(&format!("{}: {}\n", label, names.join(", ")));
+            }
+        }
+        out
+    }
+
+    fn branch(&self, function: usize, path: &mut Vec<usize>, shown: &mut [bool], out: &mut String) {
+        let indent = "  ".repeat(path.len());
+        let name = &self.names[function];
+        if path.contains(&function) {
+            out.push_str(&format!("{}{} (recursive)\n", indent, name));
+            return;
+        }
+        if shown[function] {
+            out.push_str(&format!("{}{} (see above)\n", indent, name));
+            return;
+        }
+        shown[function] = true;
+        out.push_str(&format!("{}{}\n", indent, name));
+        path.push(function);
+        for callee in &self.calls[function] {
+            self.branch(*callee, path, shown, out);
+        }
+        path.pop();
+    }
+
+    // Graphviz, with recursive calls in red and the
+    // functions that can't be reached dashed.
+    pub fn dot(&self) -> String {
+        let unreachable = self.unreachable();
+        let mut out = String::from("digraph calls {\n");
+        for (function, name) in self.names.iter().enumerate() {
+            let style = if unreachable.contains(&function) {
+                " [style=dashed]"
+            } else {
+                ""
+            };
+            out.push_str(&format!("  \"{}\"{};\n", name, style));
+        }
+        for (caller, callees) in self.calls.iter().enumerate() {
+            for callee in callees {
+                out.push_str(&format!(
+                    "  \"{}\" -> \"{}\"{};\n",
+                    self.names[caller],
+                    self.names[*callee],
+                    if caller == *callee {
+                        " [color=red]"
+                    } else {
+                        ""
+                    }
+                ));
+            }
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    pub fn export(&self, format: Format) -> String {
+        match format {
+            Format::Tree => self.tree(),
+            Format::Dot => self.dot(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod callgraph_tests {
+    use super::*;
+    use crate::parser::parse;
+    use crate::tokenizer::tokenize;
+
+    const SOURCE: &str = "fun fact dup if dup 1 - fact * else pop 1 end ret
+fun dead 1 print ret
+fun sq dup * ret
+fun twice sq sq ret
+fun main 3 fact twice print 2 sq print ret";
+
+    fn graph() -> Graph {
+        build(&parse(tokenize(SOURCE).unwrap()).unwrap())
+    }
+
+    #[test]
+    fn tree_shows_recursive_and_unreachable_functions() {
+        assert_eq!(
+            graph().tree(),
+            "main
+  fact
+    fact (recursive)
+  sq
+  twice
+    sq (see above)
+recursive: fact
+unreachable: dead
+"
+        );
+    }
+
+    #[test]
+    fn dot_marks_recursion_red() {
+        let dot = graph().dot();
+        assert!(dot.starts_with("digraph calls {\n"));
+        assert!(dot.contains("  \"dead\" [style=dashed];\n"));
+        assert!(dot.contains("  \"twice\" -> \"sq\";\n"));
+        assert!(dot.contains("  \"fact\" -> \"fact\" [color=red];\n"));
+        assert!(dot.contains("  \"main\" -> \"sq\";\n"));
+        assert_eq!(Format::parse("svg"), None);
+    }
+}
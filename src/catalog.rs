@@ -0,0 +1,493 @@
+// Error messages in other languages. A catalog maps an `Error::id` to a
+// template, where `{name}` stands for a field of the error; codes it leaves
+// out keep the English text of `Error::message`. `Table` reads one from a
+// file of lines like
+//
+//     # Français
+//     E0001 = mot inconnu `{word}`
+//     E1001 = division par zéro
+//
+// and embedders can supply their own with `Catalog`. `for_locale` picks one
+// of the catalogs that come with rorth by the usual locale variables.
+//
+// Parse errors and check findings, warnings included, share a code each, so
+// their messages have keys of their own under it, like `E0002.no-end`, in
+// `COMMENTS`. The parser and checker write the English with `text`; a
+// catalog with a template for the key gets the same `{name}`s back out of
+// it, translated too where they are messages themselves.
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::Display;
+
+use crate::common::Error;
+
+pub trait Catalog {
+    fn template(&self, id: &str) -> Option<&str>;
+}
+
+// `Error::message` as it is.
+pub struct English;
+
+impl Catalog for English {
+    fn template(&self, _: &str) -> Option<&str> {
+        None
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Table {
+    templates: BTreeMap<String, String>,
+}
+
+impl Table {
+    pub fn parse(text: &str) -> Result<Table, Error> {
+        let mut templates = BTreeMap::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let bad = |comment: String| Error::Usage {
+                comment: format!("messages:{}: {}", i + 1, comment),
+            };
+            let Some((id, template)) = line.split_once('=') else {
+                return Err(bad("expected `E0000 = message`".to_string()));
+            };
+            let id = id.trim();
+            if !is_id(id) {
+                return Err(bad(format!("`{}` is not an error code", id)));
+            }
+            templates.insert(id.to_string(), template.trim().to_string());
+        }
+        Ok(Table { templates })
+    }
+}
+
+impl Catalog for Table {
+    fn template(&self, id: &str) -> Option<&str> {
+        self.templates.get(id).map(String::as_str)
+    }
+}
+
+// The English of each keyed message. A key's code is the code of the errors
+// that carry it.
+pub const COMMENTS: &[(&str, &str)] = &[
+    ("E0002.unexpected", "Unexpected `{word}`"),
+    ("E0002.not-found", "Function not found"),
+    ("E0002.did-you-mean", "Function not found, did you mean `{name}`?"),
+    ("E0002.ambiguous", "`{name}` could be any of `{found}`"),
+    ("E0002.private", "`{name}` is private to module `{module}`"),
+    ("E0002.constant", "`{name}` is a constant, not a function"),
+    ("E0002.defined", "`{name}` is already defined"),
+    ("E0002.module-not-found", "Module not found"),
+    ("E0002.no-end", "This `{word}` has no matching end"),
+    ("E0002.needs-else", "This `end` closes `{word}`, which needs an `else`"),
+    ("E0002.needs-recover", "This `end` closes `{word}`, which needs a `recover`"),
+    ("E0002.no-while", "This `do` has no matching while"),
+    ("E0002.no-if", "This `else` has no matching if"),
+    ("E0002.no-try", "This `recover` has no matching try"),
+    ("E0002.pub", "`pub` must be followed by `fun`"),
+    ("E0002.field", "Expected a field name or `end`, found `{word}`"),
+    ("E0002.member", "Expected a member name or `end`, found `{word}`"),
+    ("E0002.two-fields", "`{name}` has two fields named `{field}`"),
+    ("E0002.two-members", "`{name}` has two members named `{field}`"),
+    ("E0002.env", "`env` expects a variable name in quotes"),
+    ("E0002.include", "`include` expects a file name in quotes"),
+    ("E0002.edition", "This rorth doesn't know edition {edition}; it reads {known}"),
+    ("E0002.pragma", "Expected `#pragma edition <year>`"),
+    ("E0003.underflow", "Not enough values on the stack for `{word}`"),
+    ("E0003.control-flow", "Control flow is not supported by the checker yet"),
+    (
+        "E0003.depth",
+        "the checker expected {expected} values on the stack after `{word}`, but there are {depth}",
+    ),
+    ("E0003.called", "{comment}, when called at {line}:{pos}"),
+    ("E0003.start", "at the start of `{name}`"),
+    ("E0003.after", "after `{word}` at {line}:{pos}"),
+    ("E0003.while-empty", "`while` is entered with an empty stack {edge}"),
+    ("E0003.end-empty", "`end` goes back to `while` with an empty stack {edge}"),
+    ("E0003.do-empty", "`do` is reached with an empty stack {edge}"),
+    (
+        "E0003.loop-depth",
+        "`end` goes back to `while` with {now} values on the stack, but the loop started with {start}",
+    ),
+    (
+        "E0003.peek-loop",
+        "`while … end` keeps its condition on the stack; write `while dup do … end` for the same loop, or `while … do … end` to take it",
+    ),
+    ("E0003.outside", "this is outside any function, so it never runs"),
+    ("E0003.runs-on", "`{name}` has no `ret`, so it runs on into `{next}`"),
+    ("E0003.no-ret", "`{name}` has no `ret`"),
+    ("E0003.too-long", "`{name}` has {size} instructions, more than the {max} allowed"),
+    ("E0003.not-a-bound", "`## {doc}` is not a loop bound; write `## bound n`"),
+    ("E0003.no-bound", "loop has no bound; write `## bound n` on the line before `while`"),
+    ("E0003.unused-private", "`{name}` is private and never called"),
+    ("E0003.divides-by-zero", "this always divides by 0"),
+    ("E0003.dropped", "this value is never used, only dropped"),
+    (
+        "E0003.left",
+        "this value is never used; it is still on the stack when `main` returns",
+    ),
+];
+
+// The English for `key` with `args` in place of its `{name}`s.
+pub fn text(key: &str, args: &[(&str, &dyn Display)]) -> String {
+    let template = COMMENTS
+        .iter()
+        .find(|(k, _)| *k == key)
+        .map_or(key, |(_, template)| template);
+    fill(template, |name| {
+        args.iter()
+            .find(|(arg, _)| *arg == name)
+            .map(|(_, value)| value.to_string())
+    })
+}
+
+// `template` with each `{name}` that `value` knows replaced. The others are
+// left as they are.
+fn fill(template: &str, value: impl Fn(&str) -> Option<String>) -> String {
+    let mut out = String::new();
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        out.push_str(&rest[..open]);
+        let after = &rest[open + 1..];
+        match after
+            .find('}')
+            .and_then(|close| Some((close, value(&after[..close])?)))
+        {
+            Some((close, value)) => {
+                out.push_str(&value);
+                rest = &after[close + 1..];
+            }
+            None => {
+                out.push('{');
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+// The `{name}`s of `template` as they are in `text`, if `text` is what it
+// makes. Each one runs to the first place the text after it matches.
+fn unfill<'t>(template: &str, text: &'t str) -> Option<Vec<(String, &'t str)>> {
+    let mut values = Vec::new();
+    let (literal, mut rest) = template.split_once('{').unwrap_or((template, ""));
+    let mut text = text.strip_prefix(literal)?;
+    if rest.is_empty() {
+        return text.is_empty().then_some(values);
+    }
+    loop {
+        let (name, after) = rest.split_once('}')?;
+        let (literal, next) = after.split_once('{').unwrap_or((after, ""));
+        let end = match next.is_empty() {
+            true => text.strip_suffix(literal).map(str::len)?,
+            false => text.find(literal)?,
+        };
+        values.push((name.to_string(), &text[..end]));
+        text = &text[end + literal.len()..];
+        if next.is_empty() {
+            return text.is_empty().then_some(values);
+        }
+        rest = next;
+    }
+}
+
+// `comment`, from an error with code `id`, in the words of `catalog`, if it
+// has them.
+fn translate(id: &str, comment: &str, catalog: &dyn Catalog) -> Option<String> {
+    COMMENTS.iter().find_map(|(key, english)| {
+        if key.split('.').next() != Some(id) {
+            return None;
+        }
+        let values = unfill(english, comment)?;
+        let template = catalog.template(key)?;
+        Some(fill(template, |name| {
+            let (_, value) = values.iter().find(|(n, _)| n == name)?;
+            Some(translate(id, value, catalog).unwrap_or_else(|| value.to_string()))
+        }))
+    })
+}
+
+// The catalogs that come with rorth, by language. English is
+// `Error::message` itself.
+pub const BUNDLED: &[(&str, &str)] = &[("fr", FRENCH)];
+
+const FRENCH: &str = "# Français
+E0001 = mot inconnu `{word}`
+E0002 = {comment}
+E0003 = {comment}
+E0004 = fonction `{name}` introuvable
+E0002.unexpected = `{word}` inattendu
+E0002.not-found = fonction introuvable
+E0002.did-you-mean = fonction introuvable, vouliez-vous dire `{name}` ?
+E0002.ambiguous = `{name}` peut désigner `{found}`
+E0002.private = `{name}` est privée au module `{module}`
+E0002.constant = `{name}` est une constante, pas une fonction
+E0002.defined = `{name}` est déjà défini
+E0002.module-not-found = module introuvable
+E0002.no-end = ce `{word}` n'a pas de `end` correspondant
+E0002.needs-else = ce `end` ferme `{word}`, qui demande un `else`
+E0002.needs-recover = ce `end` ferme `{word}`, qui demande un `recover`
+E0002.no-while = ce `do` n'a pas de `while` correspondant
+E0002.no-if = ce `else` n'a pas de `if` correspondant
+E0002.no-try = ce `recover` n'a pas de `try` correspondant
+E0002.pub = `pub` doit être suivi de `fun`
+E0002.field = nom de champ ou `end` attendu, trouvé `{word}`
+E0002.member = nom de membre ou `end` attendu, trouvé `{word}`
+E0002.two-fields = `{name}` a deux champs nommés `{field}`
+E0002.two-members = `{name}` a deux membres nommés `{field}`
+E0002.env = `env` attend un nom de variable entre guillemets
+E0002.include = `include` attend un nom de fichier entre guillemets
+E0002.edition = ce rorth ne connaît pas l'édition {edition} ; il lit {known}
+E0002.pragma = `#pragma edition <année>` attendu
+E0003.underflow = pas assez de valeurs sur la pile pour `{word}`
+E0003.control-flow = le vérificateur ne gère pas encore les structures de contrôle
+E0003.depth = le vérificateur attendait {expected} valeurs sur la pile après `{word}`, mais il y en a {depth}
+E0003.called = {comment}, lors de l'appel en {line}:{pos}
+E0003.start = au début de `{name}`
+E0003.after = après `{word}` en {line}:{pos}
+E0003.while-empty = on entre dans `while` avec une pile vide {edge}
+E0003.end-empty = `end` revient à `while` avec une pile vide {edge}
+E0003.do-empty = on arrive à `do` avec une pile vide {edge}
+E0003.loop-depth = `end` revient à `while` avec {now} valeurs sur la pile, mais la boucle a commencé avec {start}
+E0003.peek-loop = `while … end` garde sa condition sur la pile ; écrivez `while dup do … end` pour la même boucle, ou `while … do … end` pour la consommer
+E0003.outside = ceci est hors de toute fonction et ne s'exécute donc jamais
+E0003.runs-on = `{name}` n'a pas de `ret` et continue donc dans `{next}`
+E0003.no-ret = `{name}` n'a pas de `ret`
+E0003.too-long = `{name}` a {size} instructions, plus que les {max} permises
+E0003.not-a-bound = `## {doc}` n'est pas une borne de boucle ; écrivez `## bound n`
+E0003.no-bound = la boucle n'a pas de borne ; écrivez `## bound n` sur la ligne avant `while`
+E0003.unused-private = `{name}` est privée et jamais appelée
+E0003.divides-by-zero = ceci divise toujours par 0
+E0003.dropped = cette valeur n'est jamais utilisée, seulement jetée
+E0003.left = cette valeur n'est jamais utilisée ; elle est encore sur la pile quand `main` se termine
+E1001 = division par zéro
+E1002 = la pile est vide
+E1003 = dépassement arithmétique
+E1004 = {comment}
+E1005 = `{capability}` n'est pas permis ici
+E1006 = `{word}` : {comment}
+E1007 = `{word}` n'est pas pris en charge ici
+E1008 = rejeu : {comment}
+E1009 = `invoke` attend le `&nom` d'une fonction, pas {value}
+E2001 = limite de {limit} pas dépassée
+E2002 = limite de pile de {limit} dépassée
+E2003 = limite de temps de {limit} ms dépassée
+E2004 = limite de mémoire de {limit} octets dépassée
+E2005 = interrompu dans `{function}` avec {depth} valeurs sur la pile
+E3001 = {comment}
+E3002 = {path} : {comment}
+E3003 = rorth.toml:{line} : {comment}
+E3004 = bytecode : {comment}
+";
+
+// The bundled catalog for the locale `var` describes, looking at `LC_ALL`,
+// `LC_MESSAGES` and `LANG` in that order as POSIX does. None for English,
+// `C`, an unset locale or a language without a catalog, which all mean
+// `English`.
+pub fn for_locale(var: impl Fn(&str) -> Option<String>) -> Option<Table> {
+    let locale = ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .into_iter()
+        .filter_map(var)
+        .find(|value| !value.is_empty())?;
+    // `fr_FR.UTF-8@euro` is `fr`.
+    let language = locale
+        .split(['_', '.', '@'])
+        .next()
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    let (_, text) = BUNDLED.iter().find(|(name, _)| **name == language)?;
+    Table::parse(text).ok()
+}
+
+// `E0001`, or `E0002.no-end` for a keyed message under it.
+fn is_id(id: &str) -> bool {
+    let (code, key) = id.split_once('.').unwrap_or((id, "a"));
+    code.len() == 5
+        && code.starts_with('E')
+        && code[1..].bytes().all(|b| b.is_ascii_digit())
+        && !key.is_empty()
+        && key.bytes().all(|b| b.is_ascii_lowercase() || b == b'-')
+}
+
+// The message for `error` from `catalog`, without the position. A `{name}`
+// the error has no field for is left as it is.
+pub fn message(error: &Error, catalog: &dyn Catalog) -> String {
+    if let Error::Multiple(errors) = error {
+        let errors: Vec<String> = errors.iter().map(|e| message(e, catalog)).collect();
+        return errors.join("\n");
+    }
+    let Some(template) = catalog.template(error.id()) else {
+        return error.message();
+    };
+    let mut fields = error.fields();
+    if let Error::Parse { .. } | Error::StaticCheck { .. } = error {
+        for (name, value) in &mut fields {
+            if *name == "comment" {
+                *value = translate(error.id(), value, catalog).unwrap_or_else(|| value.clone());
+            }
+        }
+    }
+    fill(template, |name| {
+        let (_, value) = fields.iter().find(|(field, _)| *field == name)?;
+        Some(value.clone())
+    })
+}
+
+#[cfg(test)]
+mod catalog_tests {
+    use super::*;
+
+    #[test]
+    fn templates_fill_in_fields() {
+        let table = Table::parse(
+            "# Français\n\nE0001 = mot inconnu `{word}` {nope}\nE2001 = plus de {limit} pas",
+        )
+        .unwrap();
+        let unknown = Error::UnknownToken {
+            word: "^".to_string(),
+            pos: 1,
+            line: 1,
+        };
+        assert_eq!(message(&unknown, &table), "mot inconnu `^` {nope}");
+        let limit = Error::StepLimitExceeded {
+            limit: 10,
+            pos: 1,
+            line: 1,
+        };
+        assert_eq!(message(&limit, &table), "plus de 10 pas");
+        let empty = Error::StackEmpty { pos: 1, line: 1 };
+        assert_eq!(message(&empty, &table), "stack is empty");
+        assert_eq!(message(&unknown, &English), "unknown token `^`");
+    }
+
+    #[test]
+    fn locales_pick_a_bundled_catalog() {
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |name: &str| {
+                vars.iter()
+                    .find(|(var, _)| *var == name)
+                    .map(|(_, value)| value.to_string())
+            }
+        };
+        let empty = Error::StackEmpty { pos: 1, line: 1 };
+        let french = for_locale(env(&[("LANG", "fr_FR.UTF-8")])).unwrap();
+        assert_eq!(message(&empty, &french), "la pile est vide");
+        // `LC_ALL` over `LC_MESSAGES` over `LANG`, skipping empty ones.
+        let picked = |vars| for_locale(env(vars)).map(|table| message(&empty, &table));
+        assert_eq!(
+            picked(&[("LC_ALL", ""), ("LC_MESSAGES", "fr"), ("LANG", "en_US")]),
+            Some("la pile est vide".to_string())
+        );
+        assert_eq!(picked(&[("LC_ALL", "C"), ("LC_MESSAGES", "fr_CA")]), None);
+        assert_eq!(picked(&[("LANG", "de_DE.UTF-8")]), None);
+        assert_eq!(picked(&[]), None);
+        // The bundled catalogs are well formed.
+        for (_, text) in BUNDLED {
+            assert!(Table::parse(text).is_ok());
+        }
+    }
+
+    fn names(template: &str) -> Vec<&str> {
+        template
+            .split('{')
+            .skip(1)
+            .filter_map(|part| part.split_once('}').map(|(name, _)| name))
+            .collect()
+    }
+
+    #[test]
+    fn keyed_messages_read_back_as_themselves() {
+        for (key, english) in COMMENTS {
+            let args: Vec<(&str, String)> = names(english)
+                .into_iter()
+                .map(|name| (name, alloc::format!("<{}>", name)))
+                .collect();
+            let dyn_args: Vec<(&str, &dyn Display)> = args
+                .iter()
+                .map(|(name, value)| (*name, value as &dyn Display))
+                .collect();
+            let made = text(key, &dyn_args);
+            let (found, _) = COMMENTS
+                .iter()
+                .find(|(_, english)| unfill(english, &made).is_some())
+                .unwrap();
+            assert_eq!(found, key);
+            let values: Vec<(String, String)> = unfill(english, &made)
+                .unwrap()
+                .into_iter()
+                .map(|(name, value)| (name, value.to_string()))
+                .collect();
+            let expected: Vec<(String, String)> = args
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value))
+                .collect();
+            assert_eq!(values, expected, "{}", key);
+        }
+    }
+
+    #[test]
+    fn french_has_every_keyed_message() {
+        let french = Table::parse(FRENCH).unwrap();
+        for (key, english) in COMMENTS {
+            let template = french.template(key).unwrap_or_else(|| panic!("{}", key));
+            let mut want = names(english);
+            let mut have = names(template);
+            want.sort();
+            have.sort();
+            assert_eq!(want, have, "{}", key);
+        }
+    }
+
+    #[test]
+    fn parse_errors_and_warnings_in_french() {
+        use crate::checker::check_loop_conditions;
+        use crate::parser::parse;
+        use crate::tokenizer::tokenize;
+
+        let french = Table::parse(FRENCH).unwrap();
+        let parse_error = |source| match parse(tokenize(source).unwrap()) {
+            Err(error) => error,
+            Ok(_) => panic!("{} parsed", source),
+        };
+        assert_eq!(
+            message(
+                &parse_error("fun square dup * ret fun main 2 squar print ret"),
+                &french
+            ),
+            "fonction introuvable, vouliez-vous dire `square` ?"
+        );
+        assert_eq!(
+            message(&parse_error("fun main 1 if 2 print ret"), &french),
+            "ce `if` n'a pas de `end` correspondant"
+        );
+
+        let program =
+            parse(tokenize("fun count while 1 - end ret fun main count ret").unwrap()).unwrap();
+        let warnings: Vec<String> = check_loop_conditions(&program)
+            .iter()
+            .map(|warning| message(warning, &french))
+            .collect();
+        assert_eq!(
+            warnings,
+            ["on entre dans `while` avec une pile vide au début de `count`, lors de l'appel en 1:38"]
+        );
+    }
+
+    #[test]
+    fn bad_lines_are_reported() {
+        let comment = |text| match Table::parse(text) {
+            Err(Error::Usage { comment }) => comment,
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(comment("E0001"), "messages:1: expected `E0000 = message`");
+        assert_eq!(
+            comment("\nE01 = x"),
+            "messages:2: `E01` is not an error code"
+        );
+    }
+}
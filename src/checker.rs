@@ -1,109 +1,2086 @@
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Display;
+
+use crate::catalog::text;
 use crate::common::Error;
 use crate::parser::{Instruction, InstructionType};
+use crate::plugin;
+use crate::stack_machine::{Program, ERROR_HANDLER};
+use crate::tokenizer::{Token, TokenType};
+use crate::words;
+
+fn underflow(instruction: &Instruction) -> Error {
+    Error::StaticCheck {
+        word: format!("{}", instruction.instruction_type),
+        pos: instruction.pos,
+        line: instruction.line,
+        comment: text(
+            "E0003.underflow",
+            &[("word", &instruction.instruction_type)],
+        ),
+    }
+}
 
 pub fn check_stack_safety(program: &Vec<Instruction>) -> Result<(), Error> {
     let mut stack_size = 0;
     for instruction in program {
-        match instruction.instruction_type {
-            InstructionType::Push(_) => stack_size += 1,
-            InstructionType::Pop => stack_size -= 1,
-            InstructionType::Add
-            | InstructionType::Sub
-            | InstructionType::Mul
-            | InstructionType::Div => {
-                if stack_size < 2 {
-                    return Err(Error::StaticCheck {
-                        word: "".to_string(),
-                        pos: 0,
-                        line: 0,
-                        comment: "".to_string(),
-                    });
-                }
-                stack_size -= 1; // takes two and puts one
+        let Some((takes, leaves)) = simple_effect(&instruction.instruction_type) else {
+            return Err(Error::StaticCheck {
+                word: format!("{}", instruction.instruction_type),
+                pos: instruction.pos,
+                line: instruction.line,
+                comment: text("E0003.control-flow", &[]),
+            });
+        };
+        if stack_size < takes {
+            return Err(underflow(instruction));
+        }
+        stack_size += leaves - takes;
+    }
+    Ok(())
+}
+
+// The straight-line start of the function at `start`, entered with `depth`
+// values on the stack: the first word there that takes more than it has,
+// before a jump or a call makes the depth a guess. Cheap enough to run on
+// every parse, unlike the checks below.
+pub fn underflow_on_entry(program: &Program, start: usize, depth: usize) -> Option<Error> {
+    let mut depth = depth as i64;
+    for instruction in program.instructions.get(start..)? {
+        let (takes, leaves) = simple_effect(&instruction.instruction_type)?;
+        if depth < takes {
+            return Some(underflow(instruction));
+        }
+        if instruction.instruction_type.jumps() {
+            return None;
+        }
+        depth += leaves - takes;
+    }
+    None
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StackEffect {
+    pub inputs: usize,
+    pub outputs: usize,
+}
+
+impl Display for StackEffect {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "( {} -- {} )", self.inputs, self.outputs)
+    }
+}
+
+// How many values an instruction takes and leaves, for the ones that don't
+// jump. `if` and `while` only peek at their condition.
+pub(crate) fn simple_effect(instruction: &InstructionType) -> Option<(i64, i64)> {
+    let effect = match instruction {
+        InstructionType::Push(_) | InstructionType::Address(_) | InstructionType::Env(_) => {
+            return Some((0, 1))
+        }
+        InstructionType::Native(id) => plugin::word(*id)?.native().effect,
+        _ => words::op(instruction)?.1.effect?,
+    };
+    Some((effect.inputs as i64, effect.outputs as i64))
+}
+
+// Depth relative to the function's entry, and the lowest depth reached.
+#[derive(Clone, Copy)]
+struct Depth {
+    now: i64,
+    lowest: i64,
+}
+
+impl Depth {
+    fn apply(&mut self, takes: i64, leaves: i64) {
+        self.now -= takes;
+        self.lowest = self.lowest.min(self.now);
+        self.now += leaves;
+    }
+}
+
+#[derive(PartialEq)]
+enum BlockEnd {
+    Branch,
+    Ret,
+}
+
+struct Inference<'a> {
+    program: &'a Program,
+    known: BTreeMap<usize, Option<StackEffect>>,
+    // Depth after each instruction of the function being walked, relative
+    // to its entry. Kept in `depths` once the function has an effect.
+    seen: BTreeMap<usize, i64>,
+    depths: BTreeMap<usize, i64>,
+}
+
+impl<'a> Inference<'a> {
+    fn new(program: &'a Program) -> Self {
+        Self {
+            program,
+            known: BTreeMap::new(),
+            seen: BTreeMap::new(),
+            depths: BTreeMap::new(),
+        }
+    }
+
+    fn function(&mut self, start: usize) -> Option<StackEffect> {
+        if let Some(effect) = self.known.get(&start) {
+            return *effect;
+        }
+        // Recursion has no finite effect to offer until we reason about it.
+        self.known.insert(start, None);
+        let caller = core::mem::take(&mut self.seen);
+        let mut depth = Depth { now: 0, lowest: 0 };
+        let effect = match self.block(start, &mut depth) {
+            Some(BlockEnd::Ret) => Some(StackEffect {
+                inputs: (-depth.lowest) as usize,
+                outputs: (depth.now - depth.lowest) as usize,
+            }),
+            _ => None,
+        };
+        let seen = core::mem::replace(&mut self.seen, caller);
+        if effect.is_some() {
+            self.depths.extend(seen);
+        }
+        self.known.insert(start, effect);
+        effect
+    }
+
+    // Walks the code from `i` up to the `else`, `end` or `ret` that ends the
+    // block. An early `ret` inside a nested block gives up.
+    fn nested(&mut self, i: usize, depth: &mut Depth) -> Option<()> {
+        match self.block(i, depth)? {
+            BlockEnd::Branch => Some(()),
+            BlockEnd::Ret => None,
+        }
+    }
+
+    fn block(&mut self, mut i: usize, depth: &mut Depth) -> Option<BlockEnd> {
+        loop {
+            let instruction = &self.program.instructions.get(i)?.instruction_type;
+            if let Some((takes, leaves)) = simple_effect(instruction) {
+                depth.apply(takes, leaves);
+                self.seen.insert(i, depth.now);
+                i += 1;
+                continue;
             }
-            InstructionType::Print => {
-                if stack_size < 1 {
-                    return Err(Error::StaticCheck {
-                        word: "".to_string(),
-                        pos: 0,
-                        line: 0,
-                        comment: "".to_string(),
-                    });
-                }
-                stack_size -= 1;
+            match instruction {
+                InstructionType::If(else_idx) => {
+                    depth.apply(1, 1);
+                    self.seen.insert(i, depth.now);
+                    let mut then_depth = *depth;
+                    self.nested(i + 1, &mut then_depth)?;
+                    let InstructionType::Else(end_idx) =
+                        self.program.instructions[*else_idx].instruction_type
+                    else {
+                        return None;
+                    };
+                    let mut else_depth = *depth;
+                    self.nested(else_idx + 1, &mut else_depth)?;
+                    if then_depth.now != else_depth.now {
+                        return None;
+                    }
+                    depth.now = then_depth.now;
+                    depth.lowest = then_depth.lowest.min(else_depth.lowest);
+                    i = end_idx + 1;
+                }
+                // The `recover` branch starts from the stack as it was at
+                // `try`, plus the error code. A body that reaches below that
+                // leaves it unknown.
+                InstructionType::Try(recover_idx) => {
+                    self.seen.insert(i, depth.now);
+                    let mut body = *depth;
+                    self.nested(i + 1, &mut body)?;
+                    if body.lowest < depth.now {
+                        return None;
+                    }
+                    let InstructionType::Recover(end_idx) =
+                        self.program.instructions[*recover_idx].instruction_type
+                    else {
+                        return None;
+                    };
+                    let mut recover = *depth;
+                    recover.apply(0, 1);
+                    self.nested(recover_idx + 1, &mut recover)?;
+                    if body.now != recover.now {
+                        return None;
+                    }
+                    depth.now = body.now;
+                    depth.lowest = depth.lowest.min(recover.lowest);
+                    i = end_idx + 1;
+                }
+                InstructionType::While(end_idx) => {
+                    depth.apply(1, 1);
+                    self.seen.insert(i, depth.now);
+                    let mut body = *depth;
+                    self.nested(i + 1, &mut body)?;
+                    if body.now != depth.now {
+                        return None;
+                    }
+                    depth.lowest = body.lowest;
+                    i = end_idx + 1;
+                }
+                // Each time round starts from the same depth, so the body
+                // must leave it as the condition found it.
+                InstructionType::Loop(do_idx) => {
+                    let start = depth.now;
+                    self.seen.insert(i, start);
+                    self.nested(i + 1, depth)?;
+                    depth.apply(1, 0);
+                    self.seen.insert(*do_idx, depth.now);
+                    let InstructionType::Do(end_idx) =
+                        self.program.instructions[*do_idx].instruction_type
+                    else {
+                        return None;
+                    };
+                    let mut body = *depth;
+                    self.nested(do_idx + 1, &mut body)?;
+                    if body.now != start {
+                        return None;
+                    }
+                    depth.lowest = body.lowest;
+                    i = end_idx + 1;
+                }
+                InstructionType::Call(target) => {
+                    let effect = self.function(*target)?;
+                    depth.apply(effect.inputs as i64, effect.outputs as i64);
+                    self.seen.insert(i, depth.now);
+                    i += 1;
+                }
+                // Which function runs isn't known until then.
+                InstructionType::Invoke => return None,
+                InstructionType::Ret => {
+                    self.seen.insert(i, depth.now);
+                    return Some(BlockEnd::Ret);
+                }
+                // `else`, `end`, `do` and `recover` move nothing, apart from
+                // `do`, which its loop puts right.
+                _ => {
+                    self.seen.insert(i, depth.now);
+                    return Some(BlockEnd::Branch);
+                }
             }
-            InstructionType::Dup => {
-                if stack_size < 1 {
-                    return Err(Error::StaticCheck {
-                        word: "".to_string(),
-                        pos: 0,
-                        line: 0,
-                        comment: "".to_string(),
-                    });
-                }
-                stack_size += 1;
+        }
+    }
+}
+
+// The stack effect of every function that has a fixed one. Functions whose
+// effect depends on the path taken, or that recurse, are left out.
+pub fn infer_stack_effects(program: &Program) -> BTreeMap<String, StackEffect> {
+    let mut inference = Inference::new(program);
+    program
+        .functions
+        .iter()
+        .filter_map(|(name, start)| Some((name.clone(), inference.function(*start)?)))
+        .collect()
+}
+
+// The stack depth the checker expects after each instruction, relative to
+// the depth its function was entered with. Only functions with a fixed
+// effect have any; the rest run unchecked under `--verify`.
+pub fn predicted_depths(program: &Program) -> BTreeMap<usize, i64> {
+    let mut inference = Inference::new(program);
+    for start in program.functions.values() {
+        inference.function(*start);
+    }
+    inference.depths
+}
+
+// `--verify`: follows a run step by step and holds the stack to
+// `predicted_depths`, so a checker that is wrong about some code says so the
+// first time that code runs.
+pub struct Verifier {
+    predicted: BTreeMap<usize, i64>,
+    // The depth each running function was entered with, outermost first.
+    // None where that isn't known, as for an `on_error` handler, which is
+    // entered without a `call`.
+    entries: Vec<Option<usize>>,
+}
+
+impl Verifier {
+    pub fn new(program: &Program) -> Self {
+        Self {
+            predicted: predicted_depths(program),
+            entries: vec![],
+        }
+    }
+
+    // Starts following a run `calls` deep, with the stack at `depth` when
+    // that is where the running function was entered.
+    pub fn start(&mut self, calls: usize, depth: Option<usize>) {
+        self.entries = vec![None; calls];
+        self.entries.push(depth);
+    }
+
+    // After `instruction`, at `index`, ran in the function `calls` deep and
+    // left `depth` values on the stack.
+    pub fn step(
+        &mut self,
+        index: usize,
+        instruction: &Instruction,
+        calls: usize,
+        depth: usize,
+    ) -> Result<(), Error> {
+        if self.entries.len() <= calls {
+            // Entered by the machine rather than a `call`: the function
+            // below gets back a stack nobody predicted.
+            if let Some(below) = self.entries.last_mut() {
+                *below = None;
             }
-            InstructionType::Swap => {
-                if stack_size < 2 {
-                    return Err(Error::StaticCheck {
-                        word: "".to_string(),
-                        pos: 0,
-                        line: 0,
-                        comment: "".to_string(),
-                    });
+        }
+        self.entries.resize(calls + 1, None);
+        if let InstructionType::Call(_) = instruction.instruction_type {
+            // The stack is as the called function finds it.
+            self.entries.push(Some(depth));
+            return Ok(());
+        }
+        let expected = self.entries[calls]
+            .zip(self.predicted.get(&index))
+            .map(|(entry, predicted)| entry as i64 + predicted);
+        if instruction.instruction_type == InstructionType::Ret {
+            self.entries.truncate(calls);
+        }
+        match expected {
+            Some(expected) if expected != depth as i64 => {
+                let word = instruction.instruction_type.to_string();
+                Err(Error::StaticCheck {
+                    comment: text(
+                        "E0003.depth",
+                        &[("expected", &expected), ("word", &word), ("depth", &depth)],
+                    ),
+                    word,
+                    pos: instruction.pos,
+                    line: instruction.line,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+// `while` and its `end` peek at a condition and `do` takes one, so the
+// stack must not be empty on any edge into them. A `while … do … end` body
+// must also leave the depth it started with. Starting from `main` with an empty stack, every path
+// is followed with its exact depth, into calls too. Paths whose depth can't
+// be known are left alone, and so is code inside `try`, where an empty stack
+// is an error the program handles.
+pub fn check_loop_conditions(program: &Program) -> Vec<Error> {
+    let Some(start) = program.functions.get("main") else {
+        return vec![];
+    };
+    let mut check = LoopCheck {
+        program,
+        inference: Inference::new(program),
+        entered: BTreeMap::new(),
+        calls: vec![],
+        errors: vec![],
+    };
+    check.function(*start, 0);
+    let mut errors = check.errors;
+    errors.sort_by_key(|e| e.position());
+    errors.dedup();
+    errors
+}
+
+struct LoopCheck<'a> {
+    program: &'a Program,
+    inference: Inference<'a>,
+    // Depth on return, by function and the depth it was entered with. None
+    // while it is being walked.
+    entered: BTreeMap<(usize, i64), Option<i64>>,
+    // The call instructions that led to the code being walked.
+    calls: Vec<usize>,
+    errors: Vec<Error>,
+}
+
+impl LoopCheck<'_> {
+    fn function(&mut self, start: usize, depth: i64) -> Option<i64> {
+        // Recursion, at any depth so one that grows the stack still ends:
+        // fall back on the inferred effect.
+        let walking = self
+            .entered
+            .iter()
+            .any(|(&(function, _), returned)| function == start && returned.is_none());
+        if walking || self.entered.contains_key(&(start, depth)) {
+            return self
+                .entered
+                .get(&(start, depth))
+                .copied()
+                .flatten()
+                .or_else(|| {
+                    let effect = self.inference.function(start)?;
+                    let inputs = effect.inputs as i64;
+                    (depth >= inputs).then_some(depth - inputs + effect.outputs as i64)
+                });
+        }
+        self.entered.insert((start, depth), None);
+        let mut now = depth;
+        let returned = match self.block(start, &mut now)? {
+            BlockEnd::Ret => Some(now),
+            BlockEnd::Branch => None,
+        };
+        self.entered.insert((start, depth), returned);
+        returned
+    }
+
+    fn nested(&mut self, i: usize, now: &mut i64) -> Option<()> {
+        match self.block(i, now)? {
+            BlockEnd::Branch => Some(()),
+            BlockEnd::Ret => None,
+        }
+    }
+
+    fn block(&mut self, mut i: usize, now: &mut i64) -> Option<BlockEnd> {
+        loop {
+            let instruction = &self.program.instructions.get(i)?.instruction_type;
+            if let Some((takes, leaves)) = simple_effect(instruction) {
+                if *now < takes {
+                    return None;
                 }
+                *now += leaves - takes;
+                i += 1;
+                continue;
             }
-            InstructionType::Rot => {
-                if stack_size < 3 {
-                    return Err(Error::StaticCheck {
-                        word: "".to_string(),
-                        pos: 0,
-                        line: 0,
-                        comment: "".to_string(),
-                    });
+            match instruction {
+                InstructionType::If(else_idx) => {
+                    if *now < 1 {
+                        return None;
+                    }
+                    let mut then_depth = *now;
+                    self.nested(i + 1, &mut then_depth)?;
+                    let InstructionType::Else(end_idx) =
+                        self.program.instructions[*else_idx].instruction_type
+                    else {
+                        return None;
+                    };
+                    let mut else_depth = *now;
+                    self.nested(else_idx + 1, &mut else_depth)?;
+                    if then_depth != else_depth {
+                        return None;
+                    }
+                    *now = then_depth;
+                    i = end_idx + 1;
+                }
+                InstructionType::Try(recover_idx) => {
+                    let InstructionType::Recover(end_idx) =
+                        self.program.instructions[*recover_idx].instruction_type
+                    else {
+                        return None;
+                    };
+                    let reported = self.errors.len();
+                    let mut body = *now;
+                    let body_ends = self.nested(i + 1, &mut body);
+                    self.errors.truncate(reported);
+                    let mut recover = *now + 1;
+                    self.nested(recover_idx + 1, &mut recover)?;
+                    if body_ends.is_none() || body != recover {
+                        return None;
+                    }
+                    *now = body;
+                    i = end_idx + 1;
+                }
+                InstructionType::While(end_idx) => {
+                    if *now < 1 {
+                        let edge = self.entry_edge(i);
+                        self.report(i, text("E0003.while-empty", &[("edge", &edge)]));
+                        return None;
+                    }
+                    let mut body = *now;
+                    self.nested(i + 1, &mut body)?;
+                    if body < 1 {
+                        let edge = self.edge(*end_idx - 1);
+                        self.report(*end_idx, text("E0003.end-empty", &[("edge", &edge)]));
+                        return None;
+                    }
+                    if body != *now {
+                        return None;
+                    }
+                    i = end_idx + 1;
                 }
+                // The condition must leave a value for `do` to take, and
+                // the body must end where the loop started, since the
+                // condition runs again from there.
+                InstructionType::Loop(do_idx) => {
+                    let start = *now;
+                    self.nested(i + 1, now)?;
+                    if *now < 1 {
+                        let edge = self.edge(*do_idx - 1);
+                        self.report(*do_idx, text("E0003.do-empty", &[("edge", &edge)]));
+                        return None;
+                    }
+                    *now -= 1;
+                    let InstructionType::Do(end_idx) =
+                        self.program.instructions[*do_idx].instruction_type
+                    else {
+                        return None;
+                    };
+                    let mut body = *now;
+                    self.nested(do_idx + 1, &mut body)?;
+                    if body != start {
+                        self.report(
+                            end_idx,
+                            text("E0003.loop-depth", &[("now", &body), ("start", &start)]),
+                        );
+                        return None;
+                    }
+                    i = end_idx + 1;
+                }
+                InstructionType::Call(target) => {
+                    self.calls.push(i);
+                    let returned = self.function(*target, *now);
+                    self.calls.pop();
+                    *now = returned?;
+                    i += 1;
+                }
+                InstructionType::Invoke => return None,
+                InstructionType::Ret => return Some(BlockEnd::Ret),
+                _ => return Some(BlockEnd::Branch),
+            }
+        }
+    }
+
+    // The edge a loop is entered by: the instruction before it, or the
+    // start of the function it opens.
+    fn entry_edge(&self, i: usize) -> String {
+        match self.function_at(i) {
+            Some(name) => text("E0003.start", &[("name", &name)]),
+            None => self.edge(i - 1),
+        }
+    }
+
+    fn edge(&self, from: usize) -> String {
+        let instruction = &self.program.instructions[from];
+        text(
+            "E0003.after",
+            &[
+                ("word", &instruction.instruction_type),
+                ("line", &instruction.line),
+                ("pos", &instruction.pos),
+            ],
+        )
+    }
+
+    // The first name the function starting at `i` goes by.
+    fn function_at(&self, i: usize) -> Option<&str> {
+        self.program.symbol(i)
+    }
+
+    fn report(&mut self, i: usize, mut comment: String) {
+        let instruction = &self.program.instructions[i];
+        let word = instruction.instruction_type.to_string();
+        if let Some(call) = self.calls.last() {
+            let call = &self.program.instructions[*call];
+            comment = text(
+                "E0003.called",
+                &[
+                    ("comment", &comment),
+                    ("line", &call.line),
+                    ("pos", &call.pos),
+                ],
+            );
+        }
+        self.errors.push(Error::StaticCheck {
+            word,
+            pos: instruction.pos,
+            line: instruction.line,
+            comment,
+        });
+    }
+}
+
+// Loops in the old form, which leave their condition on the stack, for
+// `--strict-loops`. `while dup do … end` runs the same way.
+pub fn peek_loops(program: &Program) -> Vec<Error> {
+    program
+        .instructions
+        .iter()
+        .filter(|i| matches!(i.instruction_type, InstructionType::While(_)))
+        .map(|i| Error::StaticCheck {
+            word: i.instruction_type.to_string(),
+            pos: i.pos,
+            line: i.line,
+            comment: text("E0003.peek-loop", &[]),
+        })
+        .collect()
+}
+
+// How many blocks an instruction opens (1) or closes (-1).
+pub(crate) fn depth_change(instruction: &InstructionType) -> isize {
+    match instruction {
+        InstructionType::While(_)
+        | InstructionType::Loop(_)
+        | InstructionType::If(_)
+        | InstructionType::Try(_) => 1,
+        InstructionType::EndWhile(_)
+        | InstructionType::EndDo(_)
+        | InstructionType::EndIf
+        | InstructionType::EndTry => -1,
+        _ => 0,
+    }
+}
+
+// `--strict-entry`: a program is its `entry`, usually `main`, and what that
+// calls, and nothing runs by being reached in order. Code outside every
+// function is refused, and so is a function that doesn't end in `ret` and
+// would run on into the next.
+pub fn strict_entry(program: &Program, entry: &str) -> Vec<Error> {
+    if !program.functions.contains_key(entry) {
+        return vec![Error::FunctionNotFound {
+            name: entry.to_string(),
+        }];
+    }
+    let outside = |instruction: &Instruction| Error::StaticCheck {
+        word: instruction.instruction_type.to_string(),
+        pos: instruction.pos,
+        line: instruction.line,
+        comment: text("E0003.outside", &[]),
+    };
+    let spans = program.spans();
+    let mut errors = vec![];
+    let first = spans
+        .first()
+        .map_or(program.instructions.len(), |(_, span)| span.start);
+    errors.extend(program.instructions[..first].first().map(outside));
+    for (i, (name, span)) in spans.iter().enumerate() {
+        let body = &program.instructions[span.clone()];
+        // Nothing jumps past a `ret` outside every block, so what follows it
+        // is code after the function.
+        let mut depth = 0;
+        let end = body.iter().position(|instruction| {
+            depth += depth_change(&instruction.instruction_type);
+            depth == 0 && instruction.instruction_type == InstructionType::Ret
+        });
+        match end {
+            Some(end) => errors.extend(body.get(end + 1).map(outside)),
+            None => {
+                let last = body.last().unwrap_or(&program.instructions[span.start]);
+                let comment = match spans.get(i + 1) {
+                    Some((next, _)) => text("E0003.runs-on", &[("name", name), ("next", next)]),
+                    None => text("E0003.no-ret", &[("name", name)]),
+                };
+                errors.push(Error::StaticCheck {
+                    word: name.to_string(),
+                    pos: last.pos,
+                    line: last.line,
+                    comment,
+                });
+            }
+        }
+    }
+    errors
+}
+
+// `rorth check --verifiable n`, for programs meant to be read and reasoned
+// about: no function longer than `max_size` instructions, and every loop
+// saying how often it can go round with a `## bound n` line above the one
+// its `while` is on.
+pub fn verifiable(program: &Program, tokens: &[Token], max_size: usize) -> Vec<Error> {
+    let mut errors = vec![];
+    for (name, span) in program.spans() {
+        let size = span.len();
+        if size <= max_size {
+            continue;
+        }
+        // The first instruction over the limit.
+        let over = &program.instructions[span.start + max_size];
+        errors.push(Error::StaticCheck {
+            word: name.to_string(),
+            pos: over.pos,
+            line: over.line,
+            comment: text(
+                "E0003.too-long",
+                &[("name", &name), ("size", &size), ("max", &max_size)],
+            ),
+        });
+    }
+    for (i, token) in tokens.iter().enumerate() {
+        if token.token_type != TokenType::While {
+            continue;
+        }
+        // The line the loop starts on may set up its counter first.
+        let mut first = i;
+        while first > 0
+            && tokens[first - 1].line == token.line
+            && !matches!(tokens[first - 1].token_type, TokenType::Doc(_))
+        {
+            first -= 1;
+        }
+        let comment = match first.checked_sub(1).map(|i| &tokens[i].token_type) {
+            Some(TokenType::Doc(doc)) if loop_bound(doc).is_some() => continue,
+            Some(TokenType::Doc(doc)) => text("E0003.not-a-bound", &[("doc", doc)]),
+            _ => text("E0003.no-bound", &[]),
+        };
+        errors.push(Error::StaticCheck {
+            word: "while".to_string(),
+            pos: token.pos,
+            line: token.line,
+            comment,
+        });
+    }
+    errors.sort_by_key(|e| e.position());
+    errors
+}
+
+fn loop_bound(doc: &str) -> Option<u32> {
+    doc.strip_prefix("bound")?.trim().parse().ok()
+}
+
+// Private module functions nothing calls or takes the `&` of can never run.
+pub fn unused_private_functions(program: &Program) -> Vec<Error> {
+    let mut unused: Vec<(&String, usize)> = program
+        .private
+        .iter()
+        .map(|name| (name, program.functions[name]))
+        .filter(|(_, idx)| {
+            !program.instructions.iter().any(|i| {
+                i.instruction_type == InstructionType::Call(*idx)
+                    || i.instruction_type == InstructionType::Address(*idx)
+            })
+        })
+        .collect();
+    unused.sort_by_key(|(name, idx)| (*idx, *name));
+    unused
+        .into_iter()
+        .map(|(name, idx)| {
+            let (pos, line) = program
+                .instructions
+                .get(idx)
+                .map_or((0, 0), |i| (i.pos, i.line));
+            Error::StaticCheck {
+                word: name.clone(),
+                pos,
+                line,
+                comment: text("E0003.unused-private", &[("name", name)]),
+            }
+        })
+        .collect()
+}
+
+// What a value may be: every i32 from `low` to `high`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Range {
+    pub low: i32,
+    pub high: i32,
+}
+
+impl Range {
+    pub const ANY: Range = Range {
+        low: i32::MIN,
+        high: i32::MAX,
+    };
+
+    pub fn exactly(n: i32) -> Self {
+        Range { low: n, high: n }
+    }
+
+    pub fn contains(self, n: i32) -> bool {
+        self.low <= n && n <= self.high
+    }
+
+    fn hull(self, other: Range) -> Range {
+        Range {
+            low: self.low.min(other.low),
+            high: self.high.max(other.high),
+        }
+    }
+
+    // Grown to take in `next`. A bound that moves goes straight to the next
+    // of 1, 0 and -1 past it, or as far as it can, so loops settle.
+    fn widen(self, next: Range) -> Range {
+        let low = match next.low < self.low {
+            true => [1, 0, -1].into_iter().find(|t| *t <= next.low),
+            false => Some(self.low),
+        };
+        let high = match next.high > self.high {
+            true => [-1, 0, 1].into_iter().find(|t| *t >= next.high),
+            false => Some(self.high),
+        };
+        Range {
+            low: low.unwrap_or(i32::MIN),
+            high: high.unwrap_or(i32::MAX),
+        }
+    }
+
+    fn nonzero(self) -> Option<Range> {
+        match (self.low, self.high) {
+            (0, 0) => None,
+            (0, high) => Some(Range { low: 1, high }),
+            (low, 0) => Some(Range { low, high: -1 }),
+            _ => Some(self),
+        }
+    }
+
+    fn zero(self) -> Option<Range> {
+        self.contains(0).then_some(Range::exactly(0))
+    }
+
+    // `self` and `other` through `+`, `-` or `*`, `other` being the top
+    // value. The `w` ones wrap, so a result that might not fit could be
+    // anything. The others stay between the ends: the `s` ones stop there,
+    // and plain ones stop the run instead of going past.
+    fn combine(self, other: Range, f: fn(i64, i64) -> i64, bounded: bool) -> Range {
+        let ends = [
+            f(self.low as i64, other.low as i64),
+            f(self.low as i64, other.high as i64),
+            f(self.high as i64, other.low as i64),
+            f(self.high as i64, other.high as i64),
+        ];
+        let (low, high) = (ends.into_iter().min(), ends.into_iter().max());
+        let (low, high) = (low.unwrap_or(0), high.unwrap_or(0));
+        let fits = |n: i64| i32::try_from(n).ok();
+        let clamp = |n: i64| n.clamp(i32::MIN as i64, i32::MAX as i64) as i32;
+        match (fits(low), fits(high)) {
+            (Some(low), Some(high)) => Range { low, high },
+            _ if bounded => Range {
+                low: clamp(low),
+                high: clamp(high),
+            },
+            _ => Range::ANY,
+        }
+    }
+}
+
+// A value on the stack: the range it is in, and the instruction and output
+// that made it, which its copies share.
+#[derive(Clone, PartialEq)]
+struct Slot {
+    range: Range,
+    made: Option<(usize, usize)>,
+}
+
+// The stack as far as `Ranges` knows it: its depth from where the function
+// was entered, and the values on top of it, the last being the top one.
+#[derive(Clone, PartialEq)]
+struct Values {
+    depth: i64,
+    known: Vec<Slot>,
+}
+
+impl Values {
+    fn pop(&mut self) -> Slot {
+        self.depth -= 1;
+        self.known.pop().unwrap_or(Slot {
+            range: Range::ANY,
+            made: None,
+        })
+    }
+
+    fn push(&mut self, slot: Slot) {
+        self.depth += 1;
+        self.known.push(slot);
+    }
+
+    // A value from output `n` of instruction `i`. What the instruction
+    // made on an earlier time round a loop is a different value.
+    fn make(&mut self, i: usize, n: usize, range: Range) {
+        let made = Some((i, n));
+        for slot in &mut self.known {
+            if slot.made == made {
+                slot.made = None;
             }
-            InstructionType::Over => {
-                if stack_size < 2 {
-                    return Err(Error::StaticCheck {
-                        word: "".to_string(),
-                        pos: 0,
-                        line: 0,
-                        comment: "".to_string(),
-                    });
+        }
+        self.push(Slot { range, made });
+    }
+
+    // The stack once `slot`, at `at` if it is still on it, has been found
+    // to be zero or not, with its copies. None when it can't have been.
+    fn assume(&self, at: Option<usize>, slot: &Slot, nonzero: bool) -> Option<Values> {
+        let narrow = |range: Range| match nonzero {
+            true => range.nonzero(),
+            false => range.zero(),
+        };
+        narrow(slot.range)?;
+        let mut values = self.clone();
+        for (i, other) in values.known.iter_mut().enumerate() {
+            if Some(i) == at || (slot.made.is_some() && other.made == slot.made) {
+                other.range = narrow(other.range)?;
+            }
+        }
+        Some(values)
+    }
+
+    fn assume_top(&self, nonzero: bool) -> Option<Values> {
+        match self.known.last() {
+            Some(top) => self.assume(Some(self.known.len() - 1), &top.clone(), nonzero),
+            None => Some(self.clone()),
+        }
+    }
+
+    // Either of two stacks that meet at `at`, or None when their depths
+    // differ. With `widen`, `self` is where a loop starts and `other` what
+    // it comes back with. Values stay copies of each other only if they
+    // were on both ways in.
+    fn join(&self, other: &Values, at: usize, widen: bool) -> Option<Values> {
+        if self.depth != other.depth {
+            return None;
+        }
+        let n = self.known.len().min(other.known.len());
+        let ours = &self.known[self.known.len() - n..];
+        let theirs = &other.known[other.known.len() - n..];
+        let same =
+            |p: usize, q: usize| ours[p].made == ours[q].made && theirs[p].made == theirs[q].made;
+        let known = (0..n)
+            .map(|p| Slot {
+                range: match widen {
+                    true => ours[p].range.widen(theirs[p].range),
+                    false => ours[p].range.hull(theirs[p].range),
+                },
+                made: (ours[p].made.is_some() && theirs[p].made.is_some())
+                    .then(|| (at, 2 + (0..n).position(|q| same(p, q)).unwrap_or(p))),
+            })
+            .collect();
+        Some(Values {
+            depth: self.depth,
+            known,
+        })
+    }
+}
+
+fn either(a: Option<Values>, b: Option<Values>, at: usize) -> Option<Option<Values>> {
+    match (a, b) {
+        (Some(a), Some(b)) => a.join(&b, at, false).map(Some),
+        (a, None) | (None, a) => Some(a),
+    }
+}
+
+// Where the divisor of each `/`, `divmod` and `fx/` can be, by instruction.
+// A backend can leave out the check for 0 where the range doesn't have it;
+// `zero_divisors` warns where 0 is all it has. Each function is followed
+// from its start knowing nothing of the stack. `if`, `while` and `do` find
+// out whether a value is 0, for the value and its copies. Loops go round
+// until their ranges stop growing. Divisions in a function that uses
+// `try` or `invoke`, or calls one whose effect isn't known, are left out,
+// and so is every one in a program with an `on_error`, which can carry on
+// with the stack as it likes.
+pub fn divisor_ranges(program: &Program) -> BTreeMap<usize, Range> {
+    let mut found: BTreeMap<usize, Range> = BTreeMap::new();
+    if program.functions.contains_key(ERROR_HANDLER) {
+        return found;
+    }
+    let mut ranges = Ranges {
+        program,
+        inference: Inference::new(program),
+        divisors: BTreeMap::new(),
+    };
+    let starts: BTreeSet<usize> = program.functions.values().copied().collect();
+    for start in starts {
+        let entry = Values {
+            depth: 0,
+            known: vec![],
+        };
+        let walked = ranges.block(start, entry).is_some();
+        let divisors = core::mem::take(&mut ranges.divisors);
+        if !walked {
+            continue;
+        }
+        // A function without `ret` runs on into the next one.
+        for (i, range) in divisors {
+            found
+                .entry(i)
+                .and_modify(|known| *known = known.hull(range))
+                .or_insert(range);
+        }
+    }
+    found
+}
+
+pub fn zero_divisors(program: &Program) -> Vec<Error> {
+    divisor_ranges(program)
+        .into_iter()
+        .filter(|(_, range)| *range == Range::exactly(0))
+        .map(|(i, _)| {
+            let instruction = &program.instructions[i];
+            Error::StaticCheck {
+                word: instruction.instruction_type.to_string(),
+                pos: instruction.pos,
+                line: instruction.line,
+                comment: text("E0003.divides-by-zero", &[]),
+            }
+        })
+        .collect()
+}
+
+struct Ranges<'a> {
+    program: &'a Program,
+    inference: Inference<'a>,
+    divisors: BTreeMap<usize, Range>,
+}
+
+impl Ranges<'_> {
+    fn nested(&mut self, i: usize, values: Option<Values>) -> Option<Option<Values>> {
+        match values {
+            Some(values) => self.block(i, values),
+            None => Some(None),
+        }
+    }
+
+    // The values at the instruction that ends the block, or None inside
+    // when it returns first. None when the walk gives up.
+    fn block(&mut self, mut i: usize, mut values: Values) -> Option<Option<Values>> {
+        use InstructionType::*;
+        loop {
+            let instruction = &self.program.instructions.get(i)?.instruction_type;
+            if let Div | DivMod | FxDiv = instruction {
+                let divisor = values.known.last().map_or(Range::ANY, |top| top.range);
+                self.divisors
+                    .entry(i)
+                    .and_modify(|known| *known = known.hull(divisor))
+                    .or_insert(divisor);
+            }
+            match instruction {
+                Push(n) => values.make(i, 0, Range::exactly(*n)),
+                Address(start) => {
+                    let range = i32::try_from(*start).map_or(Range::ANY, Range::exactly);
+                    values.make(i, 0, range);
+                }
+                Pop => {
+                    values.pop();
+                }
+                Dup => {
+                    let a = values.pop();
+                    values.push(a.clone());
+                    values.push(a);
+                }
+                Swap => {
+                    let (a, b) = (values.pop(), values.pop());
+                    values.push(a);
+                    values.push(b);
+                }
+                Over => {
+                    let (a, b) = (values.pop(), values.pop());
+                    values.push(b.clone());
+                    values.push(a);
+                    values.push(b);
+                }
+                Rot => {
+                    let (a, b, c) = (values.pop(), values.pop(), values.pop());
+                    values.push(b);
+                    values.push(a);
+                    values.push(c);
+                }
+                Nip => {
+                    let a = values.pop();
+                    values.pop();
+                    values.push(a);
                 }
+                Add | AddWrap | AddSat | Sub | SubWrap | SubSat | Mul | MulWrap | MulSat => {
+                    let (a, b) = (values.pop().range, values.pop().range);
+                    let range = match instruction {
+                        AddWrap => b.combine(a, |b, a| b + a, false),
+                        Add | AddSat => b.combine(a, |b, a| b + a, true),
+                        SubWrap => b.combine(a, |b, a| b - a, false),
+                        Sub | SubSat => b.combine(a, |b, a| b - a, true),
+                        MulWrap => b.combine(a, |b, a| b * a, false),
+                        _ => b.combine(a, |b, a| b * a, true),
+                    };
+                    values.make(i, 0, range);
+                }
+                If(else_idx) => {
+                    let Else(end_idx) = self.program.instructions[*else_idx].instruction_type
+                    else {
+                        return None;
+                    };
+                    let then = self.nested(i + 1, values.assume_top(true))?;
+                    let otherwise = self.nested(else_idx + 1, values.assume_top(false))?;
+                    match either(then, otherwise, *else_idx)? {
+                        Some(joined) => values = joined,
+                        None => return Some(None),
+                    }
+                    i = end_idx + 1;
+                    continue;
+                }
+                While(end_idx) => {
+                    let mut head = values;
+                    let out = loop {
+                        let out = self.nested(i + 1, head.assume_top(true))?;
+                        let next = match out.as_ref().and_then(|out| out.assume_top(true)) {
+                            Some(back) => head.join(&back, i, true)?,
+                            None => head.clone(),
+                        };
+                        if next == head {
+                            break out;
+                        }
+                        head = next;
+                    };
+                    let left = out.and_then(|out| out.assume_top(false));
+                    match either(head.assume_top(false), left, *end_idx)? {
+                        Some(joined) => values = joined,
+                        None => return Some(None),
+                    }
+                    i = end_idx + 1;
+                    continue;
+                }
+                Loop(do_idx) => {
+                    let Do(end_idx) = self.program.instructions[*do_idx].instruction_type else {
+                        return None;
+                    };
+                    let mut head = values;
+                    let left = loop {
+                        let Some(mut tested) = self.nested(i + 1, Some(head.clone()))? else {
+                            return Some(None);
+                        };
+                        let condition = tested.pop();
+                        let body = tested.assume(None, &condition, true);
+                        let left = tested.assume(None, &condition, false);
+                        let next = match self.nested(do_idx + 1, body)? {
+                            Some(out) => head.join(&out, i, true)?,
+                            None => head.clone(),
+                        };
+                        if next == head {
+                            break left;
+                        }
+                        head = next;
+                    };
+                    match left {
+                        Some(left) => values = left,
+                        None => return Some(None),
+                    }
+                    i = end_idx + 1;
+                    continue;
+                }
+                Call(target) => {
+                    let effect = self.inference.function(*target)?;
+                    for _ in 0..effect.inputs {
+                        values.pop();
+                    }
+                    for n in 0..effect.outputs {
+                        values.make(i, n, Range::ANY);
+                    }
+                }
+                Ret => return Some(None),
+                Else(_) | EndWhile(_) | Do(_) | EndDo(_) | EndIf | Recover(_) | EndTry => {
+                    return Some(Some(values))
+                }
+                Try(_) | Invoke => return None,
+                _ => {
+                    let (takes, leaves) = simple_effect(instruction)?;
+                    for _ in 0..takes {
+                        values.pop();
+                    }
+                    for n in 0..leaves as usize {
+                        values.make(i, n, Range::ANY);
+                    }
+                }
+            }
+            i += 1;
+        }
+    }
+}
+
+// Which instructions may have made each value on the stack, for
+// `unused_values`. Values from before the function was entered, and those
+// calls and most words leave, are made by none.
+#[derive(Clone, PartialEq)]
+struct Makers {
+    depth: i64,
+    known: Vec<BTreeSet<usize>>,
+}
+
+impl Makers {
+    fn pop(&mut self) -> BTreeSet<usize> {
+        self.depth -= 1;
+        self.known.pop().unwrap_or_default()
+    }
+
+    fn push(&mut self, makers: BTreeSet<usize>) {
+        self.depth += 1;
+        self.known.push(makers);
+    }
+
+    fn join(&self, other: &Makers) -> Option<Makers> {
+        if self.depth != other.depth {
+            return None;
+        }
+        let n = self.known.len().max(other.known.len());
+        let padded = |makers: &Makers| {
+            let mut known = vec![BTreeSet::new(); n - makers.known.len()];
+            known.extend(makers.known.iter().cloned());
+            known
+        };
+        let known = padded(self)
+            .into_iter()
+            .zip(padded(other))
+            .map(|(mut ours, mut theirs)| {
+                ours.append(&mut theirs);
+                ours
+            })
+            .collect();
+        Some(Makers {
+            depth: self.depth,
+            known,
+        })
+    }
+}
+
+// Words whose only effect is the value they leave, so leaving it unused
+// is a mistake.
+fn makes_value(instruction: &InstructionType) -> bool {
+    use InstructionType::*;
+    matches!(
+        instruction,
+        Push(_)
+            | Address(_)
+            | Add
+            | AddWrap
+            | AddSat
+            | Sub
+            | SubWrap
+            | SubSat
+            | Mul
+            | MulWrap
+            | MulSat
+            | Div
+    )
+}
+
+// Values pushed by a number, a `&name` or arithmetic that nothing ever
+// reads: only `pop` or `nip` gets rid of them, or they are still there
+// when `main` returns. Each is pointed at where it was made. Every way
+// through a function counts, so a value some path uses is used. A value
+// left on the stack by a function other than `main` goes back to its
+// caller and counts as used; functions that use `try` or `invoke`, or
+// call one whose effect isn't known, aren't looked at.
+pub fn unused_values(program: &Program) -> Vec<Error> {
+    let main = program.functions.get("main").copied();
+    let mut uses = Uses {
+        program,
+        inference: Inference::new(program),
+        made: BTreeSet::new(),
+        used: BTreeSet::new(),
+        dropped: BTreeSet::new(),
+        returns: true,
+    };
+    let (mut made, mut used, mut dropped) = (BTreeSet::new(), BTreeSet::new(), BTreeSet::new());
+    let starts: BTreeSet<usize> = program.functions.values().copied().collect();
+    for start in starts {
+        // What `main` leaves is only read when something calls it.
+        uses.returns = Some(start) != main
+            || program
+                .instructions
+                .iter()
+                .any(|i| i.instruction_type == InstructionType::Call(start));
+        let entry = Makers {
+            depth: 0,
+            known: vec![],
+        };
+        let walked = uses.block(start, entry).is_some();
+        let (mut m, mut u, mut d) = (
+            core::mem::take(&mut uses.made),
+            core::mem::take(&mut uses.used),
+            core::mem::take(&mut uses.dropped),
+        );
+        if walked {
+            made.append(&mut m);
+            used.append(&mut u);
+            dropped.append(&mut d);
+        }
+    }
+    made.difference(&used)
+        .map(|i| {
+            let instruction = &program.instructions[*i];
+            let key = match dropped.contains(i) {
+                true => "E0003.dropped",
+                false => "E0003.left",
+            };
+            Error::StaticCheck {
+                word: instruction.instruction_type.to_string(),
+                pos: instruction.pos,
+                line: instruction.line,
+                comment: text(key, &[]),
             }
-            InstructionType::Nip => {
-                if stack_size < 2 {
-                    return Err(Error::StaticCheck {
-                        word: "".to_string(),
-                        pos: 0,
-                        line: 0,
-                        comment: "".to_string(),
-                    });
+        })
+        .collect()
+}
+
+struct Uses<'a> {
+    program: &'a Program,
+    inference: Inference<'a>,
+    made: BTreeSet<usize>,
+    used: BTreeSet<usize>,
+    dropped: BTreeSet<usize>,
+    // Whether what is left at `ret` goes to a caller.
+    returns: bool,
+}
+
+impl Uses<'_> {
+    fn read(&mut self, makers: BTreeSet<usize>) {
+        self.used.extend(makers);
+    }
+
+    fn read_top(&mut self, makers: &Makers) {
+        if let Some(top) = makers.known.last() {
+            self.used.extend(top.iter().copied());
+        }
+    }
+
+    // Like `Ranges::block`: the makers at the instruction that ends the
+    // block, None inside when it returns first, and None when the walk
+    // gives up.
+    fn block(&mut self, mut i: usize, mut makers: Makers) -> Option<Option<Makers>> {
+        use InstructionType::*;
+        loop {
+            let instruction = &self.program.instructions.get(i)?.instruction_type;
+            match instruction {
+                Pop => {
+                    let dropped = makers.pop();
+                    self.dropped.extend(dropped);
+                }
+                Nip => {
+                    let a = makers.pop();
+                    let dropped = makers.pop();
+                    self.dropped.extend(dropped);
+                    makers.push(a);
+                }
+                Dup => {
+                    let a = makers.pop();
+                    makers.push(a.clone());
+                    makers.push(a);
+                }
+                Swap => {
+                    let (a, b) = (makers.pop(), makers.pop());
+                    makers.push(a);
+                    makers.push(b);
+                }
+                Over => {
+                    let (a, b) = (makers.pop(), makers.pop());
+                    makers.push(b.clone());
+                    makers.push(a);
+                    makers.push(b);
+                }
+                Rot => {
+                    let (a, b, c) = (makers.pop(), makers.pop(), makers.pop());
+                    makers.push(b);
+                    makers.push(a);
+                    makers.push(c);
+                }
+                If(else_idx) => {
+                    let Else(end_idx) = self.program.instructions[*else_idx].instruction_type
+                    else {
+                        return None;
+                    };
+                    self.read_top(&makers);
+                    let then = self.block(i + 1, makers.clone())?;
+                    let otherwise = self.block(else_idx + 1, makers)?;
+                    makers = match (then, otherwise) {
+                        (Some(then), Some(otherwise)) => then.join(&otherwise)?,
+                        (Some(one), None) | (None, Some(one)) => one,
+                        (None, None) => return Some(None),
+                    };
+                    i = end_idx + 1;
+                    continue;
+                }
+                While(end_idx) => {
+                    self.read_top(&makers);
+                    let mut head = makers;
+                    loop {
+                        let next = match self.block(i + 1, head.clone())? {
+                            Some(out) => {
+                                self.read_top(&out);
+                                head.join(&out)?
+                            }
+                            None => head.clone(),
+                        };
+                        if next == head {
+                            break;
+                        }
+                        head = next;
+                    }
+                    makers = head;
+                    i = end_idx + 1;
+                    continue;
+                }
+                Loop(do_idx) => {
+                    let Do(end_idx) = self.program.instructions[*do_idx].instruction_type else {
+                        return None;
+                    };
+                    let mut head = makers;
+                    let left = loop {
+                        let Some(mut tested) = self.block(i + 1, head.clone())? else {
+                            return Some(None);
+                        };
+                        let condition = tested.pop();
+                        self.read(condition);
+                        let next = match self.block(do_idx + 1, tested.clone())? {
+                            Some(out) => head.join(&out)?,
+                            None => head.clone(),
+                        };
+                        if next == head {
+                            break tested;
+                        }
+                        head = next;
+                    };
+                    makers = left;
+                    i = end_idx + 1;
+                    continue;
+                }
+                Call(target) => {
+                    let effect = self.inference.function(*target)?;
+                    for _ in 0..effect.inputs {
+                        let read = makers.pop();
+                        self.read(read);
+                    }
+                    for _ in 0..effect.outputs {
+                        makers.push(BTreeSet::new());
+                    }
+                }
+                Ret => {
+                    if self.returns {
+                        for left in core::mem::take(&mut makers.known) {
+                            self.read(left);
+                        }
+                    }
+                    return Some(None);
+                }
+                Else(_) | EndWhile(_) | Do(_) | EndDo(_) | EndIf | Recover(_) | EndTry => {
+                    return Some(Some(makers))
+                }
+                Try(_) | Invoke => return None,
+                _ => {
+                    let (takes, leaves) = simple_effect(instruction)?;
+                    for _ in 0..takes {
+                        let read = makers.pop();
+                        self.read(read);
+                    }
+                    for _ in 0..leaves {
+                        let mut made = BTreeSet::new();
+                        if makes_value(instruction) {
+                            made.insert(i);
+                            self.made.insert(i);
+                        }
+                        makers.push(made);
+                    }
                 }
             }
-            // Control structures
-            InstructionType::While(_) => todo!(),
-            InstructionType::EndWhile(_) => todo!(),
-            InstructionType::If(_) => todo!(),
-            InstructionType::Else(_) => todo!(),
-            InstructionType::EndIf => todo!(),
-            InstructionType::Ret => todo!(),
-            InstructionType::Call(_) => todo!(),
-        }
-    }
-    if stack_size >= 0 {
-        return Ok(());
-    }
-    Err(Error::StaticCheck {
-        word: "".to_string(),
-        pos: 0,
-        line: 0,
-        comment: "".to_string(),
+            i += 1;
+        }
+    }
+}
+
+fn malformed(instruction: &Instruction, comment: String) -> Error {
+    Error::StaticCheck {
+        word: format!("{}", instruction.instruction_type),
+        pos: instruction.pos,
+        line: instruction.line,
+        comment,
+    }
+}
+
+// Each block's opener must jump to its closer and the other way round.
+fn check_jump(instructions: &[Instruction], from: usize, to: usize) -> Option<Error> {
+    let target = match instructions[from].instruction_type {
+        InstructionType::While(j)
+        | InstructionType::EndWhile(j)
+        | InstructionType::Loop(j)
+        | InstructionType::Do(j)
+        | InstructionType::EndDo(j)
+        | InstructionType::If(j)
+        | InstructionType::Else(j)
+        | InstructionType::Try(j)
+        | InstructionType::Recover(j) => j,
+        _ => return None,
+    };
+    (target != to).then(|| {
+        malformed(
+            &instructions[from],
+            format!(
+                "`{}` at {} should jump to {}, not {}",
+                instructions[from].instruction_type, from, to, target
+            ),
+        )
     })
 }
 
+// Checks that the jumps in `program` describe properly nested blocks inside
+// single functions and that calls land on functions. The parser only builds
+// such programs; IR from anywhere else should pass this before it runs.
+pub fn validate_program(program: &Program) -> Result<(), Error> {
+    let instructions = &program.instructions;
+    let mut errors = vec![];
+    let mut starts: Vec<(&String, usize)> =
+        program.functions.iter().map(|(n, s)| (n, *s)).collect();
+    starts.sort_by_key(|(name, start)| (*start, *name));
+    // A function with nothing in it at the very end starts right after the
+    // last instruction.
+    for (name, start) in &starts {
+        if *start > instructions.len() {
+            errors.push(Error::StaticCheck {
+                word: name.to_string(),
+                pos: 0,
+                line: 0,
+                comment: format!(
+                    "`{}` starts at {}, past the end of the program",
+                    name, start
+                ),
+            });
+        }
+    }
+    let is_start = |idx: usize| starts.iter().any(|(_, start)| *start == idx);
+
+    let mut open: Vec<usize> = vec![];
+    let mut unbalanced = vec![];
+    // Each `do` and the `while` its loop starts at.
+    let mut loops: BTreeMap<usize, usize> = BTreeMap::new();
+    for (idx, instruction) in instructions.iter().enumerate() {
+        if is_start(idx) {
+            unbalanced.append(&mut open);
+        }
+        let opener = |open: &mut Vec<usize>, wanted: fn(&InstructionType) -> bool| {
+            open.pop_if(|o| wanted(&instructions[*o].instruction_type))
+        };
+        match &instruction.instruction_type {
+            InstructionType::While(_)
+            | InstructionType::Loop(_)
+            | InstructionType::If(_)
+            | InstructionType::Try(_) => open.push(idx),
+            InstructionType::Do(_) => {
+                match opener(&mut open, |i| matches!(i, InstructionType::Loop(_))) {
+                    Some(o) => {
+                        errors.extend(check_jump(instructions, o, idx));
+                        loops.insert(idx, o);
+                    }
+                    None => unbalanced.push(idx),
+                }
+                open.push(idx);
+            }
+            InstructionType::EndDo(_) => {
+                match opener(&mut open, |i| matches!(i, InstructionType::Do(_))) {
+                    Some(o) => {
+                        errors.extend(check_jump(instructions, o, idx));
+                        if let Some(start) = loops.get(&o) {
+                            errors.extend(check_jump(instructions, idx, *start));
+                        }
+                    }
+                    None => unbalanced.push(idx),
+                }
+            }
+            InstructionType::Recover(_) => {
+                match opener(&mut open, |i| matches!(i, InstructionType::Try(_))) {
+                    Some(o) => errors.extend(check_jump(instructions, o, idx)),
+                    None => unbalanced.push(idx),
+                }
+                open.push(idx);
+            }
+            InstructionType::EndTry => {
+                match opener(&mut open, |i| matches!(i, InstructionType::Recover(_))) {
+                    Some(o) => errors.extend(check_jump(instructions, o, idx)),
+                    None => unbalanced.push(idx),
+                }
+            }
+            InstructionType::Else(_) => {
+                match opener(&mut open, |i| matches!(i, InstructionType::If(_))) {
+                    Some(o) => errors.extend(check_jump(instructions, o, idx)),
+                    None => unbalanced.push(idx),
+                }
+                open.push(idx);
+            }
+            InstructionType::EndWhile(_) => {
+                match opener(&mut open, |i| matches!(i, InstructionType::While(_))) {
+                    Some(o) => {
+                        errors.extend(check_jump(instructions, o, idx));
+                        errors.extend(check_jump(instructions, idx, o));
+                    }
+                    None => unbalanced.push(idx),
+                }
+            }
+            InstructionType::EndIf => {
+                match opener(&mut open, |i| matches!(i, InstructionType::Else(_))) {
+                    Some(o) => errors.extend(check_jump(instructions, o, idx)),
+                    None => unbalanced.push(idx),
+                }
+            }
+            InstructionType::Call(target)
+                if !is_start(*target) || *target == instructions.len() =>
+            {
+                errors.push(malformed(
+                    instruction,
+                    format!("call to {}, which is not the start of a function", target),
+                ))
+            }
+            InstructionType::Address(target)
+                if !is_start(*target) || *target == instructions.len() =>
+            {
+                errors.push(malformed(
+                    instruction,
+                    format!("address {}, which is not the start of a function", target),
+                ))
+            }
+            InstructionType::Env(index) if *index >= program.env.len() => errors.push(malformed(
+                instruction,
+                format!("environment variable {}, which has no name", index),
+            )),
+            InstructionType::Asm(index) if *index >= program.asm.len() => errors.push(malformed(
+                instruction,
+                format!("`asm` block {}, which has no text", index),
+            )),
+            InstructionType::Native(id) if plugin::word(*id).is_none() => errors.push(malformed(
+                instruction,
+                format!("plugin word {}, which is not loaded", id),
+            )),
+            _ => {}
+        }
+    }
+    unbalanced.append(&mut open);
+    unbalanced.sort();
+    for idx in unbalanced {
+        errors.push(malformed(
+            &instructions[idx],
+            format!(
+                "`{}` at {} does not belong to a block in its function",
+                instructions[idx].instruction_type, idx
+            ),
+        ));
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::from_vec(errors))
+    }
+}
+
+#[cfg(test)]
+mod test_infer_stack_effects {
+    use super::*;
+    use crate::parser::parse;
+    use crate::tokenizer::tokenize;
+
+    fn effects(source: &str) -> Vec<(String, String)> {
+        let program = parse(tokenize(source).unwrap()).unwrap();
+        let mut effects: Vec<(String, String)> = infer_stack_effects(&program)
+            .into_iter()
+            .map(|(name, effect)| (name, effect.to_string()))
+            .collect();
+        effects.sort();
+        effects
+    }
+
+    fn effect(name: &str, text: &str) -> (String, String) {
+        (name.to_string(), text.to_string())
+    }
+
+    #[test]
+    fn straight_line_and_calls() {
+        assert_eq!(
+            effects("fun empty ret fun sq dup * ret fun hyp sq swap sq + ret fun two 1 2 ret"),
+            vec![
+                effect("empty", "( 0 -- 0 )"),
+                effect("hyp", "( 2 -- 1 )"),
+                effect("sq", "( 1 -- 1 )"),
+                effect("two", "( 0 -- 2 )"),
+            ]
+        );
+    }
+
+    #[test]
+    fn control_flow() {
+        assert_eq!(
+            effects(
+                "fun abs dup 0 swap - 0 over - if pop else pop pop 0 over - end nip ret
+fun count 1 - while dup print 1 - end ret
+fun uneven if 1 else end ret
+fun early if ret else end ret
+fun loop dup if 1 - loop else end ret
+fun countdown while dup do dup print 1 - end pop ret
+fun grows while dup do 1 end ret"
+            ),
+            vec![
+                effect("abs", "( 1 -- 1 )"),
+                effect("count", "( 1 -- 1 )"),
+                effect("countdown", "( 1 -- 0 )"),
+            ]
+        );
+    }
+
+    #[test]
+    fn divmod_leaves_two() {
+        assert_eq!(
+            effects("fun mod divmod nip ret fun digits 0 10 - 3 divmod ret"),
+            vec![effect("digits", "( 0 -- 2 )"), effect("mod", "( 2 -- 1 )")]
+        );
+    }
+
+    #[test]
+    fn try_blocks() {
+        assert_eq!(
+            effects(
+                "fun quotient try 4 2 / recover end ret
+fun uneven try 1 2 recover end ret
+fun consumes try / recover end ret"
+            ),
+            vec![effect("quotient", "( 0 -- 1 )")]
+        );
+    }
+
+    #[test]
+    fn formatting_words() {
+        assert_eq!(
+            effects("fun show hex 4 print-padded dec ret"),
+            vec![effect("show", "( 1 -- 0 )")]
+        );
+    }
+}
+
+#[cfg(test)]
+mod test_verifier {
+    use super::*;
+    use crate::parser::parse;
+    use crate::stack::VecStack;
+    use crate::stack_machine::{ErrorPolicy, StackMachine, VmConfig};
+    use crate::tokenizer::tokenize;
+
+    fn program(source: &str) -> Program {
+        parse(tokenize(source).unwrap()).unwrap()
+    }
+
+    fn verified(source: &str, on_error: ErrorPolicy) -> Result<Vec<i32>, Error> {
+        let config = VmConfig {
+            verify: true,
+            on_error,
+            ..VmConfig::default()
+        };
+        StackMachine::with_config(VecStack::new(), config)
+            .run_main(&program(source))
+            .into_result()
+    }
+
+    #[test]
+    fn depths_count_from_the_function_entry() {
+        let program = program("fun sq dup * ret fun main 3 sq print ret");
+        assert_eq!(
+            predicted_depths(&program).into_iter().collect::<Vec<_>>(),
+            vec![(0, 1), (1, 0), (2, 0), (3, 1), (4, 1), (5, 0), (6, 0)]
+        );
+    }
+
+    #[test]
+    fn runs_match_the_predictions() {
+        let source = "fun neg 0 swap - ret
+fun safe try 4 0 / recover pop 0 end ret
+fun fact if dup 1 - fact * else pop 1 end ret
+fun main
+  3 while 1 - end print
+  3 while dup do dup print 1 - end pop
+  5 neg print safe print
+  5 fact print
+ret";
+        assert_eq!(
+            verified(source, ErrorPolicy::Abort),
+            Ok(vec![0, 3, 2, 1, -5, 0, 120])
+        );
+        assert_eq!(
+            verified(
+                "fun on_error pop 7 ret fun main 1 0 / print ret",
+                ErrorPolicy::Trap
+            ),
+            Ok(vec![7])
+        );
+    }
+
+    #[test]
+    fn a_wrong_prediction_stops_the_run() {
+        let program = program("fun main 1 2 + print ret");
+        let mut verifier = Verifier::new(&program);
+        verifier.predicted.insert(2, 2);
+        verifier.start(0, Some(0));
+        let steps = [(0, 1), (1, 2), (2, 1)];
+        let errors: Vec<String> = steps
+            .iter()
+            .filter_map(|&(i, depth)| {
+                verifier
+                    .step(i, &program.instructions[i], 0, depth)
+                    .err()
+                    .map(|e| e.to_string())
+            })
+            .collect();
+        assert_eq!(
+            errors,
+            vec!["1:14: the checker expected 2 values on the stack after `+`, but there are 1"]
+        );
+    }
+}
+
+#[cfg(test)]
+mod test_unused_private_functions {
+    use super::*;
+    use crate::parser::parse;
+    use crate::tokenizer::tokenize;
+
+    #[test]
+    fn flags_private_functions_nobody_calls() {
+        let program = parse(
+            tokenize(
+                "module m
+  fun used 1 ret
+  fun unused 2 ret
+  pub fun api used ret
+end
+fun main m.api print ret",
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            unused_private_functions(&program)
+                .iter()
+                .map(|e| e.to_string())
+                .collect::<Vec<_>>(),
+            vec!["3:14: `m.unused` is private and never called"]
+        );
+    }
+}
+
+#[cfg(test)]
+mod test_divisor_ranges {
+    use super::*;
+    use crate::parser::parse;
+    use crate::tokenizer::tokenize;
+
+    // The divisor ranges in the order their divisions come.
+    fn divisors(source: &str) -> Vec<Range> {
+        let program = parse(tokenize(source).unwrap()).unwrap();
+        divisor_ranges(&program).into_values().collect()
+    }
+
+    fn zeros(source: &str) -> Vec<(usize, usize)> {
+        let program = parse(tokenize(source).unwrap()).unwrap();
+        zero_divisors(&program)
+            .iter()
+            .filter_map(|e| e.position())
+            .collect()
+    }
+
+    #[test]
+    fn constants_and_counters_are_not_zero() {
+        let ranges = divisors(
+            "fun main 7 2 / print 10 while dup do 100 over / print 1 - end pop 5 rand / print ret",
+        );
+        assert_eq!(ranges[0], Range::exactly(2));
+        assert_eq!(ranges[1], Range { low: 1, high: 10 });
+        assert!(ranges[2].contains(0));
+    }
+
+    #[test]
+    fn branches_say_what_they_found() {
+        assert_eq!(
+            divisors("fun f if 2 else 3 end / ret"),
+            [Range { low: 2, high: 3 }]
+        );
+        // 0 or 1 after the first `if`, and not 0 in the second.
+        assert_eq!(
+            divisors("fun f if pop 1 else pop 0 end dup if 10 swap / else end ret"),
+            [Range::exactly(1)]
+        );
+        assert_eq!(divisors("fun f 3 4 - 1 swap / ret"), [Range::exactly(-1)]);
+    }
+
+    #[test]
+    fn zero_divisors_are_flagged() {
+        assert_eq!(zeros("fun main 1 0 / print ret"), [(1, 14)]);
+        assert_eq!(zeros("fun main 5 dup 5 - / print ret"), [(1, 20)]);
+        // The counter is 0 once the loop is done.
+        assert_eq!(
+            zeros("fun main 3 while dup do 1 - end 10 swap / print ret"),
+            [(1, 41)]
+        );
+        // Never run, so never wrong.
+        assert_eq!(zeros("fun main 0 if 1 0 / print else end ret"), []);
+        assert_eq!(zeros("fun main 1 2 / print ret"), []);
+    }
+
+    #[test]
+    fn an_error_handler_turns_it_off() {
+        assert_eq!(zeros("fun on_error pop ret fun main 1 0 / print ret"), []);
+    }
+}
+
+#[cfg(test)]
+mod test_unused_values {
+    use super::*;
+    use crate::parser::parse;
+    use crate::tokenizer::tokenize;
+
+    fn unused(source: &str) -> Vec<(usize, usize, String)> {
+        let program = parse(tokenize(source).unwrap()).unwrap();
+        unused_values(&program)
+            .into_iter()
+            .map(|e| match e {
+                Error::StaticCheck {
+                    pos, line, comment, ..
+                } => (line, pos, comment),
+                e => panic!("{:?}", e),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn values_that_are_only_dropped() {
+        let dropped = "this value is never used, only dropped".to_string();
+        assert_eq!(
+            unused("fun main 1 2 + pop 3 4 swap pop print ret"),
+            [(1, 14, dropped.clone()), (1, 20, dropped)]
+        );
+    }
+
+    #[test]
+    fn values_left_when_main_returns() {
+        assert_eq!(
+            unused("fun main 1 print 5 ret"),
+            [(
+                1,
+                18,
+                "this value is never used; it is still on the stack when `main` returns"
+                    .to_string()
+            )]
+        );
+        // Other functions give theirs back to the caller.
+        assert_eq!(unused("fun five 5 ret fun main five print ret"), []);
+    }
+
+    #[test]
+    fn any_use_on_any_path_counts() {
+        assert_eq!(unused("fun main 10 while dup do 1 - end pop ret"), []);
+        assert_eq!(unused("fun f if 1 else 2 end print ret"), []);
+        assert_eq!(unused("fun main 0 if 1 print else end pop ret"), []);
+        assert_eq!(unused("fun main 4 dup print pop ret"), []);
+    }
+}
+
+#[cfg(test)]
+mod test_loop_conditions {
+    use super::*;
+    use crate::parser::parse;
+    use crate::tokenizer::tokenize;
+
+    fn loop_errors(source: &str) -> Vec<String> {
+        let program = parse(tokenize(source).unwrap()).unwrap();
+        check_loop_conditions(&program)
+            .iter()
+            .map(|e| e.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn conditions_on_every_edge() {
+        assert!(loop_errors("fun main 3 while 1 - end pop ret").is_empty());
+        assert!(loop_errors("fun main 1 if 2 else 3 end while pop 0 end ret").is_empty());
+        assert_eq!(
+            loop_errors("fun main 1 pop while 0 end ret"),
+            vec!["1:16: `while` is entered with an empty stack after `pop` at 1:12"]
+        );
+        assert_eq!(
+            loop_errors("fun main 1 while pop end ret"),
+            vec!["1:22: `end` goes back to `while` with an empty stack after `pop` at 1:18"]
+        );
+    }
+
+    #[test]
+    fn follows_calls() {
+        assert_eq!(
+            loop_errors("fun spin while 1 - end ret\nfun main 2 spin pop spin ret"),
+            vec!["1:10: `while` is entered with an empty stack at the start of `spin`, when called at 2:21"]
+        );
+        assert!(loop_errors("fun spin while 1 - end ret\nfun main 2 spin ret").is_empty());
+    }
+
+    #[test]
+    fn recursion_that_grows_the_stack_ends() {
+        assert!(loop_errors(
+            "fun fact dup 1 - dup if fact * else pop end ret\nfun main 5 fact print ret"
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn do_loops() {
+        assert!(loop_errors("fun main 3 while dup do 1 - end pop ret").is_empty());
+        assert_eq!(
+            loop_errors("fun main 3 while pop do end ret"),
+            vec!["1:22: `do` is reached with an empty stack after `pop` at 1:18"]
+        );
+        assert_eq!(
+            loop_errors("fun main 3 while dup do 1 - dup end ret"),
+            vec!["1:33: `end` goes back to `while` with 2 values on the stack, but the loop started with 1"]
+        );
+    }
+
+    #[test]
+    fn strict_loops_flag_the_old_form() {
+        let program =
+            parse(tokenize("fun main 3 while 1 - end while dup do 1 - end pop ret").unwrap())
+                .unwrap();
+        assert_eq!(
+            peek_loops(&program)
+                .iter()
+                .map(|e| e.position())
+                .collect::<Vec<_>>(),
+            vec![Some((1, 12))]
+        );
+    }
+
+    #[test]
+    fn verifiable_programs() {
+        let source = "fun main
+  ## bound 3
+  3 while dup do 1 - end
+  while 0 end
+  ## runs a while
+  while 0 end
+  pop
+ret";
+        let tokens = tokenize(source).unwrap();
+        let program = parse(tokens.clone()).unwrap();
+        let errors: Vec<String> = verifiable(&program, &tokens, 10)
+            .iter()
+            .map(|e| e.to_string())
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                "4:3: loop has no bound; write `## bound n` on the line before `while`",
+                "6:3: `main` has 15 instructions, more than the 10 allowed",
+                "6:3: `## runs a while` is not a loop bound; write `## bound n`",
+            ]
+        );
+        assert_eq!(verifiable(&program, &tokens, 15).len(), 2);
+    }
+
+    fn entry_errors(source: &str) -> Vec<String> {
+        let program = parse(tokenize(source).unwrap()).unwrap();
+        strict_entry(&program, "main")
+            .iter()
+            .map(|e| e.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn strict_entry_wants_everything_in_functions() {
+        assert!(
+            entry_errors("fun sq dup * ret\nfun main 2 sq if 1 print ret else end ret").is_empty()
+        );
+        assert_eq!(
+            entry_errors("7 print\nfun main 1 print ret\n8 print\nfun f 2 print\nfun g ret"),
+            vec![
+                "1:1: this is outside any function, so it never runs",
+                "3:1: this is outside any function, so it never runs",
+                "4:9: `f` has no `ret`, so it runs on into `g`",
+            ]
+        );
+        assert_eq!(entry_errors("fun f ret"), vec!["function `main` not found"]);
+    }
+
+    #[test]
+    fn leaves_unknown_paths_and_try_alone() {
+        // Depth depends on the branch taken.
+        assert!(loop_errors("fun main 1 if 2 else end pop pop while end ret").is_empty());
+        assert!(loop_errors("fun main try while end recover pop end ret").is_empty());
+        assert!(loop_errors("fun f while end ret").is_empty());
+    }
+}
+
 #[cfg(test)]
 mod test_check_stack_safety {
     use super::*;
@@ -440,4 +2417,137 @@ mod test_check_stack_safety {
         ];
         assert_eq!(check_stack_safety(&program_nip_non_empty), Ok(()));
     }
+
+    #[test]
+    fn test_control_flow_is_reported() {
+        let program = vec![
+            Instruction {
+                instruction_type: InstructionType::Push(1),
+                pos: 1,
+                line: 1,
+            },
+            Instruction {
+                instruction_type: InstructionType::While(2),
+                pos: 3,
+                line: 1,
+            },
+        ];
+        assert_eq!(
+            check_stack_safety(&program),
+            Err(Error::StaticCheck {
+                word: "while".to_string(),
+                pos: 3,
+                line: 1,
+                comment: "Control flow is not supported by the checker yet".to_string(),
+            })
+        );
+    }
+}
+
+#[cfg(test)]
+mod test_validate_program {
+    use super::*;
+    use crate::parser::parse;
+    use crate::tokenizer::tokenize;
+
+    fn program(source: &str) -> Program {
+        parse(tokenize(source).unwrap()).unwrap()
+    }
+
+    fn messages(program: &Program) -> Vec<String> {
+        validate_program(program)
+            .unwrap_err()
+            .into_vec()
+            .into_iter()
+            .map(|e| e.message())
+            .collect()
+    }
+
+    #[test]
+    fn parsed_programs_are_valid() {
+        let program = program(
+            "fun sq dup * ret
+fun main 3 while dup sq print 1 - end if 1 else 2 end sq ret",
+        );
+        assert_eq!(validate_program(&program), Ok(()));
+    }
+
+    #[test]
+    fn jumps_must_match_their_block() {
+        // 0: 1  1: while  2: 1  3: -  4: end  5: ret
+        let mut bad = program("fun main 1 while 1 - end ret");
+        bad.instructions[1].instruction_type = InstructionType::While(99);
+        bad.instructions[4].instruction_type = InstructionType::EndWhile(0);
+        assert_eq!(
+            messages(&bad),
+            vec![
+                "`while` at 1 should jump to 4, not 99",
+                "`end` at 4 should jump to 1, not 0",
+            ]
+        );
+    }
+
+    #[test]
+    fn do_loop_jumps() {
+        // 0: 1  1: while  2: dup  3: do  4: end  5: ret
+        let mut bad = program("fun main 1 while dup do end ret");
+        assert_eq!(validate_program(&bad), Ok(()));
+        bad.instructions[1].instruction_type = InstructionType::Loop(2);
+        bad.instructions[3].instruction_type = InstructionType::Do(5);
+        bad.instructions[4].instruction_type = InstructionType::EndDo(0);
+        assert_eq!(
+            messages(&bad),
+            vec![
+                "`while` at 1 should jump to 3, not 2",
+                "`do` at 3 should jump to 4, not 5",
+                "`end` at 4 should jump to 1, not 0",
+            ]
+        );
+    }
+
+    #[test]
+    fn empty_function_at_the_end() {
+        // 0: ret, and `g` starts at 1 with nothing in it.
+        let mut program = program("fun main ret fun g");
+        assert_eq!(validate_program(&program), Ok(()));
+        program.instructions[0].instruction_type = InstructionType::Call(1);
+        assert_eq!(
+            messages(&program),
+            vec!["call to 1, which is not the start of a function"]
+        );
+    }
+
+    #[test]
+    fn blocks_and_calls_stay_inside_functions() {
+        // 0: 1  1: if  2: ret  3: else  4: end  5: ret  6: call 0  7: ret
+        let mut bad = program("fun f 1 if ret else end ret fun main f ret");
+        bad.instructions[6].instruction_type = InstructionType::Call(2);
+        bad.functions.insert("g".to_string(), 3);
+        bad.functions.insert("h".to_string(), 50);
+        assert_eq!(
+            messages(&bad),
+            vec![
+                "`h` starts at 50, past the end of the program",
+                "call to 2, which is not the start of a function",
+                "`if` at 1 does not belong to a block in its function",
+                "`else` at 3 does not belong to a block in its function",
+            ]
+        );
+    }
+
+    #[test]
+    fn try_blocks() {
+        // 0: try  1: 1  2: recover  3: pop  4: end  5: ret
+        let mut bad = program("fun main try 1 recover pop end ret");
+        assert_eq!(validate_program(&bad), Ok(()));
+        bad.instructions[0].instruction_type = InstructionType::Try(4);
+        bad.instructions[2].instruction_type = InstructionType::Recover(2);
+        assert_eq!(
+            messages(&bad),
+            vec![
+                "`try` at 0 should jump to 2, not 4",
+                "`recover` at 2 should jump to 4, not 2",
+            ]
+        );
+    }
 }
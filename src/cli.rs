@@ -0,0 +1,1668 @@
+use stack_machine_bez::callgraph;
+use stack_machine_bez::common::Error;
+use stack_machine_bez::diagnostics::ColorChoice;
+use stack_machine_bez::grammar;
+use stack_machine_bez::playground::Limits;
+use stack_machine_bez::stack_machine::{Division, ErrorPolicy, VmConfig};
+use stack_machine_bez::symexec;
+
+pub const USAGE: &str =
+    "usage: rorth [--color=auto|always|never] [--plugin name]... [--cache-dir dir | --no-cache] [--tab-width n] [--messages file] [run] [--entry name] [--deterministic] [--seed n] [--heartbeat n] [--max-memory bytes] [--timeout 5s] [--on-error abort|trap] [--division=trunc|floor] [--dump-stack-on-error] [--record io.log] [--replay io.log] [--strict-loops] [--strict-entry] [--stats] [--profile=annotate] [--verify] [-O0] [--unsafe-syscalls] [--viz] [--viz-line n]... [--viz-html file] [--trace-json file] [--watch [--keep-state]] [--output=lines|space|csv|none | --quiet] [--include-path dir]... [<file.sm|file.rorthc> [more.sm...] [args...]]
+       rorth repl [--deterministic] [--seed n]
+       rorth explain [--deterministic] [--seed n] [--include-path dir]... [<file.sm>]
+       rorth new <dir>
+       rorth doc [--html] [--include-path dir]... [<file.sm>]
+       rorth words
+       rorth grammar [--format ebnf|json|textmate|tree-sitter | --editor]
+       rorth selftest
+       rorth fix [--dry-run] <file.sm>...
+       rorth test --doc [--include-path dir]... [<file.sm>]
+       rorth disasm [--include-path dir]... [<file.sm|file.rorthc>]
+       rorth stats [--include-path dir]... [<file.sm|file.rorthc>]
+       rorth callgraph [--format tree|dot] [--include-path dir]... [<file.sm|file.rorthc>]
+       rorth verify-opt [--include-path dir]... [<file.sm|file.rorthc>]
+       rorth symexec [--function name] [--inputs n] [--max-steps n] [--max-paths n] [--include-path dir]... [<file.sm>]
+       rorth check [--watch] [--verifiable n] [--include-path dir]... [<file.sm>]
+       rorth build [--target bytecode|x86_64-linux] [--output file] [--strict-loops] [--strict-entry] [--include-path dir]... [<file.sm>]
+       rorth serve [--addr host:port] [--max-steps n] [--max-stack n] [--max-memory bytes] [--timeout 5s]";
+
+// Stack diagrams for `run`: drawn on stderr with `text`, or saved as a page
+// to step through with `html`. Every step, unless `lines` picks some.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Viz {
+    pub text: bool,
+    pub lines: Vec<usize>,
+    pub html: Option<String>,
+}
+
+impl Viz {
+    pub fn enabled(&self) -> bool {
+        self.text || self.html.is_some()
+    }
+}
+
+// `--output`: what goes between the values `print` writes on a run.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Output {
+    // One per line.
+    #[default]
+    Lines,
+    // On one line, with a space or a comma between them.
+    Space,
+    Csv,
+    // Not at all, for when only the exit status matters.
+    None,
+}
+
+impl Output {
+    pub fn parse(value: &str) -> Option<Output> {
+        match value {
+            "lines" => Some(Output::Lines),
+            "space" => Some(Output::Space),
+            "csv" => Some(Output::Csv),
+            "none" => Some(Output::None),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Command {
+    Run {
+        path: Option<String>,
+        // Files run after `path` on the same machine; see `host::programs_in_turn`.
+        more: Vec<String>,
+        include_paths: Vec<String>,
+        config: VmConfig,
+        viz: Viz,
+        // Refuse loops that keep their condition, see `checker::peek_loops`.
+        strict_loops: bool,
+        // Refuse code outside functions, see `checker::strict_entry`.
+        strict_entry: bool,
+        // Show the stacks and code around a runtime error, see `Renderer::crash`.
+        dump_stack: bool,
+        // Where to write what `rand` and `time` returned, and where to read
+        // it back from, see `replay`.
+        record: Option<String>,
+        replay: Option<String>,
+        // Where to write the calls as a Chrome trace, see `flame`.
+        trace_json: Option<String>,
+        // `--profile=annotate`: print the source with how often each line
+        // ran, see `heatmap`.
+        annotate: bool,
+        // Run again whenever a file changes, see `watch`, on the same
+        // machine with `keep_state`.
+        watch: bool,
+        keep_state: bool,
+        output: Output,
+    },
+    Repl {
+        config: VmConfig,
+    },
+    Explain {
+        path: Option<String>,
+        include_paths: Vec<String>,
+        config: VmConfig,
+    },
+    New {
+        path: String,
+    },
+    Doc {
+        path: Option<String>,
+        include_paths: Vec<String>,
+        html: bool,
+    },
+    Build {
+        path: Option<String>,
+        include_paths: Vec<String>,
+        output: Option<String>,
+        // `--target`, checked against `backend::BACKENDS` when building.
+        target: Option<String>,
+        strict_loops: bool,
+        strict_entry: bool,
+    },
+    Check {
+        path: Option<String>,
+        include_paths: Vec<String>,
+        // Check again whenever a file changes, see `watch`.
+        watch: bool,
+        // The most instructions a function may have; also wants a bound on
+        // every loop, see `checker::verifiable`.
+        verifiable: Option<usize>,
+    },
+    Serve {
+        addr: String,
+        limits: Limits,
+    },
+    Words,
+    // The grammar and word list, see `grammar`.
+    Grammar {
+        format: grammar::Format,
+    },
+    // Runs `selftest::CASES` and says how each went.
+    Selftest,
+    // `rorth test --doc`: checks the `#=>` comments, see `doctest`.
+    DocTest {
+        path: Option<String>,
+        include_paths: Vec<String>,
+    },
+    // Rewrites renamed words in place, see `fix`.
+    Fix {
+        paths: Vec<String>,
+        dry_run: bool,
+    },
+    Disasm {
+        path: Option<String>,
+        include_paths: Vec<String>,
+    },
+    // Instruction counts and nesting, see `stats::size`.
+    Stats {
+        path: Option<String>,
+        include_paths: Vec<String>,
+    },
+    // Who calls whom, see `callgraph`.
+    Callgraph {
+        path: Option<String>,
+        include_paths: Vec<String>,
+        format: callgraph::Format,
+    },
+    // Runs `main` with and without optimizations, see `verify_opt`.
+    VerifyOpt {
+        path: Option<String>,
+        include_paths: Vec<String>,
+    },
+    Symexec {
+        path: Option<String>,
+        include_paths: Vec<String>,
+        function: String,
+        limits: symexec::Limits,
+    },
+}
+
+// For commands whose code is behind a Cargo feature of the same name.
+fn built_with(command: &str, enabled: bool) -> Result<(), Error> {
+    if enabled {
+        return Ok(());
+    }
+    Err(Error::Usage {
+        comment: format!(
+            "`rorth {}` needs rorth built with `--features {}`",
+            command, command
+        ),
+    })
+}
+
+fn usage() -> Error {
+    Error::Usage {
+        comment: USAGE.to_string(),
+    }
+}
+
+fn number(flag: &str, value: Option<&String>) -> Result<usize, Error> {
+    value.and_then(|v| v.parse().ok()).ok_or(Error::Usage {
+        comment: format!("`{}` expects a number", flag),
+    })
+}
+
+// `500ms`, `5s` or `2m`, as milliseconds.
+fn duration(flag: &str, value: Option<&String>) -> Result<usize, Error> {
+    let bad = || Error::Usage {
+        comment: format!("`{}` expects a duration like 500ms, 5s or 2m", flag),
+    };
+    let value = value.ok_or_else(bad)?;
+    let digits = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let scale = match &value[digits..] {
+        "ms" => 1,
+        "s" => 1000,
+        "m" => 60_000,
+        _ => return Err(bad()),
+    };
+    let amount: usize = value[..digits].parse().map_err(|_| bad())?;
+    amount
+        .checked_mul(scale)
+        .filter(|&ms| ms > 0)
+        .ok_or_else(bad)
+}
+
+fn parse_serve(args: &[String]) -> Result<Command, Error> {
+    let mut addr = "127.0.0.1:8080".to_string();
+    let mut limits = Limits::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--addr" => addr = args.next().ok_or_else(usage)?.clone(),
+            "--max-steps" => limits.max_steps = number(arg, args.next())?,
+            "--max-stack" => limits.max_stack = number(arg, args.next())?,
+            "--max-memory" => limits.max_memory = number(arg, args.next())?,
+            "--timeout" => limits.timeout = duration(arg, args.next())?,
+            _ => return Err(usage()),
+        }
+    }
+    Ok(Command::Serve { addr, limits })
+}
+
+fn parse_run(args: &[String]) -> Result<Command, Error> {
+    let mut path = None;
+    let mut include_paths = vec![];
+    let mut config = VmConfig::default();
+    let mut viz = Viz::default();
+    let mut strict_loops = false;
+    let mut strict_entry = false;
+    let mut dump_stack = false;
+    let mut record = None;
+    let mut replay = None;
+    let mut trace_json = None;
+    let mut annotate = false;
+    let mut watch = false;
+    let mut keep_state = false;
+    let mut output = Output::Lines;
+    let mut more = vec![];
+    let mut args = args.iter().peekable();
+    // More `.sm` files may follow the first one; whatever comes after them
+    // belongs to the script, e.g. when it is started through a
+    // `#!/usr/bin/env rorth` line.
+    while let Some(arg) = args.next_if(|_| path.is_none()) {
+        match arg.as_str() {
+            "--deterministic" => config = config.deterministic(),
+            "--seed" => config.seed = Some(number(arg, args.next())? as u64),
+            "--entry" => config.entry = Some(args.next().ok_or_else(usage)?.clone()),
+            "--heartbeat" => {
+                config.heartbeat = Some(number(arg, args.next())?.saturating_mul(1_000_000))
+            }
+            "--max-memory" => config.max_memory = Some(number(arg, args.next())?),
+            "--timeout" => config.timeout = Some(duration(arg, args.next())?),
+            "--on-error" => {
+                config.on_error = match args.next().map(|s| s.as_str()) {
+                    Some("abort") => ErrorPolicy::Abort,
+                    Some("trap") => ErrorPolicy::Trap,
+                    _ => return Err(usage()),
+                }
+            }
+            "--division=trunc" => config.division = Division::Trunc,
+            "--division=floor" => config.division = Division::Floor,
+            _ if arg.starts_with("--division=") => {
+                return Err(Error::Usage {
+                    comment: "`--division` expects floor or trunc".to_string(),
+                })
+            }
+            "--stats" => config.stats = true,
+            "--verify" => config.verify = true,
+            "-O0" => config.optimize = false,
+            "--unsafe-syscalls" => config.allow_syscalls = true,
+            "--strict-loops" => strict_loops = true,
+            "--strict-entry" => strict_entry = true,
+            "--dump-stack-on-error" => dump_stack = true,
+            "--record" => record = Some(args.next().ok_or_else(usage)?.clone()),
+            "--replay" => replay = Some(args.next().ok_or_else(usage)?.clone()),
+            "--viz" => viz.text = true,
+            "--viz-line" => viz.lines.push(number(arg, args.next())?),
+            "--viz-html" => viz.html = Some(args.next().ok_or_else(usage)?.clone()),
+            "--trace-json" => trace_json = Some(args.next().ok_or_else(usage)?.clone()),
+            "--profile=annotate" => annotate = true,
+            "--watch" => watch = watch_flag()?,
+            "--keep-state" => keep_state = true,
+            "--quiet" => output = Output::None,
+            _ if arg.starts_with("--output=") => {
+                output = Output::parse(&arg["--output=".len()..]).ok_or_else(|| Error::Usage {
+                    comment: "`--output` expects lines, space, csv or none".to_string(),
+                })?
+            }
+            _ if arg.starts_with("--profile=") => {
+                return Err(Error::Usage {
+                    comment: "`--profile` expects annotate".to_string(),
+                })
+            }
+            "--include-path" => include_paths.push(args.next().ok_or_else(usage)?.clone()),
+            _ if arg.starts_with("--") => return Err(usage()),
+            _ => path = Some(arg.clone()),
+        }
+    }
+    while let Some(arg) = args.next_if(|arg| arg.ends_with(".sm") && !arg.starts_with("--")) {
+        more.push(arg.clone());
+    }
+    if output != Output::Lines && (viz.enabled() || trace_json.is_some()) {
+        return Err(Error::Usage {
+            comment: "`--output` and `--quiet` don't work with `--viz` or `--trace-json`"
+                .to_string(),
+        });
+    }
+    if keep_state && !watch {
+        return Err(Error::Usage {
+            comment: "`--keep-state` needs `--watch`".to_string(),
+        });
+    }
+    let elsewhere = viz.enabled() || trace_json.is_some() || record.is_some() || replay.is_some();
+    if watch && (elsewhere || annotate || !more.is_empty()) {
+        return Err(Error::Usage {
+            comment: "`--watch` runs one file, without `--viz`, `--trace-json`, `--record`, `--replay` or `--profile`".to_string(),
+        });
+    }
+    if config.entry.is_some() && !more.is_empty() {
+        return Err(Error::Usage {
+            comment: "`--entry` picks a function of one file".to_string(),
+        });
+    }
+    if viz.enabled() && !more.is_empty() {
+        return Err(Error::Usage {
+            comment: "`--viz` and `--viz-html` show one file at a time".to_string(),
+        });
+    }
+    if viz.enabled() && record.is_some() {
+        return Err(Error::Usage {
+            comment: "`--record` doesn't work with `--viz` or `--viz-html`".to_string(),
+        });
+    }
+    if trace_json.is_some() && (viz.enabled() || record.is_some() || !more.is_empty()) {
+        return Err(Error::Usage {
+            comment: "`--trace-json` traces one file, without `--viz` or `--record`".to_string(),
+        });
+    }
+    if annotate && (viz.enabled() || trace_json.is_some()) {
+        return Err(Error::Usage {
+            comment: "`--profile=annotate` doesn't work with `--viz` or `--trace-json`".to_string(),
+        });
+    }
+    if !viz.lines.is_empty() && !viz.enabled() {
+        return Err(Error::Usage {
+            comment: "`--viz-line` needs `--viz` or `--viz-html`".to_string(),
+        });
+    }
+    Ok(Command::Run {
+        path,
+        more,
+        include_paths,
+        config,
+        viz,
+        strict_loops,
+        strict_entry,
+        dump_stack,
+        record,
+        replay,
+        trace_json,
+        annotate,
+        watch,
+        keep_state,
+        output,
+    })
+}
+
+fn parse_explain(args: &[String]) -> Result<Command, Error> {
+    let mut path = None;
+    let mut include_paths = vec![];
+    let mut config = VmConfig::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--deterministic" => config = config.deterministic(),
+            "--seed" => config.seed = Some(number(arg, args.next())? as u64),
+            "--include-path" => include_paths.push(args.next().ok_or_else(usage)?.clone()),
+            _ if arg.starts_with("--") || path.is_some() => return Err(usage()),
+            _ => path = Some(arg.clone()),
+        }
+    }
+    Ok(Command::Explain {
+        path,
+        include_paths,
+        config,
+    })
+}
+
+fn parse_repl(args: &[String]) -> Result<Command, Error> {
+    let mut config = VmConfig::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--deterministic" => config = config.deterministic(),
+            "--seed" => config.seed = Some(number(arg, args.next())? as u64),
+            _ => return Err(usage()),
+        }
+    }
+    Ok(Command::Repl { config })
+}
+
+fn parse_doc(args: &[String]) -> Result<Command, Error> {
+    let mut path = None;
+    let mut include_paths = vec![];
+    let mut html = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--html" => html = true,
+            "--include-path" => include_paths.push(args.next().ok_or_else(usage)?.clone()),
+            _ if arg.starts_with("--") || path.is_some() => return Err(usage()),
+            _ => path = Some(arg.clone()),
+        }
+    }
+    Ok(Command::Doc {
+        path,
+        include_paths,
+        html,
+    })
+}
+
+fn parse_build(args: &[String]) -> Result<Command, Error> {
+    let mut path = None;
+    let mut include_paths = vec![];
+    let mut output = None;
+    let mut target = None;
+    let mut strict_loops = false;
+    let mut strict_entry = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--output" => output = Some(args.next().ok_or_else(usage)?.clone()),
+            "--target" => target = Some(args.next().ok_or_else(usage)?.clone()),
+            "--strict-loops" => strict_loops = true,
+            "--strict-entry" => strict_entry = true,
+            "--include-path" => include_paths.push(args.next().ok_or_else(usage)?.clone()),
+            _ if arg.starts_with("--") || path.is_some() => return Err(usage()),
+            _ => path = Some(arg.clone()),
+        }
+    }
+    Ok(Command::Build {
+        path,
+        include_paths,
+        output,
+        target,
+        strict_loops,
+        strict_entry,
+    })
+}
+
+// `--watch`, which only builds with the `watch` feature have.
+fn watch_flag() -> Result<bool, Error> {
+    if cfg!(feature = "watch") {
+        Ok(true)
+    } else {
+        Err(Error::Usage {
+            comment: "`--watch` needs rorth built with `--features watch`".to_string(),
+        })
+    }
+}
+
+fn parse_check(args: &[String]) -> Result<Command, Error> {
+    let mut path = None;
+    let mut include_paths = vec![];
+    let mut watch = false;
+    let mut verifiable = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--watch" => watch = watch_flag()?,
+            "--verifiable" => verifiable = Some(number(arg, args.next())?),
+            "--include-path" => include_paths.push(args.next().ok_or_else(usage)?.clone()),
+            _ if arg.starts_with("--") || path.is_some() => return Err(usage()),
+            _ => path = Some(arg.clone()),
+        }
+    }
+    Ok(Command::Check {
+        path,
+        include_paths,
+        watch,
+        verifiable,
+    })
+}
+
+// The file and `--include-path`s, for commands that take nothing else.
+fn parse_path(args: &[String]) -> Result<(Option<String>, Vec<String>), Error> {
+    let mut path = None;
+    let mut include_paths = vec![];
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--include-path" => include_paths.push(args.next().ok_or_else(usage)?.clone()),
+            _ if arg.starts_with("--") || path.is_some() => return Err(usage()),
+            _ => path = Some(arg.clone()),
+        }
+    }
+    Ok((path, include_paths))
+}
+
+fn parse_grammar(args: &[String]) -> Result<Command, Error> {
+    let mut format = grammar::Format::Ebnf;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => {
+                let value = args.next().ok_or_else(usage)?;
+                format = grammar::Format::parse(value).ok_or_else(|| Error::Usage {
+                    comment: "`--format` expects ebnf, json, textmate or tree-sitter".to_string(),
+                })?;
+            }
+            "--editor" => format = grammar::Format::Textmate,
+            _ => return Err(usage()),
+        }
+    }
+    Ok(Command::Grammar { format })
+}
+
+fn parse_stats(args: &[String]) -> Result<Command, Error> {
+    let (path, include_paths) = parse_path(args)?;
+    Ok(Command::Stats {
+        path,
+        include_paths,
+    })
+}
+
+fn parse_callgraph(args: &[String]) -> Result<Command, Error> {
+    let mut path = None;
+    let mut include_paths = vec![];
+    let mut format = callgraph::Format::Tree;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => {
+                let value = args.next().ok_or_else(usage)?;
+                format = callgraph::Format::parse(value).ok_or_else(|| Error::Usage {
+                    comment: "`--format` expects tree or dot".to_string(),
+                })?;
+            }
+            "--include-path" => include_paths.push(args.next().ok_or_else(usage)?.clone()),
+            _ if arg.starts_with("--") || path.is_some() => return Err(usage()),
+            _ => path = Some(arg.clone()),
+        }
+    }
+    Ok(Command::Callgraph {
+        path,
+        include_paths,
+        format,
+    })
+}
+
+fn parse_disasm(args: &[String]) -> Result<Command, Error> {
+    let (path, include_paths) = parse_path(args)?;
+    Ok(Command::Disasm {
+        path,
+        include_paths,
+    })
+}
+
+fn parse_verify_opt(args: &[String]) -> Result<Command, Error> {
+    let (path, include_paths) = parse_path(args)?;
+    Ok(Command::VerifyOpt {
+        path,
+        include_paths,
+    })
+}
+
+fn parse_test(args: &[String]) -> Result<Command, Error> {
+    let Some(doc) = args.iter().position(|arg| arg == "--doc") else {
+        return Err(Error::Usage {
+            comment: "`rorth test` only checks `#=>` comments so far; give `--doc`".to_string(),
+        });
+    };
+    let mut rest = args.to_vec();
+    rest.remove(doc);
+    let (path, include_paths) = parse_path(&rest)?;
+    Ok(Command::DocTest {
+        path,
+        include_paths,
+    })
+}
+
+fn parse_fix(args: &[String]) -> Result<Command, Error> {
+    let mut paths = vec![];
+    let mut dry_run = false;
+    for arg in args {
+        match arg.as_str() {
+            "--dry-run" => dry_run = true,
+            _ if arg.starts_with("--") => return Err(usage()),
+            _ => paths.push(arg.clone()),
+        }
+    }
+    if paths.is_empty() {
+        return Err(Error::Usage {
+            comment: "`rorth fix` rewrites files in place, so it needs at least one".to_string(),
+        });
+    }
+    Ok(Command::Fix { paths, dry_run })
+}
+
+fn parse_symexec(args: &[String]) -> Result<Command, Error> {
+    let mut path = None;
+    let mut include_paths = vec![];
+    let mut function = "main".to_string();
+    let mut limits = symexec::Limits::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--function" => function = args.next().ok_or_else(usage)?.clone(),
+            "--inputs" => limits.inputs = Some(number(arg, args.next())?),
+            "--max-steps" => limits.max_steps = number(arg, args.next())?,
+            "--max-paths" => limits.max_paths = number(arg, args.next())?,
+            "--include-path" => include_paths.push(args.next().ok_or_else(usage)?.clone()),
+            _ if arg.starts_with("--") || path.is_some() => return Err(usage()),
+            _ => path = Some(arg.clone()),
+        }
+    }
+    Ok(Command::Symexec {
+        path,
+        include_paths,
+        function,
+        limits,
+    })
+}
+
+fn parse_new(args: &[String]) -> Result<Command, Error> {
+    match args {
+        [path] if !path.starts_with("--") => Ok(Command::New { path: path.clone() }),
+        _ => Err(usage()),
+    }
+}
+
+// Flags followed by a separate value.
+const VALUE_FLAGS: &[&str] = &[
+    "--seed",
+    "--entry",
+    "--heartbeat",
+    "--include-path",
+    "--addr",
+    "--max-steps",
+    "--max-stack",
+    "--max-memory",
+    "--timeout",
+    "--on-error",
+    "--record",
+    "--replay",
+    "--output",
+    "--target",
+    "--viz-line",
+    "--viz-html",
+    "--trace-json",
+    "--function",
+    "--inputs",
+    "--max-paths",
+    "--format",
+    "--verifiable",
+];
+
+// Flags every subcommand understands.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Globals {
+    pub color: ColorChoice,
+    // Word packs to load, see `plugin::AVAILABLE`.
+    pub plugins: Vec<String>,
+    // Where tokens and parsed programs are kept between runs; see `cache`.
+    pub cache_dir: Option<String>,
+    pub no_cache: bool,
+    // How far apart tab stops are in reported columns; see `Renderer`.
+    pub tab_width: Option<usize>,
+    // A file of translated messages, see `catalog::Table`.
+    pub messages: Option<String>,
+}
+
+// Takes `--color=WHEN`, `--plugin name`, `--cache-dir dir`, `--no-cache`,
+// `--tab-width n` and `--messages file` out of the arguments, wherever they
+// appear before the file name.
+pub fn take_globals(args: &[String]) -> Result<(Globals, Vec<String>), Error> {
+    let mut globals = Globals::default();
+    let mut rest = vec![];
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if let Some(value) = arg.strip_prefix("--color=") {
+            globals.color = ColorChoice::parse(value).ok_or_else(|| Error::Usage {
+                comment: "`--color` expects auto, always or never".to_string(),
+            })?;
+            continue;
+        }
+        if arg == "--plugin" {
+            let name = args.next().ok_or_else(usage)?;
+            globals.plugins.push(name.clone());
+            continue;
+        }
+        if arg == "--cache-dir" {
+            globals.cache_dir = Some(args.next().ok_or_else(usage)?.clone());
+            continue;
+        }
+        if arg == "--no-cache" {
+            globals.no_cache = true;
+            continue;
+        }
+        if arg == "--messages" {
+            globals.messages = Some(args.next().ok_or_else(usage)?.clone());
+            continue;
+        }
+        if arg == "--tab-width" {
+            let width = number(arg, args.next())?;
+            if width == 0 {
+                return Err(Error::Usage {
+                    comment: "`--tab-width` must be at least 1".to_string(),
+                });
+            }
+            globals.tab_width = Some(width);
+            continue;
+        }
+        let subcommand = rest.is_empty()
+            && [
+                "run",
+                "repl",
+                "explain",
+                "new",
+                "doc",
+                "build",
+                "check",
+                "serve",
+                "words",
+                "grammar",
+                "disasm",
+                "callgraph",
+                "stats",
+                "symexec",
+                "selftest",
+                "test",
+                "fix",
+                "verify-opt",
+            ]
+            .contains(&arg.as_str());
+        rest.push(arg.clone());
+        if VALUE_FLAGS.contains(&arg.as_str()) {
+            rest.extend(args.next().cloned());
+        } else if !arg.starts_with("--") && !subcommand {
+            rest.extend(args.cloned());
+            break;
+        }
+    }
+    Ok((globals, rest))
+}
+
+pub fn parse_args(args: &[String]) -> Result<Command, Error> {
+    match args {
+        [cmd, rest @ ..] if cmd == "serve" => {
+            built_with("serve", cfg!(feature = "serve")).and_then(|()| parse_serve(rest))
+        }
+        [cmd, rest @ ..] if cmd == "run" => parse_run(rest),
+        [cmd, rest @ ..] if cmd == "new" => parse_new(rest),
+        [cmd, rest @ ..] if cmd == "repl" => {
+            built_with("repl", cfg!(feature = "repl")).and_then(|()| parse_repl(rest))
+        }
+        [cmd, rest @ ..] if cmd == "explain" => parse_explain(rest),
+        [cmd, rest @ ..] if cmd == "doc" => parse_doc(rest),
+        [cmd, rest @ ..] if cmd == "build" => parse_build(rest),
+        [cmd, rest @ ..] if cmd == "check" => parse_check(rest),
+        [cmd, rest @ ..] if cmd == "disasm" => parse_disasm(rest),
+        [cmd, rest @ ..] if cmd == "callgraph" => parse_callgraph(rest),
+        [cmd, rest @ ..] if cmd == "stats" => parse_stats(rest),
+        [cmd, rest @ ..] if cmd == "symexec" => parse_symexec(rest),
+        [cmd, rest @ ..] if cmd == "verify-opt" => parse_verify_opt(rest),
+        [cmd] if cmd == "words" => Ok(Command::Words),
+        [cmd, rest @ ..] if cmd == "grammar" => parse_grammar(rest),
+        [cmd, ..] if cmd == "words" => Err(usage()),
+        [cmd] if cmd == "selftest" => Ok(Command::Selftest),
+        [cmd, rest @ ..] if cmd == "test" => parse_test(rest),
+        [cmd, rest @ ..] if cmd == "fix" => parse_fix(rest),
+        [cmd, ..] if cmd == "selftest" => Err(usage()),
+        _ => parse_run(args),
+    }
+}
+
+#[cfg(test)]
+mod cli_tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn run_with_or_without_subcommand() {
+        assert!(matches!(
+            parse_args(&args(&["file.sm"])),
+            Ok(Command::Run { path: Some(path), .. }) if path == "file.sm"
+        ));
+        assert!(matches!(
+            parse_args(&args(&["run", "file.sm"])),
+            Ok(Command::Run { path: Some(path), .. }) if path == "file.sm"
+        ));
+    }
+
+    #[test]
+    fn arguments_after_the_file_are_left_to_the_script() {
+        match parse_args(&args(&["script.sm", "--deterministic", "x"])) {
+            Ok(Command::Run { path, config, .. }) => {
+                assert_eq!(path.as_deref(), Some("script.sm"));
+                assert_eq!(config, VmConfig::default());
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn more_files_come_before_the_script_arguments() {
+        match parse_args(&args(&["lib.sm", "dir/app.sm", "x.sm.txt", "y.sm"])) {
+            Ok(Command::Run { path, more, .. }) => {
+                assert_eq!(path.as_deref(), Some("lib.sm"));
+                assert_eq!(more, args(&["dir/app.sm"]));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(matches!(
+            parse_args(&args(&["run", "prog.rorthc", "lib.sm"])),
+            Ok(Command::Run { more, .. }) if more == ["lib.sm"]
+        ));
+        assert!(matches!(
+            parse_args(&args(&["--viz", "a.sm", "b.sm"])),
+            Err(Error::Usage { .. })
+        ));
+    }
+
+    #[test]
+    fn deterministic_flag() {
+        match parse_args(&args(&["run", "--deterministic", "file.sm"])) {
+            Ok(Command::Run { config, .. }) => {
+                assert_eq!(config, VmConfig::default().deterministic());
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        match parse_args(&args(&["--seed", "3", "--deterministic", "file.sm"])) {
+            Ok(Command::Run { config, .. }) => {
+                assert_eq!(config.seed, Some(3));
+                assert!(config.virtual_clock);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn run_without_a_file_uses_the_manifest() {
+        assert!(matches!(
+            parse_args(&args(&[])),
+            Ok(Command::Run { path: None, .. })
+        ));
+        assert!(matches!(
+            parse_args(&args(&["run", "--deterministic"])),
+            Ok(Command::Run { path: None, config, .. }) if config.virtual_clock
+        ));
+    }
+
+    #[test]
+    fn heartbeat_is_in_millions_of_steps() {
+        match parse_args(&args(&["--heartbeat", "3", "main.sm"])) {
+            Ok(Command::Run { config, .. }) => assert_eq!(config.heartbeat, Some(3_000_000)),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn viz_flags() {
+        match parse_args(&args(&[
+            "--viz",
+            "--viz-line",
+            "3",
+            "--viz-line",
+            "5",
+            "a.sm",
+        ])) {
+            Ok(Command::Run { viz, .. }) => assert_eq!(
+                viz,
+                Viz {
+                    text: true,
+                    lines: vec![3, 5],
+                    html: None,
+                }
+            ),
+            other => panic!("unexpected {:?}", other),
+        }
+        match parse_args(&args(&["run", "--viz-html", "steps.html", "a.sm"])) {
+            Ok(Command::Run { viz, .. }) => {
+                assert!(viz.enabled() && !viz.text);
+                assert_eq!(viz.html.as_deref(), Some("steps.html"));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(matches!(
+            parse_args(&args(&["--viz-line", "3", "a.sm"])),
+            Err(Error::Usage { .. })
+        ));
+    }
+
+    #[test]
+    fn memory_limit_is_in_bytes() {
+        match parse_args(&args(&["--max-memory", "65536", "main.sm"])) {
+            Ok(Command::Run { config, .. }) => assert_eq!(config.max_memory, Some(65536)),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn timeout_takes_a_unit() {
+        for (given, ms) in [("250ms", 250), ("5s", 5000), ("2m", 120_000)] {
+            match parse_args(&args(&["--timeout", given, "main.sm"])) {
+                Ok(Command::Run { config, .. }) => assert_eq!(config.timeout, Some(ms)),
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+        for given in ["5", "s", "0s", "1.5s", "5h"] {
+            assert!(matches!(
+                parse_args(&args(&["--timeout", given, "main.sm"])),
+                Err(Error::Usage { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn stats_flag() {
+        match parse_args(&args(&["run", "--stats", "main.sm"])) {
+            Ok(Command::Run { config, .. }) => assert!(config.stats),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn verify_flag() {
+        match parse_args(&args(&["--verify", "main.sm"])) {
+            Ok(Command::Run { config, .. }) => assert!(config.verify),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn syscalls_need_a_flag() {
+        for (argv, allowed) in [
+            (&["main.sm"][..], false),
+            (&["--unsafe-syscalls", "main.sm"][..], true),
+        ] {
+            match parse_args(&args(argv)) {
+                Ok(Command::Run { config, .. }) => assert_eq!(config.allow_syscalls, allowed),
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn strict_loops_flag() {
+        assert!(matches!(
+            parse_args(&args(&["--strict-loops", "main.sm"])),
+            Ok(Command::Run {
+                strict_loops: true,
+                ..
+            })
+        ));
+        assert!(matches!(
+            parse_args(&args(&["build", "--strict-loops", "main.sm"])),
+            Ok(Command::Build {
+                strict_loops: true,
+                ..
+            })
+        ));
+        assert!(matches!(
+            parse_args(&args(&["main.sm"])),
+            Ok(Command::Run {
+                strict_loops: false,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn strict_entry_flag() {
+        assert!(matches!(
+            parse_args(&args(&["--strict-entry", "main.sm"])),
+            Ok(Command::Run {
+                strict_entry: true,
+                strict_loops: false,
+                ..
+            })
+        ));
+        assert!(matches!(
+            parse_args(&args(&["build", "--strict-entry", "main.sm"])),
+            Ok(Command::Build {
+                strict_entry: true,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn entry_flag() {
+        match parse_args(&args(&["run", "--entry", "bench_sort", "lib.sm"])) {
+            Ok(Command::Run { config, .. }) => assert_eq!(config.entry(), "bench_sort"),
+            other => panic!("{:?}", other),
+        }
+        // After the file it is the script's.
+        match parse_args(&args(&["lib.sm", "--entry", "bench_sort"])) {
+            Ok(Command::Run { config, .. }) => assert_eq!(config.entry(), "main"),
+            other => panic!("{:?}", other),
+        }
+        assert!(matches!(
+            parse_args(&args(&["--entry", "f", "a.sm", "b.sm"])),
+            Err(Error::Usage { .. })
+        ));
+    }
+
+    #[test]
+    fn run_watch_flags() {
+        let watch = parse_args(&args(&["run", "--watch", "--keep-state", "turtle.sm"]));
+        if cfg!(feature = "watch") {
+            assert!(matches!(
+                watch,
+                Ok(Command::Run {
+                    watch: true,
+                    keep_state: true,
+                    ..
+                })
+            ));
+            assert!(matches!(
+                parse_args(&args(&["--watch", "--viz", "a.sm"])),
+                Err(Error::Usage { .. })
+            ));
+        } else {
+            assert!(matches!(watch, Err(Error::Usage { .. })));
+        }
+        assert!(matches!(
+            parse_args(&args(&["--keep-state", "a.sm"])),
+            Err(Error::Usage { .. })
+        ));
+    }
+
+    #[test]
+    fn output_flags() {
+        let output = |flags: &[&str]| match parse_args(&args(flags)) {
+            Ok(Command::Run { output, .. }) => Ok(output),
+            Ok(other) => panic!("{:?}", other),
+            Err(e) => Err(e),
+        };
+        assert_eq!(output(&["a.sm"]), Ok(Output::Lines));
+        assert_eq!(output(&["--output=csv", "a.sm"]), Ok(Output::Csv));
+        assert_eq!(output(&["run", "--quiet", "a.sm"]), Ok(Output::None));
+        assert!(matches!(
+            output(&["--output=tsv", "a.sm"]),
+            Err(Error::Usage { .. })
+        ));
+        assert!(matches!(
+            output(&["--output=space", "--viz", "a.sm"]),
+            Err(Error::Usage { .. })
+        ));
+    }
+
+    #[test]
+    fn record_and_replay_flags() {
+        match parse_args(&args(&[
+            "--record", "io.log", "--replay", "old.log", "a.sm",
+        ])) {
+            Ok(Command::Run { record, replay, .. }) => {
+                assert_eq!(record.as_deref(), Some("io.log"));
+                assert_eq!(replay.as_deref(), Some("old.log"));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(matches!(
+            parse_args(&args(&["--record", "io.log", "--viz", "a.sm"])),
+            Err(Error::Usage { .. })
+        ));
+    }
+
+    #[test]
+    fn trace_json_flag() {
+        match parse_args(&args(&["--trace-json", "trace.json", "a.sm"])) {
+            Ok(Command::Run { trace_json, .. }) => {
+                assert_eq!(trace_json.as_deref(), Some("trace.json"))
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        for bad in [
+            &["--trace-json", "t.json", "--viz", "a.sm"][..],
+            &["--trace-json", "t.json", "a.sm", "b.sm"][..],
+        ] {
+            assert!(matches!(parse_args(&args(bad)), Err(Error::Usage { .. })));
+        }
+    }
+
+    #[test]
+    fn profile_flag() {
+        assert!(matches!(
+            parse_args(&args(&["--profile=annotate", "a.sm"])),
+            Ok(Command::Run { annotate: true, .. })
+        ));
+        for bad in [
+            &["--profile=flat", "a.sm"][..],
+            &["--profile=annotate", "--viz", "a.sm"][..],
+        ] {
+            assert!(matches!(parse_args(&args(bad)), Err(Error::Usage { .. })));
+        }
+    }
+
+    #[test]
+    fn division_flag() {
+        for (argv, division) in [
+            (&["a.sm"][..], Division::Trunc),
+            (&["--division=floor", "a.sm"][..], Division::Floor),
+            (
+                &["--division=floor", "--division=trunc", "a.sm"][..],
+                Division::Trunc,
+            ),
+        ] {
+            match parse_args(&args(argv)) {
+                Ok(Command::Run { config, .. }) => assert_eq!(config.division, division),
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+        assert!(matches!(
+            parse_args(&args(&["--division=round", "a.sm"])),
+            Err(Error::Usage { .. })
+        ));
+    }
+
+    #[test]
+    fn dump_stack_flag() {
+        assert!(matches!(
+            parse_args(&args(&["--dump-stack-on-error", "a.sm"])),
+            Ok(Command::Run {
+                dump_stack: true,
+                ..
+            })
+        ));
+        assert!(matches!(
+            parse_args(&args(&["a.sm", "--dump-stack-on-error"])),
+            Ok(Command::Run {
+                dump_stack: false,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn error_policy() {
+        match parse_args(&args(&["--on-error", "trap", "main.sm"])) {
+            Ok(Command::Run { config, .. }) => assert_eq!(config.on_error, ErrorPolicy::Trap),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(matches!(
+            parse_args(&args(&["--on-error", "ignore", "main.sm"])),
+            Err(Error::Usage { .. })
+        ));
+    }
+
+    #[test]
+    fn include_paths() {
+        match parse_args(&args(&[
+            "--include-path",
+            "lib",
+            "--include-path",
+            "vendor",
+            "main.sm",
+        ])) {
+            Ok(Command::Run { include_paths, .. }) => {
+                assert_eq!(include_paths, vec!["lib", "vendor"]);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(matches!(
+            parse_args(&args(&["--include-path"])),
+            Err(Error::Usage { .. })
+        ));
+    }
+
+    #[test]
+    fn new_project() {
+        assert!(matches!(
+            parse_args(&args(&["new", "myproj"])),
+            Ok(Command::New { path }) if path == "myproj"
+        ));
+        assert!(matches!(
+            parse_args(&args(&["new"])),
+            Err(Error::Usage { .. })
+        ));
+        assert!(matches!(
+            parse_args(&args(&["new", "a", "b"])),
+            Err(Error::Usage { .. })
+        ));
+    }
+
+    #[test]
+    fn words_takes_no_arguments() {
+        assert!(matches!(parse_args(&args(&["words"])), Ok(Command::Words)));
+        assert!(matches!(
+            parse_args(&args(&["grammar", "--format", "json"])),
+            Ok(Command::Grammar {
+                format: grammar::Format::Json
+            })
+        ));
+        assert!(matches!(
+            parse_args(&args(&["grammar", "--editor"])),
+            Ok(Command::Grammar {
+                format: grammar::Format::Textmate
+            })
+        ));
+        assert!(matches!(
+            parse_args(&args(&["grammar", "--format", "yaml"])),
+            Err(Error::Usage { .. })
+        ));
+        assert!(matches!(
+            parse_args(&args(&["words", "dup"])),
+            Err(Error::Usage { .. })
+        ));
+    }
+
+    #[test]
+    fn selftest_takes_no_arguments() {
+        assert!(matches!(
+            parse_args(&args(&["selftest"])),
+            Ok(Command::Selftest)
+        ));
+        assert!(matches!(
+            parse_args(&args(&["selftest", "x"])),
+            Err(Error::Usage { .. })
+        ));
+    }
+
+    #[test]
+    fn disasm_flags() {
+        match parse_args(&args(&["disasm", "--include-path", "lib", "app.rorthc"])) {
+            Ok(Command::Disasm {
+                path,
+                include_paths,
+            }) => {
+                assert_eq!(path.as_deref(), Some("app.rorthc"));
+                assert_eq!(include_paths, vec!["lib".to_string()]);
+            }
+            other => panic!("{:?}", other),
+        }
+        assert!(matches!(
+            parse_args(&args(&["disasm", "a.sm", "b.sm"])),
+            Err(Error::Usage { .. })
+        ));
+    }
+
+    #[test]
+    fn stats_command() {
+        match parse_args(&args(&["stats", "--include-path", "lib", "app.sm"])) {
+            Ok(Command::Stats {
+                path,
+                include_paths,
+            }) => {
+                assert_eq!(path.as_deref(), Some("app.sm"));
+                assert_eq!(include_paths, vec!["lib".to_string()]);
+            }
+            other => panic!("{:?}", other),
+        }
+        // `--stats` on a run is another thing.
+        assert!(matches!(
+            parse_args(&args(&["stats", "--stats"])),
+            Err(Error::Usage { .. })
+        ));
+    }
+
+    #[test]
+    fn test_wants_doc() {
+        match parse_args(&args(&["test", "--doc", "prog.sm"])) {
+            Ok(Command::DocTest { path, .. }) => assert_eq!(path.as_deref(), Some("prog.sm")),
+            other => panic!("{:?}", other),
+        }
+        assert!(matches!(
+            parse_args(&args(&["test", "prog.sm", "--doc"])),
+            Ok(Command::DocTest { .. })
+        ));
+        assert!(matches!(
+            parse_args(&args(&["test", "prog.sm"])),
+            Err(Error::Usage { .. })
+        ));
+    }
+
+    #[test]
+    fn fix_wants_files() {
+        match parse_args(&args(&["fix", "a.sm", "--dry-run", "b.sm"])) {
+            Ok(Command::Fix { paths, dry_run }) => {
+                assert_eq!(paths, vec!["a.sm", "b.sm"]);
+                assert!(dry_run);
+            }
+            other => panic!("{:?}", other),
+        }
+        assert!(matches!(
+            parse_args(&args(&["fix"])),
+            Err(Error::Usage { .. })
+        ));
+    }
+
+    #[test]
+    fn callgraph_flags() {
+        match parse_args(&args(&["callgraph", "--format", "dot", "app.sm"])) {
+            Ok(Command::Callgraph { path, format, .. }) => {
+                assert_eq!(path.as_deref(), Some("app.sm"));
+                assert_eq!(format, callgraph::Format::Dot);
+            }
+            other => panic!("{:?}", other),
+        }
+        assert!(matches!(
+            parse_args(&args(&["callgraph"])),
+            Ok(Command::Callgraph {
+                path: None,
+                format: callgraph::Format::Tree,
+                ..
+            })
+        ));
+        assert!(matches!(
+            parse_args(&args(&["callgraph", "--format", "svg"])),
+            Err(Error::Usage { .. })
+        ));
+    }
+
+    #[test]
+    fn optimization_flags() {
+        match parse_args(&args(&["verify-opt", "--include-path", "lib", "a.sm"])) {
+            Ok(Command::VerifyOpt {
+                path,
+                include_paths,
+            }) => {
+                assert_eq!(path.as_deref(), Some("a.sm"));
+                assert_eq!(include_paths, vec!["lib".to_string()]);
+            }
+            other => panic!("{:?}", other),
+        }
+        for (argv, optimize) in [(&["a.sm"][..], true), (&["-O0", "a.sm"][..], false)] {
+            match parse_args(&args(argv)) {
+                Ok(Command::Run { config, .. }) => assert_eq!(config.optimize, optimize),
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn symexec_flags() {
+        match parse_args(&args(&[
+            "symexec",
+            "--function",
+            "f",
+            "--inputs",
+            "2",
+            "a.sm",
+        ])) {
+            Ok(Command::Symexec {
+                path,
+                function,
+                limits,
+                ..
+            }) => {
+                assert_eq!(path.as_deref(), Some("a.sm"));
+                assert_eq!(function, "f");
+                assert_eq!(limits.inputs, Some(2));
+                assert_eq!(limits.max_steps, symexec::Limits::default().max_steps);
+            }
+            other => panic!("{:?}", other),
+        }
+        assert!(matches!(
+            parse_args(&args(&["symexec", "--inputs", "a.sm"])),
+            Err(Error::Usage { .. })
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "repl")]
+    fn repl_flags() {
+        assert!(matches!(
+            parse_args(&args(&["repl", "--seed", "4"])),
+            Ok(Command::Repl { config }) if config.seed == Some(4)
+        ));
+        assert!(matches!(
+            parse_args(&args(&["repl", "file.sm"])),
+            Err(Error::Usage { .. })
+        ));
+    }
+
+    #[test]
+    fn explain_flags() {
+        match parse_args(&args(&["explain", "--deterministic", "lesson.sm"])) {
+            Ok(Command::Explain { path, config, .. }) => {
+                assert_eq!(path.as_deref(), Some("lesson.sm"));
+                assert_eq!(config, VmConfig::default().deterministic());
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(matches!(
+            parse_args(&args(&["explain", "a.sm", "b.sm"])),
+            Err(Error::Usage { .. })
+        ));
+    }
+
+    #[test]
+    fn doc_flags() {
+        match parse_args(&args(&["doc", "--html", "lib.sm"])) {
+            Ok(Command::Doc { path, html, .. }) => {
+                assert_eq!(path.as_deref(), Some("lib.sm"));
+                assert!(html);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(matches!(
+            parse_args(&args(&["doc"])),
+            Ok(Command::Doc {
+                path: None,
+                html: false,
+                ..
+            })
+        ));
+        assert!(matches!(
+            parse_args(&args(&["doc", "a.sm", "b.sm"])),
+            Err(Error::Usage { .. })
+        ));
+    }
+
+    #[test]
+    fn build_flags() {
+        match parse_args(&args(&[
+            "build",
+            "--output",
+            "out.rorthc",
+            "--target",
+            "bytecode",
+            "main.sm",
+        ])) {
+            Ok(Command::Build {
+                path,
+                output,
+                target,
+                ..
+            }) => {
+                assert_eq!(path.as_deref(), Some("main.sm"));
+                assert_eq!(output.as_deref(), Some("out.rorthc"));
+                assert_eq!(target.as_deref(), Some("bytecode"));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(matches!(
+            parse_args(&args(&["build"])),
+            Ok(Command::Build {
+                path: None,
+                output: None,
+                target: None,
+                ..
+            })
+        ));
+        assert!(matches!(
+            parse_args(&args(&["build", "--output"])),
+            Err(Error::Usage { .. })
+        ));
+    }
+
+    #[test]
+    fn left_out_commands_are_refused() {
+        let refused = |command: &str| match parse_args(&args(&[command])) {
+            Err(Error::Usage { comment }) => comment.contains("--features"),
+            _ => false,
+        };
+        assert_eq!(refused("repl"), !cfg!(feature = "repl"));
+        assert_eq!(refused("serve"), !cfg!(feature = "serve"));
+    }
+
+    #[test]
+    fn check_flags() {
+        assert!(matches!(
+            parse_args(&args(&["check", "--include-path", "lib", "main.sm"])),
+            Ok(Command::Check { path: Some(path), include_paths, watch: false, verifiable: None })
+                if path == "main.sm" && include_paths == ["lib"]
+        ));
+        assert!(matches!(
+            parse_args(&args(&["check", "--verifiable", "40"])),
+            Ok(Command::Check {
+                verifiable: Some(40),
+                ..
+            })
+        ));
+        let watch = parse_args(&args(&["check", "--watch"]));
+        if cfg!(feature = "watch") {
+            assert!(matches!(
+                watch,
+                Ok(Command::Check {
+                    path: None,
+                    watch: true,
+                    ..
+                })
+            ));
+        } else {
+            assert!(matches!(watch, Err(Error::Usage { .. })));
+        }
+        assert!(matches!(
+            parse_args(&args(&["check", "a.sm", "b.sm"])),
+            Err(Error::Usage { .. })
+        ));
+    }
+
+    #[test]
+    fn globals_after_verify_opt() {
+        let (globals, rest) = take_globals(&args(&["verify-opt", "--no-cache", "a.sm"])).unwrap();
+        assert!(globals.no_cache);
+        assert_eq!(rest, args(&["verify-opt", "a.sm"]));
+    }
+
+    #[test]
+    fn globals_after_a_target() {
+        let (globals, rest) = take_globals(&args(&[
+            "build",
+            "--target",
+            "bytecode",
+            "--no-cache",
+            "a.sm",
+        ]))
+        .unwrap();
+        assert!(globals.no_cache);
+        assert_eq!(rest, args(&["build", "--target", "bytecode", "a.sm"]));
+    }
+
+    #[test]
+    fn color_flag() {
+        let (globals, rest) = take_globals(&args(&[
+            "run",
+            "--color=never",
+            "--seed",
+            "--color=x",
+            "a.sm",
+            "--color=always",
+        ]))
+        .unwrap();
+        assert_eq!(globals.color, ColorChoice::Never);
+        assert_eq!(
+            rest,
+            args(&["run", "--seed", "--color=x", "a.sm", "--color=always"])
+        );
+
+        let (globals, rest) = take_globals(&args(&["--color=always", "repl"])).unwrap();
+        assert_eq!(globals.color, ColorChoice::Always);
+        assert_eq!(rest, args(&["repl"]));
+        assert!(matches!(
+            take_globals(&args(&["--color=blue"])),
+            Err(Error::Usage { .. })
+        ));
+    }
+
+    #[test]
+    fn plugin_flag() {
+        let (globals, rest) = take_globals(&args(&[
+            "--plugin", "turtle", "run", "--plugin", "audio", "a.sm", "--plugin", "x",
+        ]))
+        .unwrap();
+        assert_eq!(globals.plugins, args(&["turtle", "audio"]));
+        assert_eq!(rest, args(&["run", "a.sm", "--plugin", "x"]));
+        assert!(matches!(
+            take_globals(&args(&["--plugin"])),
+            Err(Error::Usage { .. })
+        ));
+    }
+
+    #[test]
+    fn cache_flags() {
+        let (globals, rest) = take_globals(&args(&[
+            "--cache-dir",
+            "/tmp/c",
+            "run",
+            "--no-cache",
+            "a.sm",
+            "--no-cache",
+        ]))
+        .unwrap();
+        assert_eq!(globals.cache_dir.as_deref(), Some("/tmp/c"));
+        assert!(globals.no_cache);
+        assert_eq!(rest, args(&["run", "a.sm", "--no-cache"]));
+        assert!(matches!(
+            take_globals(&args(&["run", "--cache-dir"])),
+            Err(Error::Usage { .. })
+        ));
+    }
+
+    #[test]
+    fn tab_width_flag() {
+        let (globals, rest) = take_globals(&args(&[
+            "check",
+            "--tab-width",
+            "8",
+            "a.sm",
+            "--tab-width",
+            "2",
+        ]))
+        .unwrap();
+        assert_eq!(globals.tab_width, Some(8));
+        assert_eq!(rest, args(&["check", "a.sm", "--tab-width", "2"]));
+        for bad in [
+            &["--tab-width"][..],
+            &["--tab-width", "0"],
+            &["--tab-width", "x"],
+        ] {
+            assert!(matches!(take_globals(&args(bad)), Err(Error::Usage { .. })));
+        }
+    }
+
+    #[test]
+    fn messages_flag() {
+        let (globals, rest) =
+            take_globals(&args(&["--messages", "fr.txt", "run", "a.sm"])).unwrap();
+        assert_eq!(globals.messages.as_deref(), Some("fr.txt"));
+        assert_eq!(rest, args(&["run", "a.sm"]));
+    }
+
+    #[test]
+    #[cfg(feature = "serve")]
+    fn serve_flags() {
+        match parse_args(&args(&[
+            "serve",
+            "--addr",
+            "0.0.0.0:9000",
+            "--max-steps",
+            "10",
+            "--max-memory",
+            "4096",
+            "--timeout",
+            "1s",
+        ])) {
+            Ok(Command::Serve { addr, limits }) => {
+                assert_eq!(addr, "0.0.0.0:9000");
+                assert_eq!(limits.max_steps, 10);
+                assert_eq!(limits.max_memory, 4096);
+                assert_eq!(limits.timeout, 1000);
+                assert_eq!(limits.max_stack, Limits::default().max_stack);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(matches!(
+            parse_args(&args(&["serve", "--max-steps", "lots"])),
+            Err(Error::Usage { comment }) if comment == "`--max-steps` expects a number"
+        ));
+    }
+
+    #[test]
+    fn bad_arguments() {
+        assert!(matches!(
+            parse_args(&args(&["--verbose"])),
+            Err(Error::Usage { .. })
+        ));
+    }
+}
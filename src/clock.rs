@@ -0,0 +1,68 @@
+// `time` reads milliseconds since the machine was created. The virtual clock
+// advances one millisecond per executed instruction instead, which makes
+// runs reproducible. Without `std`, or in the browser, there is no wall
+// clock and the virtual one is used.
+#[derive(Debug, Clone)]
+pub enum Clock {
+    #[cfg(all(
+        feature = "std",
+        not(all(target_arch = "wasm32", target_os = "unknown"))
+    ))]
+    Wall(std::time::Instant),
+    Virtual,
+}
+
+impl Clock {
+    pub fn wall() -> Self {
+        #[cfg(all(
+            feature = "std",
+            not(all(target_arch = "wasm32", target_os = "unknown"))
+        ))]
+        return Clock::Wall(std::time::Instant::now());
+        #[cfg(any(
+            not(feature = "std"),
+            all(target_arch = "wasm32", target_os = "unknown")
+        ))]
+        return Clock::Virtual;
+    }
+
+    pub fn is_virtual(&self) -> bool {
+        matches!(self, Clock::Virtual)
+    }
+
+    // Milliseconds since the clock started.
+    pub fn elapsed(&self, steps: usize) -> usize {
+        match self {
+            #[cfg(all(
+                feature = "std",
+                not(all(target_arch = "wasm32", target_os = "unknown"))
+            ))]
+            Clock::Wall(start) => start.elapsed().as_millis() as usize,
+            Clock::Virtual => steps,
+        }
+    }
+
+    // `elapsed` as `time` pushes it, wrapping around past `i32::MAX`.
+    pub fn millis(&self, steps: usize) -> i32 {
+        (self.elapsed(steps) % (i32::MAX as usize + 1)) as i32
+    }
+}
+
+#[cfg(test)]
+mod clock_tests {
+    use super::*;
+
+    #[test]
+    fn virtual_clock_follows_steps() {
+        assert_eq!(Clock::Virtual.millis(0), 0);
+        assert_eq!(Clock::Virtual.millis(1234), 1234);
+        assert!(Clock::Virtual.is_virtual());
+    }
+
+    #[test]
+    fn wall_clock_starts_near_zero() {
+        let clock = Clock::wall();
+        assert!(!clock.is_virtual());
+        assert!(clock.millis(1_000_000) < 1000);
+    }
+}
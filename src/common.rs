@@ -1,4 +1,10 @@
-#[derive(Debug, PartialEq, Eq)]
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Display;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Error {
     UnknownToken {
         word: String,
@@ -21,7 +27,380 @@ pub enum Error {
         pos: usize,
         line: usize,
     },
+    DivisionByZero {
+        pos: usize,
+        line: usize,
+    },
+    // A result of checked arithmetic that doesn't fit in 32 bits: `+`, `-`,
+    // `*`, `/`, `divmod` and the fixed-point words.
+    Overflow {
+        pos: usize,
+        line: usize,
+    },
+    // A bad address or block size, see `heap`.
+    Heap {
+        comment: String,
+        pos: usize,
+        line: usize,
+    },
     FunctionNotFound {
         name: String,
     },
+    // `invoke` of a value that isn't a function's `&name`.
+    NotAFunction {
+        value: i32,
+        pos: usize,
+        line: usize,
+    },
+    Io {
+        path: String,
+        comment: String,
+    },
+    Usage {
+        comment: String,
+    },
+    Bytecode {
+        comment: String,
+    },
+    StepLimitExceeded {
+        limit: usize,
+        pos: usize,
+        line: usize,
+    },
+    StackOverflow {
+        limit: usize,
+        pos: usize,
+        line: usize,
+    },
+    // Ran for longer than `VmConfig::timeout`, in milliseconds.
+    Timeout {
+        limit: usize,
+        pos: usize,
+        line: usize,
+    },
+    OutOfMemory {
+        limit: usize,
+        pos: usize,
+        line: usize,
+    },
+    CapabilityDenied {
+        capability: String,
+        pos: usize,
+        line: usize,
+    },
+    UnsupportedInstruction {
+        word: String,
+        pos: usize,
+        line: usize,
+    },
+    // A word from a plugin pack refused to run.
+    Plugin {
+        word: String,
+        comment: String,
+        pos: usize,
+        line: usize,
+    },
+    Manifest {
+        line: usize,
+        comment: String,
+    },
+    // A replayed run that asked for a value its recording doesn't have
+    // next, see `replay`.
+    Replay {
+        comment: String,
+        pos: usize,
+        line: usize,
+    },
+    // Innermost function first.
+    Interrupted {
+        backtrace: Vec<String>,
+        depth: usize,
+        pos: usize,
+        line: usize,
+    },
+    Multiple(Vec<Error>),
+}
+
+impl Error {
+    pub fn from_vec(mut errors: Vec<Error>) -> Error {
+        if errors.len() == 1 {
+            errors.remove(0)
+        } else {
+            Error::Multiple(errors)
+        }
+    }
+
+    pub fn into_vec(self) -> Vec<Error> {
+        match self {
+            Error::Multiple(errors) => errors.into_iter().flat_map(Error::into_vec).collect(),
+            e => vec![e],
+        }
+    }
+}
+
+impl Error {
+    // Line and column in the source, for errors that point into it.
+    pub fn position(&self) -> Option<(usize, usize)> {
+        match self {
+            Error::UnknownToken { pos, line, .. }
+            | Error::Parse { pos, line, .. }
+            | Error::StaticCheck { pos, line, .. }
+            | Error::StackEmpty { pos, line }
+            | Error::DivisionByZero { pos, line }
+            | Error::Overflow { pos, line }
+            | Error::Heap { pos, line, .. }
+            | Error::StepLimitExceeded { pos, line, .. }
+            | Error::StackOverflow { pos, line, .. }
+            | Error::Timeout { pos, line, .. }
+            | Error::OutOfMemory { pos, line, .. }
+            | Error::CapabilityDenied { pos, line, .. }
+            | Error::UnsupportedInstruction { pos, line, .. }
+            | Error::Plugin { pos, line, .. }
+            | Error::Replay { pos, line, .. }
+            | Error::NotAFunction { pos, line, .. }
+            | Error::Interrupted { pos, line, .. } => Some((*line, *pos)),
+            _ => None,
+        }
+    }
+
+    // The same error `lines` further down, for errors that point into the
+    // source.
+    pub fn moved(mut self, lines: isize) -> Error {
+        match &mut self {
+            Error::UnknownToken { line, .. }
+            | Error::Parse { line, .. }
+            | Error::StaticCheck { line, .. }
+            | Error::StackEmpty { line, .. }
+            | Error::DivisionByZero { line, .. }
+            | Error::Overflow { line, .. }
+            | Error::Heap { line, .. }
+            | Error::StepLimitExceeded { line, .. }
+            | Error::StackOverflow { line, .. }
+            | Error::Timeout { line, .. }
+            | Error::OutOfMemory { line, .. }
+            | Error::CapabilityDenied { line, .. }
+            | Error::UnsupportedInstruction { line, .. }
+            | Error::Plugin { line, .. }
+            | Error::Replay { line, .. }
+            | Error::NotAFunction { line, .. }
+            | Error::Interrupted { line, .. } => *line = line.wrapping_add_signed(lines),
+            _ => {}
+        }
+        self
+    }
+
+    // What went wrong, without the position.
+    pub fn message(&self) -> String {
+        match self {
+            Error::UnknownToken { word, .. } => format!("unknown token `{}`", word),
+            Error::Parse { comment, .. } | Error::StaticCheck { comment, .. } => comment.clone(),
+            Error::StackEmpty { .. } => "stack is empty".to_string(),
+            Error::DivisionByZero { .. } => "division by zero".to_string(),
+            Error::Overflow { .. } => "arithmetic overflow".to_string(),
+            Error::Heap { comment, .. } => comment.clone(),
+            Error::FunctionNotFound { name } => format!("function `{}` not found", name),
+            Error::NotAFunction { value, .. } => {
+                format!("`invoke` needs a function's `&name`, not {}", value)
+            }
+            Error::Io { path, comment } => format!("{}: {}", path, comment),
+            Error::Usage { comment } => comment.clone(),
+            Error::Bytecode { comment } => format!("bytecode: {}", comment),
+            Error::StepLimitExceeded { limit, .. } => format!("step limit of {} exceeded", limit),
+            Error::StackOverflow { limit, .. } => format!("stack limit of {} exceeded", limit),
+            Error::Timeout { limit, .. } => format!("time limit of {} ms exceeded", limit),
+            Error::OutOfMemory { limit, .. } => {
+                format!("memory limit of {} bytes exceeded", limit)
+            }
+            Error::CapabilityDenied { capability, .. } => {
+                format!("`{}` is not allowed here", capability)
+            }
+            Error::UnsupportedInstruction { word, .. } => {
+                format!("`{}` is not supported here", word)
+            }
+            Error::Plugin { word, comment, .. } => format!("`{}`: {}", word, comment),
+            Error::Manifest { line, comment } => format!("rorth.toml:{}: {}", line, comment),
+            Error::Replay { comment, .. } => format!("replay: {}", comment),
+            Error::Interrupted {
+                backtrace, depth, ..
+            } => {
+                let mut message = "interrupted".to_string();
+                if let Some((function, callers)) = backtrace.split_first() {
+                    message.push_str(&format!(" in `{}`", function));
+                    if !callers.is_empty() {
+                        message.push_str(&format!(" (called from `{}`)", callers.join("` <- `")));
+                    }
+                }
+                format!("{} with {} values on the stack", message, depth)
+            }
+            Error::Multiple(errors) => {
+                let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                errors.join("\n")
+            }
+        }
+    }
+}
+
+impl Error {
+    // The number a program sees when it handles this error itself. Limits
+    // and interrupts have none, so they always stop the run.
+    pub fn code(&self) -> Option<i32> {
+        match self {
+            Error::StackEmpty { .. } => Some(1),
+            Error::DivisionByZero { .. } => Some(2),
+            Error::CapabilityDenied { .. } => Some(3),
+            Error::Plugin { .. } => Some(4),
+            Error::Overflow { .. } => Some(5),
+            Error::Heap { .. } => Some(6),
+            Error::NotAFunction { .. } => Some(7),
+            _ => None,
+        }
+    }
+
+    // Stable across versions, for scripts and editors to tell errors apart
+    // without matching messages: E0 errors are in the source, E1 happened
+    // while it ran, E2 are limits and interrupts, E3 are about rorth's own
+    // input. Several errors go by the first.
+    pub fn id(&self) -> &'static str {
+        match self {
+            Error::UnknownToken { .. } => "E0001",
+            Error::Parse { .. } => "E0002",
+            Error::StaticCheck { .. } => "E0003",
+            Error::FunctionNotFound { .. } => "E0004",
+            Error::DivisionByZero { .. } => "E1001",
+            Error::StackEmpty { .. } => "E1002",
+            Error::Overflow { .. } => "E1003",
+            Error::Heap { .. } => "E1004",
+            Error::CapabilityDenied { .. } => "E1005",
+            Error::Plugin { .. } => "E1006",
+            Error::UnsupportedInstruction { .. } => "E1007",
+            Error::Replay { .. } => "E1008",
+            Error::NotAFunction { .. } => "E1009",
+            Error::StepLimitExceeded { .. } => "E2001",
+            Error::StackOverflow { .. } => "E2002",
+            Error::Timeout { .. } => "E2003",
+            Error::OutOfMemory { .. } => "E2004",
+            Error::Interrupted { .. } => "E2005",
+            Error::Usage { .. } => "E3001",
+            Error::Io { .. } => "E3002",
+            Error::Manifest { .. } => "E3003",
+            Error::Bytecode { .. } => "E3004",
+            Error::Multiple(errors) => errors.first().map_or("E3001", Error::id),
+        }
+    }
+
+    // What a message can mention, by name, for `catalog` templates.
+    pub fn fields(&self) -> Vec<(&'static str, String)> {
+        let text = |name, value: &String| (name, value.clone());
+        let number = |name, value: &usize| (name, value.to_string());
+        match self {
+            Error::UnknownToken { word, .. } | Error::UnsupportedInstruction { word, .. } => {
+                vec![text("word", word)]
+            }
+            Error::Parse { word, comment, .. }
+            | Error::StaticCheck { word, comment, .. }
+            | Error::Plugin { word, comment, .. } => {
+                vec![text("word", word), text("comment", comment)]
+            }
+            Error::Heap { comment, .. }
+            | Error::Usage { comment }
+            | Error::Bytecode { comment }
+            | Error::Replay { comment, .. } => vec![text("comment", comment)],
+            Error::FunctionNotFound { name } => vec![text("name", name)],
+            Error::Io { path, comment } => vec![text("path", path), text("comment", comment)],
+            Error::Manifest { line, comment } => {
+                vec![number("line", line), text("comment", comment)]
+            }
+            Error::StepLimitExceeded { limit, .. }
+            | Error::StackOverflow { limit, .. }
+            | Error::Timeout { limit, .. }
+            | Error::OutOfMemory { limit, .. } => vec![number("limit", limit)],
+            Error::CapabilityDenied { capability, .. } => vec![text("capability", capability)],
+            Error::NotAFunction { value, .. } => vec![("value", value.to_string())],
+            Error::Interrupted {
+                backtrace, depth, ..
+            } => vec![
+                ("function", backtrace.first().cloned().unwrap_or_default()),
+                number("depth", depth),
+            ],
+            Error::StackEmpty { .. }
+            | Error::DivisionByZero { .. }
+            | Error::Overflow { .. }
+            | Error::Multiple(_) => vec![],
+        }
+    }
+
+    // What `rorth` exits with, one status to each kind of `id`. Ctrl-C gets
+    // the shell's 130.
+    pub fn exit_status(&self) -> u8 {
+        match self {
+            Error::Interrupted { .. } => 130,
+            Error::Usage { .. } => 2,
+            Error::Multiple(errors) => errors.first().map_or(1, Error::exit_status),
+            error => match error.id().as_bytes()[1] {
+                b'0' => 3,
+                b'1' => 1,
+                b'2' => 4,
+                _ => 5,
+            },
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self.position() {
+            Some((line, pos)) => write!(f, "{}:{}: {}", line, pos, self.message()),
+            None => write!(f, "{}", self.message()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod common_tests {
+    use super::*;
+
+    #[test]
+    fn ids_and_exit_statuses_follow_the_kind() {
+        let empty = Error::StackEmpty { pos: 1, line: 1 };
+        let unknown = Error::UnknownToken {
+            word: "^".to_string(),
+            pos: 1,
+            line: 1,
+        };
+        let limit = Error::StepLimitExceeded {
+            limit: 1,
+            pos: 1,
+            line: 1,
+        };
+        let io = Error::Io {
+            path: "a.sm".to_string(),
+            comment: "not found".to_string(),
+        };
+        let usage = Error::Usage {
+            comment: "usage".to_string(),
+        };
+        let interrupted = Error::Interrupted {
+            backtrace: vec![],
+            depth: 0,
+            pos: 1,
+            line: 1,
+        };
+        let ids: Vec<_> = [&empty, &unknown, &limit, &io, &usage, &interrupted]
+            .iter()
+            .map(|e| (e.id(), e.exit_status()))
+            .collect();
+        assert_eq!(
+            ids,
+            [
+                ("E1002", 1),
+                ("E0001", 3),
+                ("E2001", 4),
+                ("E3002", 5),
+                ("E3001", 2),
+                ("E2005", 130)
+            ]
+        );
+        let both = Error::Multiple(vec![unknown, empty]);
+        assert_eq!((both.id(), both.exit_status()), ("E0001", 3));
+    }
 }
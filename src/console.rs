@@ -0,0 +1,153 @@
+// Reads REPL input. With the `readline` feature lines can be edited, history
+// is kept in `~/.rorth_history` (arrow keys, Ctrl-R) and Tab completes words
+// and the session's functions. Without it, lines come from stdin as is.
+#[cfg(not(feature = "readline"))]
+mod plain {
+    use std::io::{BufRead, Write};
+
+    use stack_machine_bez::common::Error;
+    use stack_machine_bez::repl::Session;
+
+    pub struct Console;
+
+    impl Console {
+        pub fn new() -> Result<Self, Error> {
+            Ok(Console)
+        }
+
+        pub fn read_line(
+            &mut self,
+            prompt: &str,
+            _session: &Session,
+        ) -> Result<Option<String>, Error> {
+            print!("{}", prompt);
+            let io_error = |e: std::io::Error| Error::Io {
+                path: "<stdin>".to_string(),
+                comment: e.to_string(),
+            };
+            std::io::stdout().flush().map_err(io_error)?;
+            let mut line = String::new();
+            match std::io::stdin()
+                .lock()
+                .read_line(&mut line)
+                .map_err(io_error)?
+            {
+                0 => Ok(None),
+                _ => Ok(Some(line)),
+            }
+        }
+
+        pub fn finish(&mut self) {}
+    }
+}
+
+#[cfg(feature = "readline")]
+mod editor {
+    use std::path::PathBuf;
+    use std::sync::atomic::Ordering;
+
+    use rustyline::completion::Completer;
+    use rustyline::error::ReadlineError;
+    use rustyline::highlight::Highlighter;
+    use rustyline::hint::Hinter;
+    use rustyline::history::DefaultHistory;
+    use rustyline::validate::Validator;
+    use rustyline::{Context, Editor, Helper};
+
+    use stack_machine_bez::common::Error;
+    use stack_machine_bez::interrupt::INTERRUPTED;
+    use stack_machine_bez::repl::Session;
+
+    pub struct Words(Vec<String>);
+
+    impl Completer for Words {
+        type Candidate = String;
+
+        fn complete(
+            &self,
+            line: &str,
+            pos: usize,
+            _ctx: &Context<'_>,
+        ) -> rustyline::Result<(usize, Vec<String>)> {
+            let start = line[..pos]
+                .rfind(|c: char| c.is_whitespace())
+                .map_or(0, |i| i + 1);
+            let prefix = &line[start..pos];
+            let words = self
+                .0
+                .iter()
+                .filter(|w| w.starts_with(prefix))
+                .cloned()
+                .collect();
+            Ok((start, words))
+        }
+    }
+
+    impl Hinter for Words {
+        type Hint = String;
+    }
+    impl Highlighter for Words {}
+    impl Validator for Words {}
+    impl Helper for Words {}
+
+    pub struct Console {
+        editor: Editor<Words, DefaultHistory>,
+        history: Option<PathBuf>,
+    }
+
+    fn editor_error(e: ReadlineError) -> Error {
+        Error::Io {
+            path: "<stdin>".to_string(),
+            comment: e.to_string(),
+        }
+    }
+
+    impl Console {
+        pub fn new() -> Result<Self, Error> {
+            let mut editor = Editor::new().map_err(editor_error)?;
+            let history =
+                std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".rorth_history"));
+            if let Some(path) = &history {
+                // There is no history yet on the first run.
+                let _ = editor.load_history(path);
+            }
+            Ok(Console { editor, history })
+        }
+
+        pub fn read_line(
+            &mut self,
+            prompt: &str,
+            session: &Session,
+        ) -> Result<Option<String>, Error> {
+            self.editor.set_helper(Some(Words(session.complete(""))));
+            match self.editor.readline(prompt) {
+                Ok(line) => {
+                    if !line.trim().is_empty() {
+                        let _ = self.editor.add_history_entry(line.as_str());
+                    }
+                    Ok(Some(line))
+                }
+                // Ctrl-C drops the line being typed, and any unfinished lines
+                // before it, as it does without line editing. Ctrl-D leaves.
+                Err(ReadlineError::Interrupted) => {
+                    INTERRUPTED.store(true, Ordering::Relaxed);
+                    Ok(Some(String::new()))
+                }
+                Err(ReadlineError::Eof) => Ok(None),
+                Err(e) => Err(editor_error(e)),
+            }
+        }
+
+        pub fn finish(&mut self) {
+            if let Some(path) = &self.history {
+                let _ = self.editor.save_history(path);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "readline")]
+pub use editor::Console;
+
+#[cfg(not(feature = "readline"))]
+pub use plain::Console;
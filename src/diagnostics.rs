@@ -0,0 +1,356 @@
+use crate::catalog::{self, Catalog, English};
+use crate::common::Error;
+use crate::line_index::{tab_column, LineIndex};
+use crate::stack_machine::{Crash, Program};
+
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const CYAN: &str = "\x1b[36m";
+const RESET: &str = "\x1b[0m";
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ColorChoice {
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    pub fn parse(value: &str) -> Option<ColorChoice> {
+        match value {
+            "auto" => Some(ColorChoice::Auto),
+            "always" => Some(ColorChoice::Always),
+            "never" => Some(ColorChoice::Never),
+            _ => None,
+        }
+    }
+
+    // `auto` colors terminals unless NO_COLOR is set (https://no-color.org).
+    pub fn enabled(self, is_terminal: bool, no_color: bool) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => is_terminal && !no_color,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+// Instructions shown on each side of the failing one in a crash dump.
+const CONTEXT: usize = 3;
+
+// The function `index` is in: the one that starts last at or before it.
+fn function_at(program: &Program, index: usize) -> &str {
+    program
+        .functions
+        .iter()
+        .filter(|(_, start)| **start <= index)
+        .max_by_key(|(name, start)| (**start, core::cmp::Reverse(*name)))
+        .map_or("?", |(name, _)| name.as_str())
+}
+
+// Turns errors into text for a terminal: a headline, and when the source is
+// at hand, the offending line with a caret under the column.
+pub struct Renderer {
+    pub color: bool,
+    // Columns are reported with tab stops this far apart, when the line is
+    // at hand. 1 counts a tab as one column, like any other character.
+    pub tab_width: usize,
+    // Where the messages come from, English unless `--messages` says
+    // otherwise.
+    pub catalog: Box<dyn Catalog>,
+}
+
+impl Renderer {
+    pub fn new(color: bool) -> Self {
+        Self {
+            color,
+            tab_width: 1,
+            catalog: Box::new(English),
+        }
+    }
+
+    pub fn with_tab_width(self, tab_width: usize) -> Self {
+        Self { tab_width, ..self }
+    }
+
+    pub fn with_catalog(self, catalog: impl Catalog + 'static) -> Self {
+        Self {
+            catalog: Box::new(catalog),
+            ..self
+        }
+    }
+
+    fn paint(&self, color: &str, text: &str) -> String {
+        if self.color {
+            format!("{}{}{}", color, text, RESET)
+        } else {
+            text.to_string()
+        }
+    }
+
+    pub fn render(
+        &self,
+        severity: Severity,
+        error: &Error,
+        source: Option<&str>,
+        path: Option<&str>,
+    ) -> String {
+        let errors = match error {
+            Error::Multiple(errors) => errors.iter().collect(),
+            error => vec![error],
+        };
+        let rendered: Vec<String> = errors
+            .into_iter()
+            .map(|e| self.render_one(severity, e, source, path))
+            .collect();
+        rendered.join("\n")
+    }
+
+    fn render_one(
+        &self,
+        severity: Severity,
+        error: &Error,
+        source: Option<&str>,
+        path: Option<&str>,
+    ) -> String {
+        let label = match severity {
+            Severity::Error => self.paint(RED, &format!("error[{}]", error.id())),
+            Severity::Warning => self.paint(YELLOW, "warning"),
+        };
+        let message = catalog::message(error, self.catalog.as_ref());
+        let mut out = format!("{}: {}\n", label, message);
+        let Some((line, col)) = error.position() else {
+            return out;
+        };
+        let text = source.and_then(|source| LineIndex::new(source).line_text(source, line));
+        let shown = text.map_or(col, |text| tab_column(text, col, self.tab_width));
+        let location = match path {
+            Some(path) => format!("{}:{}:{}", path, line, shown),
+            None => format!("{}:{}", line, shown),
+        };
+        out.push_str(&format!(" {} {}\n", self.paint(CYAN, "-->"), location));
+        let Some(text) = text else {
+            return out;
+        };
+        let number = line.to_string();
+        let gutter = " ".repeat(number.len());
+        // Keep tabs so the caret lines up however wide the terminal shows them.
+        let padding: String = text
+            .chars()
+            .take(col.saturating_sub(1))
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        out.push_str(&format!("{} {}\n", gutter, self.paint(CYAN, "|")));
+        out.push_str(&format!(
+            "{} {} {}\n",
+            self.paint(CYAN, &number),
+            self.paint(CYAN, "|"),
+            text
+        ));
+        out.push_str(&format!(
+            "{} {} {}{}\n",
+            gutter,
+            self.paint(CYAN, "|"),
+            padding,
+            self.paint(RED, "^")
+        ));
+        out
+    }
+
+    // The data stack, the calls the run was in, innermost first, and the
+    // instructions around the failing one, for `--dump-stack-on-error`.
+    pub fn crash(&self, program: &Program, crash: &Crash) -> String {
+        let at = |index: usize| {
+            program
+                .instructions
+                .get(index)
+                .map_or(String::new(), |i| format!("{}:{}", i.line, i.pos))
+        };
+        let mut out = format!(
+            "{} {}\n",
+            self.paint(CYAN, "data stack:"),
+            self.stack(&crash.stack)
+        );
+        out.push_str(&format!("{}\n", self.paint(CYAN, "return stack:")));
+        let frames = core::iter::once(crash.index).chain(crash.calls.iter().rev().copied());
+        for index in frames {
+            out.push_str(&format!(
+                "  {} at {}\n",
+                function_at(program, index),
+                at(index)
+            ));
+        }
+        out.push_str(&format!("{}\n", self.paint(CYAN, "instructions:")));
+        let first = crash.index.saturating_sub(CONTEXT);
+        let last = (crash.index + CONTEXT).min(program.instructions.len().saturating_sub(1));
+        let width = last.to_string().len();
+        for index in first..=last {
+            let Some(instruction) = program.instructions.get(index) else {
+                break;
+            };
+            let text = program.describe(&instruction.instruction_type);
+            let arrow = if index == crash.index {
+                self.paint(RED, "->")
+            } else {
+                "  ".to_string()
+            };
+            out.push_str(&format!(
+                "{} {:>width$}  {:<12} {}\n",
+                arrow,
+                index,
+                text,
+                self.paint(CYAN, &at(index)),
+                width = width
+            ));
+        }
+        out
+    }
+
+    pub fn stack(&self, values: &[i32]) -> String {
+        let values: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+        self.paint(CYAN, &format!("<{}> {}", values.len(), values.join(" ")))
+    }
+}
+
+#[cfg(test)]
+mod diagnostics_tests {
+    use super::*;
+
+    #[test]
+    fn plain_error_with_excerpt() {
+        let source = "fun main\n\t1 ^ print\nret\n";
+        let error = Error::UnknownToken {
+            word: "^".to_string(),
+            pos: 4,
+            line: 2,
+        };
+        assert_eq!(
+            Renderer::new(false).render(Severity::Error, &error, Some(source), Some("a.sm")),
+            "error[E0001]: unknown token `^`
+ --> a.sm:2:4
+  |
+2 | \t1 ^ print
+  | \t  ^
+"
+        );
+    }
+
+    #[test]
+    fn columns_follow_the_tab_width() {
+        let source = "fun main\n\t1 ^ print\nret\n";
+        let error = Error::UnknownToken {
+            word: "^".to_string(),
+            pos: 4,
+            line: 2,
+        };
+        let location = |renderer: Renderer, source| {
+            let text = renderer.render(Severity::Error, &error, source, Some("a.sm"));
+            text.lines().nth(1).unwrap().to_string()
+        };
+        assert_eq!(
+            location(Renderer::new(false), Some(source)),
+            " --> a.sm:2:4"
+        );
+        let four = || Renderer::new(false).with_tab_width(4);
+        assert_eq!(location(four(), Some(source)), " --> a.sm:2:7");
+        let eight = Renderer::new(false).with_tab_width(8);
+        assert_eq!(location(eight, Some(source)), " --> a.sm:2:11");
+        // Without the line there is nothing to expand.
+        assert_eq!(location(four(), None), " --> a.sm:2:4");
+        assert!(four()
+            .render(Severity::Error, &error, Some(source), None)
+            .ends_with("2 | \t1 ^ print\n  | \t  ^\n"));
+    }
+
+    #[test]
+    fn colored_warning() {
+        let error = Error::StaticCheck {
+            word: "f".to_string(),
+            pos: 1,
+            line: 1,
+            comment: "unused".to_string(),
+        };
+        assert_eq!(
+            Renderer::new(true).render(Severity::Warning, &error, Some("f"), None),
+            "\x1b[1;33mwarning\x1b[0m: unused
+ \x1b[36m-->\x1b[0m 1:1
+  \x1b[36m|\x1b[0m
+\x1b[36m1\x1b[0m \x1b[36m|\x1b[0m f
+  \x1b[36m|\x1b[0m \x1b[1;31m^\x1b[0m
+"
+        );
+    }
+
+    #[test]
+    fn messages_come_from_the_catalog() {
+        let table = crate::catalog::Table::parse("E1001 = division par zéro").unwrap();
+        let renderer = Renderer::new(false).with_catalog(table);
+        let error = Error::DivisionByZero { pos: 3, line: 1 };
+        assert_eq!(
+            renderer.render(Severity::Error, &error, None, None),
+            "error[E1001]: division par zéro\n --> 1:3\n"
+        );
+    }
+
+    #[test]
+    fn errors_without_a_position_or_source() {
+        let renderer = Renderer::new(false);
+        let multiple = Error::Multiple(vec![
+            Error::FunctionNotFound {
+                name: "main".to_string(),
+            },
+            Error::StackEmpty { pos: 3, line: 9 },
+        ]);
+        assert_eq!(
+            renderer.render(Severity::Error, &multiple, Some("1 +"), None),
+            "error[E0004]: function `main` not found\n\nerror[E1002]: stack is empty\n --> 9:3\n"
+        );
+    }
+
+    #[test]
+    fn crash_dump() {
+        use crate::parser::parse;
+        use crate::stack::VecStack;
+        use crate::stack_machine::StackMachine;
+        use crate::tokenizer::tokenize;
+
+        let source = "fun half 0 / ret\nfun main\n  4 half\nret";
+        let program = parse(tokenize(source).unwrap()).unwrap();
+        let mut machine = StackMachine::new(VecStack::new());
+        assert!(machine.call(&program, "main").is_err());
+        let crash = machine.take_crash().unwrap();
+        assert_eq!(
+            Renderer::new(false).crash(&program, &crash),
+            "data stack: <2> 4 0
+return stack:
+  half at 1:12
+  main at 3:5
+instructions:
+   0  0            1:10
+-> 1  /            1:12
+   2  ret          1:14
+   3  4            3:3
+   4  call half    3:5
+"
+        );
+    }
+
+    #[test]
+    fn stack_and_color_choice() {
+        assert_eq!(Renderer::new(false).stack(&[1, -2]), "<2> 1 -2");
+        assert_eq!(Renderer::new(true).stack(&[]), "\x1b[36m<0> \x1b[0m");
+        assert_eq!(ColorChoice::parse("always"), Some(ColorChoice::Always));
+        assert_eq!(ColorChoice::parse("sometimes"), None);
+        assert!(ColorChoice::Auto.enabled(true, false));
+        assert!(!ColorChoice::Auto.enabled(true, true));
+        assert!(!ColorChoice::Auto.enabled(false, false));
+        assert!(ColorChoice::Always.enabled(false, true));
+    }
+}
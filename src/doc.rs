@@ -0,0 +1,130 @@
+use crate::checker::infer_stack_effects;
+use crate::stack_machine::Program;
+
+struct Entry {
+    name: String,
+    effect: String,
+    doc: String,
+}
+
+// Public functions in the order they are defined.
+fn entries(program: &Program) -> Vec<Entry> {
+    let effects = infer_stack_effects(program);
+    let mut names: Vec<(&String, &usize)> = program
+        .functions
+        .iter()
+        .filter(|(name, _)| !program.private.contains(*name))
+        .collect();
+    names.sort_by_key(|(name, start)| (**start, *name));
+    names
+        .into_iter()
+        .map(|(name, _)| Entry {
+            name: name.clone(),
+            effect: effects
+                .get(name)
+                .map_or("( ? )".to_string(), |e| e.to_string()),
+            doc: program.docs.get(name).cloned().unwrap_or_default(),
+        })
+        .collect()
+}
+
+pub fn markdown(program: &Program, title: &str) -> String {
+    let mut out = format!("# {}\n", title);
+    for entry in entries(program) {
+        out.push_str(&format!("\n## `{}`\n\n`{}`\n", entry.name, entry.effect));
+        if !entry.doc.is_empty() {
+            out.push_str(&format!("\n{}\n", entry.doc));
+        }
+    }
+    out
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+pub fn html(program: &Program, title: &str) -> String {
+    let title = escape(title);
+    let mut out = format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>{}</title></head>\n<body>\n<h1>{}</h1>\n",
+        title, title
+    );
+    for entry in entries(program) {
+        out.push_str(&format!(
+            "<h2 id=\"{}\"><code>{}</code></h2>\n<p><code>{}</code></p>\n",
+            escape(&entry.name),
+            escape(&entry.name),
+            escape(&entry.effect)
+        ));
+        if !entry.doc.is_empty() {
+            out.push_str(&format!("<p>{}</p>\n", escape(&entry.doc)));
+        }
+    }
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+#[cfg(test)]
+mod doc_tests {
+    use super::*;
+    use crate::parser::parse;
+    use crate::tokenizer::tokenize;
+
+    const SOURCE: &str = "module math
+  ## Multiplies the top value by itself.
+  pub fun square dup * ret
+  fun helper ret
+  ## Sum of the squares.
+  ## Takes two values.
+  pub fun sum_sq square swap square + ret
+end
+# not a doc comment
+fun main 1 2 math.sum_sq print ret
+";
+
+    fn program() -> Program {
+        parse(tokenize(SOURCE).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn markdown_lists_public_functions() {
+        assert_eq!(
+            markdown(&program(), "math"),
+            "# math
+
+## `math.square`
+
+`( 1 -- 1 )`
+
+Multiplies the top value by itself.
+
+## `math.sum_sq`
+
+`( 2 -- 1 )`
+
+Sum of the squares.
+Takes two values.
+
+## `main`
+
+`( 0 -- 0 )`
+"
+        );
+    }
+
+    #[test]
+    fn html_is_escaped() {
+        let mut program = program();
+        program
+            .docs
+            .insert("main".to_string(), "Prints <sum>".to_string());
+        let html = html(&program, "a & b");
+        assert!(html.contains("<title>a &amp; b</title>"));
+        assert!(html.contains("<h2 id=\"math.square\"><code>math.square</code></h2>"));
+        assert!(html.contains("<p>Prints &lt;sum&gt;</p>"));
+        assert!(!html.contains("helper"));
+    }
+}
@@ -0,0 +1,160 @@
+// `rorth test --doc`: a `#=> 42` comment after the code on a line says what
+// that line prints. The program runs from `main` and every value a `print`
+// writes is put down to the line the `print` is on, so a line inside a loop
+// or a function called more than once lists everything it printed, in order:
+//
+//   fun main 3 while dup do dup print 1 - end pop ret   #=> 3 2 1
+//
+// Lines without a `#=>` may print what they like.
+use alloc::collections::BTreeMap;
+use alloc::rc::Rc;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::cell::RefCell;
+
+use crate::common::Error;
+use crate::stack::VecStack;
+use crate::stack_machine::{Program, StackMachine, VmConfig};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    pub line: usize,
+    pub expected: Vec<String>,
+    pub printed: Vec<String>,
+}
+
+impl Check {
+    pub fn passed(&self) -> bool {
+        self.expected == self.printed
+    }
+}
+
+#[derive(Debug)]
+pub struct Report {
+    // One for each `#=>` line, in order.
+    pub checks: Vec<Check>,
+    // How the run ended; lines after an error may have printed nothing.
+    pub result: Result<(), Error>,
+}
+
+// What each line with a `#=>` in `source` says it prints. A `#` inside a
+// string doesn't start a comment.
+pub fn expected(source: &str) -> Vec<(usize, Vec<String>)> {
+    let mut found = Vec::new();
+    for (i, text) in source.lines().enumerate() {
+        let mut in_string = false;
+        let comment = text.char_indices().find(|(_, c)| {
+            if *c == '"' {
+                in_string = !in_string;
+            }
+            *c == '#' && !in_string
+        });
+        let Some(rest) = comment.and_then(|(at, _)| text[at..].strip_prefix("#=>")) else {
+            continue;
+        };
+        found.push((i + 1, rest.split_whitespace().map(String::from).collect()));
+    }
+    found
+}
+
+// Runs `program`, whose source is `source`, and compares what each `#=>`
+// line printed with what it says. `line_of` gives the line of `source` an
+// instruction came from, by its index, or None for one from another file.
+pub fn run(
+    program: &Program,
+    source: &str,
+    config: VmConfig,
+    line_of: impl Fn(usize) -> Option<usize> + 'static,
+) -> Report {
+    let mut machine = StackMachine::with_config(VecStack::new(), config);
+    let pending: Rc<RefCell<Vec<String>>> = Rc::default();
+    let by_line: Rc<RefCell<BTreeMap<usize, Vec<String>>>> = Rc::default();
+    let written = pending.clone();
+    machine.set_output(move |text| written.borrow_mut().push(text.trim().to_string()));
+    let (printed, lines) = (pending.clone(), by_line.clone());
+    machine.set_trace(move |step| {
+        let mut printed = printed.borrow_mut();
+        if printed.is_empty() {
+            return;
+        }
+        if let Some(line) = line_of(step.index) {
+            lines
+                .borrow_mut()
+                .entry(line)
+                .or_default()
+                .append(&mut printed);
+        } else {
+            printed.clear();
+        }
+    });
+    let result = match machine.run_main(program).error {
+        Some(error) => Err(error),
+        None => Ok(()),
+    };
+    let mut by_line = by_line.take();
+    let checks = expected(source)
+        .into_iter()
+        .map(|(line, expected)| Check {
+            line,
+            expected,
+            printed: by_line.remove(&line).unwrap_or_default(),
+        })
+        .collect();
+    Report { checks, result }
+}
+
+#[cfg(test)]
+mod doctest_tests {
+    use super::*;
+    use crate::parser::parse;
+    use crate::tokenizer::tokenize;
+
+    fn report(source: &str) -> Report {
+        let program = parse(tokenize(source).unwrap()).unwrap();
+        let lines: Vec<usize> = program.instructions.iter().map(|i| i.line).collect();
+        run(&program, source, VmConfig::default(), move |index| {
+            lines.get(index).copied()
+        })
+    }
+
+    #[test]
+    fn finds_expectations_outside_strings() {
+        let source = "1 print #=> 1\n\"#=> no\" # not one\n  #=>  2   3 \n## #=> doc";
+        assert_eq!(
+            expected(source),
+            vec![
+                (1, vec!["1".to_string()]),
+                (3, vec!["2".to_string(), "3".to_string()]),
+            ]
+        );
+    }
+
+    #[test]
+    fn values_go_to_the_line_that_printed_them() {
+        let report = report(
+            "fun show dup print ret  #=> 3 2 1
+fun main
+  3 while dup do show 1 - end pop
+  255 hex print  #=> ff
+  7 print        #=> 8
+  4 print
+ret",
+        );
+        assert_eq!(report.result, Ok(()));
+        let outcomes: Vec<(usize, bool)> = report
+            .checks
+            .iter()
+            .map(|check| (check.line, check.passed()))
+            .collect();
+        assert_eq!(outcomes, vec![(1, true), (4, true), (5, false)]);
+        assert_eq!(report.checks[2].printed, vec!["7".to_string()]);
+    }
+
+    #[test]
+    fn lines_after_an_error_print_nothing() {
+        let report = report("fun main\n1 print #=> 1\n1 0 / print #=> 0\nret");
+        assert!(matches!(report.result, Err(Error::DivisionByZero { .. })));
+        assert!(report.checks[0].passed());
+        assert!(report.checks[1].printed.is_empty());
+    }
+}
@@ -0,0 +1,252 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use crate::common::Error;
+use crate::fixed;
+use crate::parser::InstructionType;
+use crate::stack::VecStack;
+use crate::stack_machine::{Program, StackMachine, Step, VmConfig};
+
+pub struct Explanation {
+    pub steps: Vec<String>,
+    pub result: Result<(), Error>,
+}
+
+// What an instruction did, given the stack before and after it ran.
+fn describe(
+    names: &BTreeMap<usize, String>,
+    instruction: &InstructionType,
+    before: &[i32],
+    after: &[i32],
+) -> String {
+    use InstructionType::*;
+    let top = |n: usize| before[before.len() - n];
+    let result = after.last().copied().unwrap_or_default();
+    match instruction {
+        Push(n) => format!("pushes {}", n),
+        Pop => format!("drops the top item ({})", top(1)),
+        Add => format!("adds {} and {} ({})", top(2), top(1), result),
+        Sub => format!("subtracts {} from {} ({})", top(1), top(2), result),
+        AddWrap | AddSat => format!("adds {} and {} ({})", top(2), top(1), result),
+        SubWrap | SubSat => format!("subtracts {} from {} ({})", top(1), top(2), result),
+        MulWrap | MulSat => format!("multiplies {} by {} ({})", top(2), top(1), result),
+        Mul => format!("multiplies {} by {} ({})", top(2), top(1), result),
+        Div => format!("divides {} by {} ({})", top(2), top(1), result),
+        DivMod => format!(
+            "divides {} by {} ({} remainder {})",
+            top(2),
+            top(1),
+            after[after.len() - 2],
+            result
+        ),
+        Print => format!("prints {}", top(1)),
+        Dup => format!("copies the top item ({}) to the top", top(1)),
+        Swap => format!("swaps the top two items ({} and {})", top(2), top(1)),
+        Rot => format!("moves the third item ({}) to the top", top(3)),
+        Over => format!("copies the second item ({}) to the top", top(2)),
+        Nip => format!("drops the second item ({})", top(2)),
+        Rand => format!("pushes a random number ({})", result),
+        Time => format!("pushes the time in milliseconds ({})", result),
+        Env(_) => format!("pushes an environment variable ({})", result),
+        While(_) if top(1) == 0 => "top is 0, so the loop is skipped".to_string(),
+        While(_) => format!("top is {}, not 0, so the loop runs", top(1)),
+        EndWhile(_) if top(1) == 0 => "top is 0, so the loop ends".to_string(),
+        EndWhile(_) => format!("top is {}, not 0, so the loop runs again", top(1)),
+        Loop(_) => "starts the loop's condition".to_string(),
+        Do(_) if top(1) == 0 => "takes 0, so the loop ends".to_string(),
+        Do(_) => format!("takes {}, not 0, so the loop runs", top(1)),
+        EndDo(_) => "goes back to the loop's condition".to_string(),
+        If(_) if top(1) == 0 => "top is 0, so the `else` branch runs".to_string(),
+        If(_) => format!("top is {}, not 0, so the `if` branch runs", top(1)),
+        Else(_) => "skips the `else` branch".to_string(),
+        EndIf => "ends the `if`".to_string(),
+        Try(_) => "starts catching errors".to_string(),
+        Recover(_) => "nothing failed, so the `recover` branch is skipped".to_string(),
+        EndTry => "ends the `try`".to_string(),
+        Call(start) => format!("calls `{}`", names.get(start).map_or("?", |n| n.as_str())),
+        Address(start) => format!(
+            "pushes where `{}` starts ({})",
+            names.get(start).map_or("?", |n| n.as_str()),
+            start
+        ),
+        // Traced as the `call` it makes.
+        Invoke => "calls the function on top".to_string(),
+        Native(_) => format!("runs `{}` from a plugin", instruction),
+        // Refused before it could run.
+        Asm(_) => "is inline assembly for a native build".to_string(),
+        Ret => "returns".to_string(),
+        Hex => "prints numbers in hexadecimal from now on".to_string(),
+        Bin => "prints numbers in binary from now on".to_string(),
+        Dec => "prints numbers in decimal from now on".to_string(),
+        PrintPadded => format!("prints {} padded to width {}", top(2), top(1)),
+        Eprint => format!("prints {} to stderr", top(1)),
+        FxMul => format!(
+            "multiplies {} by {} ({})",
+            fixed::format(top(2)),
+            fixed::format(top(1)),
+            fixed::format(result)
+        ),
+        FxDiv => format!(
+            "divides {} by {} ({})",
+            fixed::format(top(2)),
+            fixed::format(top(1)),
+            fixed::format(result)
+        ),
+        FxPrint => format!("prints {}", fixed::format(top(1))),
+        IntToFx => format!("turns {} into fixed point ({})", top(1), result),
+        FxToInt => format!(
+            "turns {} into an integer ({})",
+            fixed::format(top(1)),
+            result
+        ),
+        Alloc => format!("allocates {} cells at {}", top(1), result),
+        Fetch => format!("fetches {} from {}", result, top(1)),
+        Store => format!("stores {} at {}", top(2), top(1)),
+        GcStats => format!(
+            "frees unreachable memory, leaving {} blocks of {} cells in all",
+            after[after.len() - 2],
+            result
+        ),
+        Syscall0 | Syscall1 | Syscall2 | Syscall3 => {
+            format!("makes system call {} ({})", top(1), result)
+        }
+    }
+}
+
+fn stack(values: &[i32]) -> String {
+    let values: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+    format!("[{}]", values.join(" "))
+}
+
+fn line(names: &BTreeMap<usize, String>, step: &Step, what: &str) -> String {
+    let word = match step.instruction.instruction_type {
+        InstructionType::Call(start) => names.get(&start).cloned().unwrap_or_default(),
+        InstructionType::Address(start) => {
+            format!("&{}", names.get(&start).map_or("", |n| n.as_str()))
+        }
+        kind => kind.to_string(),
+    };
+    format!(
+        "{}:{} {}: {} — stack: {}",
+        step.instruction.line,
+        step.instruction.pos,
+        word,
+        what,
+        stack(step.stack)
+    )
+}
+
+// Runs `main`, describing every instruction as it goes.
+pub fn explain(program: &Program, config: VmConfig) -> Explanation {
+    let mut machine = StackMachine::with_config(VecStack::new(), config);
+    let steps = Rc::new(RefCell::new(vec![]));
+    let seen = steps.clone();
+    let names = Rc::new(program.symbols.clone());
+    // The stack as the next instruction finds it. A failed one sets it too,
+    // since a `try` or `on_error` that takes over changes the stack.
+    let before = Rc::new(RefCell::new(vec![]));
+    let (failures, failed, symbols) = (steps.clone(), before.clone(), names.clone());
+    machine.set_trace(move |step| {
+        let kind = &step.instruction.instruction_type;
+        let mut before = before.borrow_mut();
+        let what = describe(&names, kind, &before, step.stack);
+        seen.borrow_mut().push(line(&names, step, &what));
+        *before = step.stack.to_vec();
+    });
+    machine.set_failure_trace(move |step, error| {
+        let what = format!("fails: {}", error.message());
+        failures.borrow_mut().push(line(&symbols, step, &what));
+        *failed.borrow_mut() = step.stack.to_vec();
+    });
+    let result = machine.call(program, "main");
+    Explanation {
+        steps: steps.take(),
+        result,
+    }
+}
+
+#[cfg(test)]
+mod explain_tests {
+    use super::*;
+    use crate::parser::parse;
+    use crate::tokenizer::tokenize;
+
+    fn steps(source: &str) -> Vec<String> {
+        let program = parse(tokenize(source).unwrap()).unwrap();
+        explain(&program, VmConfig::default()).steps
+    }
+
+    #[test]
+    fn stack_words() {
+        assert_eq!(
+            steps("fun main 1 3 over swap - print ret"),
+            vec![
+                "1:10 1: pushes 1 — stack: [1]",
+                "1:12 3: pushes 3 — stack: [1 3]",
+                "1:14 over: copies the second item (1) to the top — stack: [1 3 1]",
+                "1:19 swap: swaps the top two items (3 and 1) — stack: [1 1 3]",
+                "1:24 -: subtracts 3 from 1 (-2) — stack: [1 -2]",
+                "1:26 print: prints -2 — stack: [1]",
+                "1:32 ret: returns — stack: [1]",
+            ]
+        );
+    }
+
+    #[test]
+    fn control_flow_and_calls() {
+        let steps = steps("fun down 1 - ret\nfun main 1 while down end ret");
+        assert_eq!(
+            steps[1..6],
+            [
+                "2:12 while: top is 1, not 0, so the loop runs — stack: [1]",
+                "2:18 down: calls `down` — stack: [1]",
+                "1:10 1: pushes 1 — stack: [1 1]",
+                "1:12 -: subtracts 1 from 1 (0) — stack: [0]",
+                "1:14 ret: returns — stack: [0]",
+            ]
+        );
+        assert_eq!(
+            steps[6],
+            "2:23 end: top is 0, so the loop ends — stack: [0]"
+        );
+    }
+
+    #[test]
+    fn errors_stop_the_explanation() {
+        let program = parse(tokenize("fun main 1 0 / ret").unwrap()).unwrap();
+        let explanation = explain(&program, VmConfig::default());
+        assert_eq!(
+            explanation.steps,
+            vec![
+                "1:10 1: pushes 1 — stack: [1]",
+                "1:12 0: pushes 0 — stack: [1 0]",
+                "1:14 /: fails: division by zero — stack: [1 0]",
+            ]
+        );
+        assert_eq!(
+            explanation.result,
+            Err(Error::DivisionByZero { pos: 14, line: 1 })
+        );
+    }
+
+    #[test]
+    fn recovering_starts_from_the_handlers_stack() {
+        assert_eq!(
+            steps("fun main 7 8 try 0 / recover print end print ret")[4..8],
+            [
+                "1:20 /: fails: division by zero — stack: [7 8 2]",
+                "1:30 print: prints 2 — stack: [7 8]",
+                "1:36 end: ends the `try` — stack: [7 8]",
+                "1:40 print: prints 8 — stack: [7]",
+            ]
+        );
+        assert_eq!(
+            steps("fun main try pop recover dup print print end ret")[1..3],
+            [
+                "1:14 pop: fails: stack is empty — stack: [1]",
+                "1:26 dup: copies the top item (1) to the top — stack: [1 1]",
+            ]
+        );
+    }
+}
@@ -0,0 +1,120 @@
+// `rorth fix`: rewrites the old spellings of renamed words, see
+// `words::RENAMED`, so a program written for an earlier release parses
+// again. Only whole identifiers are touched, never strings or comments, and
+// a name the file defines itself is left alone, since it is then not the
+// old word but the program's own.
+use alloc::collections::BTreeSet;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use crate::common::Error;
+use crate::line_index::LineIndex;
+use crate::tokenizer::{tokenize, TokenType};
+use crate::words::Renamed;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub line: usize,
+    pub pos: usize,
+    pub old: String,
+    pub new: &'static str,
+    pub since: &'static str,
+}
+
+// `source` with every old spelling in `renamed` replaced, and where.
+pub fn rewrite(source: &str, renamed: &[Renamed]) -> Result<(String, Vec<Change>), Error> {
+    let tokens = tokenize(source)?;
+    let defined: BTreeSet<&str> = tokens
+        .windows(2)
+        .filter(|pair| {
+            matches!(
+                pair[0].token_type,
+                TokenType::Fun | TokenType::Struct | TokenType::Enum | TokenType::Module
+            )
+        })
+        .filter_map(|pair| match &pair[1].token_type {
+            TokenType::Identifier(name) => Some(name.as_ref()),
+            _ => None,
+        })
+        .collect();
+    let index = LineIndex::new(source);
+    let mut fixed = String::with_capacity(source.len());
+    let mut copied = 0;
+    let mut changes = Vec::new();
+    for token in &tokens {
+        let TokenType::Identifier(name) = &token.token_type else {
+            continue;
+        };
+        if defined.contains(name.as_ref()) {
+            continue;
+        }
+        let Some(rename) = renamed.iter().find(|r| r.old == name.as_ref()) else {
+            continue;
+        };
+        let start = index
+            .offset(source, token.line, token.pos)
+            .expect("tokens are positioned inside their source");
+        fixed.push_str(&source[copied..start]);
+        fixed.push_str(rename.new);
+        copied = start + name.len();
+        changes.push(Change {
+            line: token.line,
+            pos: token.pos,
+            old: name.to_string(),
+            new: rename.new,
+            since: rename.since,
+        });
+    }
+    fixed.push_str(&source[copied..]);
+    Ok((fixed, changes))
+}
+
+#[cfg(test)]
+mod fix_tests {
+    use super::*;
+
+    const RENAMED: &[Renamed] = &[
+        Renamed {
+            old: "emit",
+            new: "print",
+            since: "0.2.0",
+        },
+        Renamed {
+            old: "drop",
+            new: "pop",
+            since: "0.2.0",
+        },
+    ];
+
+    #[test]
+    fn rewrites_old_spellings_in_place() {
+        let source = "fun main\n  1 emit  ## emit it\n  2 3 drop \"drop\" drop emit ret\n";
+        let (fixed, changes) = rewrite(source, RENAMED).unwrap();
+        assert_eq!(
+            fixed,
+            "fun main\n  1 print  ## emit it\n  2 3 pop \"drop\" pop print ret\n"
+        );
+        let places: Vec<(usize, usize, &str)> = changes
+            .iter()
+            .map(|c| (c.line, c.pos, c.old.as_str()))
+            .collect();
+        assert_eq!(
+            places,
+            vec![
+                (2, 5, "emit"),
+                (3, 7, "drop"),
+                (3, 19, "drop"),
+                (3, 24, "emit")
+            ]
+        );
+    }
+
+    #[test]
+    fn names_the_program_defines_are_its_own() {
+        let source = "fun drop pop ret\nfun main 1 2 drop emit ret";
+        let (fixed, changes) = rewrite(source, RENAMED).unwrap();
+        assert_eq!(fixed, "fun drop pop ret\nfun main 1 2 drop print ret");
+        assert_eq!(changes.len(), 1);
+        assert_eq!(rewrite("1 print", RENAMED).unwrap().1, vec![]);
+    }
+}
@@ -0,0 +1,89 @@
+// Q16.16 fixed-point numbers for the `fx` words: a value is an ordinary i32
+// holding the number times 65536, so `+`, `-` and the stack words work on
+// them unchanged and no float is ever involved.
+use alloc::format;
+use alloc::string::String;
+
+pub const FRACTION_BITS: u32 = 16;
+pub const ONE: i32 = 1 << FRACTION_BITS;
+// Digits `fx.` prints after the point, before trailing zeros are dropped.
+const DECIMALS: u32 = 5;
+
+// None when it doesn't fit.
+pub fn from_int(n: i32) -> Option<i32> {
+    n.checked_mul(ONE)
+}
+
+// Rounds toward zero, like `/`.
+pub fn to_int(x: i32) -> i32 {
+    x / ONE
+}
+
+// Rounds toward negative infinity. None when the product doesn't fit.
+pub fn mul(a: i32, b: i32) -> Option<i32> {
+    i32::try_from((a as i64 * b as i64) >> FRACTION_BITS).ok()
+}
+
+// Rounds toward zero. None when the quotient doesn't fit or `b` is 0.
+pub fn div(a: i32, b: i32) -> Option<i32> {
+    if b == 0 {
+        return None;
+    }
+    i32::try_from(((a as i64) << FRACTION_BITS) / b as i64).ok()
+}
+
+// Decimal, rounded to five places with trailing zeros dropped, so 1.5 is
+// "1.5" and 2 is "2".
+pub fn format(x: i32) -> String {
+    let sign = if x < 0 { "-" } else { "" };
+    let magnitude = x.unsigned_abs() as u64;
+    let scale = 10u64.pow(DECIMALS);
+    let whole = magnitude >> FRACTION_BITS;
+    let fraction = magnitude & (ONE as u64 - 1);
+    // The largest fraction, 65535/65536, still rounds down to .99998.
+    let decimals = (fraction * scale + (ONE as u64 / 2)) >> FRACTION_BITS;
+    if decimals == 0 {
+        return format!("{}{}", sign, whole);
+    }
+    let digits = format!("{:0width$}", decimals, width = DECIMALS as usize);
+    format!("{}{}.{}", sign, whole, digits.trim_end_matches('0'))
+}
+
+#[cfg(test)]
+mod fixed_tests {
+    use super::*;
+
+    #[test]
+    fn converts_integers() {
+        assert_eq!(from_int(3), Some(3 * 65536));
+        assert_eq!(from_int(-2), Some(-131072));
+        assert_eq!(from_int(32768), None);
+        assert_eq!(to_int(from_int(-7).unwrap()), -7);
+        assert_eq!(to_int(ONE + ONE / 2), 1);
+        assert_eq!(to_int(-(ONE + ONE / 2)), -1);
+    }
+
+    #[test]
+    fn multiplies_and_divides() {
+        let half = ONE / 2;
+        assert_eq!(mul(3 * ONE, half), Some(ONE + half));
+        assert_eq!(mul(-3 * ONE, half), Some(-(ONE + half)));
+        assert_eq!(div(ONE, 4 * ONE), Some(ONE / 4));
+        assert_eq!(div(-ONE, 3 * ONE), Some(-21845));
+        assert_eq!(div(ONE, 0), None);
+        assert_eq!(mul(30000 * ONE, 2 * ONE), None);
+        assert_eq!(div(30000 * ONE, ONE / 2), None);
+    }
+
+    #[test]
+    fn formats_decimals() {
+        assert_eq!(format(0), "0");
+        assert_eq!(format(2 * ONE), "2");
+        assert_eq!(format(ONE + ONE / 2), "1.5");
+        assert_eq!(format(-ONE / 4), "-0.25");
+        assert_eq!(format(div(ONE, 3 * ONE).unwrap()), "0.33333");
+        assert_eq!(format(ONE - 1), "0.99998");
+        assert_eq!(format(1), "0.00002");
+        assert_eq!(format(i32::MIN), "-32768");
+    }
+}
@@ -0,0 +1,193 @@
+// A run of `main` as a Chrome trace: an event each time a function is
+// entered or left, timed in steps so the same program always gives the same
+// trace. chrome://tracing, Perfetto and speedscope all open it and draw the
+// calls as a flame graph.
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use crate::common::Error;
+use crate::json;
+use crate::parser::InstructionType;
+use crate::stack::VecStack;
+use crate::stack_machine::{Program, StackMachine, VmConfig};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    pub name: String,
+    pub enter: bool,
+    // Steps run before it happened.
+    pub step: usize,
+}
+
+pub struct Profile {
+    pub events: Vec<Event>,
+    pub printed: Vec<String>,
+    pub result: Result<(), Error>,
+}
+
+// Functions still running, innermost last, by the index they start at.
+#[derive(Default)]
+struct Calls {
+    names: BTreeMap<usize, String>,
+    open: Vec<usize>,
+    events: Vec<Event>,
+}
+
+impl Calls {
+    fn enter(&mut self, start: usize, step: usize) {
+        let name = self.names.get(&start).cloned();
+        self.open.push(start);
+        self.events.push(Event {
+            name: name.unwrap_or_else(|| format!("@{}", start)),
+            enter: true,
+            step,
+        });
+    }
+
+    fn leave(&mut self, step: usize) {
+        let Some(start) = self.open.pop() else {
+            return;
+        };
+        let name = self.names.get(&start).cloned();
+        self.events.push(Event {
+            name: name.unwrap_or_else(|| format!("@{}", start)),
+            enter: false,
+            step,
+        });
+    }
+
+    // A `recover` jumps out of every call its `try` made; those were left
+    // without a `ret`.
+    fn unwind_to(&mut self, index: usize, step: usize) {
+        let Some((&start, _)) = self.names.range(..=index).next_back() else {
+            return;
+        };
+        if self.open.contains(&start) {
+            while self.open.last() != Some(&start) {
+                self.leave(step);
+            }
+        }
+    }
+}
+
+pub fn record(program: &Program, config: VmConfig) -> Profile {
+    let entry = config.entry().to_string();
+    let mut machine = StackMachine::with_config(VecStack::new(), config);
+    let calls = Rc::new(RefCell::new(Calls {
+        names: program.symbols.clone(),
+        ..Calls::default()
+    }));
+    if let Some(main) = program.functions.get(&entry) {
+        calls.borrow_mut().enter(*main, 0);
+    }
+    let seen = calls.clone();
+    machine.set_trace(move |step| {
+        let mut calls = seen.borrow_mut();
+        match step.instruction.instruction_type {
+            InstructionType::Call(start) => calls.enter(start, step.steps),
+            InstructionType::Ret => calls.leave(step.steps),
+            _ => calls.unwind_to(step.index, step.steps),
+        }
+    });
+    let result = machine.call(program, &entry);
+    // Whatever a failed run left open ends where it stopped.
+    let steps = machine.steps;
+    let mut calls = calls.take();
+    while !calls.open.is_empty() {
+        calls.leave(steps);
+    }
+    Profile {
+        events: calls.events,
+        printed: machine.take_output(),
+        result,
+    }
+}
+
+// The trace event format, with one step to a microsecond.
+pub fn chrome_json(events: &[Event]) -> String {
+    let events = events.iter().map(|event| {
+        json::object(&[
+            ("name", json::string(&event.name)),
+            ("ph", json::string(if event.enter { "B" } else { "E" })),
+            ("ts", event.step.to_string()),
+            ("pid", "1".to_string()),
+            ("tid", "1".to_string()),
+        ])
+    });
+    json::object(&[("traceEvents", json::array(events))])
+}
+
+#[cfg(test)]
+mod flame_tests {
+    use super::*;
+    use crate::parser::parse;
+    use crate::tokenizer::tokenize;
+
+    fn events(source: &str) -> Vec<(String, bool, usize)> {
+        let program = parse(tokenize(source).unwrap()).unwrap();
+        let profile = record(&program, VmConfig::default());
+        profile
+            .events
+            .into_iter()
+            .map(|event| (event.name, event.enter, event.step))
+            .collect()
+    }
+
+    fn pairs(events: &[(&str, bool, usize)]) -> Vec<(String, bool, usize)> {
+        events
+            .iter()
+            .map(|(name, enter, step)| (name.to_string(), *enter, *step))
+            .collect()
+    }
+
+    #[test]
+    fn calls_are_entered_and_left() {
+        assert_eq!(
+            events("fun sq dup * ret\nfun main 2 sq sq print ret"),
+            pairs(&[
+                ("main", true, 0),
+                ("sq", true, 2),
+                ("sq", false, 5),
+                ("sq", true, 6),
+                ("sq", false, 9),
+                ("main", false, 11),
+            ])
+        );
+    }
+
+    #[test]
+    fn recovered_and_failed_calls_are_closed() {
+        assert_eq!(
+            events("fun bad 1 0 / ret\nfun main try bad recover 0 end bad ret"),
+            pairs(&[
+                ("main", true, 0),
+                ("bad", true, 2),
+                ("bad", false, 6),
+                ("bad", true, 8),
+                ("bad", false, 11),
+                ("main", false, 11),
+            ])
+        );
+    }
+
+    #[test]
+    fn writes_trace_events() {
+        let events = [
+            Event {
+                name: "main".to_string(),
+                enter: true,
+                step: 0,
+            },
+            Event {
+                name: "main".to_string(),
+                enter: false,
+                step: 3,
+            },
+        ];
+        assert_eq!(
+            chrome_json(&events),
+            r#"{"traceEvents":[{"name":"main","ph":"B","ts":0,"pid":1,"tid":1},{"name":"main","ph":"E","ts":3,"pid":1,"tid":1}]}"#
+        );
+    }
+}
@@ -0,0 +1,360 @@
+// Random program generator for property tests. Generated programs are stack
+// safe by construction: the generator tracks the range of every value on the
+// stack, so it also never divides by zero or overflows an i32.
+use crate::rng::Rng;
+use crate::tokenizer::TokenType;
+
+#[derive(Debug, Clone, Copy)]
+struct Range {
+    lo: i64,
+    hi: i64,
+}
+
+impl Range {
+    fn exact(n: i64) -> Self {
+        Self { lo: n, hi: n }
+    }
+
+    fn hull(values: [i64; 4]) -> Self {
+        Self {
+            lo: *values.iter().min().unwrap(),
+            hi: *values.iter().max().unwrap(),
+        }
+    }
+
+    fn fits(&self) -> bool {
+        self.lo >= i32::MIN as i64 && self.hi <= i32::MAX as i64
+    }
+
+    fn contains_zero(&self) -> bool {
+        self.lo <= 0 && 0 <= self.hi
+    }
+}
+
+pub struct Options {
+    pub max_len: usize,
+    pub max_nesting: usize,
+    pub functions: usize,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            max_len: 60,
+            max_nesting: 2,
+            functions: 2,
+        }
+    }
+}
+
+struct Generator<'a> {
+    rng: &'a mut Rng,
+    options: &'a Options,
+    out: Vec<TokenType<'static>>,
+    functions: Vec<(String, Vec<Range>)>,
+}
+
+impl Generator<'_> {
+    fn below(&mut self, n: usize) -> usize {
+        (self.rng.next_u64() % n as u64) as usize
+    }
+
+    fn budget_left(&self) -> bool {
+        self.out.len() < self.options.max_len
+    }
+
+    fn arithmetic(&mut self, stack: &mut Vec<Range>, token: TokenType<'static>) -> bool {
+        let (a, b) = (stack[stack.len() - 1], stack[stack.len() - 2]);
+        let result = match token {
+            TokenType::Add => Range {
+                lo: b.lo + a.lo,
+                hi: b.hi + a.hi,
+            },
+            TokenType::Sub => Range {
+                lo: b.lo - a.hi,
+                hi: b.hi - a.lo,
+            },
+            TokenType::Mul => Range::hull([b.lo * a.lo, b.lo * a.hi, b.hi * a.lo, b.hi * a.hi]),
+            TokenType::Div if !a.contains_zero() => {
+                Range::hull([b.lo / a.lo, b.lo / a.hi, b.hi / a.lo, b.hi / a.hi])
+            }
+            _ => return false,
+        };
+        if !result.fits() {
+            return false;
+        }
+        stack.pop();
+        stack.pop();
+        stack.push(result);
+        self.out.push(token);
+        true
+    }
+
+    fn step(&mut self, stack: &mut Vec<Range>, floor: usize, nesting: usize) {
+        let available = stack.len() - floor;
+        match self.below(16) {
+            0..=3 => {
+                let n = self.below(100) as i32;
+                stack.push(Range::exact(n as i64));
+                self.out.push(TokenType::Num(n));
+            }
+            4 if available >= 2 => {
+                self.arithmetic(stack, TokenType::Add);
+            }
+            5 if available >= 2 => {
+                self.arithmetic(stack, TokenType::Sub);
+            }
+            6 if available >= 2 => {
+                self.arithmetic(stack, TokenType::Mul);
+            }
+            7 if available >= 2 => {
+                self.arithmetic(stack, TokenType::Div);
+            }
+            8 if available >= 1 => {
+                stack.pop();
+                self.out.push(TokenType::Print);
+            }
+            9 if available >= 1 => {
+                let top = *stack.last().unwrap();
+                stack.push(top);
+                self.out.push(TokenType::Dup);
+            }
+            10 if available >= 2 => {
+                let len = stack.len();
+                let token = match self.below(3) {
+                    0 => {
+                        stack.swap(len - 1, len - 2);
+                        TokenType::Swap
+                    }
+                    1 => {
+                        stack.push(stack[len - 2]);
+                        TokenType::Over
+                    }
+                    _ => {
+                        stack.remove(len - 2);
+                        TokenType::Nip
+                    }
+                };
+                self.out.push(token);
+            }
+            11 if available >= 3 => {
+                let c = stack.remove(stack.len() - 3);
+                stack.push(c);
+                self.out.push(TokenType::Rot);
+            }
+            12 if available >= 1 && nesting < self.options.max_nesting => {
+                self.out.push(TokenType::If);
+                self.neutral_block(stack, nesting + 1);
+                self.out.push(TokenType::Else);
+                self.neutral_block(stack, nesting + 1);
+                self.out.push(TokenType::End);
+            }
+            13 if nesting < self.options.max_nesting => {
+                let count = 1 + self.below(3) as i32;
+                self.out.push(TokenType::Num(count));
+                stack.push(Range {
+                    lo: 1,
+                    hi: count as i64,
+                });
+                self.out.push(TokenType::While);
+                // Half the time as `while dup do … end`, which runs the same.
+                if self.below(2) == 0 {
+                    self.out.push(TokenType::Dup);
+                    self.out.push(TokenType::Do);
+                }
+                self.neutral_block(stack, nesting + 1);
+                self.out.push(TokenType::Num(1));
+                self.out.push(TokenType::Sub);
+                self.out.push(TokenType::End);
+                self.out.push(TokenType::Pop);
+                stack.pop();
+            }
+            14 if !self.functions.is_empty() => {
+                let idx = self.below(self.functions.len());
+                let (name, outputs) = self.functions[idx].clone();
+                stack.extend(outputs);
+                self.out.push(TokenType::Identifier(name.into()));
+            }
+            15 if available >= 1 => {
+                stack.pop();
+                self.out.push(TokenType::Pop);
+            }
+            _ => {}
+        }
+    }
+
+    fn block(&mut self, stack: &mut Vec<Range>, floor: usize, nesting: usize) {
+        let steps = 1 + self.below(8);
+        for _ in 0..steps {
+            if !self.budget_left() {
+                break;
+            }
+            self.step(stack, floor, nesting);
+        }
+    }
+
+    // Leaves the stack exactly as it found it and never touches what was
+    // already there.
+    fn neutral_block(&mut self, stack: &mut Vec<Range>, nesting: usize) {
+        let floor = stack.len();
+        self.block(stack, floor, nesting);
+        while stack.len() > floor {
+            stack.pop();
+            self.out.push(TokenType::Pop);
+        }
+    }
+
+    fn function(&mut self, name: &str) -> Vec<Range> {
+        self.out.push(TokenType::Fun);
+        self.out
+            .push(TokenType::Identifier(name.to_string().into()));
+        let mut stack = vec![];
+        self.block(&mut stack, 0, 0);
+        self.out.push(TokenType::Ret);
+        stack
+    }
+}
+
+pub fn generate(rng: &mut Rng, options: &Options) -> Vec<TokenType<'static>> {
+    let mut generator = Generator {
+        rng,
+        options,
+        out: vec![],
+        functions: vec![],
+    };
+    for i in 0..options.functions {
+        let name = format!("f{}", i);
+        let outputs = generator.function(&name);
+        generator.functions.push((name, outputs));
+    }
+    generator.function("main");
+    generator.out
+}
+
+// Straight-line code built from stack words without any safety guarantees,
+// for checking the checker. Leaves out `*` and `/` so that programs the
+// checker accepts cannot panic on overflow or division by zero.
+pub fn random_straight_line(rng: &mut Rng, len: usize) -> Vec<TokenType<'static>> {
+    (0..len)
+        .map(|_| match rng.next_u64() % 10 {
+            0 | 1 => TokenType::Num((rng.next_u64() % 10) as i32),
+            2 => TokenType::Add,
+            3 => TokenType::Sub,
+            4 => TokenType::Print,
+            5 => TokenType::Pop,
+            6 => TokenType::Dup,
+            7 => TokenType::Swap,
+            8 => TokenType::Over,
+            _ => match rng.next_u64() % 2 {
+                0 => TokenType::Rot,
+                _ => TokenType::Nip,
+            },
+        })
+        .collect()
+}
+
+pub fn to_source(tokens: &[TokenType]) -> String {
+    tokens
+        .iter()
+        .map(|t| t.to_string())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod genprog_tests {
+    use super::*;
+    use crate::checker::check_stack_safety;
+    use crate::common::Error;
+    use crate::parser::{parse, InstructionType};
+    use crate::stack::VecStack;
+    use crate::stack_machine::{StackMachine, VmConfig};
+    use crate::tokenizer::{tokenize, Token};
+
+    const CASES: u64 = 300;
+
+    fn with_positions(tokens: Vec<TokenType<'static>>) -> Vec<Token<'static>> {
+        tokens
+            .into_iter()
+            .enumerate()
+            .map(|(pos, token_type)| Token {
+                token_type,
+                pos: pos + 1,
+                line: 1,
+            })
+            .collect()
+    }
+
+    fn instruction_types(tokens: Vec<Token>) -> Vec<InstructionType> {
+        parse(tokens)
+            .unwrap()
+            .instructions
+            .into_iter()
+            .map(|i| i.instruction_type)
+            .collect()
+    }
+
+    fn machine() -> StackMachine<VecStack<i32>> {
+        let config = VmConfig {
+            max_steps: Some(100_000),
+            ..VmConfig::default().deterministic()
+        };
+        StackMachine::with_config(VecStack::new(), config)
+    }
+
+    #[test]
+    fn generated_programs_run_without_errors() {
+        for seed in 0..CASES {
+            let tokens = generate(&mut Rng::new(seed), &Options::default());
+            let source = to_source(&tokens);
+            let program = parse(with_positions(tokens)).unwrap();
+            let valid = crate::checker::validate_program(&program);
+            assert_eq!(valid, Ok(()), "seed {}\n{}", seed, source);
+            let result = machine().execute(program);
+            assert!(result.is_ok(), "seed {}: {:?}\n{}", seed, result, source);
+        }
+    }
+
+    #[test]
+    fn display_then_tokenize_round_trips() {
+        for seed in 0..CASES {
+            let tokens = generate(&mut Rng::new(seed), &Options::default());
+            let source = to_source(&tokens);
+            let reparsed: Vec<TokenType> = tokenize(&source)
+                .unwrap()
+                .into_iter()
+                .map(|t| t.token_type)
+                .collect();
+            assert_eq!(reparsed, tokens, "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn parse_display_parse_is_stable() {
+        for seed in 0..CASES {
+            let tokens = generate(&mut Rng::new(seed), &Options::default());
+            let source = to_source(&tokens);
+            let direct = instruction_types(with_positions(tokens));
+            let reparsed = instruction_types(tokenize(&source).unwrap());
+            assert_eq!(direct, reparsed, "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn checker_accepted_programs_do_not_underflow() {
+        for seed in 0..CASES {
+            let mut rng = Rng::new(seed);
+            let tokens = random_straight_line(&mut rng, 12);
+            let source = to_source(&tokens);
+            let program = parse(with_positions(tokens)).unwrap();
+            let checked = check_stack_safety(&program.instructions);
+            let executed = machine().execute_iter(program.instructions);
+            if checked.is_ok() {
+                assert!(executed.is_ok(), "seed {}: {}", seed, source);
+            }
+            if let Err(Error::StackEmpty { .. }) = executed {
+                assert!(checked.is_err(), "seed {}: {}", seed, source);
+            }
+        }
+    }
+}
@@ -0,0 +1,342 @@
+// `rorth grammar`: the language as the tokenizer and parser accept it, for
+// editor plugins and documentation. The words and keywords come from
+// `words::BUILTINS` and the loaded plugin packs; the rules that put them
+// together are written out here, and a test checks they name every keyword
+// in the table.
+use crate::json;
+use crate::plugin;
+use crate::words::BUILTINS;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Ebnf,
+    Json,
+    // A TextMate grammar, which VS Code and most editors highlight with.
+    Textmate,
+    // The `grammar.js` of a tree-sitter parser, to start one from.
+    TreeSitter,
+}
+
+impl Format {
+    pub fn parse(value: &str) -> Option<Format> {
+        match value {
+            "ebnf" => Some(Format::Ebnf),
+            "json" => Some(Format::Json),
+            "textmate" => Some(Format::Textmate),
+            "tree-sitter" => Some(Format::TreeSitter),
+            _ => None,
+        }
+    }
+}
+
+// How words are put together. `word` and `keyword` are filled in from the
+// table.
+const RULES: &[(&str, &str)] = &[
+    ("program", "{ item }"),
+    (
+        "item",
+        "[ doc ] [ \"pub\" ] \"fun\" name { statement } \"ret\"
+     | \"module\" name { item } \"end\"
+     | \"import\" name
+     | \"include\" string
+     | \"struct\" name { name } \"end\"
+     | \"enum\" name { name } \"end\"
+     | statement",
+    ),
+    ("statement", "word | number | name | address | env | block"),
+    ("env", "\"env\" string"),
+    (
+        "block",
+        "\"while\" { statement } [ \"do\" { statement } ] \"end\"
+     | \"if\" { statement } [ \"else\" { statement } ] \"end\"
+     | \"try\" { statement } \"recover\" { statement } \"end\"
+     | \"asm\" string \"end\"",
+    ),
+    ("number", "digit { digit }"),
+    ("name", "name_char { name_char | \".\" } [ \"@\" | \"!\" ]"),
+    ("name_char", "letter | digit | \"_\""),
+    ("address", "\"&\" name"),
+    (
+        "string",
+        "'\"' { ? any character but '\"' and newline ? } '\"'",
+    ),
+    ("doc", "\"##\" { ? any character but newline ? }"),
+    ("comment", "\"#\" { ? any character but newline ? }"),
+];
+
+// The words that compile to an instruction, built in or from a loaded pack.
+fn word_names() -> Vec<&'static str> {
+    let builtins = BUILTINS
+        .iter()
+        .filter(|word| word.op.is_some())
+        .map(|word| word.name);
+    let natives = plugin::natives().into_iter().map(|word| word.name);
+    builtins.chain(natives).collect()
+}
+
+fn keywords() -> impl Iterator<Item = &'static str> {
+    BUILTINS
+        .iter()
+        .filter(|word| word.op.is_none())
+        .map(|word| word.name)
+}
+
+fn alternatives<'a>(names: impl Iterator<Item = &'a str>) -> String {
+    let quoted: Vec<String> = names.map(|name| format!("\"{}\"", name)).collect();
+    quoted.join(" | ")
+}
+
+pub fn ebnf() -> String {
+    let mut out = String::from(
+        "(* rorth grammar. Tokens are separated by whitespace; comments are skipped. *)\n",
+    );
+    for (name, definition) in RULES {
+        out.push_str(&format!("{} = {} ;\n", name, definition));
+    }
+    out.push_str(&format!(
+        "word = {} ;\n",
+        alternatives(word_names().into_iter())
+    ));
+    out.push_str(&format!("keyword = {} ;\n", alternatives(keywords())));
+    out
+}
+
+pub fn json() -> String {
+    let rules = RULES.iter().map(|(name, definition)| {
+        json::object(&[
+            ("name", json::string(name)),
+            ("definition", json::string(definition)),
+        ])
+    });
+    let builtins = BUILTINS.iter().map(|word| {
+        let effect = word.op.as_ref().and_then(|op| op.effect);
+        (word.name, effect, word.description)
+    });
+    let natives = plugin::natives()
+        .into_iter()
+        .map(|word| (word.name, Some(word.effect), word.description));
+    let words = builtins.chain(natives).map(|(name, effect, description)| {
+        let effect = effect.map_or("null".to_string(), |e| json::string(&e.to_string()));
+        json::object(&[
+            ("name", json::string(name)),
+            ("effect", effect),
+            ("description", json::string(description)),
+        ])
+    });
+    json::object(&[
+        ("rules", json::array(rules)),
+        ("keywords", json::array(keywords().map(json::string))),
+        ("words", json::array(words)),
+    ])
+}
+
+// Words stand between whitespace, so `+` in `+w` isn't highlighted alone.
+fn between_spaces(alternatives: &str) -> String {
+    format!("(?<![^\\s])(?:{})(?![^\\s])", alternatives)
+}
+
+fn regex_escape(name: &str) -> String {
+    let mut out = String::new();
+    for c in name.chars() {
+        if "\\^$.|?*+()[]{}".contains(c) {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+// Longest first, so no word is cut short by one that starts it.
+fn regex_of(mut names: Vec<&str>) -> String {
+    names.sort_by_key(|name| core::cmp::Reverse(name.len()));
+    let escaped: Vec<String> = names.into_iter().map(regex_escape).collect();
+    between_spaces(&escaped.join("|"))
+}
+
+fn pattern(scope: &str, regex: &str) -> String {
+    json::object(&[
+        ("name", json::string(&format!("{}.rorth", scope))),
+        ("match", json::string(regex)),
+    ])
+}
+
+pub fn textmate() -> String {
+    let order = [
+        "doc",
+        "comment",
+        "string",
+        "definition",
+        "keyword",
+        "word",
+        "number",
+    ];
+    let patterns = order
+        .iter()
+        .map(|name| json::object(&[("include", json::string(&format!("#{}", name)))]));
+    let definition = json::object(&[
+        (
+            "match",
+            json::string(&between_spaces(
+                "(fun|module|struct|enum)\\s+([A-Za-z0-9_][A-Za-z0-9_.]*)",
+            )),
+        ),
+        (
+            "captures",
+            json::object(&[
+                (
+                    "1",
+                    json::object(&[("name", json::string("keyword.control.rorth"))]),
+                ),
+                (
+                    "2",
+                    json::object(&[("name", json::string("entity.name.function.rorth"))]),
+                ),
+            ]),
+        ),
+    ]);
+    let repository = json::object(&[
+        ("doc", pattern("comment.block.documentation", "##.*$")),
+        ("comment", pattern("comment.line.number-sign", "#.*$")),
+        ("string", pattern("string.quoted.double", "\"[^\"]*\"")),
+        ("definition", definition),
+        (
+            "keyword",
+            pattern("keyword.control", &regex_of(keywords().collect())),
+        ),
+        (
+            "word",
+            pattern("support.function.builtin", &regex_of(word_names())),
+        ),
+        (
+            "number",
+            pattern("constant.numeric", &between_spaces("[0-9]+")),
+        ),
+    ]);
+    json::object(&[
+        ("name", json::string("rorth")),
+        ("scopeName", json::string("source.rorth")),
+        ("fileTypes", json::array([json::string("sm")])),
+        ("patterns", json::array(patterns)),
+        ("repository", repository),
+    ])
+}
+
+// The rules of `RULES` in tree-sitter's terms. Functions end where the next
+// item starts, since `ret` may also come early inside one.
+pub fn tree_sitter() -> String {
+    let choice = |names: Vec<&str>| {
+        let quoted: Vec<String> = names.into_iter().map(json::string).collect();
+        format!("choice({})", quoted.join(", "))
+    };
+    format!(
+        "// Generated by `rorth grammar --format tree-sitter`.
+module.exports = grammar({{
+  name: 'rorth',
+  extras: $ => [/\\s/, $.comment],
+  rules: {{
+    program: $ => repeat($._item),
+    _item: $ => choice($.function, $.module, $.import, $.include, $.struct, $.enum, $._statement),
+    function: $ => prec.right(seq(optional($.doc), optional('pub'), 'fun', field('name', $.name), repeat($._statement))),
+    module: $ => seq('module', field('name', $.name), repeat($._item), 'end'),
+    import: $ => seq('import', $.name),
+    include: $ => seq('include', $.string),
+    struct: $ => seq('struct', field('name', $.name), repeat($.name), 'end'),
+    enum: $ => seq('enum', field('name', $.name), repeat($.name), 'end'),
+    _statement: $ => choice($.word, $.number, $.name, $.address, $.env, $._block),
+    env: $ => seq('env', $.string),
+    _block: $ => choice($.while, $.if, $.try, $.asm),
+    while: $ => seq('while', repeat($._statement), optional(seq('do', repeat($._statement))), 'end'),
+    if: $ => seq('if', repeat($._statement), optional(seq('else', repeat($._statement))), 'end'),
+    try: $ => seq('try', repeat($._statement), 'recover', repeat($._statement), 'end'),
+    asm: $ => seq('asm', $.string, 'end'),
+    word: $ => {},
+    number: $ => /[0-9]+/,
+    name: $ => /[A-Za-z0-9_][A-Za-z0-9_.]*[@!]?/,
+    address: $ => /&[A-Za-z0-9_][A-Za-z0-9_.]*/,
+    string: $ => /\"[^\"\\n]*\"/,
+    doc: $ => token(prec(1, /##[^\\n]*/)),
+    comment: $ => /#[^\\n]*/,
+  }}
+}});
+",
+        choice(word_names())
+    )
+}
+
+pub fn export(format: Format) -> String {
+    match format {
+        Format::Ebnf => ebnf(),
+        Format::Json => json(),
+        Format::Textmate => textmate(),
+        Format::TreeSitter => tree_sitter(),
+    }
+}
+
+#[cfg(test)]
+mod grammar_tests {
+    use super::*;
+
+    // A keyword the rules don't mention is one the parser takes that the
+    // grammar doesn't say how.
+    #[test]
+    fn rules_use_every_keyword() {
+        let rules: String = RULES.iter().map(|(_, definition)| *definition).collect();
+        for keyword in keywords() {
+            assert!(
+                rules.contains(&format!("\"{}\"", keyword)),
+                "`{}` is in no rule",
+                keyword
+            );
+        }
+    }
+
+    #[test]
+    fn words_come_from_the_table() {
+        let ebnf = ebnf();
+        let word = ebnf
+            .lines()
+            .find(|line| line.starts_with("word = "))
+            .unwrap();
+        assert!(word.starts_with("word = \"+\" | \"-\""));
+        assert!(word.contains("\"divmod\""));
+        assert!(!word.contains("\"while\""));
+        assert!(ebnf.contains("keyword = \"while\" | \"do\""));
+        let parsed = json::parse(&json()).unwrap();
+        let words = parsed.get("words").and_then(|w| w.as_array()).unwrap();
+        assert_eq!(words.len(), BUILTINS.len() + plugin::natives().len());
+        let dup = words
+            .iter()
+            .find(|w| w.get("name").and_then(|n| n.as_str()) == Some("dup"))
+            .unwrap();
+        assert_eq!(
+            dup.get("effect").and_then(|e| e.as_str()),
+            Some("( 1 -- 2 )")
+        );
+        assert_eq!(Format::parse("yaml"), None);
+    }
+
+    #[test]
+    fn editor_grammars_know_every_word() {
+        let textmate = json::parse(&textmate()).unwrap();
+        let repository = textmate.get("repository").unwrap();
+        let regex = |name| {
+            let pattern = repository.get(name).and_then(|p| p.get("match"));
+            pattern.and_then(|m| m.as_str()).unwrap().to_string()
+        };
+        // Longest first, or `print` would match the start of it.
+        assert!(regex("word").starts_with("(?<![^\\s])(?:print-padded|"));
+        assert!(regex("word").contains("\\+w"));
+        assert!(regex("keyword").contains("recover"));
+        let tree_sitter = tree_sitter();
+        for name in word_names() {
+            assert!(tree_sitter.contains(&json::string(name)), "{}", name);
+        }
+        for keyword in keywords() {
+            assert!(
+                tree_sitter.contains(&format!("'{}'", keyword)),
+                "{}",
+                keyword
+            );
+        }
+    }
+}
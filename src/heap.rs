@@ -0,0 +1,196 @@
+// Blocks of memory handed out by `alloc`, addressed by plain numbers so they
+// sit on the stack like any other value. Nothing frees a block by hand:
+// `collect` keeps the blocks something still reaches and drops the rest. A
+// block is reached through any value inside its address range, on the stack
+// or in a cell of a reached block. Values and addresses look the same, so a
+// number that happens to fall inside a block keeps it too; that costs memory,
+// never a block still in use.
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::vec;
+use alloc::vec::Vec;
+
+// Where the first block starts. Far from the small numbers programs count
+// with, so those don't keep blocks alive.
+pub const BASE: i32 = 0x1000_0000;
+// Allocations between collections, in cells, before any block is live.
+const FIRST_COLLECTION: usize = 1024;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GcStats {
+    pub blocks: usize,
+    pub cells: usize,
+    pub collections: usize,
+    // Blocks dropped by collections so far.
+    pub freed: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Heap {
+    // By the address of their first cell.
+    blocks: BTreeMap<i32, Vec<i32>>,
+    next: i32,
+    cells: usize,
+    // Cells allocated since the last collection, and how many make the next.
+    since: usize,
+    threshold: usize,
+    collections: usize,
+    freed: usize,
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self {
+            blocks: BTreeMap::new(),
+            next: BASE,
+            cells: 0,
+            since: 0,
+            threshold: FIRST_COLLECTION,
+            collections: 0,
+            freed: 0,
+        }
+    }
+}
+
+impl Heap {
+    // The address of a new block of `size` zeroed cells, or `None` when the
+    // addresses have run out. Addresses are never handed out twice, and
+    // blocks are a cell apart, so an address just past one block is in none.
+    pub fn alloc(&mut self, size: usize) -> Option<i32> {
+        let start = self.next;
+        let end = i32::try_from(size)
+            .ok()
+            .and_then(|n| start.checked_add(n))?;
+        self.next = end.checked_add(1)?;
+        self.blocks.insert(start, vec![0; size]);
+        self.cells += size;
+        self.since += size;
+        Some(start)
+    }
+
+    // Whether enough was allocated since the last collection to make
+    // another worth it.
+    pub fn due(&self) -> bool {
+        self.since >= self.threshold
+    }
+
+    // The block `address` points into, and the offset in it.
+    fn find(&self, address: i32) -> Option<(i32, usize)> {
+        let (start, cells) = self.blocks.range(..=address).next_back()?;
+        let offset = (address - start) as usize;
+        (offset < cells.len()).then_some((*start, offset))
+    }
+
+    pub fn get(&self, address: i32) -> Option<i32> {
+        let (start, offset) = self.find(address)?;
+        Some(self.blocks[&start][offset])
+    }
+
+    pub fn set(&mut self, address: i32, value: i32) -> Option<()> {
+        let (start, offset) = self.find(address)?;
+        self.blocks.get_mut(&start)?[offset] = value;
+        Some(())
+    }
+
+    // Drops every block not reached from `roots`.
+    pub fn collect(&mut self, roots: &[i32]) {
+        let mut reached = BTreeSet::new();
+        let mut pending: Vec<i32> = roots.to_vec();
+        while let Some(value) = pending.pop() {
+            let Some((start, _)) = self.find(value) else {
+                continue;
+            };
+            if reached.insert(start) {
+                pending.extend(&self.blocks[&start]);
+            }
+        }
+        let before = self.blocks.len();
+        self.blocks.retain(|start, _| reached.contains(start));
+        self.freed += before - self.blocks.len();
+        self.cells = self.blocks.values().map(Vec::len).sum();
+        self.collections += 1;
+        self.since = 0;
+        self.threshold = self.cells.max(FIRST_COLLECTION);
+    }
+
+    // Cells in blocks not yet collected.
+    pub fn cells(&self) -> usize {
+        self.cells
+    }
+
+    pub fn stats(&self) -> GcStats {
+        GcStats {
+            blocks: self.blocks.len(),
+            cells: self.cells,
+            collections: self.collections,
+            freed: self.freed,
+        }
+    }
+}
+
+#[cfg(test)]
+mod heap_tests {
+    use super::*;
+
+    #[test]
+    fn cells_start_zeroed_and_keep_what_is_stored() {
+        let mut heap = Heap::default();
+        let a = heap.alloc(2).unwrap();
+        let b = heap.alloc(1).unwrap();
+        assert_eq!(heap.get(a + 1), Some(0));
+        heap.set(a + 1, 7).unwrap();
+        assert_eq!(heap.get(a + 1), Some(7));
+        // Past the end of `a` is neither `a` nor `b`.
+        assert_eq!(heap.get(a + 2), None);
+        assert_eq!(heap.set(a + 2, 1), None);
+        assert_eq!(heap.get(b), Some(0));
+        assert_eq!(heap.get(3), None);
+    }
+
+    #[test]
+    fn unreached_blocks_are_collected() {
+        let mut heap = Heap::default();
+        let kept = heap.alloc(2).unwrap();
+        let inner = heap.alloc(1).unwrap();
+        let lost = heap.alloc(3).unwrap();
+        heap.set(kept, inner).unwrap();
+        // A cycle that nothing outside reaches.
+        heap.set(lost, lost).unwrap();
+        // An address into the middle of a block counts.
+        heap.collect(&[5, kept + 1]);
+        assert_eq!(heap.get(inner), Some(0));
+        assert_eq!(heap.get(lost), None);
+        assert_eq!(
+            heap.stats(),
+            GcStats {
+                blocks: 2,
+                cells: 3,
+                collections: 1,
+                freed: 1,
+            }
+        );
+        heap.collect(&[]);
+        assert_eq!(heap.stats().blocks, 0);
+        assert_eq!(heap.cells(), 0);
+    }
+
+    #[test]
+    fn collections_come_due_as_blocks_are_allocated() {
+        let mut heap = Heap::default();
+        heap.alloc(FIRST_COLLECTION - 1).unwrap();
+        assert!(!heap.due());
+        heap.alloc(1).unwrap();
+        assert!(heap.due());
+        heap.collect(&[]);
+        assert!(!heap.due());
+    }
+
+    #[test]
+    fn addresses_run_out() {
+        let mut heap = Heap {
+            next: i32::MAX - 4,
+            ..Heap::default()
+        };
+        assert_eq!(heap.alloc(2), Some(i32::MAX - 4));
+        assert_eq!(heap.alloc(2), None);
+    }
+}
@@ -0,0 +1,96 @@
+// `--profile=annotate`: the source of a run with how often each line's
+// instructions ran, their share of every instruction run, and a bar scaled
+// to the hottest line, so the hot spots show up in the code itself.
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::source_map::SourceMap;
+
+const BAR: usize = 20;
+
+// `executed` is `Stats::executed`; `read` gives the text of a source file.
+// Files nothing ran in are left out.
+pub fn annotate(
+    map: &SourceMap,
+    executed: &[usize],
+    read: impl Fn(&Path) -> Option<String>,
+) -> String {
+    // By file, then line.
+    let mut lines: BTreeMap<(usize, usize), usize> = BTreeMap::new();
+    for (origin, count) in map.instructions.iter().zip(executed) {
+        if *count > 0 {
+            *lines.entry((origin.source, origin.line)).or_default() += count;
+        }
+    }
+    let total: usize = lines.values().sum();
+    let hottest = lines.values().copied().max().unwrap_or(0);
+    let width = total.to_string().len();
+    let mut out = String::new();
+    for (source, path) in map.sources.iter().enumerate() {
+        if !lines.keys().any(|(file, _)| *file == source) {
+            continue;
+        }
+        out.push_str(&format!("{}:\n", path.display()));
+        let Some(text) = read(path) else {
+            out.push_str("  (source not found)\n");
+            continue;
+        };
+        for (i, code) in text.lines().enumerate() {
+            let count = lines.get(&(source, i + 1)).copied().unwrap_or(0);
+            let heat = if count == 0 {
+                format!("{:width$}  {:6}  {:BAR$}", "", "", "", width = width)
+            } else {
+                let percent = count as f64 * 100.0 / total as f64;
+                let bar = "#".repeat((count * BAR).div_ceil(hottest));
+                format!(
+                    "{:>width$}  {:>5.1}%  {:BAR$}",
+                    count,
+                    percent,
+                    bar,
+                    width = width
+                )
+            };
+            out.push_str(format!("{} | {}", heat, code).trim_end());
+            out.push('\n');
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod heatmap_tests {
+    use super::*;
+    use crate::parser::parse;
+    use crate::stack::VecStack;
+    use crate::stack_machine::{StackMachine, VmConfig};
+    use crate::tokenizer::tokenize;
+    use std::path::PathBuf;
+
+    #[test]
+    fn lines_show_their_counts() {
+        let source = "fun main\n  3 while\n    1 -\n  end\n\n  pop ret\n";
+        let tokens = tokenize(source).unwrap();
+        let program = parse(tokens.clone()).unwrap();
+        let config = VmConfig {
+            stats: true,
+            ..VmConfig::default()
+        };
+        let mut machine = StackMachine::with_config(VecStack::new(), config);
+        machine.call(&program, "main").unwrap();
+        let map = SourceMap::new(&program, &tokens, &[(0, PathBuf::from("loop.sm"))]);
+        let annotated = annotate(&map, &machine.stats.executed, |path| {
+            (path == Path::new("loop.sm")).then(|| source.to_string())
+        });
+        assert_eq!(
+            annotated,
+            "loop.sm:
+                                 | fun main
+ 2   15.4%  #######              |   3 while
+ 6   46.2%  #################### |     1 -
+ 3   23.1%  ##########           |   end
+                                 |
+ 2   15.4%  #######              |   pop ret
+"
+        );
+    }
+}
@@ -0,0 +1,358 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::common::Error;
+use crate::include::load_tokens;
+use crate::parser::parse_recovering;
+use crate::replay::Event;
+use crate::stack::VecStack;
+use crate::stack_machine::{Crash, Program, StackMachine, VmConfig};
+use crate::stats::Stats;
+use crate::tokenizer::{tokenize_recovering, Token};
+
+pub trait SourceLoader {
+    fn load(&self, path: &str) -> Result<String, Error>;
+}
+
+pub struct FsLoader;
+
+impl SourceLoader for FsLoader {
+    fn load(&self, path: &str) -> Result<String, Error> {
+        std::fs::read_to_string(path).map_err(|e| Error::Io {
+            path: path.to_string(),
+            comment: e.to_string(),
+        })
+    }
+}
+
+pub fn run_source(source: &str, config: VmConfig, out: &mut dyn Write) -> Result<(), Error> {
+    let tokens = tokenize_recovering(source).map_err(Error::from_vec)?;
+    run_tokens(tokens, config, out)
+}
+
+pub fn run_file(
+    loader: &dyn SourceLoader,
+    path: &Path,
+    search_paths: Vec<PathBuf>,
+    config: VmConfig,
+    out: &mut dyn Write,
+) -> Result<(), Error> {
+    let tokens = load_tokens(loader, path, search_paths)?;
+    run_tokens(tokens, config, out)
+}
+
+fn run_tokens(tokens: Vec<Token>, config: VmConfig, out: &mut dyn Write) -> Result<(), Error> {
+    let program = parse_recovering(tokens).map_err(Error::from_vec)?;
+    run_program(program, config, out)
+}
+
+// Runs a compiled program, e.g. one read back with `bytecode::decode`.
+pub fn run_program(program: Program, config: VmConfig, out: &mut dyn Write) -> Result<(), Error> {
+    run_counted(&program, config, out).0
+}
+
+// Like `run_program`, also handing back what the machine counted when
+// `config.stats` is on.
+pub fn run_counted(
+    program: &Program,
+    config: VmConfig,
+    out: &mut dyn Write,
+) -> (Result<(), Error>, Stats) {
+    let outcome = run_in_turn(std::slice::from_ref(program), config, out);
+    (outcome.result, outcome.stats)
+}
+
+// How `run_in_turn` went, and what the machine counted.
+pub struct Outcome {
+    pub result: Result<(), Error>,
+    pub stats: Stats,
+    // For an error from running: which program it was and where it stopped.
+    pub crash: Option<(usize, Crash)>,
+    // What `rand` and `time` returned, when `config.record` is on.
+    pub recording: Vec<Event>,
+}
+
+// A machine for running programs from the command line, with heartbeats
+// going to stderr.
+pub fn machine(config: VmConfig) -> StackMachine<VecStack<i32>> {
+    let mut machine = StackMachine::with_config(VecStack::new(), config);
+    machine.set_heartbeat(|status| {
+        eprintln!(
+            "heartbeat: {} steps, in `{}` at line {}, stack depth {}",
+            status.steps, status.function, status.line, status.depth
+        )
+    });
+    machine
+}
+
+// Runs `program`'s `main` on `machine`, which keeps the stack and memory it
+// leaves, and writes out what it printed, up to an error or Ctrl-C too.
+pub fn run_on(
+    machine: &mut StackMachine<VecStack<i32>>,
+    program: &Program,
+    out: &mut dyn Write,
+) -> (Result<(), Error>, Option<Crash>) {
+    let mut result = machine.run_main(program);
+    let written = result.output.iter().try_for_each(|line| {
+        writeln!(out, "{}", line).map_err(|e| Error::Io {
+            path: "<output>".to_string(),
+            comment: e.to_string(),
+        })
+    });
+    let outcome = match result.error {
+        Some(error) => Err(error),
+        None => Ok(()),
+    };
+    (written.and(outcome), result.crash.take())
+}
+
+// Runs each program's `main` on the same machine, one after another, so each
+// starts with the stack and memory the one before left behind. Stops at the
+// first error.
+pub fn run_in_turn(programs: &[Program], config: VmConfig, out: &mut dyn Write) -> Outcome {
+    run_each(&mut machine(config), programs, out)
+}
+
+// Like `run_in_turn`, on a machine set up by the caller, e.g. with its own
+// output sink.
+pub fn run_each(
+    machine: &mut StackMachine<VecStack<i32>>,
+    programs: &[Program],
+    out: &mut dyn Write,
+) -> Outcome {
+    for (i, program) in programs.iter().enumerate() {
+        let (result, crash) = run_on(machine, program, out);
+        if let Err(error) = result {
+            return Outcome {
+                result: Err(error),
+                recording: machine.take_recording(),
+                stats: core::mem::take(&mut machine.stats),
+                crash: crash.map(|crash| (i, crash)),
+            };
+        }
+    }
+    Outcome {
+        result: Ok(()),
+        recording: machine.take_recording(),
+        stats: core::mem::take(&mut machine.stats),
+        crash: None,
+    }
+}
+
+// The programs to run for files given one after another, as in
+// `rorth run lib.sm script.sm`. Each file is parsed after the ones before it,
+// so it can call what they define, and its program is kept if it defines a
+// `main` of its own; a file without one only adds definitions. When no file
+// has a `main`, the last program is kept so running it says so.
+pub fn programs_in_turn(
+    files: Vec<Vec<Token>>,
+    parse: impl Fn(Vec<Token>) -> Result<Program, Error>,
+) -> Result<Vec<Program>, Error> {
+    let mut tokens = vec![];
+    let mut programs = vec![];
+    let mut last = None;
+    // Instructions lowered from the files so far, which parsing the next
+    // file leaves as they were.
+    let mut before = 0;
+    for file in files {
+        tokens.extend(file);
+        let program = parse(tokens.clone())?;
+        let own_main = program
+            .functions
+            .get("main")
+            .is_some_and(|start| *start >= before);
+        before = program.instructions.len();
+        if own_main {
+            programs.push(program);
+        } else {
+            last = Some(program);
+        }
+    }
+    if programs.is_empty() {
+        programs.extend(last);
+    }
+    Ok(programs)
+}
+
+#[cfg(test)]
+mod host_tests {
+    use super::*;
+
+    struct MapLoader(Vec<(&'static str, &'static str)>);
+
+    impl SourceLoader for MapLoader {
+        fn load(&self, path: &str) -> Result<String, Error> {
+            self.0
+                .iter()
+                .find(|(name, _)| *name == path)
+                .map(|(_, source)| source.to_string())
+                .ok_or(Error::Io {
+                    path: path.to_string(),
+                    comment: "not found".to_string(),
+                })
+        }
+    }
+
+    #[test]
+    fn run_source_writes_printed_values() {
+        let mut out = Vec::new();
+        run_source(
+            "fun main 2 3 + print 7 print ret",
+            VmConfig::default(),
+            &mut out,
+        )
+        .unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "5\n7\n");
+    }
+
+    #[test]
+    fn run_source_reports_errors() {
+        let mut out = Vec::new();
+        let result = run_source("fun main + ret", VmConfig::default(), &mut out);
+        assert!(matches!(
+            result,
+            Err(Error::StaticCheck {
+                pos: 10,
+                line: 1,
+                ..
+            })
+        ));
+
+        let result = run_source("fun main 4 4 print 0 / ret", VmConfig::default(), &mut out);
+        assert_eq!(result, Err(Error::DivisionByZero { pos: 22, line: 1 }));
+        assert_eq!(out, b"4\n");
+    }
+
+    #[test]
+    fn run_source_reports_all_parse_errors() {
+        let mut out = Vec::new();
+        let result = run_source(
+            "fun main nope else 1 end ret",
+            VmConfig::default(),
+            &mut out,
+        );
+        assert_eq!(
+            result.map_err(|e| e.to_string()),
+            Err("1:10: Function not found\n1:15: Unexpected `else`".to_string())
+        );
+    }
+
+    #[test]
+    fn custom_loader() {
+        // A module from a file only the loader knows, imported by short name.
+        let loader = MapLoader(vec![
+            (
+                "main.sm",
+                "include \"math.sm\" import math fun main 3 square print 2 math.square print ret",
+            ),
+            (
+                "lib/math.sm",
+                "module math fun mul_self dup * ret pub fun square mul_self ret end",
+            ),
+        ]);
+        let run = |path: &str| {
+            let mut out = Vec::new();
+            let result = run_file(
+                &loader,
+                Path::new(path),
+                vec![PathBuf::from("lib")],
+                VmConfig::default(),
+                &mut out,
+            );
+            (result, String::from_utf8(out).unwrap())
+        };
+        assert_eq!(run("main.sm"), (Ok(()), "9\n4\n".to_string()));
+        assert_eq!(
+            run("missing.sm").0,
+            Err(Error::Io {
+                path: "missing.sm".to_string(),
+                comment: "not found".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn run_file_resolves_includes() {
+        let loader = MapLoader(vec![
+            ("main.sm", "include \"two.sm\" fun main two print ret"),
+            ("lib/two.sm", "fun two 2 ret"),
+        ]);
+        let mut out = Vec::new();
+        run_file(
+            &loader,
+            Path::new("main.sm"),
+            vec![PathBuf::from("lib")],
+            VmConfig::default(),
+            &mut out,
+        )
+        .unwrap();
+        assert_eq!(out, b"2\n");
+    }
+
+    fn run_files(files: &[&str]) -> (Result<(), Error>, String) {
+        let files = files
+            .iter()
+            .map(|source| tokenize_recovering(source).unwrap())
+            .collect();
+        let mut out = Vec::new();
+        let result = programs_in_turn(files, |tokens| {
+            parse_recovering(tokens).map_err(Error::from_vec)
+        })
+        .and_then(|programs| run_in_turn(&programs, VmConfig::default(), &mut out).result);
+        (result, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn files_in_turn_share_definitions_and_the_stack() {
+        let lib = "fun sq dup * ret";
+        let first = "fun main 3 sq ret";
+        let second = "fun main sq print ret";
+        assert_eq!(
+            run_files(&[lib, first, second]),
+            (Ok(()), "81\n".to_string())
+        );
+        // A file without `main` runs nothing, a later one can redefine.
+        let cube = "fun sq dup dup * * ret";
+        assert_eq!(
+            run_files(&[lib, first, cube, second]),
+            (Ok(()), "729\n".to_string())
+        );
+        assert_eq!(
+            run_files(&[lib, cube]).0,
+            Err(Error::FunctionNotFound {
+                name: "main".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn files_in_turn_stop_at_the_first_error() {
+        let (result, out) = run_files(&["fun main 1 1 print 0 / ret", "fun main 2 print ret"]);
+        assert_eq!(result, Err(Error::DivisionByZero { pos: 22, line: 1 }));
+        assert_eq!(out, "1\n");
+        let (result, _) = run_files(&["fun main ret", "fun main nope ret"]);
+        assert!(
+            matches!(result, Err(Error::Parse { .. }) | Err(Error::Multiple(_))),
+            "{:?}",
+            result
+        );
+    }
+
+    #[test]
+    fn deterministic_runs_match() {
+        let source = "fun main rand print rand print time print ret";
+        let mut first = Vec::new();
+        let mut second = Vec::new();
+        run_source(source, VmConfig::default().deterministic(), &mut first).unwrap();
+        run_source(source, VmConfig::default().deterministic(), &mut second).unwrap();
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn fs_loader_reports_missing_file() {
+        assert!(matches!(
+            FsLoader.load("/definitely/not/here.sm"),
+            Err(Error::Io { .. })
+        ));
+    }
+}
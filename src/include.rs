@@ -0,0 +1,443 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use crate::cache::Cache;
+use crate::catalog::text;
+use crate::common::Error;
+use crate::host::SourceLoader;
+use crate::tokenizer::{into_owned, tokenize_recovering, Token, TokenType, FIRST_EDITION};
+
+// Replaces `include "file.sm"` with the tokens of that file. A name is looked
+// up next to the including file first, then in each search path in order.
+// Every file is included once, which also stops include cycles. A
+// `#pragma edition` counts to the end of its own file: an included file
+// starts in the first edition, and the including one goes on in its own.
+//
+// Files are found a level of includes at a time and each level is tokenized
+// on as many threads as there are cores; splicing them together afterwards
+// goes through the includes in order, so the tokens and the first error
+// reported are the same as tokenizing one file after another.
+pub struct Includer<'a> {
+    loader: &'a dyn SourceLoader,
+    search_paths: Vec<PathBuf>,
+    seen: HashSet<PathBuf>,
+    cache: Option<&'a Cache>,
+    origins: Vec<(usize, PathBuf)>,
+    // Tokens handed out by earlier calls to `load`.
+    loaded: usize,
+}
+
+// A tokenized file and where each of its includes led.
+struct File {
+    tokens: Result<Vec<Token<'static>>, Error>,
+    includes: Vec<Result<PathBuf, Error>>,
+}
+
+fn normalize(path: &Path) -> PathBuf {
+    path.components().collect()
+}
+
+// Owned, as the sources are gone by the time the tokens are spliced.
+fn tokenize(cache: Option<&Cache>, source: &str) -> Result<Vec<Token<'static>>, Error> {
+    let tokens = match cache {
+        Some(cache) => cache.tokens(source),
+        None => tokenize_recovering(source).map_err(Error::from_vec),
+    };
+    tokens.map(into_owned)
+}
+
+// Results in the order of `sources`.
+fn tokenize_all(
+    cache: Option<&Cache>,
+    sources: &[String],
+) -> Vec<Result<Vec<Token<'static>>, Error>> {
+    let threads = std::thread::available_parallelism()
+        .map_or(1, usize::from)
+        .min(sources.len());
+    if threads <= 1 {
+        return sources
+            .iter()
+            .map(|source| tokenize(cache, source))
+            .collect();
+    }
+    let chunk = sources.len().div_ceil(threads);
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = sources
+            .chunks(chunk)
+            .map(|part| {
+                scope.spawn(move || {
+                    part.iter()
+                        .map(|source| tokenize(cache, source))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| {
+                worker
+                    .join()
+                    .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+            })
+            .collect()
+    })
+}
+
+// The file names of the includes in `tokens`, up to the first `include`
+// without one, where splicing stops with an error anyway.
+fn include_names<'a>(tokens: &'a [Token]) -> Vec<&'a str> {
+    let mut names = vec![];
+    let mut tokens = tokens.iter();
+    while let Some(token) = tokens.next() {
+        if token.token_type != TokenType::Include {
+            continue;
+        }
+        match tokens.next() {
+            Some(Token {
+                token_type: TokenType::Str(name),
+                ..
+            }) => names.push(name.as_ref()),
+            _ => break,
+        }
+    }
+    names
+}
+
+impl<'a> Includer<'a> {
+    pub fn new(loader: &'a dyn SourceLoader, search_paths: Vec<PathBuf>) -> Self {
+        Self {
+            loader,
+            search_paths,
+            seen: HashSet::new(),
+            cache: None,
+            origins: vec![],
+            loaded: 0,
+        }
+    }
+
+    // Tokenizes each file through `cache`, if there is one.
+    pub fn with_cache(self, cache: Option<&'a Cache>) -> Self {
+        Self { cache, ..self }
+    }
+
+    pub fn load(&mut self, path: &Path) -> Result<Vec<Token<'static>>, Error> {
+        let path = normalize(path);
+        let source = self.loader.load(&path.display().to_string())?;
+        let mut files = self.discover(path.clone(), source);
+        self.seen.insert(path.clone());
+        let mut tokens = vec![];
+        self.expand(&path, &mut files, &mut tokens)?;
+        self.loaded += tokens.len();
+        Ok(tokens)
+    }
+
+    // Which file the tokens `load` returned came from: each entry holds from
+    // its token index up to the next entry's. After several calls to `load`,
+    // the indexes are into all their tokens one after another.
+    pub fn origins(&self) -> &[(usize, PathBuf)] {
+        &self.origins
+    }
+
+    // Every file reachable from `path`, tokenized.
+    fn discover(&self, path: PathBuf, source: String) -> HashMap<PathBuf, File> {
+        let mut files = HashMap::new();
+        let mut queued = HashSet::from([path.clone()]);
+        let mut level = vec![(path, source)];
+        while !level.is_empty() {
+            let (paths, sources): (Vec<PathBuf>, Vec<String>) = level.into_iter().unzip();
+            let mut next = vec![];
+            for (path, tokens) in paths.into_iter().zip(tokenize_all(self.cache, &sources)) {
+                let names = tokens.as_deref().map_or(vec![], include_names);
+                let includes = names
+                    .into_iter()
+                    .map(|name| {
+                        let (included, source) = self.locate(name, &path)?;
+                        if queued.insert(included.clone()) {
+                            next.push((included.clone(), source));
+                        }
+                        Ok(included)
+                    })
+                    .collect();
+                files.insert(path, File { tokens, includes });
+            }
+            level = next;
+        }
+        files
+    }
+
+    fn locate(&self, name: &str, from: &Path) -> Result<(PathBuf, String), Error> {
+        let here = from.parent().unwrap_or(Path::new(""));
+        for dir in std::iter::once(here).chain(self.search_paths.iter().map(PathBuf::as_path)) {
+            let candidate = normalize(&dir.join(name));
+            if let Ok(source) = self.loader.load(&candidate.display().to_string()) {
+                return Ok((candidate, source));
+            }
+        }
+        Err(Error::Io {
+            path: name.to_string(),
+            comment: "not found next to the including file or in the include paths".to_string(),
+        })
+    }
+
+    // Each file is expanded once, so it can be taken out of `files`.
+    fn expand(
+        &mut self,
+        path: &Path,
+        files: &mut HashMap<PathBuf, File>,
+        result: &mut Vec<Token<'static>>,
+    ) -> Result<(), Error> {
+        let file = files
+            .remove(path)
+            .expect("every reachable file was tokenized");
+        let mut includes = file.includes.into_iter();
+        let mut tokens = file.tokens?.into_iter();
+        self.origins
+            .push((self.loaded + result.len(), path.to_path_buf()));
+        let mut edition = FIRST_EDITION;
+        while let Some(token) = tokens.next() {
+            if let TokenType::Edition(year) = token.token_type {
+                edition = year;
+            }
+            if token.token_type != TokenType::Include {
+                result.push(token);
+                continue;
+            }
+            if !matches!(
+                tokens.next(),
+                Some(Token {
+                    token_type: TokenType::Str(_),
+                    ..
+                })
+            ) {
+                return Err(Error::Parse {
+                    word: "include".to_string(),
+                    pos: token.pos,
+                    line: token.line,
+                    comment: text("E0002.include", &[]),
+                });
+            }
+            let included = includes.next().expect("one result per include")?;
+            if self.seen.insert(included.clone()) {
+                let switch = |year| Token {
+                    token_type: TokenType::Edition(year),
+                    ..token
+                };
+                if edition != FIRST_EDITION {
+                    result.push(switch(FIRST_EDITION));
+                }
+                let start = result.len();
+                self.expand(&included, files, result)?;
+                self.origins
+                    .push((self.loaded + result.len(), path.to_path_buf()));
+                if edition != FIRST_EDITION
+                    || result[start..]
+                        .iter()
+                        .any(|t| matches!(t.token_type, TokenType::Edition(_)))
+                {
+                    result.push(switch(edition));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn load_tokens(
+    loader: &dyn SourceLoader,
+    path: &Path,
+    search_paths: Vec<PathBuf>,
+) -> Result<Vec<Token<'static>>, Error> {
+    Includer::new(loader, search_paths).load(path)
+}
+
+#[cfg(test)]
+mod include_tests {
+    use super::*;
+    use crate::parser::parse;
+    use crate::stack::VecStack;
+    use crate::stack_machine::StackMachine;
+
+    struct MapLoader(Vec<(&'static str, &'static str)>);
+
+    impl SourceLoader for MapLoader {
+        fn load(&self, path: &str) -> Result<String, Error> {
+            self.0
+                .iter()
+                .find(|(name, _)| *name == path)
+                .map(|(_, source)| source.to_string())
+                .ok_or(Error::Io {
+                    path: path.to_string(),
+                    comment: "not found".to_string(),
+                })
+        }
+    }
+
+    fn run(loader: &MapLoader, search_paths: &[&str]) -> Result<Vec<i32>, Error> {
+        let search_paths = search_paths.iter().map(PathBuf::from).collect();
+        let tokens = load_tokens(loader, Path::new("app/main.sm"), search_paths)?;
+        StackMachine::new(VecStack::new()).execute(parse(tokens)?)
+    }
+
+    #[test]
+    fn includes_relative_to_the_including_file() {
+        let loader = MapLoader(vec![
+            (
+                "app/main.sm",
+                "include \"util/sq.sm\" fun main 3 sq print ret",
+            ),
+            ("app/util/sq.sm", "fun sq dup * ret"),
+        ]);
+        assert_eq!(run(&loader, &[]), Ok(vec![9]));
+    }
+
+    #[test]
+    fn falls_back_to_search_paths_in_order() {
+        let loader = MapLoader(vec![
+            ("app/main.sm", "include \"one.sm\" fun main one print ret"),
+            ("lib/one.sm", "fun one 1 ret"),
+            ("vendor/one.sm", "fun one 2 ret"),
+        ]);
+        assert_eq!(run(&loader, &["vendor", "lib"]), Ok(vec![2]));
+        assert_eq!(run(&loader, &["lib", "vendor"]), Ok(vec![1]));
+        assert_eq!(
+            run(&loader, &[]),
+            Err(Error::Io {
+                path: "one.sm".to_string(),
+                comment: "not found next to the including file or in the include paths".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn files_are_included_once() {
+        let loader = MapLoader(vec![
+            (
+                "app/main.sm",
+                "include \"a.sm\" include \"./b.sm\" fun main a b + print ret",
+            ),
+            ("app/a.sm", "include \"b.sm\" fun a 1 ret"),
+            ("app/b.sm", "include \"main.sm\" fun b 2 ret"),
+        ]);
+        assert_eq!(run(&loader, &[]), Ok(vec![3]));
+    }
+
+    #[test]
+    fn editions_end_with_their_file() {
+        let loader = MapLoader(vec![
+            (
+                "app/main.sm",
+                "#pragma edition 2026\ninclude \"a.sm\" include \"b.sm\"\n\
+                 fun main 5 print a b ret",
+            ),
+            ("app/a.sm", "fun a 1 print 2 print ret"),
+            ("app/b.sm", "#pragma edition 2026\nfun b 3 print pop ret"),
+        ]);
+        assert_eq!(run(&loader, &[]), Ok(vec![5, 1, 2, 3]));
+        let loader = MapLoader(vec![
+            (
+                "app/main.sm",
+                "include \"b.sm\" fun main 1 2 print print b ret",
+            ),
+            ("app/b.sm", "#pragma edition 2026\nfun b 3 print pop ret"),
+        ]);
+        assert_eq!(run(&loader, &[]), Ok(vec![2, 1, 3]));
+    }
+
+    #[test]
+    fn include_needs_a_file_name() {
+        let loader = MapLoader(vec![("app/main.sm", "fun main ret\ninclude main")]);
+        assert_eq!(
+            run(&loader, &[]),
+            Err(Error::Parse {
+                word: "include".to_string(),
+                pos: 1,
+                line: 2,
+                comment: text("E0002.include", &[]),
+            })
+        );
+    }
+
+    #[test]
+    fn includes_splice_in_order() {
+        let loader = MapLoader(vec![
+            (
+                "app/main.sm",
+                "include \"a.sm\" include \"b.sm\" include \"c.sm\" include \"d.sm\"\n\
+                 fun main a b c d print print print print ret",
+            ),
+            ("app/a.sm", "include \"e.sm\" fun a e 1 + ret"),
+            ("app/b.sm", "fun b 2 ret"),
+            ("app/c.sm", "include \"b.sm\" fun c b 1 + ret"),
+            ("app/d.sm", "fun d 4 ret"),
+            ("app/e.sm", "fun e 0 ret"),
+        ]);
+        let tokens = load_tokens(&loader, Path::new("app/main.sm"), vec![]).unwrap();
+        let functions: Vec<&TokenType> = tokens
+            .windows(2)
+            .filter(|pair| pair[0].token_type == TokenType::Fun)
+            .map(|pair| &pair[1].token_type)
+            .collect();
+        let names =
+            ["e", "a", "b", "c", "d", "main"].map(|name| TokenType::Identifier(name.into()));
+        assert_eq!(functions, names.iter().collect::<Vec<_>>());
+        assert_eq!(run(&loader, &[]), Ok(vec![4, 3, 2, 1]));
+    }
+
+    #[test]
+    fn the_first_broken_include_is_reported() {
+        let loader = MapLoader(vec![
+            (
+                "app/main.sm",
+                "include \"a.sm\" include \"b.sm\" include \"missing.sm\" fun main ret",
+            ),
+            ("app/a.sm", "include \"c.sm\" fun a ret"),
+            ("app/b.sm", "fun b \"unclosed ret"),
+            ("app/c.sm", "fun c 99999999999 ret"),
+        ]);
+        for _ in 0..10 {
+            assert!(matches!(
+                run(&loader, &[]),
+                Err(Error::Parse {
+                    line: 1,
+                    pos: 7,
+                    ..
+                })
+            ));
+        }
+    }
+
+    #[test]
+    fn origins_say_which_file_tokens_came_from() {
+        let loader = MapLoader(vec![
+            ("app/main.sm", "1 include \"a.sm\" 2 include \"b.sm\" 3"),
+            ("app/a.sm", "include \"b.sm\" 4"),
+            ("app/b.sm", "5 6"),
+        ]);
+        let mut includer = Includer::new(&loader, vec![]);
+        let tokens = includer.load(Path::new("app/main.sm")).unwrap();
+        // 1 | 5 6 | 4 | 2 | 3
+        assert_eq!(tokens.len(), 6);
+        let origins: Vec<(usize, &str)> = includer
+            .origins()
+            .iter()
+            .map(|(start, path)| (*start, path.to_str().unwrap()))
+            .collect();
+        assert_eq!(
+            origins,
+            vec![
+                (0, "app/main.sm"),
+                (1, "app/a.sm"),
+                (1, "app/b.sm"),
+                (3, "app/a.sm"),
+                (4, "app/main.sm"),
+            ]
+        );
+
+        // A second file carries on where the first left off, and what was
+        // included already isn't again.
+        let more = includer.load(Path::new("app/a.sm")).unwrap();
+        assert_eq!(more.len(), 1);
+        let (start, path) = includer.origins().last().unwrap();
+        assert_eq!((*start, path.to_str().unwrap()), (6, "app/a.sm"));
+    }
+}
@@ -0,0 +1,279 @@
+// Keeps one file tokenized and parsed between edits, so an editor can show
+// diagnostics on every keystroke. An edit tokenizes only the lines it
+// touched and lowers again from the function it starts in; functions after
+// it are reused, moved to their new lines and instruction indexes, unless the
+// edit declared something differently. `program` always agrees with
+// tokenizing and parsing the whole text from scratch. Includes are not
+// expanded: an `include` is reported like any stray word.
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use crate::common::Error;
+use crate::line_index::LineIndex;
+use crate::parser::{lower, Edit, Lowered};
+use crate::stack_machine::Program;
+use crate::tokenizer::{into_owned, tokenize_all, Token};
+
+pub struct Parser {
+    text: String,
+    tokens: Vec<Token<'static>>,
+    // From the tokenizer, in order. While there are any, nothing is parsed,
+    // as when running a file.
+    token_errors: Vec<Error>,
+    lowered: Option<Lowered>,
+}
+
+impl Parser {
+    pub fn new(text: &str) -> Self {
+        let (tokens, token_errors) = tokenize_all(text);
+        let lowered = token_errors.is_empty().then(|| lower(&tokens, None));
+        Self {
+            text: text.into(),
+            tokens: into_owned(tokens),
+            token_errors,
+            lowered,
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn tokens(&self) -> &[Token<'static>] {
+        &self.tokens
+    }
+
+    // Replaces the bytes in `range` with `new_text`. Panics, like
+    // `String::replace_range`, if `range` doesn't fall on character
+    // boundaries.
+    pub fn update(&mut self, range: Range<usize>, new_text: &str) {
+        let index = LineIndex::new(&self.text);
+        let (first, _) = index.line_col(&self.text, range.start);
+        let (last, _) = index.line_col(&self.text, range.end);
+        let lines = new_text.matches('\n').count() as isize
+            - self.text[range.clone()].matches('\n').count() as isize;
+        self.text.replace_range(range, new_text);
+
+        // Whole lines, from the first one touched to the last.
+        let index = LineIndex::new(&self.text);
+        let start = index
+            .line_start(first)
+            .expect("the edit starts on an existing line");
+        let end = index
+            .line_start(last.wrapping_add_signed(lines) + 1)
+            .unwrap_or(self.text.len());
+        let (tokens, errors) = tokenize_all(&self.text[start..end]);
+        let above = first as isize - 1;
+
+        let old = self.tokens.partition_point(|token| token.line < first)
+            ..self.tokens.partition_point(|token| token.line <= last);
+        let new = old.start..old.start + tokens.len();
+        self.tokens.splice(
+            old.clone(),
+            tokens.into_iter().map(|token| Token {
+                line: token.line.wrapping_add_signed(above),
+                ..token.into_owned()
+            }),
+        );
+        for token in &mut self.tokens[new.end..] {
+            token.line = token.line.wrapping_add_signed(lines);
+        }
+
+        let line_of = |error: &Error| error.position().map_or(0, |(line, _)| line);
+        let removed = self.token_errors.partition_point(|e| line_of(e) < first)
+            ..self.token_errors.partition_point(|e| line_of(e) <= last);
+        let after = removed.start + errors.len();
+        self.token_errors
+            .splice(removed, errors.into_iter().map(|error| error.moved(above)));
+        for error in &mut self.token_errors[after..] {
+            *error = error.clone().moved(lines);
+        }
+
+        self.lowered = if self.token_errors.is_empty() {
+            let edit = Edit {
+                old,
+                new,
+                line: last + 1,
+                lines,
+            };
+            Some(lower(
+                &self.tokens,
+                self.lowered.as_ref().map(|previous| (previous, &edit)),
+            ))
+        } else {
+            None
+        };
+    }
+
+    // What `parse_recovering(tokenize_recovering(text)?)` returns.
+    pub fn program(&self) -> Result<Program, Vec<Error>> {
+        match &self.lowered {
+            Some(lowered) => lowered.clone().into_program(),
+            None => Err(self.token_errors.clone()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod incremental_tests {
+    use super::*;
+    use crate::bytecode;
+    use crate::parser::parse_recovering;
+    use crate::rng::Rng;
+    use crate::tokenizer::tokenize_recovering;
+    use alloc::format;
+    use alloc::string::ToString;
+    use alloc::vec;
+
+    fn fresh(text: &str) -> Result<Vec<u8>, Vec<Error>> {
+        tokenize_recovering(text)
+            .and_then(parse_recovering)
+            .map(|program| bytecode::encode(&program))
+    }
+
+    fn assert_fresh(parser: &Parser) {
+        let text = parser.text();
+        assert_eq!(
+            parser.tokens(),
+            &tokenize_all(text).0[..],
+            "tokens of {:?}",
+            text
+        );
+        assert_eq!(
+            parser.program().map(|program| bytecode::encode(&program)),
+            fresh(text),
+            "program of {:?}",
+            text
+        );
+    }
+
+    fn replace(parser: &mut Parser, old: &str, new: &str) {
+        let start = parser.text().find(old).expect("text to replace");
+        parser.update(start..start + old.len(), new);
+        assert_fresh(parser);
+    }
+
+    const SOURCE: &str = "\
+## Squares.
+fun sq dup * ret
+module geo
+  pub fun area sq ret
+  fun hidden 1 ret
+end
+import geo
+fun count 3 while dup do dup print 1 - end pop ret
+fun main
+  2 area print
+  1 if 7 print else 8 print end
+  count
+ret
+";
+
+    #[test]
+    fn edits_agree_with_parsing_from_scratch() {
+        let mut parser = Parser::new(SOURCE);
+        assert_fresh(&parser);
+        replace(&mut parser, "dup *", "dup dup * *");
+        replace(&mut parser, "## Squares.", "## Cubes.\n## Really.");
+        replace(&mut parser, "7 print", "7 print 9 print");
+        replace(&mut parser, "fun hidden 1 ret", "");
+        replace(&mut parser, "count\n", "count count\n  missing\n");
+        replace(&mut parser, "  missing\n", "");
+        replace(&mut parser, "fun sq", "fun square");
+        replace(&mut parser, "fun square", "fun sq");
+        replace(&mut parser, "else", "elsewhere");
+        replace(&mut parser, "elsewhere", "else");
+        replace(&mut parser, "2 area", "2 \"area");
+        replace(&mut parser, "\"area", "area");
+        replace(&mut parser, "import geo\n", "");
+        replace(&mut parser, "fun count", "import geo\nfun count");
+        replace(&mut parser, "1 - end pop", "1 - pop");
+        replace(&mut parser, "1 - pop", "1 - end pop");
+        replace(&mut parser, "end\nimport", "import");
+        replace(&mut parser, "fun count", "#pragma edition 2026\nfun count");
+        replace(&mut parser, "2026", "2024");
+        replace(&mut parser, "#pragma edition 2024\n", "");
+        parser.update(0..parser.text().len(), "fun main 1 print ret");
+        assert_fresh(&parser);
+        parser.update(0..0, "\u{feff}");
+        assert_fresh(&parser);
+    }
+
+    #[test]
+    fn random_edits_agree_with_parsing_from_scratch() {
+        let snippets = [
+            "fun f ",
+            "fun g ",
+            "ret ",
+            "1 ",
+            "f ",
+            "g ",
+            "print ",
+            "while ",
+            "do ",
+            "end ",
+            "if ",
+            "else ",
+            "try ",
+            "recover ",
+            "module m ",
+            "pub ",
+            "import m ",
+            "struct p a end ",
+            "enum e a b end ",
+            "m.f ",
+            "## doc\n",
+            "\n",
+            "\"s\" ",
+            "^ ",
+            "dup ",
+            "",
+        ];
+        let mut rng = Rng::new(7);
+        let mut parser = Parser::new(SOURCE);
+        for _ in 0..2000 {
+            let text = parser.text();
+            let boundaries: Vec<usize> = text
+                .char_indices()
+                .map(|(i, _)| i)
+                .chain([text.len()])
+                .collect();
+            let mut pick = |n: usize| (rng.next_u64() % n as u64) as usize;
+            let a = boundaries[pick(boundaries.len())];
+            let b = boundaries[pick(boundaries.len())];
+            let range = a.min(b)..a.max(b).min(a.min(b) + 12);
+            let range = range.start..*boundaries.iter().find(|&&i| i >= range.end).unwrap();
+            let snippet = snippets[pick(snippets.len())].to_string();
+            parser.update(range, &snippet);
+            assert_fresh(&parser);
+            if parser.text().len() > 600 {
+                parser = Parser::new(SOURCE);
+            }
+        }
+    }
+
+    #[test]
+    fn tokenizer_errors_are_kept_by_line() {
+        let mut parser = Parser::new("fun main\n^\n1 print\n$ ret");
+        assert_fresh(&parser);
+        let errors = |parser: &Parser| parser.program().err().unwrap_or_default();
+        assert_eq!(errors(&parser).len(), 2);
+        replace(&mut parser, "^\n", "\n\n\n");
+        assert_eq!(
+            errors(&parser),
+            vec![Error::UnknownToken {
+                word: "$".to_string(),
+                pos: 1,
+                line: 6,
+            }]
+        );
+        replace(&mut parser, "$", "");
+        assert_eq!(
+            parser
+                .program()
+                .map(|program| format!("{}", program.instructions.len())),
+            Ok("3".to_string())
+        );
+    }
+}
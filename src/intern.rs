@@ -0,0 +1,90 @@
+// Names stored once and passed around as small numbers. The parser keeps
+// its function names here, so looking one up or declaring it again copies a
+// `Symbol` instead of a `String`.
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Symbol(u32);
+
+impl Symbol {
+    // As a plain number, for an instruction to carry.
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+
+    pub fn from_index(index: usize) -> Symbol {
+        Symbol(index as u32)
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SymbolTable {
+    names: Vec<Box<str>>,
+    index: BTreeMap<Box<str>, Symbol>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // The symbol for `name`, added if it is new.
+    pub fn intern(&mut self, name: &str) -> Symbol {
+        if let Some(symbol) = self.get(name) {
+            return symbol;
+        }
+        let symbol = Symbol(self.names.len() as u32);
+        self.names.push(name.into());
+        self.index.insert(name.into(), symbol);
+        symbol
+    }
+
+    // The symbol for `name` if it was interned, without adding it.
+    pub fn get(&self, name: &str) -> Option<Symbol> {
+        self.index.get(name).copied()
+    }
+
+    pub fn resolve(&self, symbol: Symbol) -> &str {
+        &self.names[symbol.0 as usize]
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    // Every name, in the order it was first interned.
+    pub fn iter(&self) -> impl Iterator<Item = (Symbol, &str)> {
+        self.names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| (Symbol(i as u32), &**name))
+    }
+}
+
+#[cfg(test)]
+mod intern_tests {
+    use super::*;
+
+    #[test]
+    fn names_are_stored_once() {
+        let mut table = SymbolTable::new();
+        let square = table.intern("square");
+        let main = table.intern("m.main");
+        assert_eq!(table.intern("square"), square);
+        assert_ne!(square, main);
+        assert_eq!(table.len(), 2);
+        assert_eq!(table.resolve(main), "m.main");
+        assert_eq!(table.get("square"), Some(square));
+        assert_eq!(table.get("cube"), None);
+        assert_eq!(
+            table.iter().collect::<Vec<_>>(),
+            [(square, "square"), (main, "m.main")]
+        );
+    }
+}
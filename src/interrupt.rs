@@ -0,0 +1,42 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
+// Set by the Ctrl-C handler, polled by machines with `interruptible` set.
+pub static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+pub fn take() -> bool {
+    INTERRUPTED.swap(false, Ordering::Relaxed)
+}
+
+#[cfg(all(unix, feature = "std"))]
+mod unix {
+    use std::os::raw::c_int;
+    use std::sync::atomic::Ordering;
+
+    const SIGINT: c_int = 2;
+
+    extern "C" {
+        fn signal(signum: c_int, handler: extern "C" fn(c_int)) -> usize;
+        fn _exit(status: c_int) -> !;
+    }
+
+    // Only touches an atomic, which is safe inside a signal handler. A second
+    // Ctrl-C before the first was noticed quits for real.
+    extern "C" fn on_sigint(_: c_int) {
+        if super::INTERRUPTED.swap(true, Ordering::Relaxed) {
+            unsafe { _exit(130) }
+        }
+    }
+
+    pub fn install() {
+        unsafe {
+            signal(SIGINT, on_sigint);
+        }
+    }
+}
+
+// Makes Ctrl-C stop the running program instead of the process. Does nothing
+// where there are no signals, or without `std`.
+pub fn install() {
+    #[cfg(all(unix, feature = "std"))]
+    unix::install();
+}
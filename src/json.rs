@@ -0,0 +1,240 @@
+pub fn string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+pub fn array<I: IntoIterator<Item = String>>(items: I) -> String {
+    format!("[{}]", items.into_iter().collect::<Vec<_>>().join(","))
+}
+
+pub fn object(fields: &[(&str, String)]) -> String {
+    let fields: Vec<String> = fields
+        .iter()
+        .map(|(key, value)| format!("{}:{}", string(key), value))
+        .collect();
+    format!("{{{}}}", fields.join(","))
+}
+
+// A value read back by `parse`. Numbers are whole, which is all rorth
+// writes.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(i64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_number(&self) -> Option<i64> {
+        match self {
+            Value::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+}
+
+struct Reader<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+}
+
+impl Reader<'_> {
+    fn skip_space(&mut self) {
+        while self.chars.next_if(|c| c.is_ascii_whitespace()).is_some() {}
+    }
+
+    fn expect(&mut self, word: &str) -> Option<()> {
+        word.chars()
+            .all(|c| self.chars.next() == Some(c))
+            .then_some(())
+    }
+
+    fn string(&mut self) -> Option<String> {
+        self.expect("\"")?;
+        let mut out = String::new();
+        loop {
+            match self.chars.next()? {
+                '"' => return Some(out),
+                '\\' => out.push(match self.chars.next()? {
+                    'n' => '\n',
+                    'r' => '\r',
+                    't' => '\t',
+                    'u' => {
+                        let hex: String =
+                            (0..4).map(|_| self.chars.next()).collect::<Option<_>>()?;
+                        char::from_u32(u32::from_str_radix(&hex, 16).ok()?)?
+                    }
+                    c => c,
+                }),
+                c => out.push(c),
+            }
+        }
+    }
+
+    fn value(&mut self) -> Option<Value> {
+        self.skip_space();
+        let value = match *self.chars.peek()? {
+            'n' => self.expect("null").map(|()| Value::Null)?,
+            't' => self.expect("true").map(|()| Value::Bool(true))?,
+            'f' => self.expect("false").map(|()| Value::Bool(false))?,
+            '"' => Value::String(self.string()?),
+            '[' => {
+                self.chars.next();
+                let mut items = vec![];
+                self.skip_space();
+                if self.chars.next_if_eq(&']').is_none() {
+                    loop {
+                        items.push(self.value()?);
+                        match self.chars.next()? {
+                            ',' => {}
+                            ']' => break,
+                            _ => return None,
+                        }
+                    }
+                }
+                Value::Array(items)
+            }
+            '{' => {
+                self.chars.next();
+                let mut fields = vec![];
+                self.skip_space();
+                if self.chars.next_if_eq(&'}').is_none() {
+                    loop {
+                        self.skip_space();
+                        let key = self.string()?;
+                        self.skip_space();
+                        self.expect(":")?;
+                        fields.push((key, self.value()?));
+                        match self.chars.next()? {
+                            ',' => {}
+                            '}' => break,
+                            _ => return None,
+                        }
+                    }
+                }
+                Value::Object(fields)
+            }
+            _ => {
+                let mut digits = String::new();
+                while let Some(c) = self.chars.next_if(|c| *c == '-' || c.is_ascii_digit()) {
+                    digits.push(c);
+                }
+                Value::Number(digits.parse().ok()?)
+            }
+        };
+        self.skip_space();
+        Some(value)
+    }
+}
+
+// None for anything that isn't a single JSON value with whole numbers.
+pub fn parse(text: &str) -> Option<Value> {
+    let mut reader = Reader {
+        chars: text.chars().peekable(),
+    };
+    let value = reader.value()?;
+    reader.chars.next().is_none().then_some(value)
+}
+
+#[cfg(test)]
+mod json_tests {
+    use super::*;
+
+    #[test]
+    fn escapes_strings() {
+        assert_eq!(string("plain"), "\"plain\"");
+        assert_eq!(string("a \"b\"\n"), "\"a \\\"b\\\"\\n\"");
+        assert_eq!(string("\\"), "\"\\\\\"");
+        assert_eq!(string("\u{1}"), "\"\\u0001\"");
+    }
+
+    #[test]
+    fn builds_arrays_and_objects() {
+        assert_eq!(array(vec![]), "[]");
+        assert_eq!(array(vec!["1".to_string(), "2".to_string()]), "[1,2]");
+        assert_eq!(
+            object(&[("a", "1".to_string()), ("b", string("x"))]),
+            "{\"a\":1,\"b\":\"x\"}"
+        );
+    }
+
+    #[test]
+    fn reads_back_what_it_writes() {
+        let text = object(&[
+            ("name", string("a \"b\"\n\u{1}")),
+            ("list", array(vec!["1".to_string(), "-20".to_string()])),
+            ("empty", array(vec![])),
+            ("nested", object(&[])),
+        ]);
+        let value = parse(&text).unwrap();
+        assert_eq!(
+            value.get("name").and_then(Value::as_str),
+            Some("a \"b\"\n\u{1}")
+        );
+        assert_eq!(
+            value.get("list").and_then(Value::as_array),
+            Some(&[Value::Number(1), Value::Number(-20)][..])
+        );
+        assert_eq!(value.get("empty"), Some(&Value::Array(vec![])));
+        assert_eq!(value.get("nested"), Some(&Value::Object(vec![])));
+        assert_eq!(
+            parse(" { \"a\" : [ true , false , null ] } "),
+            Some(Value::Object(vec![(
+                "a".to_string(),
+                Value::Array(vec![Value::Bool(true), Value::Bool(false), Value::Null])
+            )]))
+        );
+    }
+
+    #[test]
+    fn rejects_what_it_cannot_read() {
+        for text in [
+            "",
+            "[1,",
+            "{\"a\" 1}",
+            "1.5",
+            "[1] 2",
+            "\"open",
+            "nul",
+            "{1:2}",
+        ] {
+            assert_eq!(parse(text), None, "{:?}", text);
+        }
+    }
+}
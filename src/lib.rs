@@ -0,0 +1,83 @@
+// Without the `std` feature only the language itself is built: tokenizer,
+// parser, checker, machine and bytecode, on `core` and `alloc`. Everything
+// that touches files, the terminal or the network needs `std`, and the REPL
+// and the server are left out of builds without `repl` and `serve`.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "audio")]
+pub mod audio;
+pub mod backend;
+pub mod builder;
+pub mod bytecode;
+#[cfg(feature = "std")]
+pub mod cache;
+pub mod callgraph;
+pub mod catalog;
+pub mod checker;
+pub mod clock;
+pub mod common;
+#[cfg(feature = "std")]
+pub mod diagnostics;
+#[cfg(feature = "std")]
+pub mod doc;
+pub mod doctest;
+#[cfg(feature = "std")]
+pub mod explain;
+pub mod fix;
+pub mod fixed;
+#[cfg(feature = "std")]
+pub mod flame;
+#[cfg(feature = "std")]
+pub mod genprog;
+#[cfg(feature = "std")]
+pub mod grammar;
+pub mod heap;
+#[cfg(feature = "std")]
+pub mod heatmap;
+#[cfg(feature = "std")]
+pub mod host;
+#[cfg(feature = "std")]
+pub mod include;
+pub mod incremental;
+pub mod intern;
+pub mod interrupt;
+#[cfg(feature = "std")]
+pub mod json;
+pub mod line_index;
+#[cfg(feature = "std")]
+pub mod manifest;
+pub mod parser;
+#[cfg(feature = "std")]
+pub mod playground;
+pub mod plugin;
+#[cfg(feature = "std")]
+pub mod printer;
+#[cfg(feature = "repl")]
+pub mod repl;
+pub mod replay;
+pub mod rng;
+pub mod selftest;
+#[cfg(feature = "serve")]
+pub mod server;
+#[cfg(feature = "std")]
+pub mod source_map;
+pub mod stack;
+pub mod stack_machine;
+pub mod stats;
+pub mod suggest;
+pub mod symexec;
+pub mod syscall;
+pub mod tokenizer;
+#[cfg(feature = "turtle")]
+pub mod turtle;
+pub mod verify_opt;
+#[cfg(feature = "std")]
+pub mod viz;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "watch")]
+pub mod watch;
+pub mod words;
+pub mod x86_64;
@@ -0,0 +1,137 @@
+// Maps byte offsets to 1-based line and column numbers. Lines end at `\n`;
+// a `\r` before it belongs to the line break, and every other character,
+// tabs included, is one column wide.
+use alloc::vec;
+use alloc::vec::Vec;
+
+pub struct LineIndex {
+    line_starts: Vec<usize>,
+}
+
+impl LineIndex {
+    pub fn new(text: &str) -> Self {
+        let mut line_starts = vec![0];
+        line_starts.extend(
+            text.bytes()
+                .enumerate()
+                .filter(|(_, b)| *b == b'\n')
+                .map(|(offset, _)| offset + 1),
+        );
+        Self { line_starts }
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.line_starts.len()
+    }
+
+    pub fn line_start(&self, line: usize) -> Option<usize> {
+        self.line_starts.get(line.checked_sub(1)?).copied()
+    }
+
+    pub fn line_col(&self, text: &str, offset: usize) -> (usize, usize) {
+        let line = match self.line_starts.binary_search(&offset) {
+            Ok(line) => line,
+            Err(next) => next - 1,
+        };
+        let column = text[self.line_starts[line]..offset].chars().count();
+        (line + 1, column + 1)
+    }
+
+    pub fn offset(&self, text: &str, line: usize, column: usize) -> Option<usize> {
+        let start = self.line_start(line)?;
+        let line_text = self.line_text(text, line)?;
+        match line_text.char_indices().nth(column.checked_sub(1)?) {
+            Some((offset, _)) => Some(start + offset),
+            None if column - 1 == line_text.chars().count() => Some(start + line_text.len()),
+            None => None,
+        }
+    }
+
+    pub fn line_text<'a>(&self, text: &'a str, line: usize) -> Option<&'a str> {
+        let start = self.line_start(line)?;
+        let end = self.line_start(line + 1).unwrap_or(text.len());
+        Some(
+            text[start..end]
+                .trim_end_matches('\n')
+                .trim_end_matches('\r'),
+        )
+    }
+}
+
+// `column`, counted one per character as `line_col` does, as an editor shows
+// it with tab stops every `tab_width` columns. A width of 1 changes nothing.
+pub fn tab_column(line_text: &str, column: usize, tab_width: usize) -> usize {
+    let tab_width = tab_width.max(1);
+    let before = line_text.chars().take(column.saturating_sub(1));
+    let shown = before.fold(0, |at, c| {
+        if c == '\t' {
+            (at / tab_width + 1) * tab_width
+        } else {
+            at + 1
+        }
+    });
+    // Past the end of the line, every column is one character.
+    shown + 1 + column.saturating_sub(1 + line_text.chars().count())
+}
+
+#[cfg(test)]
+mod line_index_tests {
+    use super::*;
+
+    #[test]
+    fn maps_offsets_to_lines_and_columns() {
+        let text = "ab\ncd\n\nef";
+        let index = LineIndex::new(text);
+        assert_eq!(index.line_count(), 4);
+        assert_eq!(index.line_col(text, 0), (1, 1));
+        assert_eq!(index.line_col(text, 1), (1, 2));
+        assert_eq!(index.line_col(text, 3), (2, 1));
+        assert_eq!(index.line_col(text, 6), (3, 1));
+        assert_eq!(index.line_col(text, 8), (4, 2));
+    }
+
+    #[test]
+    fn columns_count_characters() {
+        let text = "é\tx";
+        let index = LineIndex::new(text);
+        assert_eq!(index.line_col(text, 3), (1, 3));
+        assert_eq!(index.offset(text, 1, 3), Some(3));
+    }
+
+    #[test]
+    fn line_text_strips_line_breaks() {
+        let text = "one\r\ntwo\nthree";
+        let index = LineIndex::new(text);
+        assert_eq!(index.line_text(text, 1), Some("one"));
+        assert_eq!(index.line_text(text, 2), Some("two"));
+        assert_eq!(index.line_text(text, 3), Some("three"));
+        assert_eq!(index.line_text(text, 4), None);
+        assert_eq!(index.line_col(text, 5), (2, 1));
+    }
+
+    #[test]
+    fn offset_is_inverse_of_line_col() {
+        let text = "a b\n  c";
+        let index = LineIndex::new(text);
+        for offset in 0..text.len() {
+            let (line, column) = index.line_col(text, offset);
+            assert_eq!(index.offset(text, line, column), Some(offset));
+        }
+        assert_eq!(index.offset(text, 1, 9), None);
+    }
+
+    #[test]
+    fn tabs_move_to_the_next_stop() {
+        let text = "\tab\t\tc";
+        assert_eq!(tab_column(text, 1, 4), 1);
+        assert_eq!(tab_column(text, 2, 4), 5);
+        assert_eq!(tab_column(text, 4, 4), 7);
+        assert_eq!(tab_column(text, 5, 4), 9);
+        assert_eq!(tab_column(text, 6, 4), 13);
+        assert_eq!(tab_column(text, 6, 8), 25);
+        assert_eq!(tab_column("  \tx", 4, 4), 5);
+        for column in 1..=8 {
+            assert_eq!(tab_column(text, column, 1), column);
+        }
+    }
+}
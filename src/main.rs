@@ -1,26 +1,1299 @@
-use common::Error;
-use parser::parse;
-use stack::VecStack;
-use stack_machine::StackMachine;
-use tokenizer::tokenize;
-
-mod checker;
-mod common;
-mod parser;
-mod stack;
-mod stack_machine;
-mod tokenizer;
-
-fn main() -> Result<(), Error> {
-    let args: Vec<String> = std::env::args().collect();
-    let input = &args[1];
-    let input = std::fs::read_to_string(input).expect("Failed to read file");
-    let tokens = tokenize(&input)?;
-    let program = parse(tokens)?;
-    let mut machine = StackMachine::new(VecStack::new());
-    let result = machine.execute(program)?;
-    for value in result {
-        println!("{}", value);
+use std::cell::RefCell;
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use stack_machine_bez::backend;
+use stack_machine_bez::bytecode;
+use stack_machine_bez::cache::{self, Cache};
+use stack_machine_bez::callgraph;
+use stack_machine_bez::catalog::{self, Table};
+use stack_machine_bez::checker::{self, check_loop_conditions, peek_loops};
+use stack_machine_bez::common::Error;
+use stack_machine_bez::diagnostics::{ColorChoice, Renderer, Severity};
+use stack_machine_bez::doc;
+use stack_machine_bez::doctest;
+use stack_machine_bez::explain::explain;
+use stack_machine_bez::fix;
+use stack_machine_bez::flame;
+use stack_machine_bez::grammar;
+use stack_machine_bez::heatmap;
+use stack_machine_bez::host::{self, programs_in_turn, run_each, FsLoader};
+use stack_machine_bez::include::Includer;
+use stack_machine_bez::interrupt;
+use stack_machine_bez::manifest::{locate, scaffold};
+use stack_machine_bez::parser::parse_recovering;
+use stack_machine_bez::plugin;
+#[cfg(feature = "repl")]
+use stack_machine_bez::repl::Session;
+use stack_machine_bez::replay::{self, Event};
+use stack_machine_bez::selftest;
+#[cfg(feature = "serve")]
+use stack_machine_bez::server::serve;
+use stack_machine_bez::source_map::{token_source, SourceMap};
+use stack_machine_bez::stack::VecStack;
+use stack_machine_bez::stack_machine::{Program, StackMachine, VmConfig};
+use stack_machine_bez::stats::{self, Stats};
+use stack_machine_bez::symexec::{self, explore};
+use stack_machine_bez::tokenizer::{tokenize, Token, TokenType};
+use stack_machine_bez::verify_opt;
+use stack_machine_bez::viz;
+#[cfg(feature = "watch")]
+use stack_machine_bez::watch::{self, Watcher};
+use stack_machine_bez::words;
+
+use cli::{parse_args, take_globals, Command, Output, Viz};
+#[cfg(feature = "repl")]
+use console::Console;
+
+mod cli;
+#[cfg(feature = "repl")]
+mod console;
+
+// An error and the file it came from, if any, so it can be shown in context.
+struct Failure {
+    error: Error,
+    path: Option<PathBuf>,
+    // The error's position is known to be in `path`, includes or not.
+    exact: bool,
+    // Shown after the error, see `--dump-stack-on-error`.
+    dump: Option<Box<str>>,
+}
+
+impl From<Error> for Failure {
+    fn from(error: Error) -> Self {
+        Failure {
+            error,
+            path: None,
+            exact: false,
+            dump: None,
+        }
+    }
+}
+
+fn in_file(path: &Path) -> impl Fn(Error) -> Failure + '_ {
+    move |error| Failure {
+        error,
+        path: Some(path.to_path_buf()),
+        exact: false,
+        dump: None,
+    }
+}
+
+// Errors from running a compiled program point at the source it was built
+// from, when its source map says which file that is.
+fn in_program(path: &Path) -> impl Fn(Error) -> Failure + '_ {
+    move |error| {
+        let source = is_compiled(path)
+            .then(|| std::fs::read(path).ok())
+            .flatten()
+            .and_then(|bytes| SourceMap::read(path, &bytes).ok())
+            .and_then(|map| {
+                let (line, pos) = error.position()?;
+                map.source_at(line, pos).map(Path::to_path_buf)
+            });
+        match source {
+            Some(source) => Failure {
+                error,
+                path: Some(source),
+                exact: true,
+                dump: None,
+            },
+            None => in_file(path)(error),
+        }
+    }
+}
+
+// Positions only say line and column, not which file, so with includes in
+// play the excerpt could come from the wrong one. Leave it out then.
+fn excerpt_source(path: &Path) -> Option<String> {
+    if is_compiled(path) {
+        return None;
+    }
+    let source = std::fs::read_to_string(path).ok()?;
+    let tokens = tokenize(&source).unwrap_or_default();
+    if tokens.iter().any(|t| t.token_type == TokenType::Include) {
+        None
+    } else {
+        Some(source)
+    }
+}
+
+fn is_compiled(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext == bytecode::EXTENSION)
+}
+
+fn io_error(path: &Path) -> impl Fn(std::io::Error) -> Error + '_ {
+    move |e| Error::Io {
+        path: path.display().to_string(),
+        comment: e.to_string(),
+    }
+}
+
+fn current_dir() -> Result<PathBuf, Error> {
+    std::env::current_dir().map_err(|e| Error::Io {
+        path: ".".to_string(),
+        comment: e.to_string(),
+    })
+}
+
+fn stdout_error(e: std::io::Error) -> Error {
+    Error::Io {
+        path: "<stdout>".to_string(),
+        comment: e.to_string(),
+    }
+}
+
+// The file to work on and where its includes are looked up: the command line
+// paths first, then the ones from the project manifest.
+fn project(
+    path: Option<String>,
+    include_paths: Vec<String>,
+) -> Result<(PathBuf, Vec<PathBuf>), Error> {
+    let project = locate(&current_dir()?, path.as_deref().map(Path::new))?;
+    let mut search_paths: Vec<PathBuf> = include_paths.iter().map(PathBuf::from).collect();
+    search_paths.extend(project.search_paths);
+    Ok((project.entry, search_paths))
+}
+
+// The tokens of `entry` with its includes spliced in, and which file each
+// run of them came from.
+type Source = (Vec<Token<'static>>, Vec<(usize, PathBuf)>);
+
+fn load_source(
+    entry: &Path,
+    search_paths: Vec<PathBuf>,
+    cache: Option<&Cache>,
+) -> Result<Source, Error> {
+    let mut includer = Includer::new(&FsLoader, search_paths).with_cache(cache);
+    let tokens = includer.load(entry)?;
+    Ok((tokens, includer.origins().to_vec()))
+}
+
+fn parse_tokens(tokens: Vec<Token>, cache: Option<&Cache>) -> Result<Program, Error> {
+    match cache {
+        Some(cache) => cache.program(tokens),
+        None => parse_recovering(tokens).map_err(Error::from_vec),
+    }
+}
+
+fn load_program(
+    entry: &Path,
+    search_paths: Vec<PathBuf>,
+    cache: Option<&Cache>,
+) -> Result<Program, Error> {
+    if is_compiled(entry) {
+        let bytes = std::fs::read(entry).map_err(io_error(entry))?;
+        return bytecode::decode(&bytes);
+    }
+    let (tokens, _) = load_source(entry, search_paths, cache)?;
+    parse_tokens(tokens, cache)
+}
+
+// Where each instruction of `program`, loaded from `entry`, came from. A
+// compiled program has one only if `rorth build` wrote it alongside.
+fn source_map(
+    entry: &Path,
+    search_paths: Vec<PathBuf>,
+    cache: Option<&Cache>,
+    program: &Program,
+) -> Result<Option<SourceMap>, Error> {
+    if is_compiled(entry) {
+        let bytes = std::fs::read(entry).map_err(io_error(entry))?;
+        return Ok(SourceMap::read(entry, &bytes).ok());
+    }
+    let (tokens, origins) = load_source(entry, search_paths, cache)?;
+    Ok(Some(SourceMap::new(program, &tokens, &origins)))
+}
+
+// `--strict-loops`: old-style loops are errors, so a program can be moved to
+// `while … do … end` one loop at a time. `--strict-entry`: so is code outside
+// every function, and a function that runs on into the next.
+#[derive(Clone, Copy)]
+struct Strict<'a> {
+    loops: bool,
+    entry: bool,
+    function: &'a str,
+}
+
+impl Strict<'_> {
+    fn check(self, program: &Program) -> Result<(), Error> {
+        let mut errors = vec![];
+        if self.loops {
+            errors.extend(peek_loops(program));
+        }
+        if self.entry {
+            errors.extend(checker::strict_entry(program, self.function));
+        }
+        errors.sort_by_key(|error| error.position());
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::from_vec(errors))
+        }
+    }
+}
+
+// Each warning shown in the file it came from, when `map` says which.
+fn warn(renderer: &Renderer, entry: &Path, map: Option<&SourceMap>, warnings: &[Error]) {
+    for warning in warnings {
+        let exact = map.and_then(|map| {
+            let (line, pos) = warning.position()?;
+            map.source_at(line, pos)
+        });
+        let (path, source) = match exact {
+            Some(path) => (path, std::fs::read_to_string(path).ok()),
+            None => (entry, excerpt_source(entry)),
+        };
+        let path = path.display().to_string();
+        eprint!(
+            "{}",
+            renderer.render(Severity::Warning, warning, source.as_deref(), Some(&path))
+        );
+    }
+}
+
+fn report(renderer: &Renderer, failure: Failure) {
+    let Failure {
+        error,
+        path,
+        exact,
+        dump,
+    } = failure;
+    let source = match &path {
+        Some(path) if exact => std::fs::read_to_string(path).ok(),
+        path => path.as_deref().and_then(excerpt_source),
+    };
+    let path = path.map(|p| p.display().to_string());
+    eprint!(
+        "{}",
+        renderer.render(Severity::Error, &error, source.as_deref(), path.as_deref())
+    );
+    if let Some(dump) = dump {
+        eprint!("\n{}", dump);
+    }
+}
+
+// Each of the errors from parsing or running `tokens` in the file it came
+// from, when the tokens there, or the instructions in `map`, all say which.
+fn in_sources(
+    error: Error,
+    entry: &Path,
+    tokens: &[Token],
+    origins: &[(usize, PathBuf)],
+    map: Option<&SourceMap>,
+) -> Vec<Failure> {
+    error
+        .into_vec()
+        .into_iter()
+        .map(|error| {
+            let source = error.position().and_then(|(line, pos)| match map {
+                Some(map) => map.source_at(line, pos),
+                None => token_source(tokens, origins, line, pos),
+            });
+            match source {
+                Some(source) => Failure {
+                    error,
+                    path: Some(source.to_path_buf()),
+                    exact: true,
+                    dump: None,
+                },
+                None => in_file(entry)(error),
+            }
+        })
+        .collect()
+}
+
+// Reports all but the last failure, which is left to report on the way out.
+fn all_but_last(renderer: &Renderer, mut failures: Vec<Failure>) -> Failure {
+    let last = failures.pop().expect("at least one failure");
+    for failure in failures {
+        report(renderer, failure);
+        eprintln!();
+    }
+    last
+}
+
+// What `run` keeps from a run besides its output.
+struct Keep {
+    // The stacks and code around an error, see `Renderer::crash`.
+    dump_stack: bool,
+    // Where to write what `rand` and `time` returned, see `replay`.
+    record: Option<PathBuf>,
+    // `--stats`, and `--profile=annotate`, which counts without the report.
+    stats: bool,
+    annotate: bool,
+    // The machine, stack and memory, for the next run with `--watch`.
+    state: bool,
+}
+
+impl Keep {
+    fn show_profile(
+        &self,
+        stats: &Stats,
+        program: &Program,
+        map: Option<&SourceMap>,
+        elapsed: Duration,
+    ) {
+        if self.stats {
+            eprint!("{}", stats::report(stats, program, elapsed));
+        }
+        if !self.annotate {
+            return;
+        }
+        match map {
+            Some(map) => eprint!(
+                "{}",
+                heatmap::annotate(map, &stats.executed, |path| {
+                    std::fs::read_to_string(path).ok()
+                })
+            ),
+            None => eprintln!("no source map to annotate; rebuild the program to get one"),
+        }
+    }
+
+    fn save_recording(&self, events: &[Event]) -> Result<(), Error> {
+        let Some(path) = &self.record else {
+            return Ok(());
+        };
+        std::fs::write(path, replay::write(events)).map_err(|e| Error::Io {
+            path: path.display().to_string(),
+            comment: e.to_string(),
+        })
+    }
+}
+
+fn read_messages(path: &str) -> Result<Table, Error> {
+    let text = std::fs::read_to_string(path).map_err(|e| Error::Io {
+        path: path.to_string(),
+        comment: e.to_string(),
+    })?;
+    Table::parse(&text)
+}
+
+fn read_replay(path: &str) -> Result<Vec<Event>, Error> {
+    let io = |comment: String| Error::Io {
+        path: path.to_string(),
+        comment,
+    };
+    let text = std::fs::read_to_string(path).map_err(|e| io(e.to_string()))?;
+    replay::read(&text).map_err(io)
+}
+
+// The machines `run` runs on. With `--output` and `--quiet` the values
+// `print` writes go to stdout as they are printed, separated as asked; one
+// per line, the default, is left to `host`, which writes them out after each
+// run.
+struct Machines {
+    config: VmConfig,
+    output: Output,
+    // Whether a value has been written since the last `finish`, and the
+    // first error writing one.
+    written: Rc<RefCell<(bool, Option<std::io::Error>)>>,
+}
+
+impl Machines {
+    fn new(config: VmConfig, output: Output) -> Self {
+        Machines {
+            config,
+            output,
+            written: Rc::default(),
+        }
+    }
+
+    fn machine(&self) -> StackMachine<VecStack<i32>> {
+        let mut machine = host::machine(self.config.clone());
+        let separator = match self.output {
+            Output::Lines => return machine,
+            Output::None => {
+                machine.set_output(|_| {});
+                return machine;
+            }
+            Output::Space => " ",
+            Output::Csv => ",",
+        };
+        let written = self.written.clone();
+        machine.set_output(move |text| {
+            let (any, error) = &mut *written.borrow_mut();
+            let before = if *any { separator } else { "" };
+            *any = true;
+            if error.is_none() {
+                *error = write!(std::io::stdout(), "{}{}", before, text).err();
+            }
+        });
+        machine
+    }
+
+    // Ends the line the values of one run went on.
+    fn finish(&self, out: &mut dyn Write) -> Result<(), Error> {
+        let (any, error) = std::mem::take(&mut *self.written.borrow_mut());
+        if let Some(error) = error {
+            return Err(stdout_error(error));
+        }
+        if any {
+            writeln!(out).map_err(stdout_error)?;
+        }
+        Ok(())
+    }
+}
+
+// `rorth run lib.sm app.sm`: the files run one after another on one machine,
+// each seeing what the ones before it defined. An include is loaded once for
+// all of them.
+fn run_in_turn_files(
+    entries: &[PathBuf],
+    search_paths: Vec<PathBuf>,
+    cache: Option<&Cache>,
+    strict: Strict<'_>,
+    keep: &Keep,
+    machines: &Machines,
+    renderer: &Renderer,
+) -> Result<(), Failure> {
+    let mut includer = Includer::new(&FsLoader, search_paths).with_cache(cache);
+    let mut files = vec![];
+    for entry in entries {
+        if is_compiled(entry) {
+            return Err(in_file(entry)(Error::Usage {
+                comment: "only `.sm` files can be run one after another".to_string(),
+            }));
+        }
+        files.push(includer.load(entry).map_err(in_file(entry))?);
+    }
+    let tokens: Vec<Token> = files.iter().flatten().cloned().collect();
+    let origins = includer.origins();
+    let entry = &entries[0];
+    let programs = programs_in_turn(files, |tokens| parse_tokens(tokens, cache))
+        .map_err(|e| all_but_last(renderer, in_sources(e, entry, &tokens, origins, None)))?;
+    for (i, program) in programs.iter().enumerate() {
+        // The files before the last needn't have a `main`; the last program
+        // has all their code in it.
+        let strict = Strict {
+            entry: strict.entry && i + 1 == programs.len(),
+            ..strict
+        };
+        strict
+            .check(program)
+            .map_err(|e| all_but_last(renderer, in_sources(e, entry, &tokens, origins, None)))?;
+    }
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+    let started = Instant::now();
+    let outcome = run_each(&mut machines.machine(), &programs, &mut out);
+    machines.finish(&mut out)?;
+    let last = programs.last().expect("one program per run");
+    // Every program is the one before it with more added, so the last one
+    // maps every instruction that ran.
+    let map = SourceMap::new(last, &tokens, origins);
+    keep.show_profile(&outcome.stats, last, Some(&map), started.elapsed());
+    out.flush().map_err(stdout_error)?;
+    let saved = keep.save_recording(&outcome.recording);
+    let dump = outcome
+        .crash
+        .filter(|_| keep.dump_stack)
+        .map(|(i, crash)| renderer.crash(&programs[i], &crash).into());
+    saved?;
+    outcome.result.map_err(|e| {
+        let mut failures = in_sources(e, entry, &tokens, origins, Some(&map));
+        if let Some(last) = failures.last_mut() {
+            last.dump = dump;
+        }
+        all_but_last(renderer, failures)
+    })
+}
+
+// Each file tokens came from, once.
+fn files_of(origins: &[(usize, PathBuf)]) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = vec![];
+    for (_, path) in origins {
+        if !files.contains(path) {
+            files.push(path.clone());
+        }
+    }
+    files
+}
+
+// Everything `build` would find wrong with `entry`, without writing anything.
+// Returns the files that were read, for `--watch`; errors in an included
+// file point at it.
+fn check(
+    entry: &Path,
+    search_paths: Vec<PathBuf>,
+    cache: Option<&Cache>,
+    verifiable: Option<usize>,
+    renderer: &Renderer,
+) -> Result<Vec<PathBuf>, Vec<Failure>> {
+    let (tokens, origins) =
+        load_source(entry, search_paths, cache).map_err(|e| vec![in_file(entry)(e)])?;
+    let files = files_of(&origins);
+    let program = parse_tokens(tokens.clone(), cache)
+        .map_err(|e| in_sources(e, entry, &tokens, &origins, None))?;
+    if let Some(max_size) = verifiable {
+        let errors = checker::verifiable(&program, &tokens, max_size);
+        if !errors.is_empty() {
+            return Err(in_sources(
+                Error::from_vec(errors),
+                entry,
+                &tokens,
+                &origins,
+                None,
+            ));
+        }
+    }
+    let map = SourceMap::new(&program, &tokens, &origins);
+    warn(
+        renderer,
+        entry,
+        Some(&map),
+        &[
+            check_loop_conditions(&program),
+            checker::zero_divisors(&program),
+            checker::unused_values(&program),
+        ]
+        .concat(),
+    );
+    Ok(files)
+}
+
+// `rorth test --doc`: runs `entry` and says which of its `#=>` lines
+// printed what they say, and which didn't.
+// Rewrites each file's renamed words, see `fix`, saying what changed. With
+// `dry_run` the files are left as they are.
+fn fix(paths: &[String], dry_run: bool) -> Result<(), Failure> {
+    let mut fixed = (0, 0);
+    for path in paths {
+        let path = Path::new(path);
+        let source = std::fs::read_to_string(path).map_err(io_error(path))?;
+        let (rewritten, changes) = fix::rewrite(&source, words::RENAMED).map_err(in_file(path))?;
+        for change in &changes {
+            println!(
+                "{}:{}:{}: `{}` is `{}` since {}",
+                path.display(),
+                change.line,
+                change.pos,
+                change.old,
+                change.new,
+                change.since
+            );
+        }
+        if changes.is_empty() {
+            continue;
+        }
+        if !dry_run {
+            std::fs::write(path, rewritten).map_err(io_error(path))?;
+        }
+        fixed = (fixed.0 + changes.len(), fixed.1 + 1);
+    }
+    println!(
+        "{} {} word(s) in {} file(s)",
+        if dry_run { "would fix" } else { "fixed" },
+        fixed.0,
+        fixed.1
+    );
+    Ok(())
+}
+
+fn doc_test(
+    entry: &Path,
+    search_paths: Vec<PathBuf>,
+    cache: Option<&Cache>,
+    renderer: &Renderer,
+) -> Result<(), Failure> {
+    if is_compiled(entry) {
+        return Err(Error::Usage {
+            comment: "`rorth test --doc` reads `#=>` comments from a `.sm` file".to_string(),
+        }
+        .into());
+    }
+    let source = std::fs::read_to_string(entry).map_err(io_error(entry))?;
+    let (tokens, origins) = load_source(entry, search_paths, cache).map_err(in_file(entry))?;
+    let program = parse_tokens(tokens.clone(), cache)
+        .map_err(|e| all_but_last(renderer, in_sources(e, entry, &tokens, &origins, None)))?;
+    let map = SourceMap::new(&program, &tokens, &origins);
+    // Lines of included files aren't lines of this one.
+    let own = map.sources.iter().position(|path| path == entry);
+    let lines: Vec<Option<usize>> = map
+        .instructions
+        .iter()
+        .map(|origin| (Some(origin.source) == own).then_some(origin.line))
+        .collect();
+    let config = VmConfig {
+        interruptible: true,
+        ..VmConfig::default().deterministic()
+    };
+    let report = doctest::run(&program, &source, config, move |index| {
+        lines.get(index).copied().flatten()
+    });
+    let mut failed = 0;
+    for check in &report.checks {
+        let place = format!("{}:{}", entry.display(), check.line);
+        if check.passed() {
+            println!("ok    {}", place);
+        } else {
+            failed += 1;
+            let printed = match check.printed.is_empty() {
+                true => "nothing".to_string(),
+                false => format!("`{}`", check.printed.join(" ")),
+            };
+            println!(
+                "FAIL  {}: expected `{}`, printed {}",
+                place,
+                check.expected.join(" "),
+                printed
+            );
+        }
+    }
+    let total = report.checks.len();
+    println!("{} passed, {} failed", total - failed, failed);
+    if let Err(error) = report.result {
+        let failures = in_sources(error, entry, &tokens, &origins, Some(&map));
+        return Err(all_but_last(renderer, failures));
+    }
+    if failed > 0 {
+        return Err(Error::Usage {
+            comment: format!("{} of {} `#=>` lines didn't match", failed, total),
+        }
+        .into());
     }
     Ok(())
 }
+
+// `rorth symexec`: every failure `function` was found to reach, each with
+// the inputs that reach it.
+fn symexec(
+    entry: &Path,
+    search_paths: Vec<PathBuf>,
+    cache: Option<&Cache>,
+    function: &str,
+    limits: symexec::Limits,
+) -> Result<(), Vec<Failure>> {
+    let (tokens, origins) =
+        load_source(entry, search_paths, cache).map_err(|e| vec![in_file(entry)(e)])?;
+    let program = parse_tokens(tokens.clone(), cache)
+        .map_err(|e| in_sources(e, entry, &tokens, &origins, None))?;
+    let report = explore(&program, function, limits).map_err(|e| vec![in_file(entry)(e)])?;
+    eprintln!(
+        "followed {} paths, {} cut short",
+        report.paths, report.cut_short
+    );
+    let map = SourceMap::new(&program, &tokens, &origins);
+    let failures: Vec<Failure> = report
+        .findings
+        .into_iter()
+        .flat_map(|finding| {
+            let inputs = format!("{}\n", finding.inputs());
+            in_sources(finding.error, entry, &tokens, &origins, Some(&map))
+                .into_iter()
+                .map(move |failure| Failure {
+                    dump: Some(inputs.clone().into()),
+                    ..failure
+                })
+        })
+        .collect();
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(failures)
+    }
+}
+
+// `rorth run --watch`: runs `entry` again each time it or a file it includes
+// is saved, until Ctrl-C. Every run starts on a new machine, or with
+// `keep.state` on the one the last run left, stack and memory included.
+#[cfg(feature = "watch")]
+fn run_on_save(
+    entry: &Path,
+    search_paths: Vec<PathBuf>,
+    cache: Option<&Cache>,
+    keep: &Keep,
+    strict: Strict<'_>,
+    machines: &Machines,
+    renderer: &Renderer,
+) -> Result<(), Failure> {
+    let mut machine = machines.machine();
+    // Kept when loading fails, so fixing a broken include is noticed.
+    let mut files = vec![entry.to_path_buf()];
+    loop {
+        if !keep.state {
+            machine = machines.machine();
+        }
+        // Saved while the program ran, which stops it.
+        let mut saved = vec![];
+        let ran = load_source(entry, search_paths.clone(), cache)
+            .map_err(|e| vec![in_file(entry)(e)])
+            .and_then(|(tokens, origins)| {
+                files = files_of(&origins);
+                let failed = |e| in_sources(e, entry, &tokens, &origins, None);
+                let program = parse_tokens(tokens.clone(), cache).map_err(failed)?;
+                strict.check(&program).map_err(failed)?;
+                let map = SourceMap::new(&program, &tokens, &origins);
+                let stdout = std::io::stdout();
+                let mut out = stdout.lock();
+                let (result, changed) = Watcher::new(files.clone())
+                    .interrupt_on_change(watch::INTERVAL, || {
+                        host::run_on(&mut machine, &program, &mut out).0
+                    });
+                saved = changed;
+                machines
+                    .finish(&mut out)
+                    .and_then(|()| out.flush().map_err(stdout_error))
+                    .map_err(|e| vec![e.into()])?;
+                result.map_err(|e| in_sources(e, entry, &tokens, &origins, Some(&map)))
+            });
+        match ran {
+            Ok(()) => eprintln!("{}: done", entry.display()),
+            Err(failures) => {
+                if let Some(stop) = failures
+                    .iter()
+                    .position(|f| matches!(f.error, Error::Interrupted { .. }))
+                {
+                    if saved.is_empty() {
+                        return Err(failures.into_iter().nth(stop).expect("found above"));
+                    }
+                    eprintln!("{}: stopped", entry.display());
+                } else {
+                    for (i, failure) in failures.into_iter().enumerate() {
+                        if i > 0 {
+                            eprintln!();
+                        }
+                        report(renderer, failure);
+                    }
+                    files.push(entry.to_path_buf());
+                }
+            }
+        }
+        let changed = if saved.is_empty() {
+            let mut watcher = Watcher::new(files.clone());
+            eprintln!("watching {} file(s), Ctrl-C to stop", watcher.len());
+            let Some(changed) = watcher.wait(watch::INTERVAL) else {
+                return Ok(());
+            };
+            changed
+        } else {
+            saved
+        };
+        eprintln!();
+        for path in changed {
+            eprintln!("{} changed", path.display());
+        }
+    }
+}
+
+// Checks `entry` again each time it or a file it includes is saved, until
+// Ctrl-C.
+#[cfg(feature = "watch")]
+fn check_on_save(
+    entry: &Path,
+    search_paths: Vec<PathBuf>,
+    cache: Option<&Cache>,
+    verifiable: Option<usize>,
+    renderer: &Renderer,
+) -> Result<(), Failure> {
+    // Kept when a check fails early, so fixing a broken include is noticed.
+    let mut files = vec![entry.to_path_buf()];
+    loop {
+        match check(entry, search_paths.clone(), cache, verifiable, renderer) {
+            Ok(read) => {
+                files = read;
+                eprintln!("{}: ok", entry.display());
+            }
+            Err(failures) => {
+                for (i, failure) in failures.into_iter().enumerate() {
+                    if i > 0 {
+                        eprintln!();
+                    }
+                    report(renderer, failure);
+                }
+                files.push(entry.to_path_buf());
+            }
+        }
+        let mut watcher = Watcher::new(files.clone());
+        eprintln!("watching {} file(s), Ctrl-C to stop", watcher.len());
+        let Some(changed) = watcher.wait(watch::INTERVAL) else {
+            return Ok(());
+        };
+        eprintln!();
+        for path in changed {
+            eprintln!("{} changed", path.display());
+        }
+    }
+}
+
+fn visualize(
+    program: &Program,
+    entry: &Path,
+    config: VmConfig,
+    options: &Viz,
+    out: &mut dyn Write,
+) -> Result<(), Error> {
+    let recording = viz::record(program, config, &options.lines);
+    if options.text {
+        for frame in &recording.frames {
+            eprint!("{}", viz::text(frame));
+        }
+    }
+    if let Some(path) = &options.html {
+        let title = entry.display().to_string();
+        std::fs::write(path, viz::html(&recording.frames, &title))
+            .map_err(io_error(Path::new(path)))?;
+    }
+    for value in recording.printed {
+        writeln!(out, "{}", value).map_err(stdout_error)?;
+    }
+    recording.result
+}
+
+fn trace(
+    program: &Program,
+    config: VmConfig,
+    path: &Path,
+    out: &mut dyn Write,
+) -> Result<(), Error> {
+    let profile = flame::record(program, config);
+    std::fs::write(path, flame::chrome_json(&profile.events)).map_err(io_error(path))?;
+    for value in profile.printed {
+        writeln!(out, "{}", value).map_err(stdout_error)?;
+    }
+    profile.result
+}
+
+#[cfg(feature = "repl")]
+fn repl(config: VmConfig, renderer: &Renderer) -> Result<(), Error> {
+    let mut session = Session::new(VmConfig {
+        interruptible: true,
+        ..config
+    });
+    let mut console = Console::new()?;
+    // A line that leaves a block open waits under a `...>` prompt for the
+    // lines that close it. Ctrl-C there drops what was typed so far.
+    let mut pending = String::new();
+    loop {
+        let prompt = if pending.is_empty() { "> " } else { "...> " };
+        let Some(line) = console.read_line(prompt, &session)? else {
+            break;
+        };
+        if interrupt::take() && !pending.is_empty() {
+            pending.clear();
+            continue;
+        }
+        if pending.is_empty() && Session::is_command(&line) {
+            match session.command(&line, &FsLoader) {
+                Ok(message) => println!("{}", message),
+                Err(e) => eprint!("{}", renderer.render(Severity::Error, &e, None, None)),
+            }
+            continue;
+        }
+        pending.push_str(&line);
+        if !line.ends_with('\n') {
+            pending.push('\n');
+        }
+        if !Session::is_complete(&pending) {
+            continue;
+        }
+        let line = std::mem::take(&mut pending);
+        match session.eval(&line) {
+            Ok(printed) => {
+                for line in printed {
+                    println!("{}", line);
+                }
+            }
+            Err(e) => {
+                for line in session.take_output() {
+                    println!("{}", line);
+                }
+                eprint!(
+                    "{}",
+                    renderer.render(Severity::Error, &e, Some(&line), None)
+                )
+            }
+        }
+        if !session.stack().is_empty() {
+            println!("{}", renderer.stack(session.stack()));
+        }
+    }
+    console.finish();
+    Ok(())
+}
+
+fn run(args: &[String], renderer: &Renderer, cache: Option<&Cache>) -> Result<(), Failure> {
+    match parse_args(args)? {
+        Command::Run {
+            path,
+            more,
+            include_paths,
+            config,
+            viz,
+            strict_loops,
+            strict_entry,
+            dump_stack,
+            record,
+            replay,
+            trace_json,
+            annotate,
+            watch,
+            keep_state,
+            output,
+        } => {
+            let (entry, search_paths) = project(path, include_paths)?;
+            let function = config.entry().to_string();
+            let strict = Strict {
+                loops: strict_loops,
+                entry: strict_entry,
+                function: &function,
+            };
+            let keep = Keep {
+                dump_stack,
+                record: record.map(PathBuf::from),
+                stats: config.stats,
+                annotate,
+                state: keep_state,
+            };
+            let config = VmConfig {
+                interruptible: true,
+                stats: keep.stats || keep.annotate,
+                record: keep.record.is_some(),
+                replay: replay.as_deref().map(read_replay).transpose()?,
+                ..config
+            };
+            let machines = Machines::new(config.clone(), output);
+            if !more.is_empty() {
+                let entries: Vec<PathBuf> = std::iter::once(entry)
+                    .chain(more.iter().map(PathBuf::from))
+                    .collect();
+                return run_in_turn_files(
+                    &entries,
+                    search_paths,
+                    cache,
+                    strict,
+                    &keep,
+                    &machines,
+                    renderer,
+                );
+            }
+            // `parse_args` only accepts `--watch` in builds that have it.
+            #[cfg(feature = "watch")]
+            if watch {
+                return run_on_save(
+                    &entry,
+                    search_paths,
+                    cache,
+                    &keep,
+                    strict,
+                    &machines,
+                    renderer,
+                );
+            }
+            #[cfg(not(feature = "watch"))]
+            let _ = (watch, keep.state);
+            if strict.loops || strict.entry {
+                load_program(&entry, search_paths.clone(), cache)
+                    .and_then(|program| strict.check(&program))
+                    .map_err(in_file(&entry))?;
+            }
+            let stdout = std::io::stdout();
+            let mut out = stdout.lock();
+            let mut dump = None;
+            let result = if viz.enabled() {
+                load_program(&entry, search_paths, cache)
+                    .and_then(|program| visualize(&program, &entry, config, &viz, &mut out))
+            } else if let Some(path) = trace_json {
+                load_program(&entry, search_paths, cache)
+                    .and_then(|program| trace(&program, config, Path::new(&path), &mut out))
+            } else {
+                load_program(&entry, search_paths.clone(), cache).and_then(|program| {
+                    let map = match keep.annotate {
+                        true => source_map(&entry, search_paths, cache, &program)?,
+                        false => None,
+                    };
+                    let started = Instant::now();
+                    let programs = std::slice::from_ref(&program);
+                    let outcome = run_each(&mut machines.machine(), programs, &mut out);
+                    machines.finish(&mut out)?;
+                    keep.show_profile(&outcome.stats, &program, map.as_ref(), started.elapsed());
+                    if keep.dump_stack {
+                        dump = outcome
+                            .crash
+                            .map(|(_, crash)| renderer.crash(&program, &crash).into());
+                    }
+                    let saved = keep.save_recording(&outcome.recording);
+                    outcome.result.and(saved)
+                })
+            };
+            out.flush().map_err(stdout_error)?;
+            result.map_err(|error| Failure {
+                dump,
+                ..in_program(&entry)(error)
+            })
+        }
+        Command::Doc {
+            path,
+            include_paths,
+            html,
+        } => {
+            let (entry, search_paths) = project(path, include_paths)?;
+            let program = load_program(&entry, search_paths, cache).map_err(in_file(&entry))?;
+            let title = entry
+                .file_stem()
+                .map_or("rorth".into(), |stem| stem.to_string_lossy());
+            let text = if html {
+                doc::html(&program, &title)
+            } else {
+                doc::markdown(&program, &title)
+            };
+            Ok(std::io::stdout()
+                .write_all(text.as_bytes())
+                .map_err(stdout_error)?)
+        }
+        Command::Build {
+            path,
+            include_paths,
+            output,
+            target,
+            strict_loops,
+            strict_entry,
+        } => {
+            let strict = Strict {
+                loops: strict_loops,
+                entry: strict_entry,
+                function: "main",
+            };
+            let backend = target.map_or(Ok(backend::default()), |target| backend::find(&target))?;
+            let (entry, search_paths) = project(path, include_paths)?;
+            let (program, map) = if is_compiled(&entry) {
+                let program = load_program(&entry, search_paths, cache);
+                (program.map_err(in_file(&entry))?, None)
+            } else {
+                let (tokens, origins) =
+                    load_source(&entry, search_paths, cache).map_err(in_file(&entry))?;
+                let program = parse_tokens(tokens.clone(), cache).map_err(in_file(&entry))?;
+                let map = SourceMap::new(&program, &tokens, &origins);
+                (program, Some(map))
+            };
+            strict.check(&program).map_err(in_file(&entry))?;
+            // Worth knowing before the run fails on it, but not worth
+            // refusing to build over.
+            warn(
+                renderer,
+                &entry,
+                map.as_ref(),
+                &[
+                    check_loop_conditions(&program),
+                    checker::zero_divisors(&program),
+                    checker::unused_values(&program),
+                ]
+                .concat(),
+            );
+            let output =
+                output.map_or_else(|| entry.with_extension(backend.extension()), PathBuf::from);
+            let bytes = backend.emit(&program).map_err(in_file(&entry))?;
+            std::fs::write(&output, &bytes).map_err(io_error(&output))?;
+            // Maps instructions of a `.rorthc`; assembly has its own labels.
+            if let Some(map) = map.filter(|_| backend.extension() == bytecode::EXTENSION) {
+                map.write(&output, &bytes)?;
+            }
+            Ok(())
+        }
+        #[cfg(feature = "repl")]
+        Command::Repl { config } => Ok(repl(config, renderer)?),
+        Command::Explain {
+            path,
+            include_paths,
+            config,
+        } => {
+            let (entry, search_paths) = project(path, include_paths)?;
+            let program = load_program(&entry, search_paths, cache).map_err(in_file(&entry))?;
+            let config = VmConfig {
+                interruptible: true,
+                ..config
+            };
+            let explanation = explain(&program, config);
+            let stdout = std::io::stdout();
+            let mut out = stdout.lock();
+            for step in &explanation.steps {
+                writeln!(out, "{}", step).map_err(stdout_error)?;
+            }
+            out.flush().map_err(stdout_error)?;
+            Ok(explanation.result.map_err(in_program(&entry))?)
+        }
+        Command::Check {
+            path,
+            include_paths,
+            watch,
+            verifiable,
+        } => {
+            let (entry, search_paths) = project(path, include_paths)?;
+            // `parse_args` only accepts `--watch` in builds that have it.
+            #[cfg(feature = "watch")]
+            if watch {
+                return check_on_save(&entry, search_paths, cache, verifiable, renderer);
+            }
+            #[cfg(not(feature = "watch"))]
+            let _ = watch;
+            check(&entry, search_paths, cache, verifiable, renderer)
+                .map(|_| ())
+                .map_err(|failures| all_but_last(renderer, failures))
+        }
+        Command::New { path } => Ok(scaffold(Path::new(&path))?),
+        #[cfg(feature = "serve")]
+        Command::Serve { addr, limits } => Ok(serve(&addr, limits)?),
+        Command::Words => {
+            print!("{}", words::listing());
+            Ok(())
+        }
+        Command::Grammar { format } => {
+            println!("{}", grammar::export(format).trim_end());
+            Ok(())
+        }
+        Command::Selftest => {
+            let outcomes = selftest::run();
+            let mut failed = 0;
+            for outcome in &outcomes {
+                match &outcome.failure {
+                    None => println!("ok    {}", outcome.name),
+                    Some(failure) => {
+                        failed += 1;
+                        println!("FAIL  {}: {}", outcome.name, failure);
+                    }
+                }
+            }
+            println!("{} passed, {} failed", outcomes.len() - failed, failed);
+            if failed > 0 {
+                return Err(Error::Usage {
+                    comment: format!("{} of {} self-tests failed", failed, outcomes.len()),
+                }
+                .into());
+            }
+            Ok(())
+        }
+        Command::Fix { paths, dry_run } => fix(&paths, dry_run),
+        Command::DocTest {
+            path,
+            include_paths,
+        } => {
+            let (entry, search_paths) = project(path, include_paths)?;
+            doc_test(&entry, search_paths, cache, renderer)
+        }
+        Command::Symexec {
+            path,
+            include_paths,
+            function,
+            limits,
+        } => {
+            let (entry, search_paths) = project(path, include_paths)?;
+            symexec(&entry, search_paths, cache, &function, limits)
+                .map_err(|failures| all_but_last(renderer, failures))
+        }
+        Command::VerifyOpt {
+            path,
+            include_paths,
+        } => {
+            let (entry, search_paths) = project(path, include_paths)?;
+            let program = load_program(&entry, search_paths, cache).map_err(in_file(&entry))?;
+            let config = VmConfig {
+                interruptible: true,
+                ..VmConfig::default()
+            };
+            let result =
+                verify_opt::compare(&program, config).map_err(|difference| Error::Usage {
+                    comment: difference.to_string(),
+                })?;
+            let ending = match &result.error {
+                Some(error) => format!("; both stopped with: {}", error),
+                None => String::new(),
+            };
+            println!(
+                "same output, final stack, steps and error with and without optimizations ({} steps){}",
+                result.steps, ending
+            );
+            Ok(())
+        }
+        Command::Disasm {
+            path,
+            include_paths,
+        } => {
+            let (entry, search_paths) = project(path, include_paths)?;
+            let program = load_program(&entry, search_paths, cache).map_err(in_file(&entry))?;
+            Ok(std::io::stdout()
+                .write_all(bytecode::disassemble(&program).as_bytes())
+                .map_err(stdout_error)?)
+        }
+        Command::Stats {
+            path,
+            include_paths,
+        } => {
+            let (entry, search_paths) = project(path, include_paths)?;
+            let program = load_program(&entry, search_paths, cache).map_err(in_file(&entry))?;
+            Ok(std::io::stdout()
+                .write_all(stats::size_report(&stats::size(&program)).as_bytes())
+                .map_err(stdout_error)?)
+        }
+        Command::Callgraph {
+            path,
+            include_paths,
+            format,
+        } => {
+            let (entry, search_paths) = project(path, include_paths)?;
+            let program = load_program(&entry, search_paths, cache).map_err(in_file(&entry))?;
+            Ok(std::io::stdout()
+                .write_all(callgraph::build(&program).export(format).as_bytes())
+                .map_err(stdout_error)?)
+        }
+        // `parse_args` refuses the commands this build left out.
+        #[cfg(not(feature = "repl"))]
+        Command::Repl { config } => unreachable!("repl with {:?}", config),
+        #[cfg(not(feature = "serve"))]
+        Command::Serve { addr, limits } => unreachable!("serve on {} with {:?}", addr, limits),
+    }
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let no_color = std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+    let tty = std::io::stderr().is_terminal();
+    let (globals, args) = match take_globals(&args) {
+        Ok(split) => split,
+        // Shown as `--color` would by default, since it wasn't read.
+        Err(e) => {
+            return fail(
+                &Renderer::new(ColorChoice::Auto.enabled(tty, no_color)),
+                e.into(),
+            )
+        }
+    };
+    let mut renderer = Renderer::new(globals.color.enabled(tty, no_color))
+        .with_tab_width(globals.tab_width.unwrap_or(1));
+    if let Some(path) = &globals.messages {
+        match read_messages(path) {
+            Ok(table) => renderer = renderer.with_catalog(table),
+            Err(e) => return fail(&renderer, e.into()),
+        }
+    } else if let Some(table) = catalog::for_locale(|name| std::env::var(name).ok()) {
+        renderer = renderer.with_catalog(table);
+    }
+    if let Err(e) = globals
+        .plugins
+        .iter()
+        .try_for_each(|name| plugin::load(name))
+    {
+        return fail(&renderer, e.into());
+    }
+    interrupt::install();
+    // `--no-cache` wins over `--cache-dir`.
+    let cache = match (globals.no_cache, globals.cache_dir) {
+        (true, _) => None,
+        (false, Some(dir)) => Some(Cache::new(dir)),
+        (false, None) => cache::default_dir().map(Cache::new),
+    };
+    match run(&args, &renderer, cache.as_ref()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(failure) => fail(&renderer, failure),
+    }
+}
+
+fn fail(renderer: &Renderer, failure: Failure) -> ExitCode {
+    let status = failure.error.exit_status();
+    report(renderer, failure);
+    ExitCode::from(status)
+}
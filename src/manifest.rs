@@ -0,0 +1,371 @@
+use std::path::{Path, PathBuf};
+
+use crate::common::Error;
+
+pub const MANIFEST_FILE: &str = "rorth.toml";
+
+const MAIN_SOURCE: &str = "fun main
+  42 print
+ret
+";
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Manifest {
+    pub name: String,
+    pub entry: String,
+    pub include: Vec<String>,
+    pub deps: Vec<String>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Project {
+    pub entry: PathBuf,
+    pub search_paths: Vec<PathBuf>,
+}
+
+fn manifest_error(line: usize, comment: &str) -> Error {
+    Error::Manifest {
+        line,
+        comment: comment.to_string(),
+    }
+}
+
+fn io_error(path: &Path, e: std::io::Error) -> Error {
+    Error::Io {
+        path: path.display().to_string(),
+        comment: e.to_string(),
+    }
+}
+
+fn parse_string(value: &str) -> Option<(String, &str)> {
+    let mut chars = value.strip_prefix('"')?.char_indices();
+    let mut result = String::new();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Some((result, &value[i + 2..])),
+            '\\' => match chars.next()?.1 {
+                '"' => result.push('"'),
+                '\\' => result.push('\\'),
+                'n' => result.push('\n'),
+                't' => result.push('\t'),
+                _ => return None,
+            },
+            _ => result.push(c),
+        }
+    }
+    None
+}
+
+fn parse_array(value: &str) -> Option<(Vec<String>, &str)> {
+    let mut rest = value.strip_prefix('[')?.trim_start();
+    let mut items = Vec::new();
+    loop {
+        if let Some(after) = rest.strip_prefix(']') {
+            return Some((items, after));
+        }
+        let (item, after) = parse_string(rest)?;
+        items.push(item);
+        rest = after.trim_start();
+        if let Some(after) = rest.strip_prefix(',') {
+            rest = after.trim_start();
+        } else if !rest.starts_with(']') {
+            return None;
+        }
+    }
+}
+
+fn is_end_of_line(rest: &str) -> bool {
+    let rest = rest.trim();
+    rest.is_empty() || rest.starts_with('#')
+}
+
+impl Manifest {
+    pub fn new(name: &str) -> Manifest {
+        Manifest {
+            name: name.to_string(),
+            entry: "src/main.sm".to_string(),
+            include: vec!["src".to_string()],
+            deps: vec![],
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Manifest, Error> {
+        let mut name = None;
+        let mut entry = None;
+        let mut include = Vec::new();
+        let mut deps = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line_no = i + 1;
+            let line = line.trim();
+            if is_end_of_line(line) {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| manifest_error(line_no, "Expected `key = value`"))?;
+            let value = value.trim();
+            match key.trim() {
+                key @ ("name" | "entry") => {
+                    let value = parse_string(value)
+                        .filter(|(_, rest)| is_end_of_line(rest))
+                        .ok_or_else(|| manifest_error(line_no, "Expected a string"))?
+                        .0;
+                    if key == "name" {
+                        name = Some(value);
+                    } else {
+                        entry = Some(value);
+                    }
+                }
+                key @ ("include" | "deps") => {
+                    let value = parse_array(value)
+                        .filter(|(_, rest)| is_end_of_line(rest))
+                        .ok_or_else(|| manifest_error(line_no, "Expected a list of strings"))?
+                        .0;
+                    if key == "include" {
+                        include = value;
+                    } else {
+                        deps = value;
+                    }
+                }
+                other => return Err(manifest_error(line_no, &format!("Unknown key `{}`", other))),
+            }
+        }
+        Ok(Manifest {
+            name: name.ok_or_else(|| manifest_error(0, "Missing `name`"))?,
+            entry: entry.ok_or_else(|| manifest_error(0, "Missing `entry`"))?,
+            include,
+            deps,
+        })
+    }
+
+    pub fn to_toml(&self) -> String {
+        format!(
+            "name = {}\nentry = {}\ninclude = {}\ndeps = {}\n",
+            quote(&self.name),
+            quote(&self.entry),
+            quote_all(&self.include),
+            quote_all(&self.deps)
+        )
+    }
+
+    // Include folders come first, then every library found in the dependency
+    // folders: a library with its own manifest contributes its include
+    // folders, any other directory is searched as is.
+    pub fn search_paths(&self, root: &Path) -> Result<Vec<PathBuf>, Error> {
+        let mut paths: Vec<PathBuf> = self.include.iter().map(|p| root.join(p)).collect();
+        for deps in &self.deps {
+            let deps = root.join(deps);
+            let Ok(entries) = std::fs::read_dir(&deps) else {
+                continue;
+            };
+            let mut libs: Vec<PathBuf> = entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.is_dir())
+                .collect();
+            libs.sort();
+            for lib in libs {
+                let manifest = lib.join(MANIFEST_FILE);
+                if manifest.is_file() {
+                    let manifest = Manifest::load(&manifest)?;
+                    paths.extend(manifest.include.iter().map(|p| lib.join(p)));
+                } else {
+                    paths.push(lib);
+                }
+            }
+        }
+        Ok(paths)
+    }
+
+    pub fn load(path: &Path) -> Result<Manifest, Error> {
+        let text = std::fs::read_to_string(path).map_err(|e| io_error(path, e))?;
+        Manifest::parse(&text)
+    }
+}
+
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn quote_all(items: &[String]) -> String {
+    let items: Vec<String> = items.iter().map(|s| quote(s)).collect();
+    format!("[{}]", items.join(", "))
+}
+
+pub fn find(start: &Path) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .map(|dir| dir.join(MANIFEST_FILE))
+        .find(|path| path.is_file())
+}
+
+// Works out what `rorth run` should run: the given file, or the entry point
+// of the project around `cwd`. The project's search paths apply either way.
+pub fn locate(cwd: &Path, file: Option<&Path>) -> Result<Project, Error> {
+    let start = match file.and_then(Path::parent) {
+        Some(dir) => cwd.join(dir),
+        None => cwd.to_path_buf(),
+    };
+    let Some(path) = find(&start) else {
+        return match file {
+            Some(file) => Ok(Project {
+                entry: file.to_path_buf(),
+                search_paths: vec![],
+            }),
+            None => Err(Error::Io {
+                path: MANIFEST_FILE.to_string(),
+                comment: "no file given and no manifest found".to_string(),
+            }),
+        };
+    };
+    let manifest = Manifest::load(&path)?;
+    let root = path.parent().unwrap_or(Path::new("."));
+    Ok(Project {
+        entry: file.map_or_else(|| root.join(&manifest.entry), Path::to_path_buf),
+        search_paths: manifest.search_paths(root)?,
+    })
+}
+
+pub fn scaffold(dir: &Path) -> Result<(), Error> {
+    let name = dir
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| Error::Usage {
+            comment: format!("`{}` is not a valid project name", dir.display()),
+        })?;
+    let manifest = dir.join(MANIFEST_FILE);
+    if manifest.exists() {
+        return Err(Error::Io {
+            path: manifest.display().to_string(),
+            comment: "already exists".to_string(),
+        });
+    }
+    for sub in ["src", "tests"] {
+        let path = dir.join(sub);
+        std::fs::create_dir_all(&path).map_err(|e| io_error(&path, e))?;
+    }
+    let main = dir.join("src").join("main.sm");
+    if !main.exists() {
+        std::fs::write(&main, MAIN_SOURCE).map_err(|e| io_error(&main, e))?;
+    }
+    std::fs::write(&manifest, Manifest::new(name).to_toml()).map_err(|e| io_error(&manifest, e))
+}
+
+#[cfg(test)]
+mod manifest_tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rorth-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn parse_manifest() {
+        let text = "# project
+name = \"demo\"
+entry = \"src/app.sm\"   # the entry point
+include = [\"src\", \"lib/vendor\"]
+";
+        assert_eq!(
+            Manifest::parse(text),
+            Ok(Manifest {
+                name: "demo".to_string(),
+                entry: "src/app.sm".to_string(),
+                include: vec!["src".to_string(), "lib/vendor".to_string()],
+                deps: vec![],
+            })
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut manifest = Manifest::new("quo\"te");
+        manifest.include.push("a\\b".to_string());
+        assert_eq!(Manifest::parse(&manifest.to_toml()), Ok(manifest));
+        assert_eq!(
+            Manifest::parse("name = \"x\"\nentry = \"m.sm\"\ninclude = []")
+                .unwrap()
+                .include,
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(
+            Manifest::parse("name = \"x\"\nversion = \"1\""),
+            Err(manifest_error(2, "Unknown key `version`"))
+        );
+        assert_eq!(
+            Manifest::parse("name = x"),
+            Err(manifest_error(1, "Expected a string"))
+        );
+        assert_eq!(
+            Manifest::parse("name = \"x\"\nentry = \"m\"\ninclude = [\"a\" \"b\"]"),
+            Err(manifest_error(3, "Expected a list of strings"))
+        );
+        assert_eq!(
+            Manifest::parse("entry = \"m.sm\""),
+            Err(manifest_error(0, "Missing `name`"))
+        );
+        assert_eq!(
+            Manifest::parse("[package]"),
+            Err(manifest_error(1, "Expected `key = value`"))
+        );
+    }
+
+    #[test]
+    fn scaffold_creates_a_runnable_project() {
+        let dir = temp_dir("scaffold").join("myproj");
+        scaffold(&dir).unwrap();
+        assert!(dir.join("tests").is_dir());
+        let manifest = Manifest::load(&dir.join(MANIFEST_FILE)).unwrap();
+        assert_eq!(manifest, Manifest::new("myproj"));
+
+        let project = locate(&dir.join("src"), None).unwrap();
+        assert_eq!(project.entry, dir.join("src/main.sm"));
+        assert_eq!(project.search_paths, vec![dir.join("src")]);
+        assert_eq!(std::fs::read_to_string(project.entry).unwrap(), MAIN_SOURCE);
+
+        assert!(matches!(scaffold(&dir), Err(Error::Io { .. })));
+        std::fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn dependency_folders() {
+        let dir = temp_dir("deps");
+        let mut manifest = Manifest::new("app");
+        manifest.deps.push("vendor".to_string());
+        scaffold(&dir.join("vendor/mathlib")).unwrap();
+        std::fs::create_dir_all(dir.join("vendor/plain")).unwrap();
+        std::fs::write(dir.join(MANIFEST_FILE), manifest.to_toml()).unwrap();
+
+        let project = locate(&dir, Some(Path::new("other.sm"))).unwrap();
+        assert_eq!(project.entry, PathBuf::from("other.sm"));
+        assert_eq!(
+            project.search_paths,
+            vec![
+                dir.join("src"),
+                dir.join("vendor/mathlib/src"),
+                dir.join("vendor/plain"),
+            ]
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn no_manifest() {
+        let dir = temp_dir("no-manifest");
+        std::fs::create_dir_all(&dir).unwrap();
+        assert!(matches!(locate(&dir, None), Err(Error::Io { .. })));
+        assert_eq!(
+            locate(&dir, Some(Path::new("a.sm"))),
+            Ok(Project {
+                entry: PathBuf::from("a.sm"),
+                search_paths: vec![],
+            })
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,12 +1,23 @@
-use std::collections::HashMap;
-use std::fmt::Display;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Display;
+use core::ops::Range;
 
+use crate::catalog::text;
+use crate::checker;
 use crate::common;
-use crate::tokenizer::{Token, TokenType};
+use crate::intern::{Symbol, SymbolTable};
+use crate::tokenizer::{Token, TokenType, FIRST_EDITION};
 
-use crate::stack_machine::Program;
+use crate::plugin;
+use crate::stack_machine::{Program, ERROR_HANDLER};
+use crate::suggest;
+use crate::words;
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum InstructionType {
     Push(i32),
     Pop,
@@ -14,59 +25,129 @@ pub enum InstructionType {
     Sub,
     Mul,
     Div,
+    DivMod,
+    AddWrap,
+    SubWrap,
+    MulWrap,
+    AddSat,
+    SubSat,
+    MulSat,
     // TODO: LE, GE, EQ, NE, AND, OR
     Print,
     While(usize),
     EndWhile(usize),
+    // `while … do … end`: `Loop` marks where the condition starts and points
+    // at its `Do`, which pops the condition and jumps to its `EndDo` on 0,
+    // which jumps back to the `Loop`.
+    Loop(usize),
+    Do(usize),
+    EndDo(usize),
     If(usize),
     Else(usize),
     EndIf,
+    // Jumps to its `recover`, which jumps to the `end`.
+    Try(usize),
+    Recover(usize),
+    EndTry,
     Dup,
     Swap,
     Rot,
     Over,
     Nip,
+    Rand,
+    Time,
     Call(usize),
+    // `&name`: pushes where the function starts, for `invoke` to call.
+    Address(usize),
+    Invoke,
+    // `env "NAME"`, by where the name is in `Program::env`.
+    Env(usize),
+    // `asm "..." end`, by where the text is in `Program::asm`. Only native
+    // backends build it.
+    Asm(usize),
+    // A word from a loaded plugin pack, by `plugin::native` id.
+    Native(usize),
     Ret,
+    Hex,
+    Bin,
+    Dec,
+    PrintPadded,
+    Eprint,
+    FxMul,
+    FxDiv,
+    FxPrint,
+    IntToFx,
+    FxToInt,
+    Alloc,
+    Fetch,
+    Store,
+    GcStats,
+    Syscall0,
+    Syscall1,
+    Syscall2,
+    Syscall3,
 }
 
 impl Display for InstructionType {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{}",
-            match self {
-                InstructionType::While(_) => "while".into(),
-                InstructionType::EndWhile(_) => "end".into(),
-                InstructionType::Push(n) => n.to_string(),
-                InstructionType::Pop => "pop".into(),
-                InstructionType::Add => "+".into(),
-                InstructionType::Sub => "-".into(),
-                InstructionType::Mul => "*".into(),
-                InstructionType::Div => "/".into(),
-                InstructionType::Print => "print".into(),
-                InstructionType::Dup => "dup".into(),
-                InstructionType::Swap => "swap".into(),
-                InstructionType::Rot => "rot".into(),
-                InstructionType::Over => "over".into(),
-                InstructionType::Nip => "nip".into(),
-                InstructionType::If(_) => "if".into(),
-                InstructionType::Else(_) => "else".into(),
-                InstructionType::EndIf => "end".into(),
-                InstructionType::Ret => "ret".into(),
-                InstructionType::Call(i) => format!("call {}", i),
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            InstructionType::While(_) | InstructionType::Loop(_) => write!(f, "while"),
+            InstructionType::Do(_) => write!(f, "do"),
+            InstructionType::EndWhile(_)
+            | InstructionType::EndDo(_)
+            | InstructionType::EndIf
+            | InstructionType::EndTry => {
+                write!(f, "end")
             }
-        )
+            InstructionType::Push(n) => write!(f, "{}", n),
+            InstructionType::If(_) => write!(f, "if"),
+            InstructionType::Else(_) => write!(f, "else"),
+            InstructionType::Try(_) => write!(f, "try"),
+            InstructionType::Recover(_) => write!(f, "recover"),
+            InstructionType::Call(i) => write!(f, "call {}", i),
+            InstructionType::Address(i) => write!(f, "address {}", i),
+            InstructionType::Env(_) => write!(f, "env"),
+            InstructionType::Asm(_) => write!(f, "asm"),
+            InstructionType::Native(id) => match plugin::word(*id) {
+                Some(loaded) => write!(f, "{}", loaded.native().name),
+                None => write!(f, "native {}", id),
+            },
+            word => match words::op(word) {
+                Some((name, _)) => write!(f, "{}", name),
+                None => write!(f, "{:?}", word),
+            },
+        }
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Instruction {
     pub instruction_type: InstructionType,
     pub pos: usize,
     pub line: usize,
 }
 
+impl InstructionType {
+    // The instruction a jump or a call goes to.
+    pub(crate) fn target_mut(&mut self) -> Option<&mut usize> {
+        use InstructionType::*;
+        match self {
+            While(j) | EndWhile(j) | Loop(j) | Do(j) | EndDo(j) | If(j) | Else(j) | Try(j)
+            | Recover(j) | Call(j) | Address(j) => Some(j),
+            _ => None,
+        }
+    }
+
+    // Whether the next instruction to run may not be the one after this.
+    pub(crate) fn jumps(mut self) -> bool {
+        match self {
+            InstructionType::Address(_) => false,
+            InstructionType::Invoke | InstructionType::Ret => true,
+            _ => self.target_mut().is_some(),
+        }
+    }
+}
+
 impl Instruction {
     fn set_jmp_pos(&self, jmp_pos: usize) -> Result<Instruction, common::Error> {
         match self.instruction_type {
@@ -78,6 +159,10 @@ impl Instruction {
                 instruction_type: InstructionType::EndWhile(jmp_pos),
                 ..*self
             }),
+            InstructionType::Do(_) => Ok(Instruction {
+                instruction_type: InstructionType::Do(jmp_pos),
+                ..*self
+            }),
             InstructionType::If(_) => Ok(Instruction {
                 instruction_type: InstructionType::If(jmp_pos),
                 ..*self
@@ -86,6 +171,14 @@ impl Instruction {
                 instruction_type: InstructionType::Else(jmp_pos),
                 ..*self
             }),
+            InstructionType::Try(_) => Ok(Instruction {
+                instruction_type: InstructionType::Try(jmp_pos),
+                ..*self
+            }),
+            InstructionType::Recover(_) => Ok(Instruction {
+                instruction_type: InstructionType::Recover(jmp_pos),
+                ..*self
+            }),
             _ => Err(common::Error::Parse {
                 word: format!("{:?}", self.instruction_type),
                 pos: self.pos,
@@ -96,105 +189,348 @@ impl Instruction {
     }
 }
 
-pub fn parse(tokens: Vec<Token>) -> Result<Program, common::Error> {
-    let mut instructions = Vec::new();
-    let mut stack: Vec<usize> = vec![];
-    let mut functions: HashMap<String, usize> = HashMap::new();
-    let mut i = 0;
-    while let Some(token) = tokens.get(i) {
-        match &token.token_type {
-            TokenType::Num(n) => instructions.push(Instruction {
-                instruction_type: InstructionType::Push(*n),
-                pos: token.pos,
-                line: token.line,
-            }),
-            TokenType::Add => instructions.push(Instruction {
-                instruction_type: InstructionType::Add,
-                pos: token.pos,
-                line: token.line,
-            }),
-            TokenType::Sub => instructions.push(Instruction {
-                instruction_type: InstructionType::Sub,
-                pos: token.pos,
-                line: token.line,
-            }),
-            TokenType::Mul => instructions.push(Instruction {
-                instruction_type: InstructionType::Mul,
+// A `module` block, or the file itself at the bottom of the stack. `blocks`
+// is how many `while`/`if` blocks were open when the module started, so an
+// `end` seen at that depth closes the module.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Scope {
+    prefix: String,
+    imports: Vec<String>,
+    blocks: usize,
+    pos: usize,
+    line: usize,
+}
+
+struct Parser {
+    instructions: Vec<Instruction>,
+    stack: Vec<usize>,
+    // Qualified function names, which the tables below are keyed by.
+    names: SymbolTable,
+    functions: BTreeMap<Symbol, usize>,
+    constants: BTreeMap<Symbol, i32>,
+    private: BTreeSet<Symbol>,
+    public_next: Option<(usize, usize)>,
+    docs: BTreeMap<Symbol, String>,
+    pending_doc: Vec<String>,
+    scopes: Vec<Scope>,
+    definitions: Vec<Definition>,
+    // From the last `#pragma edition`, for the words that mean something
+    // else from one edition to the next.
+    edition: u16,
+    i: usize,
+}
+
+// A `fun` as it was declared. Kept in order, so the function tables can be
+// rebuilt as they were at any earlier point.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Definition {
+    name: Symbol,
+    start: usize,
+    public: bool,
+    doc: Option<String>,
+    // For a constant from an `enum`, which is not a function at all.
+    value: Option<i32>,
+}
+
+impl Parser {
+    fn new() -> Self {
+        Self {
+            instructions: Vec::new(),
+            stack: vec![],
+            names: SymbolTable::new(),
+            functions: BTreeMap::new(),
+            constants: BTreeMap::new(),
+            private: BTreeSet::new(),
+            public_next: None,
+            docs: BTreeMap::new(),
+            pending_doc: vec![],
+            scopes: vec![Scope {
+                prefix: String::new(),
+                imports: vec![],
+                blocks: 0,
+                pos: 0,
+                line: 0,
+            }],
+            definitions: vec![],
+            edition: FIRST_EDITION,
+            i: 0,
+        }
+    }
+
+    fn qualify(prefix: &str, name: &str) -> String {
+        if prefix.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}.{}", prefix, name)
+        }
+    }
+
+    // The function or constant `name` declared in module `prefix`, if there
+    // is one.
+    fn find(&self, prefix: &str, name: &str) -> Option<Symbol> {
+        let symbol = if prefix.is_empty() {
+            self.names.get(name)
+        } else {
+            self.names.get(&Self::qualify(prefix, name))
+        }?;
+        (self.functions.contains_key(&symbol) || self.constants.contains_key(&symbol))
+            .then_some(symbol)
+    }
+
+    // Names are looked up in the enclosing modules from the inside out, then
+    // in the imported modules, which must not disagree.
+    fn resolve(&self, token: &Token, name: &str) -> Result<InstructionType, common::Error> {
+        let symbol = self.lookup(token, name)?;
+        let name = self.names.resolve(symbol);
+        if let Some((module, _)) = name.rsplit_once('.') {
+            let here = &self.current_prefix();
+            let inside = here == module || here.starts_with(&format!("{}.", module));
+            if self.private.contains(&symbol) && !inside {
+                return Err(common::Error::Parse {
+                    word: name.to_string(),
+                    pos: token.pos,
+                    line: token.line,
+                    comment: text("E0002.private", &[("name", &name), ("module", &module)]),
+                });
+            }
+        }
+        Ok(match self.constants.get(&symbol) {
+            Some(value) => InstructionType::Push(*value),
+            None => InstructionType::Call(self.functions[&symbol]),
+        })
+    }
+
+    fn current_prefix(&self) -> String {
+        self.scopes
+            .last()
+            .map_or(String::new(), |s| s.prefix.clone())
+    }
+
+    fn lookup(&self, token: &Token, name: &str) -> Result<Symbol, common::Error> {
+        if let Some(symbol) = self
+            .scopes
+            .iter()
+            .rev()
+            .find_map(|scope| self.find(&scope.prefix, name))
+        {
+            return Ok(symbol);
+        }
+        let mut found: Vec<Symbol> = self
+            .scopes
+            .iter()
+            .flat_map(|scope| scope.imports.iter())
+            .filter_map(|module| self.find(module, name))
+            .collect();
+        found.dedup();
+        match found.as_slice() {
+            [symbol] => Ok(*symbol),
+            [] => Err(common::Error::Parse {
+                word: name.to_string(),
                 pos: token.pos,
                 line: token.line,
+                comment: match self.closest(name) {
+                    Some(near) => text("E0002.did-you-mean", &[("name", &near)]),
+                    None => text("E0002.not-found", &[]),
+                },
             }),
-            TokenType::Div => instructions.push(Instruction {
-                instruction_type: InstructionType::Div,
+            _ => Err(common::Error::Parse {
+                word: name.to_string(),
                 pos: token.pos,
                 line: token.line,
+                comment: text(
+                    "E0002.ambiguous",
+                    &[
+                        ("name", &name),
+                        (
+                            "found",
+                            &found
+                                .iter()
+                                .map(|symbol| self.names.resolve(*symbol))
+                                .collect::<Vec<_>>()
+                                .join("`, `"),
+                        ),
+                    ],
+                ),
             }),
-            TokenType::Print => instructions.push(Instruction {
-                instruction_type: InstructionType::Print,
+        }
+    }
+
+    // A known word or function spelled like `name`, as it would be written
+    // here: functions of the enclosing modules without their prefix.
+    fn closest(&self, name: &str) -> Option<String> {
+        let mut known: Vec<String> = vec![];
+        for function in self.functions.keys().chain(self.constants.keys()) {
+            let function = self.names.resolve(*function);
+            for scope in &self.scopes {
+                let short = scope
+                    .prefix
+                    .is_empty()
+                    .then_some(function)
+                    .or_else(|| function.strip_prefix(&format!("{}.", scope.prefix)));
+                known.extend(short.map(String::from));
+            }
+        }
+        known.extend(words::BUILTINS.iter().map(|word| word.name.to_string()));
+        known.extend(plugin::natives().iter().map(|word| word.name.to_string()));
+        suggest::closest(name, known.iter().map(String::as_str)).map(String::from)
+    }
+
+    fn name_after(&mut self, tokens: &[Token], comment: &str) -> Result<String, common::Error> {
+        let token = &tokens[self.i];
+        self.i += 1;
+        match tokens.get(self.i) {
+            Some(Token {
+                token_type: TokenType::Identifier(name),
+                ..
+            }) => Ok(name.to_string()),
+            _ => Err(common::Error::Parse {
+                word: format!("{}", token.token_type),
                 pos: token.pos,
                 line: token.line,
+                comment: comment.to_string(),
             }),
-            TokenType::Pop => instructions.push(Instruction {
-                instruction_type: InstructionType::Pop,
+        }
+    }
+
+    fn define(&mut self, definition: &Definition) {
+        let name = definition.name;
+        if let Some(value) = definition.value {
+            self.constants.insert(name, value);
+            return;
+        }
+        if definition.public {
+            self.private.remove(&name);
+        } else {
+            self.private.insert(name);
+        }
+        if let Some(doc) = &definition.doc {
+            self.docs.insert(name, doc.clone());
+        }
+        self.functions.insert(name, definition.start);
+    }
+
+    fn current_scope(&mut self) -> &mut Scope {
+        self.scopes
+            .last_mut()
+            .expect("the file scope is never closed")
+    }
+
+    fn parse_token(&mut self, tokens: &[Token]) -> Result<(), common::Error> {
+        let token = &tokens[self.i];
+        if token.token_type != TokenType::Fun {
+            if let Some((pos, line)) = self.public_next.take() {
+                return Err(Self::misplaced_pub(pos, line));
+            }
+        }
+        if !matches!(
+            token.token_type,
+            TokenType::Doc(_) | TokenType::Pub | TokenType::Fun | TokenType::Struct
+        ) {
+            self.pending_doc.clear();
+        }
+        match &token.token_type {
+            TokenType::Num(n) => self.instructions.push(Instruction {
+                instruction_type: InstructionType::Push(*n),
                 pos: token.pos,
                 line: token.line,
             }),
             TokenType::While => {
-                stack.push(instructions.len());
-                instructions.push(Instruction {
+                self.stack.push(self.instructions.len());
+                self.instructions.push(Instruction {
                     instruction_type: InstructionType::While(0),
                     pos: token.pos,
                     line: token.line,
                 });
             }
+            TokenType::End
+                if self.scopes.len() > 1 && self.current_scope().blocks == self.stack.len() =>
+            {
+                self.scopes.pop();
+            }
             TokenType::End => {
-                let opener_idx = stack.pop().ok_or(common::Error::Parse {
+                let opener_idx = self.stack.pop().ok_or(common::Error::Parse {
                     word: format!("{}", token.token_type),
                     pos: token.pos,
                     line: token.line,
-                    comment: format!("Unexpected `{}`", token.token_type),
+                    comment: text("E0002.unexpected", &[("word", &token.token_type)]),
                 })?;
-                instructions.push(Instruction {
-                    instruction_type: match instructions[opener_idx].instruction_type {
+                self.instructions.push(Instruction {
+                    instruction_type: match &self.instructions[opener_idx].instruction_type {
                         InstructionType::While(_) => InstructionType::EndWhile(opener_idx),
+                        // Until its `end`, a `do` points back at its `while`.
+                        InstructionType::Do(loop_idx) => InstructionType::EndDo(*loop_idx),
                         InstructionType::Else(_) => InstructionType::EndIf,
-                        _ => {
-                            println!(
-                                "{:?}",
-                                instructions
-                                    .into_iter()
-                                    .map(|i| i.instruction_type)
-                                    .collect::<Vec<_>>()
-                            );
-                            println!("opener_idx: {}", opener_idx);
-                            panic!("Unexpected `end`")
+                        InstructionType::Recover(_) => InstructionType::EndTry,
+                        opener => {
+                            let key = match opener {
+                                InstructionType::Try(_) => "E0002.needs-recover",
+                                _ => "E0002.needs-else",
+                            };
+                            return Err(common::Error::Parse {
+                                word: format!("{}", token.token_type),
+                                pos: token.pos,
+                                line: token.line,
+                                comment: text(key, &[("word", &opener)]),
+                            });
                         }
                     },
                     pos: token.pos,
                     line: token.line,
                 });
-                instructions[opener_idx] = instructions[opener_idx].set_jmp_pos(i)?;
+                let end_idx = self.instructions.len() - 1;
+                self.instructions[opener_idx] =
+                    self.instructions[opener_idx].set_jmp_pos(end_idx)?;
+            }
+            TokenType::Do => {
+                let opener_idx = self.stack.pop().ok_or(common::Error::Parse {
+                    word: format!("{}", token.token_type),
+                    pos: token.pos,
+                    line: token.line,
+                    comment: text("E0002.unexpected", &[("word", &token.token_type)]),
+                })?;
+                if !matches!(
+                    self.instructions[opener_idx].instruction_type,
+                    InstructionType::While(_)
+                ) {
+                    return Err(common::Error::Parse {
+                        word: format!("{}", token.token_type),
+                        pos: token.pos,
+                        line: token.line,
+                        comment: text("E0002.no-while", &[]),
+                    });
+                }
+                let do_idx = self.instructions.len();
+                self.instructions[opener_idx].instruction_type = InstructionType::Loop(do_idx);
+                self.stack.push(do_idx);
+                self.instructions.push(Instruction {
+                    instruction_type: InstructionType::Do(opener_idx),
+                    pos: token.pos,
+                    line: token.line,
+                });
             }
             TokenType::If => {
-                stack.push(instructions.len());
-                instructions.push(Instruction {
+                self.stack.push(self.instructions.len());
+                self.instructions.push(Instruction {
                     instruction_type: InstructionType::If(0),
                     pos: token.pos,
                     line: token.line,
                 });
             }
             TokenType::Else => {
-                let opener_idx = stack.pop().ok_or(common::Error::Parse {
+                let opener_idx = self.stack.pop().ok_or(common::Error::Parse {
                     word: format!("{}", token.token_type),
                     pos: token.pos,
                     line: token.line,
-                    comment: format!("Unexpected `{}`", token.token_type),
+                    comment: text("E0002.unexpected", &[("word", &token.token_type)]),
                 })?;
 
-                match instructions[opener_idx].instruction_type {
+                match self.instructions[opener_idx].instruction_type {
                     InstructionType::If(_) => {
-                        instructions[opener_idx] = instructions[opener_idx].set_jmp_pos(i)?;
-                        stack.push(instructions.len());
-                        instructions.push(Instruction {
+                        let else_idx = self.instructions.len();
+                        self.instructions[opener_idx] =
+                            self.instructions[opener_idx].set_jmp_pos(else_idx)?;
+                        self.stack.push(self.instructions.len());
+                        self.instructions.push(Instruction {
                             instruction_type: InstructionType::Else(0),
                             pos: token.pos,
                             line: token.line,
@@ -205,105 +541,906 @@ pub fn parse(tokens: Vec<Token>) -> Result<Program, common::Error> {
                             word: format!("{}", token.token_type),
                             pos: token.pos,
                             line: token.line,
-                            comment: "This `else` has no matching if".to_string(),
+                            comment: text("E0002.no-if", &[]),
                         });
                     }
                 }
             }
-            TokenType::Dup => instructions.push(Instruction {
-                instruction_type: InstructionType::Dup,
-                pos: token.pos,
-                line: token.line,
-            }),
-            TokenType::Swap => instructions.push(Instruction {
-                instruction_type: InstructionType::Swap,
-                pos: token.pos,
-                line: token.line,
-            }),
-            TokenType::Rot => instructions.push(Instruction {
-                instruction_type: InstructionType::Rot,
-                pos: token.pos,
-                line: token.line,
-            }),
-            TokenType::Over => instructions.push(Instruction {
-                instruction_type: InstructionType::Over,
-                pos: token.pos,
-                line: token.line,
-            }),
-            TokenType::Nip => instructions.push(Instruction {
-                instruction_type: InstructionType::Nip,
-                pos: token.pos,
-                line: token.line,
-            }),
-            TokenType::Identifier(ident) => match functions.get(ident) {
-                Some(i) => instructions.push(Instruction {
-                    instruction_type: InstructionType::Call(*i),
+            TokenType::Try => {
+                self.stack.push(self.instructions.len());
+                self.instructions.push(Instruction {
+                    instruction_type: InstructionType::Try(0),
                     pos: token.pos,
                     line: token.line,
-                }),
-                None => {
+                });
+            }
+            TokenType::Recover => {
+                let opener_idx = self.stack.pop().ok_or(common::Error::Parse {
+                    word: format!("{}", token.token_type),
+                    pos: token.pos,
+                    line: token.line,
+                    comment: text("E0002.unexpected", &[("word", &token.token_type)]),
+                })?;
+                if !matches!(
+                    self.instructions[opener_idx].instruction_type,
+                    InstructionType::Try(_)
+                ) {
                     return Err(common::Error::Parse {
                         word: format!("{}", token.token_type),
                         pos: token.pos,
                         line: token.line,
-                        comment: "Function not found".to_string(),
-                    })
+                        comment: text("E0002.no-try", &[]),
+                    });
                 }
-            },
+                let recover_idx = self.instructions.len();
+                self.instructions[opener_idx] =
+                    self.instructions[opener_idx].set_jmp_pos(recover_idx)?;
+                self.stack.push(recover_idx);
+                self.instructions.push(Instruction {
+                    instruction_type: InstructionType::Recover(0),
+                    pos: token.pos,
+                    line: token.line,
+                });
+            }
+            TokenType::Identifier(ident) => {
+                let instruction_type = match (self.resolve(token, ident), plugin::native(ident)) {
+                    (Ok(instruction_type), _) => instruction_type,
+                    (Err(_), Some(id)) => InstructionType::Native(id),
+                    (Err(e), None) => return Err(e),
+                };
+                self.instructions.push(Instruction {
+                    instruction_type,
+                    pos: token.pos,
+                    line: token.line,
+                })
+            }
+            TokenType::Address(ident) => {
+                let InstructionType::Call(start) = self.resolve(token, ident)? else {
+                    return Err(common::Error::Parse {
+                        word: format!("{}", token.token_type),
+                        pos: token.pos,
+                        line: token.line,
+                        comment: text("E0002.constant", &[("name", ident)]),
+                    });
+                };
+                self.instructions.push(Instruction {
+                    instruction_type: InstructionType::Address(start),
+                    pos: token.pos,
+                    line: token.line,
+                })
+            }
             TokenType::Fun => {
-                i += 1;
-                match tokens.get(i) {
+                let name = self.name_after(tokens, "Function name is missing")?;
+                let public = self.public_next.take().is_some() || self.scopes.len() == 1;
+                let qualified = Self::qualify(&self.current_scope().prefix, &name);
+                let symbol = self.names.intern(&qualified);
+                // Functions may be defined again, but not over a constant.
+                if self.constants.contains_key(&symbol) {
+                    let token = &tokens[self.i];
+                    return Err(common::Error::Parse {
+                        word: name,
+                        pos: token.pos,
+                        line: token.line,
+                        comment: text("E0002.defined", &[("name", &qualified)]),
+                    });
+                }
+                let definition = Definition {
+                    name: symbol,
+                    start: self.instructions.len(),
+                    public,
+                    doc: (!self.pending_doc.is_empty()).then(|| self.pending_doc.join("\n")),
+                    value: None,
+                };
+                self.pending_doc.clear();
+                self.define(&definition);
+                self.definitions.push(definition);
+            }
+            TokenType::Struct => self.parse_struct(tokens)?,
+            TokenType::Enum => self.parse_enum(tokens)?,
+            TokenType::Pub => self.public_next = Some((token.pos, token.line)),
+            TokenType::Doc(text) => self.pending_doc.push(text.to_string()),
+            TokenType::Module => {
+                let name = self.name_after(tokens, "Module name is missing")?;
+                let prefix = Self::qualify(&self.current_scope().prefix, &name);
+                let blocks = self.stack.len();
+                self.scopes.push(Scope {
+                    prefix,
+                    imports: vec![],
+                    blocks,
+                    pos: token.pos,
+                    line: token.line,
+                });
+            }
+            TokenType::Import => {
+                let name = self.name_after(tokens, "Module name is missing")?;
+                let prefix = format!("{}.", name);
+                if !self
+                    .functions
+                    .keys()
+                    .chain(self.constants.keys())
+                    .any(|f| self.names.resolve(*f).starts_with(&prefix))
+                {
+                    return Err(common::Error::Parse {
+                        word: name,
+                        pos: tokens[self.i].pos,
+                        line: tokens[self.i].line,
+                        comment: text("E0002.module-not-found", &[]),
+                    });
+                }
+                self.current_scope().imports.push(name);
+            }
+            // Text for a native backend to paste into its output. The
+            // interpreter and the bytecode backend refuse it.
+            TokenType::Asm => {
+                let (
                     Some(Token {
-                        token_type: TokenType::Identifier(name),
+                        token_type: TokenType::Str(text),
                         ..
-                    }) => {
-                        functions.insert(name.to_owned(), instructions.len());
-                    }
-                    _ => {
-                        return Err(common::Error::Parse {
-                            word: "function".to_string(),
-                            pos: token.pos,
-                            line: token.line,
-                            comment: "Function name is missing".to_string(),
-                        })
+                    }),
+                    Some(Token {
+                        token_type: TokenType::End,
+                        ..
+                    }),
+                ) = (tokens.get(self.i + 1), tokens.get(self.i + 2))
+                else {
+                    return Err(common::Error::Parse {
+                        word: "asm".to_string(),
+                        pos: token.pos,
+                        line: token.line,
+                        comment: "Expected `asm \"...\" end`".to_string(),
+                    });
+                };
+                // Numbered for the program in `finish`.
+                let symbol = self.names.intern(text);
+                self.instructions.push(Instruction {
+                    instruction_type: InstructionType::Asm(symbol.index()),
+                    pos: token.pos,
+                    line: token.line,
+                });
+                self.i += 2;
+            }
+            TokenType::Edition(year) => self.edition = *year,
+            // Leaves the value it printed from 2026 on.
+            TokenType::Print if self.edition >= 2026 => {
+                for instruction_type in [InstructionType::Dup, InstructionType::Print] {
+                    self.instructions.push(Instruction {
+                        instruction_type,
+                        pos: token.pos,
+                        line: token.line,
+                    });
+                }
+            }
+            TokenType::Env => {
+                let Some(Token {
+                    token_type: TokenType::Str(name),
+                    ..
+                }) = tokens.get(self.i + 1)
+                else {
+                    return Err(common::Error::Parse {
+                        word: "env".to_string(),
+                        pos: token.pos,
+                        line: token.line,
+                        comment: text("E0002.env", &[]),
+                    });
+                };
+                // Numbered for the program in `finish`.
+                let symbol = self.names.intern(name);
+                self.instructions.push(Instruction {
+                    instruction_type: InstructionType::Env(symbol.index()),
+                    pos: token.pos,
+                    line: token.line,
+                });
+                self.i += 1;
+            }
+            // The host splices included files in before parsing.
+            TokenType::Include | TokenType::Str(_) => {
+                return Err(common::Error::Parse {
+                    word: format!("{}", token.token_type),
+                    pos: token.pos,
+                    line: token.line,
+                    comment: text("E0002.unexpected", &[("word", &token.token_type)]),
+                })
+            }
+            // Words that are a single instruction, see `words::BUILTINS`.
+            other => {
+                let instruction_type = words::by_token(other)
+                    .and_then(|word| word.op.as_ref().map(|op| op.instruction))
+                    .ok_or_else(|| common::Error::Parse {
+                        word: other.to_string(),
+                        pos: token.pos,
+                        line: token.line,
+                        comment: text("E0002.unexpected", &[("word", other)]),
+                    })?;
+                self.instructions.push(Instruction {
+                    instruction_type,
+                    pos: token.pos,
+                    line: token.line,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    // Error recovery: a stray `else`, `recover` or `do` starts a block nobody will close, so skip
+    // to its `end`, or stop before the next function.
+    fn synchronize(&mut self, tokens: &[Token]) {
+        let mut depth = 0;
+        while let Some(token) = tokens.get(self.i + 1) {
+            match token.token_type {
+                TokenType::Fun => return,
+                TokenType::While | TokenType::If | TokenType::Try => depth += 1,
+                TokenType::End if depth == 0 => {
+                    self.i += 1;
+                    return;
+                }
+                TokenType::End => depth -= 1,
+                _ => {}
+            }
+            self.i += 1;
+        }
+    }
+
+    // `struct Point x y end` is sugar for functions over the memory words:
+    // `Point` pushes its size in cells, `Point.x` the offset of `x`, and
+    // `Point.x@` and `Point.x!` fetch and store `x` given the block's
+    // address. They are public, so code using a module can use its structs.
+    fn parse_struct(&mut self, tokens: &[Token]) -> Result<(), common::Error> {
+        let opener = &tokens[self.i];
+        let name = self.name_after(tokens, "Struct name is missing")?;
+        let fields =
+            self.names_until_end(tokens, opener, &name, ["E0002.field", "E0002.two-fields"])?;
+        let doc = match self.pending_doc.is_empty() {
+            true => format!("The size of a `{}` in cells.", name),
+            false => self.pending_doc.join("\n"),
+        };
+        self.pending_doc.clear();
+        let size = fields.len() as i32;
+        self.define_word(&name, doc, opener, &[InstructionType::Push(size)]);
+        for (offset, (field, token)) in fields.iter().enumerate() {
+            let offset = offset as i32;
+            // The block's own address needs nothing added.
+            let at = match offset {
+                0 => vec![],
+                _ => vec![InstructionType::Push(offset), InstructionType::Add],
+            };
+            let words = [
+                (
+                    "",
+                    format!("The offset of `{}` in a `{}`.", field, name),
+                    vec![InstructionType::Push(offset)],
+                ),
+                (
+                    "@",
+                    format!(
+                        "Fetches `{}` of the `{}` at the address on top.",
+                        field, name
+                    ),
+                    [&at[..], &[InstructionType::Fetch]].concat(),
+                ),
+                (
+                    "!",
+                    format!(
+                        "Stores `{}` of the `{}` at the address on top.",
+                        field, name
+                    ),
+                    [&at[..], &[InstructionType::Store]].concat(),
+                ),
+            ];
+            for (suffix, doc, body) in words {
+                let word = format!("{}.{}{}", name, field, suffix);
+                self.define_word(&word, doc, token, &body);
+            }
+        }
+        Ok(())
+    }
+
+    // `enum Color red green blue end` numbers `Color.red`, `Color.green` and
+    // `Color.blue` from 0. Each is a constant the parser pushes in its place.
+    fn parse_enum(&mut self, tokens: &[Token]) -> Result<(), common::Error> {
+        let opener = &tokens[self.i];
+        let name = self.name_after(tokens, "Enum name is missing")?;
+        let members =
+            self.names_until_end(tokens, opener, &name, ["E0002.member", "E0002.two-members"])?;
+        let prefix = Self::qualify(&self.current_scope().prefix, &name);
+        for (member, token) in &members {
+            let qualified = Self::qualify(&prefix, member);
+            if self.names.get(&qualified).is_some_and(|symbol| {
+                self.functions.contains_key(&symbol) || self.constants.contains_key(&symbol)
+            }) {
+                return Err(common::Error::Parse {
+                    word: member.to_string(),
+                    pos: token.pos,
+                    line: token.line,
+                    comment: text("E0002.defined", &[("name", &qualified)]),
+                });
+            }
+        }
+        for (value, (member, _)) in members.iter().enumerate() {
+            let definition = Definition {
+                name: self.names.intern(&Self::qualify(&prefix, member)),
+                start: self.instructions.len(),
+                public: true,
+                doc: None,
+                value: Some(value as i32),
+            };
+            self.define(&definition);
+            self.definitions.push(definition);
+        }
+        Ok(())
+    }
+
+    // The names after a `struct` or `enum` up to its `end`. `keys` are the
+    // messages for something else in their place and for a name twice.
+    fn names_until_end<'t>(
+        &mut self,
+        tokens: &'t [Token],
+        opener: &Token,
+        name: &str,
+        keys: [&str; 2],
+    ) -> Result<Vec<(String, &'t Token<'t>)>, common::Error> {
+        let mut names: Vec<(String, &Token)> = vec![];
+        loop {
+            self.i += 1;
+            let Some(token) = tokens.get(self.i) else {
+                return Err(common::Error::Parse {
+                    word: opener.token_type.to_string(),
+                    pos: opener.pos,
+                    line: opener.line,
+                    comment: text("E0002.no-end", &[("word", &opener.token_type)]),
+                });
+            };
+            let found = match &token.token_type {
+                TokenType::End => return Ok(names),
+                TokenType::Identifier(found) if !found.contains(['.', '@', '!']) => found,
+                other => {
+                    return Err(common::Error::Parse {
+                        word: other.to_string(),
+                        pos: token.pos,
+                        line: token.line,
+                        comment: text(keys[0], &[("word", other)]),
+                    })
+                }
+            };
+            if names.iter().any(|(seen, _)| seen == found) {
+                return Err(common::Error::Parse {
+                    word: found.to_string(),
+                    pos: token.pos,
+                    line: token.line,
+                    comment: text(keys[1], &[("name", &name), ("field", found)]),
+                });
+            }
+            names.push((found.to_string(), token));
+        }
+    }
+
+    // A public function with `body`, placed at `token`.
+    fn define_word(&mut self, name: &str, doc: String, token: &Token, body: &[InstructionType]) {
+        let qualified = Self::qualify(&self.current_scope().prefix, name);
+        let definition = Definition {
+            name: self.names.intern(&qualified),
+            start: self.instructions.len(),
+            public: true,
+            doc: Some(doc),
+            value: None,
+        };
+        self.define(&definition);
+        self.definitions.push(definition);
+        for instruction_type in body.iter().chain([&InstructionType::Ret]) {
+            self.instructions.push(Instruction {
+                instruction_type: *instruction_type,
+                pos: token.pos,
+                line: token.line,
+            });
+        }
+    }
+
+    fn unclosed(&self, opener_idx: usize) -> common::Error {
+        let opener = &self.instructions[opener_idx];
+        common::Error::Parse {
+            word: format!("{}", opener.instruction_type),
+            pos: opener.pos,
+            line: opener.line,
+            comment: text("E0002.no-end", &[("word", &opener.instruction_type)]),
+        }
+    }
+
+    fn misplaced_pub(pos: usize, line: usize) -> common::Error {
+        common::Error::Parse {
+            word: "pub".to_string(),
+            pos,
+            line,
+            comment: text("E0002.pub", &[]),
+        }
+    }
+
+    // Modules left open and a `pub` at the very end of the input.
+    fn unfinished(&self) -> Vec<common::Error> {
+        let mut errors: Vec<common::Error> = self.scopes[1..]
+            .iter()
+            .rev()
+            .map(|scope| common::Error::Parse {
+                word: "module".to_string(),
+                pos: scope.pos,
+                line: scope.line,
+                comment: text("E0002.no-end", &[("word", &"module")]),
+            })
+            .collect();
+        if let Some((pos, line)) = self.public_next {
+            errors.push(Self::misplaced_pub(pos, line));
+        }
+        errors
+    }
+
+    fn finish(mut self) -> Program {
+        // A function defined again replaces the earlier definition, so calls
+        // made before that go to the last one too. Leave a call alone when
+        // another function starts at the same place, as an empty one can.
+        let live: BTreeSet<usize> = self.functions.values().copied().collect();
+        let replaced: BTreeMap<usize, usize> = self
+            .definitions
+            .iter()
+            .filter(|definition| definition.value.is_none() && !live.contains(&definition.start))
+            .map(|definition| (definition.start, self.functions[&definition.name]))
+            .collect();
+        if !replaced.is_empty() {
+            for instruction in &mut self.instructions {
+                if let InstructionType::Call(target) | InstructionType::Address(target) =
+                    &mut instruction.instruction_type
+                {
+                    *target = replaced.get(target).copied().unwrap_or(*target);
+                }
+            }
+        }
+        let names = &self.names;
+        let name = |symbol: Symbol| names.resolve(symbol).to_string();
+        let mut env: Vec<String> = vec![];
+        let mut asm: Vec<String> = vec![];
+        for instruction in &mut self.instructions {
+            let (index, table) = match &mut instruction.instruction_type {
+                InstructionType::Env(index) => (index, &mut env),
+                InstructionType::Asm(index) => (index, &mut asm),
+                _ => continue,
+            };
+            let text = name(Symbol::from_index(*index));
+            *index = table.iter().position(|t| *t == text).unwrap_or_else(|| {
+                table.push(text);
+                table.len() - 1
+            });
+        }
+        let mut program = Program {
+            instructions: self.instructions,
+            functions: self
+                .functions
+                .into_iter()
+                .map(|(f, start)| (name(f), start))
+                .collect(),
+            private: self.private.into_iter().map(name).collect(),
+            docs: self
+                .docs
+                .into_iter()
+                .map(|(f, doc)| (name(f), doc))
+                .collect(),
+            symbols: BTreeMap::new(),
+            env,
+            asm,
+        };
+        program.index_symbols();
+        // Jumps are recorded by instruction index while walking tokens, so an
+        // index mixed up with the other would only show at run time.
+        debug_assert_eq!(checker::validate_program(&program), Ok(()));
+        program
+    }
+}
+
+pub fn parse(tokens: Vec<Token>) -> Result<Program, common::Error> {
+    let mut parser = Parser::new();
+    while parser.i < tokens.len() {
+        parser.parse_token(&tokens)?;
+        parser.i += 1;
+    }
+    if let Some(opener_idx) = parser.stack.pop() {
+        return Err(parser.unclosed(opener_idx));
+    }
+    match parser.unfinished().into_iter().next() {
+        Some(e) => Err(e),
+        None => checked_entry(parser.finish()),
+    }
+}
+
+// `main` starts on an empty stack, so a word at its start that needs more
+// than what came before it can't work; say so now rather than on the run.
+// With an `on_error` handler the program may mean to catch that.
+fn checked_entry(program: Program) -> Result<Program, common::Error> {
+    let underflow = program
+        .functions
+        .get("main")
+        .filter(|_| !program.functions.contains_key(ERROR_HANDLER))
+        .and_then(|start| checker::underflow_on_entry(&program, *start, 0));
+    match underflow {
+        Some(e) => Err(e),
+        None => Ok(program),
+    }
+}
+
+// Like `parse`, but keeps going after an error and returns every error found.
+pub fn parse_recovering(tokens: Vec<Token>) -> Result<Program, Vec<common::Error>> {
+    lower(&tokens, None).into_program()
+}
+
+// A block that `tokens` open and don't close, and the word it waits for
+// next: `do`, `else` or `recover` halfway into a `while`, `if` or `try`,
+// `end` after that and for `struct`, `enum`, `module` and `asm`, and `ret`
+// for a `fun`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Open {
+    pub word: String,
+    pub pos: usize,
+    pub line: usize,
+    pub expects: &'static str,
+}
+
+pub enum Partial {
+    Complete(Program),
+    // Outermost first, so their count is how deep the next line is nested.
+    Incomplete(Vec<Open>),
+}
+
+// The blocks still open at the end of `tokens`. A `fun` is open until its
+// `ret`, or until the next `fun` or the `end` of its module. Closers that
+// don't match what is open are left for parsing to report.
+pub fn open_blocks(tokens: &[Token]) -> Vec<Open> {
+    let mut open: Vec<Open> = vec![];
+    let in_function = |open: &Vec<Open>| open.last().is_some_and(|o| o.expects == "ret");
+    for token in tokens {
+        let expects = match token.token_type {
+            TokenType::While => "do",
+            TokenType::If => "else",
+            TokenType::Try => "recover",
+            TokenType::Struct | TokenType::Enum | TokenType::Module | TokenType::Asm => "end",
+            TokenType::Fun => {
+                if in_function(&open) {
+                    open.pop();
+                }
+                "ret"
+            }
+            TokenType::Do | TokenType::Else | TokenType::Recover => {
+                let opener = match token.token_type {
+                    TokenType::Do => "do",
+                    TokenType::Else => "else",
+                    _ => "recover",
+                };
+                if let Some(block) = open.last_mut().filter(|o| o.expects == opener) {
+                    block.expects = "end";
+                }
+                continue;
+            }
+            TokenType::Ret if in_function(&open) => {
+                open.pop();
+                continue;
+            }
+            TokenType::End => {
+                if in_function(&open) {
+                    open.pop();
+                }
+                open.pop();
+                continue;
+            }
+            _ => continue,
+        };
+        open.push(Open {
+            word: token.token_type.to_string(),
+            pos: token.pos,
+            line: token.line,
+            expects,
+        });
+    }
+    open
+}
+
+// For input that arrives a piece at a time, like REPL lines or a file being
+// typed: whether `tokens` are a whole program yet, and if not what is still
+// open. Complete input is parsed as `parse_recovering` does.
+pub fn parse_partial(tokens: Vec<Token>) -> Result<Partial, Vec<common::Error>> {
+    let open = open_blocks(&tokens);
+    if !open.is_empty() {
+        return Ok(Partial::Incomplete(open));
+    }
+    parse_recovering(tokens).map(Partial::Complete)
+}
+
+// The parser between two top-level items: no block open and no `pub` or doc
+// comment waiting for its `fun`. Everything else it knows is in the
+// instructions, definitions and errors before this point, which nothing
+// after it changes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Checkpoint {
+    token: usize,
+    instructions: usize,
+    definitions: usize,
+    errors: usize,
+    scopes: Vec<Scope>,
+    edition: u16,
+}
+
+// Everything `parse_recovering` found, kept so that `incremental::Parser`
+// can lower again from the last checkpoint before an edit.
+#[derive(Clone, Default)]
+pub(crate) struct Lowered {
+    // Only grows, so symbols from an earlier result mean the same here.
+    names: SymbolTable,
+    instructions: Vec<Instruction>,
+    definitions: Vec<Definition>,
+    errors: Vec<common::Error>,
+    checkpoints: Vec<Checkpoint>,
+    // Left open at the end of the input.
+    stack: Vec<usize>,
+    scopes: Vec<Scope>,
+    public_next: Option<(usize, usize)>,
+}
+
+// The tokens `old` of the previous input were replaced by `new`, which start
+// at the same index. Lines from `line` on, numbered as before the edit,
+// moved by `lines`.
+pub(crate) struct Edit {
+    pub(crate) old: Range<usize>,
+    pub(crate) new: Range<usize>,
+    pub(crate) line: usize,
+    pub(crate) lines: isize,
+}
+
+impl Edit {
+    fn moved(&self, line: usize) -> usize {
+        if line >= self.line {
+            line.wrapping_add_signed(self.lines)
+        } else {
+            line
+        }
+    }
+
+    fn moved_error(&self, error: &common::Error) -> common::Error {
+        match error.position() {
+            Some((line, _)) if line >= self.line => error.clone().moved(self.lines),
+            _ => error.clone(),
+        }
+    }
+
+    fn moved_scope(&self, scope: &Scope) -> Scope {
+        Scope {
+            line: self.moved(scope.line),
+            ..scope.clone()
+        }
+    }
+}
+
+impl Parser {
+    fn between_items(&self, tokens: &[Token]) -> bool {
+        self.stack.is_empty()
+            && self.public_next.is_none()
+            && self.pending_doc.is_empty()
+            && matches!(
+                tokens[self.i].token_type,
+                TokenType::Fun
+                    | TokenType::Pub
+                    | TokenType::Doc(_)
+                    | TokenType::Module
+                    | TokenType::Struct
+                    | TokenType::Enum
+            )
+    }
+
+    fn checkpoint(&self, errors: usize) -> Checkpoint {
+        Checkpoint {
+            token: self.i,
+            instructions: self.instructions.len(),
+            definitions: self.definitions.len(),
+            errors,
+            scopes: self.scopes.clone(),
+            edition: self.edition,
+        }
+    }
+
+    fn restore(lowered: &Lowered, checkpoint: &Checkpoint) -> Self {
+        let mut parser = Parser::new();
+        parser.names = lowered.names.clone();
+        parser.instructions = lowered.instructions[..checkpoint.instructions].to_vec();
+        parser.definitions = lowered.definitions[..checkpoint.definitions].to_vec();
+        for definition in &lowered.definitions[..checkpoint.definitions] {
+            parser.define(definition);
+        }
+        parser.scopes = checkpoint.scopes.clone();
+        parser.edition = checkpoint.edition;
+        parser.i = checkpoint.token;
+        parser
+    }
+}
+
+// Walks the tokens like `parse`, recovering from errors, and remembers a
+// checkpoint between items. Given the previous result and what changed
+// since, it starts from the last checkpoint before the edit, and once it
+// reaches a checkpoint after the edit in the same state as last time, it
+// takes the rest from the previous result, moved to the new indexes and lines.
+pub(crate) fn lower(tokens: &[Token], previous: Option<(&Lowered, &Edit)>) -> Lowered {
+    let mut parser = Parser::new();
+    let mut errors = vec![];
+    let mut checkpoints = vec![];
+    let mut resumed = 0;
+    if let Some((previous, edit)) = previous {
+        parser.names = previous.names.clone();
+        // Recovering from an error stops in front of a `fun`, so whether a
+        // checkpoint is reached at all depends on its own token too.
+        let before = previous
+            .checkpoints
+            .partition_point(|checkpoint| checkpoint.token < edit.old.start);
+        if let Some(checkpoint) = before.checked_sub(1).map(|i| &previous.checkpoints[i]) {
+            parser = Parser::restore(previous, checkpoint);
+            errors = previous.errors[..checkpoint.errors].to_vec();
+            checkpoints = previous.checkpoints[..before - 1].to_vec();
+            resumed = checkpoint.definitions;
+        }
+    }
+    let mut rejoin = previous;
+    while parser.i < tokens.len() {
+        if parser.between_items(tokens) {
+            let here = parser.checkpoint(errors.len());
+            if let Some((previous, edit)) = rejoin.filter(|(_, edit)| here.token >= edit.new.end) {
+                let token = here.token - edit.new.end + edit.old.end;
+                let same = previous
+                    .checkpoints
+                    .binary_search_by_key(&token, |checkpoint| checkpoint.token)
+                    .ok()
+                    .filter(|&at| {
+                        let old = &previous.checkpoints[at];
+                        old.definitions == here.definitions
+                            && old.edition == here.edition
+                            && old
+                                .scopes
+                                .iter()
+                                .map(|s| edit.moved_scope(s))
+                                .eq(here.scopes.iter().cloned())
+                    });
+                if let Some(at) = same {
+                    let old = &previous.checkpoints[at];
+                    if previous.definitions[resumed..old.definitions]
+                        == parser.definitions[resumed..]
+                    {
+                        return rejoined(parser, errors, checkpoints, previous, edit, at);
                     }
+                    // Declared differently, so no later point can match either.
+                    rejoin = None;
                 }
             }
-            TokenType::Ret => {
-                instructions.push(Instruction {
-                    instruction_type: InstructionType::Ret,
-                    pos: token.pos,
-                    line: token.line,
-                });
+            checkpoints.push(here);
+        }
+        if let Err(e) = parser.parse_token(tokens) {
+            errors.push(e);
+            // A missing name leaves `i` past the last token.
+            if tokens.get(parser.i).is_some_and(|token| {
+                matches!(
+                    token.token_type,
+                    TokenType::Else | TokenType::Recover | TokenType::Do
+                )
+            }) {
+                parser.synchronize(tokens);
             }
         }
-        i += 1;
+        parser.i += 1;
     }
-    if !stack.is_empty() {
-        let Token {
-            line,
-            pos,
-            token_type,
-        } = tokens
-            .get(stack.pop().unwrap())
-            .ok_or(common::Error::Parse {
-                word: "".to_string(),
-                pos: 0,
-                line: 0,
-                comment: "impossible index".to_string(),
-            })?;
-
-        Err(common::Error::Parse {
-            word: format!("{}", token_type),
-            pos: *pos,
-            line: *line,
-            comment: format!("This `{}` has no matching end", token_type),
-        })
-    } else {
-        Ok(Program {
-            instructions,
-            functions,
-        })
+    Lowered {
+        names: parser.names,
+        instructions: parser.instructions,
+        definitions: parser.definitions,
+        errors,
+        checkpoints,
+        stack: parser.stack,
+        scopes: parser.scopes,
+        public_next: parser.public_next,
+    }
+}
+
+// The new result up to the previous checkpoint `at`, and the previous one
+// after it.
+fn rejoined(
+    parser: Parser,
+    mut errors: Vec<common::Error>,
+    mut checkpoints: Vec<Checkpoint>,
+    previous: &Lowered,
+    edit: &Edit,
+    at: usize,
+) -> Lowered {
+    let old = &previous.checkpoints[at];
+    let start = old.instructions;
+    let new_errors = errors.len();
+    let new_instructions = parser.instructions.len();
+    let moved_index = |index: usize| {
+        if index >= start {
+            index - start + new_instructions
+        } else {
+            index
+        }
+    };
+    let names = parser.names;
+    let mut instructions = parser.instructions;
+    let mut definitions = parser.definitions;
+    instructions.extend(previous.instructions[start..].iter().map(|instruction| {
+        let mut instruction_type = instruction.instruction_type;
+        if let Some(target) = instruction_type.target_mut() {
+            *target = moved_index(*target);
+        }
+        Instruction {
+            instruction_type,
+            pos: instruction.pos,
+            line: edit.moved(instruction.line),
+        }
+    }));
+    definitions.extend(
+        previous.definitions[old.definitions..]
+            .iter()
+            .map(|definition| Definition {
+                start: moved_index(definition.start),
+                ..definition.clone()
+            }),
+    );
+    errors.extend(
+        previous.errors[old.errors..]
+            .iter()
+            .map(|e| edit.moved_error(e)),
+    );
+    checkpoints.extend(previous.checkpoints[at..].iter().map(|checkpoint| {
+        Checkpoint {
+            token: checkpoint.token - edit.old.end + edit.new.end,
+            instructions: checkpoint.instructions - start + new_instructions,
+            definitions: checkpoint.definitions,
+            errors: checkpoint.errors - old.errors + new_errors,
+            scopes: checkpoint
+                .scopes
+                .iter()
+                .map(|s| edit.moved_scope(s))
+                .collect(),
+            edition: checkpoint.edition,
+        }
+    }));
+    Lowered {
+        names,
+        instructions,
+        definitions,
+        errors,
+        checkpoints,
+        stack: previous
+            .stack
+            .iter()
+            .map(|&index| moved_index(index))
+            .collect(),
+        scopes: previous
+            .scopes
+            .iter()
+            .map(|s| edit.moved_scope(s))
+            .collect(),
+        public_next: previous
+            .public_next
+            .map(|(pos, line)| (pos, edit.moved(line))),
+    }
+}
+
+impl Lowered {
+    pub(crate) fn into_program(self) -> Result<Program, Vec<common::Error>> {
+        let mut parser = Parser::new();
+        parser.names = self.names;
+        for definition in &self.definitions {
+            parser.define(definition);
+        }
+        parser.instructions = self.instructions;
+        parser.definitions = self.definitions;
+        parser.stack = self.stack;
+        parser.scopes = self.scopes;
+        parser.public_next = self.public_next;
+        let mut errors = self.errors;
+        errors.extend(parser.stack.iter().map(|idx| parser.unclosed(*idx)));
+        errors.extend(parser.unfinished());
+        if errors.is_empty() {
+            checked_entry(parser.finish()).map_err(|e| vec![e])
+        } else {
+            Err(errors)
+        }
     }
 }
 
@@ -758,7 +1895,7 @@ mod parser_test {
                 line: 1,
             },
             Token {
-                token_type: TokenType::Identifier("test".to_string()),
+                token_type: TokenType::Identifier("test".into()),
                 pos: 1,
                 line: 1,
             },
@@ -795,7 +1932,7 @@ mod parser_test {
                 line: 1,
             },
             Token {
-                token_type: TokenType::Identifier("test".to_string()),
+                token_type: TokenType::Identifier("test".into()),
                 pos: 1,
                 line: 1,
             },
@@ -828,7 +1965,7 @@ mod parser_test {
     #[test]
     fn test_call() {
         let tokens = vec![Token {
-            token_type: TokenType::Identifier("test".to_string()),
+            token_type: TokenType::Identifier("test".into()),
             pos: 1,
             line: 1,
         }];
@@ -859,7 +1996,7 @@ mod parser_test {
                 line: 1,
             },
             Token {
-                token_type: TokenType::Identifier("test".to_string()),
+                token_type: TokenType::Identifier("test".into()),
                 pos: 1,
                 line: 1,
             },
@@ -869,7 +2006,7 @@ mod parser_test {
                 line: 1,
             },
             Token {
-                token_type: TokenType::Identifier("test".to_string()),
+                token_type: TokenType::Identifier("test".into()),
                 pos: 1,
                 line: 1,
             },
@@ -891,4 +2028,593 @@ mod parser_test {
             ]
         );
     }
+
+    #[test]
+    fn jumps_use_instruction_indexes_after_functions() {
+        let tokens = crate::tokenizer::tokenize("fun f 1 if 2 else 3 end 1 while end ret").unwrap();
+        let program = parse(tokens).unwrap();
+        let types: Vec<_> = program
+            .instructions
+            .into_iter()
+            .map(|i| i.instruction_type)
+            .collect();
+        assert_eq!(
+            types,
+            vec![
+                InstructionType::Push(1),
+                InstructionType::If(3),
+                InstructionType::Push(2),
+                InstructionType::Else(5),
+                InstructionType::Push(3),
+                InstructionType::EndIf,
+                InstructionType::Push(1),
+                InstructionType::While(8),
+                InstructionType::EndWhile(7),
+                InstructionType::Ret,
+            ]
+        );
+    }
+
+    fn recovered_errors(source: &str) -> Vec<String> {
+        let tokens = crate::tokenizer::tokenize(source).unwrap();
+        parse_recovering(tokens)
+            .err()
+            .unwrap_or_default()
+            .iter()
+            .map(|e| e.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn unknown_names_suggest_near_ones() {
+        assert_eq!(
+            recovered_errors("fun square dup * ret\nfun main 3 sqaure pritn ret"),
+            vec![
+                "2:12: Function not found, did you mean `square`?",
+                "2:19: Function not found, did you mean `print`?"
+            ]
+        );
+        // Inside a module its functions go by their short names.
+        assert_eq!(
+            recovered_errors("module m fun helper ret fun g helpr ret end"),
+            vec!["1:31: Function not found, did you mean `helper`?"]
+        );
+    }
+
+    #[test]
+    fn recovery_reports_every_unknown_function() {
+        assert_eq!(
+            recovered_errors("fun main foo 1 bar ret"),
+            vec!["1:10: Function not found", "1:16: Function not found"]
+        );
+    }
+
+    #[test]
+    fn recovery_skips_stray_else_block() {
+        assert_eq!(
+            recovered_errors("fun main\n1 else 2 while end end\nend\nret"),
+            vec!["2:3: Unexpected `else`", "3:1: Unexpected `end`"]
+        );
+    }
+
+    #[test]
+    fn do_loops() {
+        let tokens = crate::tokenizer::tokenize("fun main 3 while dup do 1 - end ret").unwrap();
+        let types: Vec<InstructionType> = parse(tokens)
+            .unwrap()
+            .instructions
+            .into_iter()
+            .map(|i| i.instruction_type)
+            .collect();
+        assert_eq!(
+            types,
+            vec![
+                InstructionType::Push(3),
+                InstructionType::Loop(3),
+                InstructionType::Dup,
+                InstructionType::Do(6),
+                InstructionType::Push(1),
+                InstructionType::Sub,
+                InstructionType::EndDo(1),
+                InstructionType::Ret,
+            ]
+        );
+        assert_eq!(
+            recovered_errors("fun main do 1 end 1 if do else end ret"),
+            vec![
+                "1:10: Unexpected `do`",
+                "1:24: This `do` has no matching while"
+            ]
+        );
+    }
+
+    #[test]
+    fn recovery_survives_a_missing_name_at_the_end() {
+        assert_eq!(
+            recovered_errors("fun main ret fun"),
+            vec!["1:14: Function name is missing"]
+        );
+    }
+
+    #[test]
+    fn recovery_stops_skipping_at_next_function() {
+        assert_eq!(
+            recovered_errors("fun f else 1 fun main nope ret"),
+            vec!["1:7: Unexpected `else`", "1:23: Function not found"]
+        );
+    }
+
+    #[test]
+    fn recovery_reports_every_unclosed_block() {
+        assert_eq!(
+            recovered_errors("fun main 1 while 1 if ret"),
+            vec![
+                "1:12: This `while` has no matching end",
+                "1:20: This `if` has no matching end"
+            ]
+        );
+    }
+
+    #[test]
+    fn recovery_returns_program_when_there_are_no_errors() {
+        let tokens = crate::tokenizer::tokenize("fun main 1 print ret").unwrap();
+        let program = parse_recovering(tokens).unwrap();
+        assert_eq!(program.instructions.len(), 3);
+    }
+
+    #[test]
+    fn end_of_if_without_else_is_an_error() {
+        let tokens = crate::tokenizer::tokenize("1 if 2 end").unwrap();
+        assert_eq!(
+            parse(tokens).map(|_| ()).map_err(|e| e.to_string()),
+            Err("1:8: This `end` closes `if`, which needs an `else`".to_string())
+        );
+    }
+
+    fn run(source: &str) -> Result<Vec<i32>, common::Error> {
+        use crate::stack::VecStack;
+        use crate::stack_machine::StackMachine;
+        let program = parse(crate::tokenizer::tokenize(source)?)?;
+        StackMachine::new(VecStack::new()).execute(program)
+    }
+
+    #[test]
+    fn print_keeps_its_value_from_edition_2026() {
+        assert_eq!(run("fun main 1 2 print print ret"), Ok(vec![2, 1]));
+        assert_eq!(
+            run("fun main 1 2 print\n#pragma edition 2026\nprint ret"),
+            Ok(vec![2, 1])
+        );
+        assert_eq!(
+            run("#pragma edition 2026\nfun main 1 2 print print + print ret"),
+            Ok(vec![2, 2, 3])
+        );
+    }
+
+    #[test]
+    fn try_jumps_to_recover_and_end() {
+        let tokens = crate::tokenizer::tokenize("fun f try 1 recover pop end ret").unwrap();
+        let types: Vec<_> = parse(tokens)
+            .unwrap()
+            .instructions
+            .into_iter()
+            .map(|i| i.instruction_type)
+            .collect();
+        assert_eq!(
+            types,
+            vec![
+                InstructionType::Try(2),
+                InstructionType::Push(1),
+                InstructionType::Recover(4),
+                InstructionType::Pop,
+                InstructionType::EndTry,
+                InstructionType::Ret,
+            ]
+        );
+
+        let errors = |source: &str| {
+            parse(crate::tokenizer::tokenize(source).unwrap())
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        };
+        assert_eq!(
+            errors("fun f try 1 end ret"),
+            Err("1:13: This `end` closes `try`, which needs a `recover`".to_string())
+        );
+        assert_eq!(
+            errors("fun f 1 if recover end ret"),
+            Err("1:12: This `recover` has no matching try".to_string())
+        );
+        assert_eq!(
+            recovered_errors("fun main\n1 recover 2 try recover end end\nend\nret"),
+            vec!["2:3: Unexpected `recover`", "3:1: Unexpected `end`"]
+        );
+    }
+
+    #[test]
+    fn try_recovers_from_errors() {
+        assert_eq!(
+            run("fun main try 1 print recover pop end 2 print ret"),
+            Ok(vec![1, 2])
+        );
+        assert_eq!(
+            run("fun main 1 try 7 5 0 / print recover print end print ret"),
+            Ok(vec![2, 1])
+        );
+        // Unwinds the calls made inside the `try`.
+        assert_eq!(
+            run("fun div0 0 / ret\nfun main 10 try 4 div0 print recover print end print ret"),
+            Ok(vec![2, 10])
+        );
+        // A `ret` leaves the `try` it is in, and the error reaches the outer one.
+        assert_eq!(
+            run("fun safe try 1 ret recover end ret\nfun main try safe + recover print end ret"),
+            Ok(vec![1])
+        );
+        assert_eq!(
+            run("fun main try try + recover + end recover print end ret"),
+            Ok(vec![1])
+        );
+    }
+
+    #[test]
+    fn module_functions_are_qualified() {
+        let source = "module math
+  pub fun square dup * ret
+  pub fun cube dup square * ret
+end
+fun main 3 math.square print 2 math.cube print ret";
+        assert_eq!(run(source), Ok(vec![9, 8]));
+        let program = parse(crate::tokenizer::tokenize(source).unwrap()).unwrap();
+        assert!(program.functions.contains_key("math.square"));
+        assert!(!program.functions.contains_key("square"));
+    }
+
+    #[test]
+    fn import_brings_names_into_scope() {
+        assert_eq!(
+            run("module math pub fun square dup * ret end
+import math
+fun main 4 square print ret"),
+            Ok(vec![16])
+        );
+        assert_eq!(
+            run("module a pub fun f 1 ret end module b pub fun f 2 ret end
+import a import b
+fun main f print ret")
+            .map_err(|e| e.to_string()),
+            Err("3:10: `f` could be any of `a.f`, `b.f`".to_string())
+        );
+        assert_eq!(
+            run("import nope fun main ret").map_err(|e| e.to_string()),
+            Err("1:8: Module not found".to_string())
+        );
+    }
+
+    #[test]
+    fn nested_modules_and_blocks() {
+        assert_eq!(
+            run("module outer
+  module inner pub fun one 1 ret end
+  fun two inner.one dup + ret
+  pub fun pick 1 if one else two end ret
+end
+fun main outer.pick print outer.inner.one print ret")
+            .map_err(|e| e.to_string()),
+            Err("4:21: Function not found".to_string())
+        );
+        assert_eq!(
+            run("module outer
+  module inner pub fun one 1 ret end
+  fun two inner.one dup + ret
+  pub fun pick 0 if inner.one else two end nip ret
+end
+fun main outer.pick print outer.inner.one print ret"),
+            Ok(vec![2, 1])
+        );
+    }
+
+    #[test]
+    fn unclosed_module() {
+        assert_eq!(
+            recovered_errors("module m fun f ret"),
+            vec!["1:1: This `module` has no matching end"]
+        );
+        let tokens = crate::tokenizer::tokenize("module").unwrap();
+        assert_eq!(
+            parse(tokens).map(|_| ()).map_err(|e| e.to_string()),
+            Err("1:1: Module name is missing".to_string())
+        );
+    }
+
+    #[test]
+    fn structs_name_the_cells_of_a_block() {
+        let source = "module geo
+  struct Point x y end
+  pub fun at Point alloc swap over Point.y! swap over Point.x! ret
+end
+import geo
+fun main 3 4 at dup Point.x@ print Point.y@ print Point.y print geo.Point print ret";
+        assert_eq!(run(source), Ok(vec![3, 4, 1, 2]));
+        let program = parse(crate::tokenizer::tokenize(source).unwrap()).unwrap();
+        for word in ["geo.Point", "geo.Point.x", "geo.Point.x@", "geo.Point.y!"] {
+            assert!(program.functions.contains_key(word), "{}", word);
+        }
+        assert!(program.private.is_empty());
+        assert_eq!(
+            program.docs["geo.Point.y@"],
+            "Fetches `y` of the `Point` at the address on top."
+        );
+    }
+
+    #[test]
+    fn struct_errors() {
+        let error = |source| {
+            parse(crate::tokenizer::tokenize(source).unwrap())
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        };
+        assert_eq!(
+            error("struct P x y x end"),
+            Err("1:14: `P` has two fields named `x`".to_string())
+        );
+        assert_eq!(
+            error("struct P x print end"),
+            Err("1:12: Expected a field name or `end`, found `print`".to_string())
+        );
+        assert_eq!(
+            error("struct P x"),
+            Err("1:1: This `struct` has no matching end".to_string())
+        );
+        assert_eq!(
+            error("struct end"),
+            Err("1:1: Struct name is missing".to_string())
+        );
+    }
+
+    #[test]
+    fn enum_members_are_numbered_constants() {
+        let source = "module paint enum Color red green blue end end
+import paint
+fun main Color.blue print paint.Color.green print Color.red print ret";
+        assert_eq!(run(source), Ok(vec![2, 1, 0]));
+        let program = parse(crate::tokenizer::tokenize(source).unwrap()).unwrap();
+        assert_eq!(
+            program.instructions[0].instruction_type,
+            InstructionType::Push(2)
+        );
+        assert_eq!(program.functions.keys().collect::<Vec<_>>(), ["main"]);
+    }
+
+    #[test]
+    fn enum_errors() {
+        let error = |source| {
+            parse(crate::tokenizer::tokenize(source).unwrap())
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        };
+        assert_eq!(
+            error("enum C a b a end"),
+            Err("1:12: `C` has two members named `a`".to_string())
+        );
+        assert_eq!(
+            error("enum C a"),
+            Err("1:1: This `enum` has no matching end".to_string())
+        );
+        assert_eq!(
+            error("fun C.a ret enum C a end"),
+            Err("1:20: `C.a` is already defined".to_string())
+        );
+        assert_eq!(
+            error("enum C a end fun C.a ret"),
+            Err("1:18: `C.a` is already defined".to_string())
+        );
+    }
+
+    #[test]
+    fn asm_blocks_keep_their_text() {
+        let program = parse(
+            crate::tokenizer::tokenize(
+                "fun main\n  asm \"nop\" end\n  1 print\n  asm \"nop\" end\nret",
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            program.instructions[0],
+            Instruction {
+                instruction_type: InstructionType::Asm(0),
+                pos: 3,
+                line: 2
+            }
+        );
+        assert_eq!(
+            program.instructions[3].instruction_type,
+            InstructionType::Asm(0)
+        );
+        assert_eq!(program.asm, ["nop"]);
+        assert_eq!(
+            recovered_errors("fun main asm ret"),
+            vec!["1:10: Expected `asm \"...\" end`"]
+        );
+    }
+
+    #[test]
+    fn module_functions_are_private_unless_pub() {
+        let source = "module m
+  fun helper 1 ret
+  pub fun api helper 1 + ret
+  module inner pub fun deep helper ret end
+end
+";
+        assert_eq!(
+            run(&format!("{}fun main m.api print ret", source)),
+            Ok(vec![2])
+        );
+        assert_eq!(
+            run(&format!("{}fun main m.inner.deep print ret", source)),
+            Ok(vec![1])
+        );
+        assert_eq!(
+            run(&format!("{}fun main m.helper print ret", source)).map_err(|e| e.to_string()),
+            Err("6:10: `m.helper` is private to module `m`".to_string())
+        );
+        assert_eq!(
+            run(&format!("{}import m fun main helper print ret", source))
+                .map_err(|e| e.to_string()),
+            Err("6:19: `m.helper` is private to module `m`".to_string())
+        );
+        let program = parse(crate::tokenizer::tokenize(source).unwrap()).unwrap();
+        assert_eq!(program.private, BTreeSet::from(["m.helper".to_string()]));
+    }
+
+    #[test]
+    fn calls_go_to_the_last_definition() {
+        assert_eq!(
+            run("fun sq dup * ret
+fun four 2 sq ret
+fun sq 1 + ret
+fun main four print 5 sq print ret"),
+            Ok(vec![3, 6])
+        );
+        // `a` and `b` start at the same place, and `a` is redefined.
+        assert_eq!(
+            run("fun a fun b 7 ret fun a 8 ret fun main b print a print ret"),
+            Ok(vec![7, 8])
+        );
+    }
+
+    #[test]
+    fn main_that_starts_short_is_rejected() {
+        let error = parse(crate::tokenizer::tokenize("fun main 1 + ret").unwrap()).err();
+        assert_eq!(
+            error.map(|e| e.to_string()),
+            Some("1:12: Not enough values on the stack for `+`".to_string())
+        );
+        let errors = parse_recovering(crate::tokenizer::tokenize("fun main\n  pop ret").unwrap());
+        assert_eq!(errors.err().map(|e| e.len()), Some(1));
+        // Only the straight-line start is looked at, and only in `main`.
+        assert!(
+            parse(crate::tokenizer::tokenize("fun main 1 if + else 2 end ret").unwrap()).is_ok()
+        );
+        assert!(
+            parse(crate::tokenizer::tokenize("fun add + ret fun main 1 add ret").unwrap()).is_ok()
+        );
+        // A handler may be there to catch it.
+        assert!(
+            parse(crate::tokenizer::tokenize("fun on_error ret fun main + ret").unwrap()).is_ok()
+        );
+    }
+
+    #[test]
+    fn pub_must_precede_fun() {
+        assert_eq!(
+            recovered_errors("pub 1 fun main ret pub"),
+            vec![
+                "1:1: `pub` must be followed by `fun`",
+                "1:20: `pub` must be followed by `fun`"
+            ]
+        );
+    }
+
+    #[test]
+    fn lowering_again_reuses_what_follows_the_edit() {
+        let tokens = |source: &'static str| crate::tokenizer::tokenize(source).unwrap();
+        let before = tokens("fun a 1 ret\nfun b 2 ret\nfun c a b + ret");
+        let mut previous = lower(&before, None);
+        // Only the previous result has it, so it shows which part was reused.
+        let marker = common::Error::Usage {
+            comment: "marker".to_string(),
+        };
+        previous.errors.push(marker.clone());
+
+        // `1` becomes `1 1 +`.
+        let after = tokens("fun a 1 1 + ret\nfun b 2 ret\nfun c a b + ret");
+        let edit = Edit {
+            old: 2..3,
+            new: 2..5,
+            line: 2,
+            lines: 0,
+        };
+        let lowered = lower(&after, Some((&previous, &edit)));
+        assert_eq!(lowered.errors, vec![marker.clone()]);
+        assert_eq!(lowered.checkpoints.len(), 3);
+        let mut reparsed = lower(&after, None);
+        reparsed.errors.push(marker);
+        assert_eq!(lowered.instructions, reparsed.instructions);
+        assert_eq!(lowered.definitions, reparsed.definitions);
+        assert_eq!(lowered.checkpoints, reparsed.checkpoints);
+
+        // Renaming `a` leaves `c` calling a function that is gone.
+        let renamed = tokens("fun z 1 ret\nfun b 2 ret\nfun c a b + ret");
+        let edit = Edit {
+            old: 1..2,
+            new: 1..2,
+            line: 2,
+            lines: 0,
+        };
+        let lowered = lower(&renamed, Some((&previous, &edit)));
+        assert_eq!(lowered.errors, lower(&renamed, None).errors);
+    }
+
+    #[test]
+    fn addresses_are_of_functions() {
+        let program = |source| parse(crate::tokenizer::tokenize(source).unwrap());
+        let sq = program("fun sq dup * ret fun sq 3 ret fun main &sq invoke ret").unwrap();
+        // A function defined again is the one every `&` means.
+        assert_eq!(
+            sq.instructions[5].instruction_type,
+            InstructionType::Address(sq.functions["sq"])
+        );
+        assert_eq!(
+            program("enum Color red end fun main &Color.red ret")
+                .err()
+                .map(|e| e.to_string()),
+            Some("1:29: `Color.red` is a constant, not a function".to_string())
+        );
+        assert!(program("fun main &nope ret").is_err());
+    }
+
+    #[test]
+    fn completeness_waits_for_every_opener() {
+        let complete =
+            |source| open_blocks(&crate::tokenizer::tokenize(source).unwrap()).is_empty();
+        assert!(complete("1 2 + print"));
+        assert!(complete("fun sq dup * ret"));
+        assert!(complete("fun f if 1 ret else 2 end ret"));
+        assert!(complete("fun a 1 fun b 2 ret"));
+        assert!(complete("module m fun f 1 end"));
+        assert!(complete("struct P x y end"));
+        assert!(complete("1 end"));
+        assert!(!complete("fun sq dup *"));
+        assert!(!complete("fun f if 1 ret else"));
+        assert!(!complete("3 while dup do"));
+        assert!(!complete("module m fun f 1 ret"));
+        assert!(!complete("struct P x"));
+    }
+
+    #[test]
+    fn partial_input_says_what_is_open() {
+        let partial = |source| parse_partial(crate::tokenizer::tokenize(source).unwrap());
+        let Ok(Partial::Incomplete(open)) = partial("fun main\n  3 while dup do\n    if") else {
+            panic!("expected open blocks");
+        };
+        let open: Vec<(&str, usize, usize, &str)> = open
+            .iter()
+            .map(|o| (o.word.as_str(), o.line, o.pos, o.expects))
+            .collect();
+        assert_eq!(
+            open,
+            vec![
+                ("fun", 1, 1, "ret"),
+                ("while", 2, 5, "end"),
+                ("if", 3, 5, "else")
+            ]
+        );
+        assert!(matches!(
+            partial("fun main 1 print ret"),
+            Ok(Partial::Complete(program)) if program.functions.contains_key("main")
+        ));
+        assert!(partial("fun main nope ret").is_err());
+    }
 }
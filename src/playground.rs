@@ -0,0 +1,284 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::common::Error;
+use crate::json;
+use crate::parser::parse_recovering;
+use crate::stack::VecStack;
+use crate::stack_machine::{StackMachine, VmConfig};
+use crate::tokenizer::tokenize_recovering;
+
+#[derive(Debug)]
+pub struct Limits {
+    pub max_source_len: usize,
+    pub max_steps: usize,
+    pub max_stack: usize,
+    pub max_memory: usize,
+    // Milliseconds of wall time per run.
+    pub timeout: usize,
+}
+
+impl Limits {
+    pub fn vm_config(&self) -> VmConfig {
+        VmConfig {
+            max_steps: Some(self.max_steps),
+            max_stack: Some(self.max_stack),
+            max_memory: Some(self.max_memory),
+            timeout: Some(self.timeout),
+            ..VmConfig::sandboxed()
+        }
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_source_len: 64 * 1024,
+            max_steps: 1_000_000,
+            max_stack: 4096,
+            max_memory: 1 << 20,
+            timeout: 5000,
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct PlaygroundResult {
+    pub tokens: Vec<String>,
+    pub ir: Vec<String>,
+    pub output: Vec<i32>,
+    // What `eprint` wrote, kept apart from `output`.
+    pub stderr: Vec<String>,
+    pub errors: Vec<String>,
+    // The `Error::id` of each of `errors`.
+    pub codes: Vec<String>,
+    pub steps: usize,
+    pub time_micros: u128,
+}
+
+impl PlaygroundResult {
+    pub fn to_json(&self) -> String {
+        json::object(&[
+            (
+                "tokens",
+                json::array(self.tokens.iter().map(|t| json::string(t))),
+            ),
+            ("ir", json::array(self.ir.iter().map(|i| json::string(i)))),
+            (
+                "output",
+                json::array(self.output.iter().map(|v| v.to_string())),
+            ),
+            (
+                "stderr",
+                json::array(self.stderr.iter().map(|s| json::string(s))),
+            ),
+            (
+                "errors",
+                json::array(self.errors.iter().map(|e| json::string(e))),
+            ),
+            (
+                "codes",
+                json::array(self.codes.iter().map(|c| json::string(c))),
+            ),
+            ("steps", self.steps.to_string()),
+            ("time_micros", self.time_micros.to_string()),
+        ])
+    }
+}
+
+pub fn evaluate(source: &str, limits: &Limits) -> PlaygroundResult {
+    let mut result = PlaygroundResult::default();
+    let timer = Timer::start();
+    let evaluated = if source.len() > limits.max_source_len {
+        Err(Error::Usage {
+            comment: format!(
+                "source is {} bytes, the limit is {}",
+                source.len(),
+                limits.max_source_len
+            ),
+        })
+    } else {
+        evaluate_into(source, limits, &mut result)
+    };
+    if let Err(e) = evaluated {
+        for e in e.into_vec() {
+            result.errors.push(e.to_string());
+            result.codes.push(e.id().to_string());
+        }
+    }
+    result.time_micros = timer.elapsed_micros();
+    result
+}
+
+fn evaluate_into(
+    source: &str,
+    limits: &Limits,
+    result: &mut PlaygroundResult,
+) -> Result<(), Error> {
+    let tokens = tokenize_recovering(source).map_err(Error::from_vec)?;
+    result.tokens = tokens.iter().map(|t| t.token_type.to_string()).collect();
+    let program = parse_recovering(tokens).map_err(Error::from_vec)?;
+    result.ir = program
+        .instructions
+        .iter()
+        .enumerate()
+        .map(|(idx, i)| format!("{}: {}", idx, program.describe(&i.instruction_type)))
+        .collect();
+    let mut machine = StackMachine::with_config(VecStack::new(), limits.vm_config());
+    let stderr = Rc::new(RefCell::new(vec![]));
+    let sink = stderr.clone();
+    machine.set_error_sink(move |line| sink.borrow_mut().push(line.to_string()));
+    let run = machine.run_main(&program);
+    result.steps = run.steps;
+    result.stderr = stderr.take();
+    result.output = run.printed;
+    match run.error {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+// `Instant::now` panics on wasm32-unknown-unknown, which is exactly where
+// the playground runs, so timing is skipped there.
+struct Timer {
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    start: std::time::Instant,
+}
+
+impl Timer {
+    fn start() -> Self {
+        Self {
+            #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+            start: std::time::Instant::now(),
+        }
+    }
+
+    fn elapsed_micros(&self) -> u128 {
+        #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+        return self.start.elapsed().as_micros();
+        #[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+        return 0;
+    }
+}
+
+#[cfg(test)]
+mod playground_tests {
+    use super::*;
+
+    #[test]
+    fn evaluates_program() {
+        let result = evaluate("fun main 2 3 + print ret", &Limits::default());
+        assert_eq!(
+            result.tokens,
+            vec!["fun", "main", "2", "3", "+", "print", "ret"]
+        );
+        assert_eq!(
+            result.ir,
+            vec!["0: 2", "1: 3", "2: +", "3: print", "4: ret"]
+        );
+        assert_eq!(result.output, vec![5]);
+        assert!(result.errors.is_empty());
+        assert_eq!(result.steps, 5);
+    }
+
+    #[test]
+    fn reports_tokenizer_errors() {
+        let result = evaluate("fun main ^ ret", &Limits::default());
+        assert_eq!(result.errors, vec!["1:10: unknown token `^`"]);
+        assert_eq!(result.codes, vec!["E0001"]);
+        let result = evaluate("fun main ^ & ret", &Limits::default());
+        assert_eq!(
+            result.errors,
+            vec!["1:10: unknown token `^`", "1:12: unknown token `&`"]
+        );
+        assert!(result.tokens.is_empty());
+    }
+
+    #[test]
+    fn reports_every_parse_error() {
+        let result = evaluate("fun main a b ret", &Limits::default());
+        assert_eq!(
+            result.errors,
+            vec!["1:10: Function not found", "1:12: Function not found"]
+        );
+    }
+
+    #[test]
+    fn keeps_output_before_runtime_error() {
+        let result = evaluate("fun main 1 1 print 0 / ret", &Limits::default());
+        assert_eq!(result.output, vec![1]);
+        assert_eq!(result.errors, vec!["1:22: division by zero"]);
+        assert_eq!(result.codes, vec!["E1001"]);
+    }
+
+    #[test]
+    fn eprint_is_kept_apart() {
+        let result = evaluate("fun main 1 print 255 hex eprint ret", &Limits::default());
+        assert_eq!(result.output, vec![1]);
+        assert_eq!(result.stderr, vec!["ff"]);
+    }
+
+    #[test]
+    fn enforces_step_limit() {
+        let limits = Limits {
+            max_steps: 10,
+            ..Limits::default()
+        };
+        let result = evaluate("fun main 1 while end ret", &limits);
+        assert_eq!(result.errors, vec!["1:18: step limit of 10 exceeded"]);
+        assert_eq!(result.codes, vec!["E2001"]);
+        assert_eq!(result.steps, 11);
+    }
+
+    #[test]
+    fn enforces_stack_limit() {
+        let limits = Limits {
+            max_stack: 3,
+            ..Limits::default()
+        };
+        let result = evaluate("fun main 1 while dup end ret", &limits);
+        assert_eq!(result.errors, vec!["1:18: stack limit of 3 exceeded"]);
+    }
+
+    #[test]
+    fn enforces_memory_limit() {
+        let limits = Limits {
+            max_memory: 64,
+            ..Limits::default()
+        };
+        let result = evaluate("fun deeper deeper ret\nfun main deeper ret", &limits);
+        assert_eq!(
+            result.errors,
+            vec!["1:12: memory limit of 64 bytes exceeded"]
+        );
+    }
+
+    #[test]
+    fn enforces_source_limit() {
+        let limits = Limits {
+            max_source_len: 4,
+            ..Limits::default()
+        };
+        let result = evaluate("fun main ret", &limits);
+        assert_eq!(result.errors, vec!["source is 12 bytes, the limit is 4"]);
+        assert_eq!(result.steps, 0);
+    }
+
+    #[test]
+    fn serializes_to_json() {
+        let result = PlaygroundResult {
+            tokens: vec!["1".to_string()],
+            ir: vec!["0: 1".to_string()],
+            output: vec![1, 2],
+            stderr: vec!["3".to_string()],
+            errors: vec!["bad \"thing\"".to_string()],
+            codes: vec!["E3001".to_string()],
+            steps: 3,
+            time_micros: 4,
+        };
+        assert_eq!(
+            result.to_json(),
+            r#"{"tokens":["1"],"ir":["0: 1"],"output":[1,2],"stderr":["3"],"errors":["bad \"thing\""],"codes":["E3001"],"steps":3,"time_micros":4}"#
+        );
+    }
+}
@@ -0,0 +1,358 @@
+// Word packs: extra built-ins for a domain, such as drawing or sound, kept
+// out of the core language. Packs are compiled in behind a feature and
+// switched on by name with `--plugin`; programs embedding rorth can also
+// `register` their own. Once a pack is loaded its words parse like built-ins,
+// unless a function of the same name hides them.
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+use crate::checker::StackEffect;
+use crate::common::Error;
+use crate::stack::Stack;
+use crate::words::Fault;
+
+pub struct Pack {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub words: &'static [Native],
+    // Whether `finish` writes files, which `VmConfig::allow_fs` has to
+    // allow before any of the words runs.
+    pub writes_files: bool,
+    // State for one machine, made the first time one of the words runs.
+    pub start: fn() -> Box<dyn PackState>,
+}
+
+pub struct Native {
+    pub name: &'static str,
+    pub effect: StackEffect,
+    pub description: &'static str,
+}
+
+pub trait PackState {
+    // Runs `pack.words[word]`. The machine makes sure the effect's inputs
+    // are on the stack.
+    fn call(&mut self, word: usize, stack: &mut dyn Stack<i32>) -> Result<(), Fault>;
+
+    // Called once after the program ends without an error, to write out
+    // whatever the pack collected.
+    fn finish(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+// The packs this build was compiled with.
+pub const AVAILABLE: &[&Pack] = &[
+    #[cfg(feature = "audio")]
+    &crate::audio::PACK,
+    #[cfg(feature = "turtle")]
+    &crate::turtle::PACK,
+];
+
+// Room for this many loaded packs.
+pub const MAX_LOADED: usize = 16;
+
+// Loaded packs, in the order they were loaded. Slots are filled once and
+// never cleared, so the ids handed out by `native` stay valid. Atomics rather
+// than a lock, so this works without `std` too.
+static LOADED: [AtomicPtr<Pack>; MAX_LOADED] =
+    [const { AtomicPtr::new(ptr::null_mut()) }; MAX_LOADED];
+
+fn loaded() -> impl Iterator<Item = &'static Pack> {
+    LOADED.iter().map_while(|slot| {
+        // Slots only ever hold null or a `&'static Pack` from `register`.
+        unsafe { slot.load(Ordering::Acquire).as_ref() }
+    })
+}
+
+// Makes a pack's words available to everything parsed from now on. Loading
+// the same pack twice does nothing.
+pub fn register(pack: &'static Pack) -> Result<(), Error> {
+    let new = pack as *const Pack as *mut Pack;
+    for slot in &LOADED {
+        match slot.compare_exchange(ptr::null_mut(), new, Ordering::AcqRel, Ordering::Acquire) {
+            Ok(_) => return Ok(()),
+            // As above, a taken slot holds a `&'static Pack`.
+            Err(taken) if unsafe { (*taken).name } == pack.name => return Ok(()),
+            Err(_) => {}
+        }
+    }
+    Err(Error::Usage {
+        comment: format!(
+            "can't load plugin `{}`, at most {} can be loaded",
+            pack.name, MAX_LOADED
+        ),
+    })
+}
+
+// Registers one of the `AVAILABLE` packs by name.
+pub fn load(name: &str) -> Result<(), Error> {
+    match AVAILABLE.iter().find(|pack| pack.name == name) {
+        Some(pack) => register(pack),
+        None if AVAILABLE.is_empty() => Err(Error::Usage {
+            comment: format!("no plugin `{}`: this rorth was built without any", name),
+        }),
+        None => Err(Error::Usage {
+            comment: format!(
+                "no plugin `{}`, try one of: {}",
+                name,
+                AVAILABLE
+                    .iter()
+                    .map(|pack| pack.name)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }),
+    }
+}
+
+// A loaded word, as `InstructionType::Native` refers to it.
+#[derive(Clone, Copy)]
+pub struct Loaded {
+    pub pack: &'static Pack,
+    // Position of the pack among the loaded ones.
+    pub pack_index: usize,
+    pub word: usize,
+}
+
+impl Loaded {
+    pub fn native(&self) -> &'static Native {
+        &self.pack.words[self.word]
+    }
+}
+
+pub fn native(name: &str) -> Option<usize> {
+    loaded()
+        .flat_map(|pack| pack.words)
+        .position(|word| word.name == name)
+}
+
+pub fn word(id: usize) -> Option<Loaded> {
+    let mut id = id;
+    for (pack_index, pack) in loaded().enumerate() {
+        if id < pack.words.len() {
+            return Some(Loaded {
+                pack,
+                pack_index,
+                word: id,
+            });
+        }
+        id -= pack.words.len();
+    }
+    None
+}
+
+// The words of every loaded pack, for `rorth words`.
+pub fn natives() -> Vec<&'static Native> {
+    loaded().flat_map(|pack| pack.words).collect()
+}
+
+// The running state of the loaded packs inside one machine.
+#[derive(Default)]
+pub struct States(BTreeMap<usize, Box<dyn PackState>>);
+
+impl States {
+    pub fn call(&mut self, id: usize, stack: &mut dyn Stack<i32>) -> Result<(), Fault> {
+        let Some(loaded) = word(id) else {
+            return Err(Fault::Failed(
+                "this word's plugin is not loaded".to_string(),
+            ));
+        };
+        self.0
+            .entry(loaded.pack_index)
+            .or_insert_with(loaded.pack.start)
+            .call(loaded.word, stack)
+    }
+
+    // Finishes every pack that ran, in the order they were loaded.
+    pub fn finish(&mut self) -> Result<(), Error> {
+        core::mem::take(&mut self.0)
+            .into_values()
+            .try_for_each(|mut state| state.finish())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod plugin_tests {
+    use super::*;
+    use crate::host::run_program;
+    use crate::parser::{parse, InstructionType};
+    use crate::stack_machine::VmConfig;
+    use crate::tokenizer::tokenize;
+
+    // Adds up what it is given. The names are unlikely to clash with other
+    // tests, which share the loaded packs.
+    pub(crate) static TALLY: Pack = Pack {
+        name: "tally",
+        description: "keeps a running total",
+        words: &[
+            Native {
+                name: "tallyadd",
+                effect: StackEffect {
+                    inputs: 1,
+                    outputs: 0,
+                },
+                description: "adds the top value to the total",
+            },
+            Native {
+                name: "tallytotal",
+                effect: StackEffect {
+                    inputs: 0,
+                    outputs: 1,
+                },
+                description: "pushes the total",
+            },
+        ],
+        writes_files: false,
+        start: || Box::new(Tally(0)),
+    };
+
+    // Would write its marks to a file at the end.
+    pub(crate) static SKETCH: Pack = Pack {
+        name: "sketch",
+        description: "keeps marks for a file",
+        words: &[Native {
+            name: "sketchmark",
+            effect: StackEffect {
+                inputs: 0,
+                outputs: 0,
+            },
+            description: "makes a mark",
+        }],
+        writes_files: true,
+        start: || Box::new(Tally(0)),
+    };
+
+    struct Tally(i32);
+
+    impl PackState for Tally {
+        fn call(&mut self, word: usize, stack: &mut dyn Stack<i32>) -> Result<(), Fault> {
+            match word {
+                0 => match stack.pop() {
+                    Some(n) if n < 0 => Err(Fault::Failed("can't tally less than 0".to_string())),
+                    Some(n) => {
+                        self.0 += n;
+                        Ok(())
+                    }
+                    None => Ok(()),
+                },
+                _ => {
+                    stack.push(self.0);
+                    Ok(())
+                }
+            }
+        }
+    }
+
+    fn run(source: &str) -> (Result<(), Error>, String) {
+        let mut out = vec![];
+        let result = parse(tokenize(source).unwrap())
+            .and_then(|program| run_program(program, VmConfig::default(), &mut out));
+        (result, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn loaded_words_run_with_their_own_state() {
+        register(&TALLY).unwrap();
+        register(&TALLY).unwrap();
+        assert_eq!(
+            run("fun main 3 tallyadd 4 tallyadd tallytotal print ret"),
+            (Ok(()), "7\n".to_string())
+        );
+        // A new machine starts from scratch.
+        assert_eq!(
+            run("fun main tallytotal print ret"),
+            (Ok(()), "0\n".to_string())
+        );
+    }
+
+    #[test]
+    fn packs_that_write_files_need_allow_fs() {
+        register(&SKETCH).unwrap();
+        let program = || parse(tokenize("fun main\n  sketchmark ret").unwrap()).unwrap();
+        let mut out = vec![];
+        assert_eq!(
+            run_program(program(), VmConfig::sandboxed(), &mut out),
+            Err(Error::CapabilityDenied {
+                capability: "sketchmark".to_string(),
+                pos: 3,
+                line: 2,
+            })
+        );
+        assert_eq!(
+            run_program(program(), VmConfig::default(), &mut out),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn functions_hide_loaded_words() {
+        register(&TALLY).unwrap();
+        assert_eq!(
+            run("fun tallytotal 9 ret fun main tallytotal print ret"),
+            (Ok(()), "9\n".to_string())
+        );
+    }
+
+    #[test]
+    fn loaded_words_are_checked_and_can_fail() {
+        register(&TALLY).unwrap();
+        let program = parse(tokenize("fun main 1 tallyadd ret").unwrap()).unwrap();
+        let id = native("tallyadd").unwrap();
+        assert_eq!(
+            program.instructions[0..2]
+                .iter()
+                .map(|i| i.instruction_type)
+                .collect::<Vec<_>>(),
+            vec![InstructionType::Push(1), InstructionType::Native(id)]
+        );
+        let underflow = parse(tokenize("fun f tallyadd ret").unwrap()).unwrap();
+        assert!(matches!(
+            crate::checker::check_stack_safety(&underflow.instructions),
+            Err(Error::StaticCheck { .. })
+        ));
+        assert_eq!(
+            run("fun main 0 1 - tallyadd ret").0,
+            Err(Error::Plugin {
+                word: "tallyadd".to_string(),
+                comment: "can't tally less than 0".to_string(),
+                pos: 16,
+                line: 1
+            })
+        );
+    }
+
+    #[test]
+    fn failed_words_leave_the_stack_alone() {
+        register(&TALLY).unwrap();
+        let mut out = vec![];
+        let program =
+            parse(tokenize("fun on_error print print ret fun main 0 1 - tallyadd ret").unwrap())
+                .unwrap();
+        let config = VmConfig {
+            on_error: crate::stack_machine::ErrorPolicy::Trap,
+            ..VmConfig::default()
+        };
+        assert_eq!(run_program(program, config, &mut out), Ok(()));
+        assert_eq!(String::from_utf8(out).unwrap(), "4\n-1\n");
+    }
+
+    #[test]
+    fn compiled_programs_name_their_plugin_words() {
+        register(&TALLY).unwrap();
+        let program = parse(tokenize("fun main 2 tallyadd tallytotal print ret").unwrap()).unwrap();
+        let bytes = crate::bytecode::encode(&program);
+        let decoded = crate::bytecode::decode(&bytes).unwrap();
+        assert_eq!(decoded.instructions, program.instructions);
+        assert!(crate::bytecode::disassemble(&decoded).contains("1d  native tallyadd"));
+    }
+
+    #[test]
+    fn unknown_plugins() {
+        assert!(matches!(load("nope"), Err(Error::Usage { .. })));
+    }
+}
@@ -0,0 +1,221 @@
+use std::collections::BTreeMap;
+
+use crate::parser::InstructionType;
+use crate::stack_machine::Program;
+
+fn push_literal(n: i32) -> String {
+    // The tokenizer has no negative literals.
+    match n {
+        i32::MIN => format!("0 {} - 1 -", i32::MAX),
+        n if n < 0 => format!("0 {} -", -n),
+        n => n.to_string(),
+    }
+}
+
+impl Program {
+    // Prints the program back as source that parses to the same instructions
+    // and function table. Call targets that have no name get a generated one.
+    // The parser only resolves calls to functions defined above them, so IR
+    // with forward calls prints but does not parse back. Negative pushes have
+    // no literal syntax and come back as subtractions, which shifts indexes.
+    pub fn to_source(&self) -> String {
+        let mut names: BTreeMap<usize, Vec<String>> = BTreeMap::new();
+        for (name, idx) in &self.functions {
+            names.entry(*idx).or_default().push(name.clone());
+        }
+        for names in names.values_mut() {
+            names.sort();
+        }
+        for instruction in &self.instructions {
+            if let InstructionType::Call(target) | InstructionType::Address(target) =
+                instruction.instruction_type
+            {
+                names.entry(target).or_insert_with(|| {
+                    let mut name = format!("fn_{}", target);
+                    while self.functions.contains_key(&name) {
+                        name.push('_');
+                    }
+                    vec![name]
+                });
+            }
+        }
+
+        let mut lines = vec![];
+        let mut blocks: usize = 0;
+        let mut in_function = false;
+        for idx in 0..=self.instructions.len() {
+            if let Some(names) = names.get(&idx) {
+                if !lines.is_empty() {
+                    lines.push(String::new());
+                }
+                for name in names {
+                    lines.push(format!("{}fun {}", "  ".repeat(blocks), name));
+                }
+                in_function = true;
+            }
+            let Some(instruction) = self.instructions.get(idx) else {
+                break;
+            };
+            use InstructionType::*;
+            let text = match instruction.instruction_type {
+                Push(n) => push_literal(n),
+                Call(target) => names[&target][0].clone(),
+                Address(target) => format!("&{}", names[&target][0]),
+                Env(index) => format!("env \"{}\"", self.env[index]),
+                Asm(index) => format!("asm \"{}\" end", self.asm[index]),
+                ref other => other.to_string(),
+            };
+            if matches!(
+                instruction.instruction_type,
+                EndWhile(_) | EndDo(_) | Do(_) | EndIf | Else(_) | Recover(_) | EndTry
+            ) {
+                blocks = blocks.saturating_sub(1);
+            }
+            let indent = blocks + in_function as usize;
+            lines.push(format!("{}{}", "  ".repeat(indent), text));
+            if matches!(
+                instruction.instruction_type,
+                While(_) | Loop(_) | Do(_) | If(_) | Else(_) | Try(_) | Recover(_)
+            ) {
+                blocks += 1;
+            }
+        }
+        let mut source = lines.join("\n");
+        source.push('\n');
+        source
+    }
+}
+
+#[cfg(test)]
+mod printer_tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::genprog::{generate, Options};
+    use crate::parser::{parse, Instruction};
+    use crate::rng::Rng;
+    use crate::tokenizer::tokenize;
+
+    fn types(program: &Program) -> Vec<&InstructionType> {
+        program
+            .instructions
+            .iter()
+            .map(|i| &i.instruction_type)
+            .collect()
+    }
+
+    fn round_trip(source: &str) {
+        let program = parse(tokenize(source).unwrap()).unwrap();
+        let printed = program.to_source();
+        let reparsed = parse(tokenize(&printed).unwrap()).unwrap();
+        assert_eq!(types(&program), types(&reparsed), "{}", printed);
+        assert_eq!(program.functions, reparsed.functions, "{}", printed);
+    }
+
+    #[test]
+    fn prints_do_loops() {
+        let source = "fun main 3 while dup do 1 - end pop ret";
+        let program = parse(tokenize(source).unwrap()).unwrap();
+        assert_eq!(
+            program.to_source(),
+            "fun main
+  3
+  while
+    dup
+  do
+    1
+    -
+  end
+  pop
+  ret
+"
+        );
+        round_trip(source);
+    }
+
+    #[test]
+    fn prints_nested_blocks() {
+        let source = "fun main 3 while dup if print else pop end 1 - end ret";
+        let program = parse(tokenize(source).unwrap()).unwrap();
+        assert_eq!(
+            program.to_source(),
+            "fun main
+  3
+  while
+    dup
+    if
+      print
+    else
+      pop
+    end
+    1
+    -
+  end
+  ret
+"
+        );
+    }
+
+    #[test]
+    fn prints_try_blocks() {
+        let source = "fun main try 1 0 / recover pop 0 end print ret";
+        round_trip(source);
+        let program = parse(tokenize(source).unwrap()).unwrap();
+        assert_eq!(
+            program.to_source(),
+            "fun main\n  try\n    1\n    0\n    /\n  recover\n    pop\n    0\n  end\n  print\n  ret\n"
+        );
+    }
+
+    #[test]
+    fn prints_calls_by_name() {
+        let source = "fun square dup * ret fun main 3 square print ret";
+        let program = parse(tokenize(source).unwrap()).unwrap();
+        assert_eq!(
+            program.to_source(),
+            "fun square\n  dup\n  *\n  ret\n\nfun main\n  3\n  square\n  print\n  ret\n"
+        );
+    }
+
+    #[test]
+    fn names_anonymous_call_targets_and_negative_pushes() {
+        let instruction = |instruction_type| Instruction {
+            instruction_type,
+            pos: 1,
+            line: 1,
+        };
+        let program = Program {
+            instructions: vec![
+                instruction(InstructionType::Push(i32::MIN)),
+                instruction(InstructionType::Ret),
+                instruction(InstructionType::Push(-5)),
+                instruction(InstructionType::Call(0)),
+                instruction(InstructionType::Ret),
+            ],
+            functions: BTreeMap::from([("main".to_string(), 2)]),
+            ..Default::default()
+        };
+        let printed = program.to_source();
+        let reparsed = parse(tokenize(&printed).unwrap()).unwrap();
+        assert_eq!(reparsed.functions.get("fn_0"), Some(&0));
+        assert_eq!(reparsed.functions.get("main"), Some(&6));
+        assert!(printed.contains("0 5 -"));
+        assert!(printed.contains("0 2147483647 - 1 -"));
+    }
+
+    #[test]
+    fn round_trips_example_programs() {
+        round_trip(include_str!("../test_factorial_with_main.sm"));
+        round_trip(include_str!("../test_recursion.sm"));
+        round_trip(include_str!("../test_if_else.sm"));
+        round_trip(include_str!("../test_file_while.sm"));
+    }
+
+    #[test]
+    fn round_trips_generated_programs() {
+        for seed in 0..200 {
+            let tokens = generate(&mut Rng::new(seed), &Options::default());
+            round_trip(&crate::genprog::to_source(&tokens));
+        }
+    }
+}
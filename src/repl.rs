@@ -0,0 +1,303 @@
+use crate::checker;
+use crate::common::Error;
+use crate::host::SourceLoader;
+use crate::parser::{self, parse_recovering};
+use crate::stack::{Stack, VecStack};
+use crate::stack_machine::{StackMachine, VmConfig, ERROR_HANDLER};
+use crate::tokenizer::{tokenize_recovering, Token, TokenType};
+use crate::words;
+
+// The code typed at the prompt runs as the body of this function.
+const LINE_FUNCTION: &str = "__repl__";
+
+const COMMANDS: &str = ":load <file.sm>, :save <file.sm>";
+
+// Lines that define something are kept and put in front of every later line,
+// so they can call what was defined before. Any other line runs right away on
+// a stack that lives as long as the session.
+pub struct Session {
+    definitions: String,
+    functions: Vec<String>,
+    machine: StackMachine<VecStack<i32>>,
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token<'_>>, Error> {
+    tokenize_recovering(source).map_err(Error::from_vec)
+}
+
+fn is_definition(tokens: &[Token]) -> bool {
+    tokens.iter().any(|t| {
+        matches!(
+            t.token_type,
+            TokenType::Fun
+                | TokenType::Module
+                | TokenType::Import
+                | TokenType::Pub
+                | TokenType::Struct
+                | TokenType::Enum
+                | TokenType::Doc(_)
+        )
+    })
+}
+
+impl Session {
+    pub fn new(config: VmConfig) -> Self {
+        Self {
+            definitions: String::new(),
+            functions: vec![],
+            machine: StackMachine::with_config(VecStack::new(), config),
+        }
+    }
+
+    pub fn eval(&mut self, line: &str) -> Result<Vec<String>, Error> {
+        let line_tokens = tokenize(line)?;
+        if line_tokens.is_empty() {
+            return Ok(vec![]);
+        }
+        if is_definition(&line_tokens) {
+            self.define(line)?;
+            return Ok(vec![]);
+        }
+        let mut tokens = tokenize(&self.definitions)?;
+        let (pos, line_no) = (line_tokens[0].pos, line_tokens[0].line);
+        let token = |token_type| Token {
+            token_type,
+            pos,
+            line: line_no,
+        };
+        tokens.push(token(TokenType::Fun));
+        tokens.push(token(TokenType::Identifier(LINE_FUNCTION.into())));
+        tokens.extend(line_tokens);
+        tokens.push(token(TokenType::Ret));
+        let program = parse_recovering(tokens).map_err(Error::from_vec)?;
+        // The line starts on the session's stack, so a word that needs more
+        // than that is caught before anything on the line runs.
+        if !program.functions.contains_key(ERROR_HANDLER) {
+            let start = program.functions[LINE_FUNCTION];
+            let depth = self.stack().len();
+            if let Some(e) = checker::underflow_on_entry(&program, start, depth) {
+                return Err(e);
+            }
+        }
+        // After an error, what the line printed is left for `take_output`.
+        self.machine.call(&program, LINE_FUNCTION)?;
+        Ok(self.machine.take_output())
+    }
+
+    pub fn take_output(&mut self) -> Vec<String> {
+        self.machine.take_output()
+    }
+
+    // Adds definitions to the session if they parse together with the ones
+    // it already has.
+    pub fn define(&mut self, source: &str) -> Result<(), Error> {
+        let separator = if source.ends_with('\n') { "" } else { "\n" };
+        let definitions = format!("{}{}{}", self.definitions, source, separator);
+        let program = parse_recovering(tokenize(&definitions)?).map_err(Error::from_vec)?;
+        let mut functions: Vec<String> = program.functions.into_keys().collect();
+        functions.sort();
+        self.definitions = definitions;
+        self.functions = functions;
+        Ok(())
+    }
+
+    // `:load` adds a file's definitions to the session, `:save` writes the
+    // session's definitions to a file. Returns a message for the user.
+    pub fn command(&mut self, line: &str, loader: &dyn SourceLoader) -> Result<String, Error> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            [":load", path] => {
+                let before = self.functions.len();
+                self.define(&loader.load(path)?)?;
+                Ok(format!(
+                    "loaded {} function(s) from {}",
+                    self.functions.len() - before,
+                    path
+                ))
+            }
+            [":save", path] => {
+                std::fs::write(path, &self.definitions).map_err(|e| Error::Io {
+                    path: path.to_string(),
+                    comment: e.to_string(),
+                })?;
+                Ok(format!(
+                    "saved {} function(s) to {}",
+                    self.functions.len(),
+                    path
+                ))
+            }
+            _ => Err(Error::Usage {
+                comment: format!("commands: {}", COMMANDS),
+            }),
+        }
+    }
+
+    // Whether `input` closes every `while`, `if`, `fun` and other block it
+    // opens. Until it does the console keeps reading lines onto it. Input
+    // that doesn't tokenize is complete, so the error is shown.
+    pub fn is_complete(input: &str) -> bool {
+        tokenize(input).map_or(true, |tokens| parser::open_blocks(&tokens).is_empty())
+    }
+
+    pub fn is_command(line: &str) -> bool {
+        line.trim_start().starts_with(':')
+    }
+
+    pub fn stack(&self) -> &[i32] {
+        self.machine.stack.as_slice()
+    }
+
+    pub fn definitions(&self) -> &str {
+        &self.definitions
+    }
+
+    pub fn functions(&self) -> &[String] {
+        &self.functions
+    }
+
+    // Reserved words and defined functions starting with `prefix`.
+    pub fn complete(&self, prefix: &str) -> Vec<String> {
+        let mut words: Vec<String> = words::reserved()
+            .map(|w| w.to_string())
+            .chain(self.functions.iter().cloned())
+            .filter(|w| w.starts_with(prefix))
+            .collect();
+        words.sort();
+        words.dedup();
+        words
+    }
+}
+
+#[cfg(test)]
+mod repl_tests {
+    use super::*;
+    use crate::host::FsLoader;
+
+    #[test]
+    fn stack_lives_across_lines() {
+        let mut session = Session::new(VmConfig::default());
+        assert_eq!(session.eval("1 2"), Ok(vec![]));
+        assert_eq!(session.eval("+ dup print"), Ok(vec!["3".to_string()]));
+        assert_eq!(session.stack(), &[3]);
+        assert_eq!(session.eval(""), Ok(vec![]));
+    }
+
+    #[test]
+    fn definitions_are_kept() {
+        let mut session = Session::new(VmConfig::default());
+        assert_eq!(session.eval("fun sq dup * ret"), Ok(vec![]));
+        assert_eq!(
+            session.eval("module m pub fun twice 2 * ret end"),
+            Ok(vec![])
+        );
+        assert_eq!(
+            session.eval("3 sq m.twice print"),
+            Ok(vec!["18".to_string()])
+        );
+        assert_eq!(session.functions(), &["m.twice", "sq"]);
+        assert_eq!(
+            session.definitions(),
+            "fun sq dup * ret\nmodule m pub fun twice 2 * ret end\n"
+        );
+        assert_eq!(session.eval("struct P x end"), Ok(vec![]));
+        assert_eq!(
+            session.eval("1 alloc 5 over P.x! P.x@ print"),
+            Ok(vec!["5".to_string()])
+        );
+    }
+
+    #[test]
+    fn lines_that_start_short_do_not_run() {
+        let mut session = Session::new(VmConfig::default());
+        assert_eq!(
+            session.eval("5 print +").map_err(|e| e.to_string()),
+            Err("1:9: Not enough values on the stack for `+`".to_string())
+        );
+        assert_eq!(session.take_output(), Vec::<String>::new());
+        // What the earlier lines left counts.
+        assert_eq!(session.eval("1 2"), Ok(vec![]));
+        assert_eq!(session.eval("+ print"), Ok(vec!["3".to_string()]));
+    }
+
+    #[test]
+    fn open_blocks_wait_for_more_lines() {
+        let mut input = String::from("fun countdown\n");
+        assert!(!Session::is_complete(&input));
+        input.push_str("  while dup do dup print 1 -\n");
+        assert!(!Session::is_complete(&input));
+        input.push_str("  end ret\n");
+        assert!(Session::is_complete(&input));
+        assert!(Session::is_complete("\"open"));
+        let mut session = Session::new(VmConfig::default());
+        assert_eq!(session.eval(&input), Ok(vec![]));
+        assert_eq!(
+            session.eval("2 countdown\npop"),
+            Ok(vec!["2".to_string(), "1".to_string()])
+        );
+    }
+
+    #[test]
+    fn errors_leave_the_session_usable() {
+        let mut session = Session::new(VmConfig::default());
+        assert_eq!(
+            session.eval("nope").map_err(|e| e.to_string()),
+            Err("1:1: Function not found".to_string())
+        );
+        assert!(session.eval("fun broken else ret").is_err());
+        assert_eq!(session.definitions(), "");
+        assert_eq!(
+            session.eval("5 print 0 0 /").map_err(|e| e.to_string()),
+            Err("1:13: division by zero".to_string())
+        );
+        assert_eq!(session.take_output(), vec!["5"]);
+        assert_eq!(session.eval("7 print"), Ok(vec!["7".to_string()]));
+    }
+
+    #[test]
+    fn save_and_load() {
+        let path = std::env::temp_dir().join(format!("rorth-repl-{}.sm", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut session = Session::new(VmConfig::default());
+        session.eval("fun sq dup * ret").unwrap();
+        session.eval("1 2 3").unwrap();
+        assert_eq!(
+            session.command(&format!(":save {}", path), &FsLoader),
+            Ok(format!("saved 1 function(s) to {}", path))
+        );
+
+        let mut fresh = Session::new(VmConfig::default());
+        assert_eq!(
+            fresh.command(&format!(":load {}", path), &FsLoader),
+            Ok(format!("loaded 1 function(s) from {}", path))
+        );
+        assert_eq!(fresh.eval("5 sq print"), Ok(vec!["25".to_string()]));
+        assert_eq!(fresh.definitions(), "fun sq dup * ret\n");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn bad_commands() {
+        let mut session = Session::new(VmConfig::default());
+        assert!(Session::is_command("  :load x"));
+        assert!(!Session::is_command("1 print"));
+        assert!(matches!(
+            session.command(":load", &FsLoader),
+            Err(Error::Usage { .. })
+        ));
+        assert!(matches!(
+            session.command(":load /definitely/not/here.sm", &FsLoader),
+            Err(Error::Io { .. })
+        ));
+    }
+
+    #[test]
+    fn completion() {
+        let mut session = Session::new(VmConfig::default());
+        session.eval("fun printer 1 print ret").unwrap();
+        assert_eq!(
+            session.complete("pri"),
+            vec!["print", "print-padded", "printer"]
+        );
+        assert_eq!(session.complete("im"), vec!["import"]);
+    }
+}
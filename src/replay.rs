@@ -0,0 +1,103 @@
+// What a run read from outside the program, in order, so it can be run
+// again the same way: `rorth run --record io.log` writes it down and
+// `--replay io.log` hands the same values back. The log is text, one
+// `rand`, `time` or `env` and its value per line, so it can go into a bug report
+// as it is.
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Display;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Rand,
+    Time,
+    Env,
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Source::Rand => write!(f, "rand"),
+            Source::Time => write!(f, "time"),
+            Source::Env => write!(f, "env"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Event {
+    pub source: Source,
+    pub value: i32,
+}
+
+pub fn write(events: &[Event]) -> String {
+    events
+        .iter()
+        .map(|event| format!("{} {}\n", event.source, event.value))
+        .collect()
+}
+
+// Blank lines and lines starting with `#` are skipped, so a log can be
+// annotated by hand.
+pub fn read(text: &str) -> Result<Vec<Event>, String> {
+    text.lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(number, line)| {
+            let bad = || {
+                format!(
+                    "line {}: expected `rand <n>`, `time <n>` or `env <n>`",
+                    number
+                )
+            };
+            let (source, value) = line.split_once(' ').ok_or_else(bad)?;
+            let source = match source {
+                "rand" => Source::Rand,
+                "time" => Source::Time,
+                "env" => Source::Env,
+                _ => return Err(bad()),
+            };
+            let value = value.trim().parse().map_err(|_| bad())?;
+            Ok(Event { source, value })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod replay_tests {
+    use super::*;
+    use alloc::string::ToString;
+    use alloc::vec;
+
+    #[test]
+    fn logs_read_back_as_written() {
+        let events = vec![
+            Event {
+                source: Source::Rand,
+                value: 1_234_567,
+            },
+            Event {
+                source: Source::Time,
+                value: 0,
+            },
+        ];
+        let text = write(&events);
+        assert_eq!(text, "rand 1234567\ntime 0\n");
+        assert_eq!(read(&text), Ok(events));
+        assert_eq!(read("# seen on CI\n\ntime 12\n").map(|e| e.len()), Ok(1));
+    }
+
+    #[test]
+    fn bad_lines_say_where() {
+        assert_eq!(
+            read("rand 1\nrand\n"),
+            Err("line 2: expected `rand <n>`, `time <n>` or `env <n>`".to_string())
+        );
+        assert_eq!(
+            read("clock 1"),
+            Err("line 1: expected `rand <n>`, `time <n>` or `env <n>`".to_string())
+        );
+    }
+}
@@ -0,0 +1,64 @@
+// xorshift64*, small and good enough for games and tests. Not for crypto.
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+pub const DETERMINISTIC_SEED: u64 = 0x2545_f491_4f6c_dd1d;
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // A zero state would only ever produce zeros.
+        Self(if seed == 0 { DETERMINISTIC_SEED } else { seed })
+    }
+
+    // Without `std` there is nothing to draw entropy from, so this is the
+    // deterministic seed there.
+    pub fn from_entropy() -> Self {
+        #[cfg(feature = "std")]
+        {
+            use std::hash::{BuildHasher, Hasher};
+            let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+            hasher.write_u64(0);
+            Self::new(hasher.finish())
+        }
+        #[cfg(not(feature = "std"))]
+        Self::new(DETERMINISTIC_SEED)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    // Non-negative, so it can be used with `/` as a modulus-free range trick.
+    pub fn next_i32(&mut self) -> i32 {
+        (self.next_u64() >> 33) as i32
+    }
+}
+
+#[cfg(test)]
+mod rng_tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_same_sequence() {
+        let mut a = Rng::new(42);
+        let mut b = Rng::new(42);
+        for _ in 0..100 {
+            assert_eq!(a.next_i32(), b.next_i32());
+        }
+    }
+
+    #[test]
+    fn values_are_non_negative() {
+        let mut rng = Rng::new(7);
+        assert!((0..1000).all(|_| rng.next_i32() >= 0));
+    }
+
+    #[test]
+    fn zero_seed_is_usable() {
+        let mut rng = Rng::new(0);
+        assert_ne!(rng.next_u64(), 0);
+    }
+}
@@ -0,0 +1,448 @@
+// `rorth selftest`: small programs with known results that between them run
+// every instruction the machine has and every way a run can fail. Each runs
+// as parsed and again after a trip through the bytecode, so a build on a new
+// platform can be checked without the test suite. Plugin words are left to
+// their packs.
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::bytecode;
+use crate::parser::parse;
+use crate::replay::{Event, Source};
+use crate::stack::VecStack;
+use crate::stack_machine::{Division, ErrorPolicy, Program, RunResult, StackMachine, VmConfig};
+use crate::tokenizer::tokenize;
+
+pub struct Case {
+    pub name: &'static str,
+    source: &'static str,
+    // Changes the case needs to a deterministic `VmConfig`.
+    setup: fn(VmConfig) -> VmConfig,
+    output: &'static [&'static str],
+    // How the run fails, as the error reads, if it does.
+    error: Option<&'static str>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outcome {
+    pub name: &'static str,
+    // What went wrong, if anything.
+    pub failure: Option<String>,
+}
+
+fn as_is(config: VmConfig) -> VmConfig {
+    config
+}
+
+pub const CASES: &[Case] = &[
+    Case {
+        name: "arithmetic",
+        source: "fun main 7 5 + print 7 5 - print 7 5 * print 7 2 / print ret",
+        setup: as_is,
+        output: &["12", "2", "35", "3"],
+        error: None,
+    },
+    Case {
+        name: "stack words",
+        source: "fun main
+  1 2 swap print print
+  1 2 over print print print
+  1 2 3 rot print print print
+  1 2 nip print
+  5 dup print print
+  1 2 pop print
+ret",
+        setup: as_is,
+        output: &["1", "2", "1", "2", "1", "1", "3", "2", "2", "5", "5", "1"],
+        error: None,
+    },
+    Case {
+        name: "number formats",
+        source: "fun main 255 hex print 5 bin print dec 42 print 7 4 print-padded ret",
+        setup: as_is,
+        output: &["ff", "101", "42", "   7"],
+        error: None,
+    },
+    Case {
+        name: "eprint",
+        source: "fun main 1 2 eprint print ret",
+        setup: as_is,
+        output: &["1"],
+        error: None,
+    },
+    Case {
+        name: "fixed point",
+        source: "fun main
+  3 int>fx 2 int>fx fx* fx.
+  7 int>fx 2 int>fx fx/ fx.
+  5 int>fx fx>int print
+ret",
+        setup: as_is,
+        output: &["6", "3.5", "5"],
+        error: None,
+    },
+    Case {
+        name: "if and else",
+        source: "fun main
+  1 if 10 print else 20 print end pop
+  0 if 10 print else 20 print end pop
+ret",
+        setup: as_is,
+        output: &["10", "20"],
+        error: None,
+    },
+    Case {
+        name: "while … end",
+        source: "fun main 3 while dup print 1 - end pop ret",
+        setup: as_is,
+        output: &["3", "2", "1"],
+        error: None,
+    },
+    Case {
+        name: "while … do … end",
+        source: "fun main 3 while dup do dup print 1 - end pop ret",
+        setup: as_is,
+        output: &["3", "2", "1"],
+        error: None,
+    },
+    Case {
+        name: "calls",
+        source: "fun sq dup * ret fun main 4 sq print ret",
+        setup: as_is,
+        output: &["16"],
+        error: None,
+    },
+    Case {
+        name: "rand and time",
+        source: "fun main rand dup - print time time swap - print ret",
+        setup: as_is,
+        output: &["0", "1"],
+        error: None,
+    },
+    Case {
+        name: "memory",
+        source: "fun main
+  2 alloc dup 1 + 9 swap !
+  dup 1 + @ print
+  gc-stats print print
+  pop 3 alloc pop gc-stats print print
+ret",
+        setup: as_is,
+        output: &["9", "2", "1", "0", "0"],
+        error: None,
+    },
+    Case {
+        name: "try and recover",
+        source: "fun main
+  try 1 0 / recover print end
+  try 5 recover pop 0 end print
+ret",
+        setup: as_is,
+        output: &["2", "5"],
+        error: None,
+    },
+    Case {
+        name: "ret leaves try",
+        source: "fun f try 7 ret recover end ret
+fun main f print 1 0 / ret",
+        setup: as_is,
+        output: &["7"],
+        error: Some("2:22: division by zero"),
+    },
+    Case {
+        name: "on_error",
+        source: "fun on_error print 0 ret fun main 1 0 / print ret",
+        setup: |config| VmConfig {
+            on_error: ErrorPolicy::Trap,
+            ..config
+        },
+        output: &["2", "0"],
+        error: None,
+    },
+    Case {
+        name: "empty stack",
+        source: "fun f pop pop ret fun main 1 f ret",
+        setup: as_is,
+        output: &[],
+        error: Some("1:11: stack is empty"),
+    },
+    Case {
+        name: "condition on an empty stack",
+        source: "fun f if else end ret fun main f ret",
+        setup: as_is,
+        output: &[],
+        error: Some("1:7: stack is empty"),
+    },
+    Case {
+        name: "division by zero",
+        source: "fun main 1 0 / ret",
+        setup: as_is,
+        output: &[],
+        error: Some("1:14: division by zero"),
+    },
+    Case {
+        name: "overflow",
+        source: "fun main 2147483647 1 + ret",
+        setup: as_is,
+        output: &[],
+        error: Some("1:23: arithmetic overflow"),
+    },
+    Case {
+        name: "wrapping and saturating",
+        source: "fun main
+  2147483647 1 +w print  0 2147483647 - 2 -w print  65536 65536 *w print
+  2147483647 1 +s print  0 2147483647 - 2 -s print  65536 0 65536 - *s print
+ret",
+        setup: as_is,
+        output: &[
+            "-2147483648",
+            "2147483647",
+            "0",
+            "2147483647",
+            "-2147483648",
+            "-2147483648",
+        ],
+        error: None,
+    },
+    Case {
+        name: "floored divmod",
+        source: "fun main 0 7 - 3 divmod print print ret",
+        setup: |config| VmConfig {
+            division: Division::Floor,
+            ..config
+        },
+        output: &["2", "-3"],
+        error: None,
+    },
+    Case {
+        name: "bad address",
+        source: "fun main 5 @ ret",
+        setup: as_is,
+        output: &[],
+        error: Some("1:12: no memory at address 5"),
+    },
+    Case {
+        name: "fixed point overflow",
+        source: "fun main 100000 int>fx ret",
+        setup: as_is,
+        output: &[],
+        error: Some("1:17: arithmetic overflow"),
+    },
+    Case {
+        name: "step limit",
+        source: "fun main 1 while end ret",
+        setup: |config| VmConfig {
+            max_steps: Some(100),
+            ..config
+        },
+        output: &[],
+        error: Some("1:18: step limit of 100 exceeded"),
+    },
+    Case {
+        name: "stack limit",
+        source: "fun main 1 while dup end ret",
+        setup: |config| VmConfig {
+            max_stack: Some(16),
+            ..config
+        },
+        output: &[],
+        error: Some("1:18: stack limit of 16 exceeded"),
+    },
+    Case {
+        name: "memory limit",
+        source: "fun main 1 while dup end ret",
+        setup: |config| VmConfig {
+            max_memory: Some(256),
+            ..config
+        },
+        output: &[],
+        error: Some("1:18: memory limit of 256 bytes exceeded"),
+    },
+    Case {
+        name: "time limit",
+        source: "fun main 1 while end ret",
+        setup: |config| VmConfig {
+            timeout: Some(50),
+            ..config
+        },
+        output: &[],
+        error: Some("1:18: time limit of 50 ms exceeded"),
+    },
+    Case {
+        name: "denied capability",
+        source: "fun main time print ret",
+        setup: |config| VmConfig {
+            allow_time: false,
+            virtual_clock: false,
+            ..config
+        },
+        output: &[],
+        error: Some("1:10: `time` is not allowed here"),
+    },
+    Case {
+        name: "denied syscall",
+        source: "fun main 39 syscall0 0 60 syscall1 0 0 3 syscall2 0 0 0 2 syscall3 ret",
+        setup: as_is,
+        output: &[],
+        error: Some("1:13: `syscall0` is not allowed here"),
+    },
+    Case {
+        name: "environment",
+        source: "fun main env \"RORTH_SELFTEST\" print ret",
+        setup: |config| VmConfig {
+            replay: Some(vec![Event {
+                source: Source::Env,
+                value: 7,
+            }]),
+            ..config
+        },
+        output: &["7"],
+        error: None,
+    },
+    Case {
+        name: "no main",
+        source: "fun f ret",
+        setup: as_is,
+        output: &[],
+        error: Some("function `main` not found"),
+    },
+];
+
+// Runs every case, in order.
+pub fn run() -> Vec<Outcome> {
+    CASES
+        .iter()
+        .map(|case| Outcome {
+            name: case.name,
+            failure: check(case).err(),
+        })
+        .collect()
+}
+
+fn check(case: &Case) -> Result<(), String> {
+    let tokens = tokenize(case.source).map_err(|e| e.to_string())?;
+    let program = parse(tokens).map_err(|e| e.to_string())?;
+    compare(case, &program).map_err(|e| format!("as parsed: {}", e))?;
+    let compiled = bytecode::decode(&bytecode::encode(&program)).map_err(|e| e.to_string())?;
+    compare(case, &compiled).map_err(|e| format!("from bytecode: {}", e))
+}
+
+fn compare(case: &Case, program: &Program) -> Result<(), String> {
+    let config = (case.setup)(VmConfig::default().deterministic());
+    let mut machine = StackMachine::with_config(VecStack::new(), config);
+    machine.set_error_sink(|_| {});
+    let RunResult { output, error, .. } = machine.run_main(program);
+    if output != case.output {
+        return Err(format!(
+            "expected output {:?}, got {:?}",
+            case.output, output
+        ));
+    }
+    let error = error.map(|e| e.to_string());
+    if error.as_deref() != case.error {
+        return Err(match (case.error, error) {
+            (Some(expected), Some(error)) => format!("expected `{}`, got `{}`", expected, error),
+            (Some(expected), None) => format!("expected `{}`, but it ran", expected),
+            (None, error) => format!("failed with `{}`", error.unwrap_or_default()),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod selftest_tests {
+    use super::*;
+    use crate::parser::InstructionType;
+    use core::mem::discriminant;
+
+    #[test]
+    fn every_case_passes() {
+        let failed: Vec<Outcome> = run().into_iter().filter(|o| o.failure.is_some()).collect();
+        assert!(failed.is_empty(), "{:#?}", failed);
+    }
+
+    #[test]
+    fn cases_run_the_same_without_optimizations() {
+        for case in CASES {
+            let Ok(program) = tokenize(case.source).and_then(parse) else {
+                continue;
+            };
+            let config = (case.setup)(VmConfig::default());
+            if let Err(difference) = crate::verify_opt::compare(&program, config) {
+                panic!("{}: {}", case.name, difference);
+            }
+        }
+    }
+
+    #[test]
+    fn every_instruction_is_run() {
+        use InstructionType::*;
+        let all = [
+            Push(0),
+            Pop,
+            Add,
+            Sub,
+            Mul,
+            Div,
+            DivMod,
+            AddWrap,
+            SubWrap,
+            MulWrap,
+            AddSat,
+            SubSat,
+            MulSat,
+            Print,
+            While(0),
+            EndWhile(0),
+            Loop(0),
+            Do(0),
+            EndDo(0),
+            If(0),
+            Else(0),
+            EndIf,
+            Try(0),
+            Recover(0),
+            EndTry,
+            Dup,
+            Swap,
+            Rot,
+            Over,
+            Nip,
+            Rand,
+            Time,
+            Env(0),
+            Call(0),
+            Ret,
+            Hex,
+            Bin,
+            Dec,
+            PrintPadded,
+            Eprint,
+            FxMul,
+            FxDiv,
+            FxPrint,
+            IntToFx,
+            FxToInt,
+            Alloc,
+            Fetch,
+            Store,
+            GcStats,
+            Syscall0,
+            Syscall1,
+            Syscall2,
+            Syscall3,
+        ];
+        let used: Vec<_> = CASES
+            .iter()
+            .flat_map(|case| parse(tokenize(case.source).unwrap()).unwrap().instructions)
+            .map(|i| discriminant(&i.instruction_type))
+            .collect();
+        let missing: Vec<_> = all
+            .iter()
+            .filter(|i| !used.contains(&discriminant(*i)))
+            .collect();
+        assert!(missing.is_empty(), "{:?}", missing);
+    }
+}
@@ -0,0 +1,378 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::checker::check_stack_safety;
+use crate::common::Error;
+use crate::json;
+use crate::parser::parse_recovering;
+use crate::playground::{evaluate, Limits};
+use crate::tokenizer::tokenize_recovering;
+
+// What one client may take of the server, whatever the program limits: the
+// request line and headers together, how many headers, the time to send
+// the whole request, and connections open at once.
+const MAX_HEADER_BYTES: u64 = 8 * 1024;
+const MAX_HEADERS: usize = 64;
+const REQUEST_TIME: Duration = Duration::from_secs(5);
+const MAX_CONNECTIONS: usize = 64;
+
+pub struct Response {
+    pub status: u16,
+    pub body: String,
+}
+
+impl Response {
+    fn json(status: u16, body: String) -> Self {
+        Self { status, body }
+    }
+
+    fn error(status: u16, message: &str) -> Self {
+        Self::json(
+            status,
+            json::object(&[("errors", json::array(vec![json::string(message)]))]),
+        )
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        413 => "Payload Too Large",
+        431 => "Request Header Fields Too Large",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    }
+}
+
+fn check(source: &str) -> Response {
+    let result = tokenize_recovering(source)
+        .map_err(Error::from_vec)
+        .and_then(|tokens| parse_recovering(tokens).map_err(Error::from_vec))
+        .and_then(|program| check_stack_safety(&program.instructions));
+    let errors = result.err().map_or(vec![], Error::into_vec);
+    Response::json(
+        200,
+        json::object(&[
+            ("ok", errors.is_empty().to_string()),
+            (
+                "errors",
+                json::array(errors.iter().map(|e| json::string(&e.to_string()))),
+            ),
+            (
+                "codes",
+                json::array(errors.iter().map(|e| json::string(e.id()))),
+            ),
+        ]),
+    )
+}
+
+pub fn handle(method: &str, path: &str, body: &str, limits: &Limits) -> Response {
+    match (method, path) {
+        ("POST", "/run") => Response::json(200, evaluate(body, limits).to_json()),
+        ("POST", "/check") => check(body),
+        (_, "/run") | (_, "/check") => Response::error(405, "use POST"),
+        _ => Response::error(404, "unknown endpoint"),
+    }
+}
+
+// Reads from the stream until `until`, however slowly the bytes come.
+struct Timed<'a> {
+    stream: &'a TcpStream,
+    until: Instant,
+}
+
+impl Read for Timed<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let left = self.until.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(std::io::ErrorKind::TimedOut.into());
+        }
+        self.stream.set_read_timeout(Some(left))?;
+        (&mut &*self.stream).read(buf)
+    }
+}
+
+fn timed_out(e: &std::io::Error) -> bool {
+    matches!(
+        e.kind(),
+        std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock
+    )
+}
+
+fn read_request(stream: &TcpStream, limits: &Limits, time: Duration) -> Result<Response, String> {
+    let mut reader = BufReader::new(Timed {
+        stream,
+        until: Instant::now() + time,
+    });
+    let mut header_bytes = 0;
+    // A line, or None when the headers have grown past `MAX_HEADER_BYTES`.
+    let mut line = |reader: &mut BufReader<Timed>| -> std::io::Result<Option<String>> {
+        let mut line = String::new();
+        let room = MAX_HEADER_BYTES - header_bytes;
+        let read = reader.by_ref().take(room).read_line(&mut line)?;
+        header_bytes += read as u64;
+        Ok((line.ends_with('\n') || read == 0).then_some(line))
+    };
+    let answer = |e: std::io::Error| match timed_out(&e) {
+        true => Ok(Response::error(408, "the request took too long")),
+        false => Err(e.to_string()),
+    };
+    let too_large = || Ok(Response::error(431, "request headers are too large"));
+
+    let request_line = match line(&mut reader) {
+        Ok(Some(line)) => line,
+        Ok(None) => return too_large(),
+        Err(e) => return answer(e),
+    };
+    let mut parts = request_line.split_whitespace();
+    let (method, path) = match (parts.next(), parts.next()) {
+        (Some(method), Some(path)) => (method.to_string(), path.to_string()),
+        _ => return Ok(Response::error(400, "malformed request line")),
+    };
+
+    let mut content_length = 0;
+    let mut headers = 0;
+    loop {
+        let header = match line(&mut reader) {
+            Ok(Some(header)) => header,
+            Ok(None) => return too_large(),
+            Err(e) => return answer(e),
+        };
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        headers += 1;
+        if headers > MAX_HEADERS {
+            return too_large();
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = match value.trim().parse() {
+                    Ok(n) => n,
+                    Err(_) => return Ok(Response::error(400, "bad Content-Length")),
+                };
+            }
+        }
+    }
+    if content_length > limits.max_source_len {
+        return Ok(Response::error(413, "program is too large"));
+    }
+
+    let mut body = vec![0; content_length];
+    if let Err(e) = reader.read_exact(&mut body) {
+        return answer(e);
+    }
+    match String::from_utf8(body) {
+        Ok(body) => Ok(handle(&method, &path, &body, limits)),
+        Err(_) => Ok(Response::error(400, "body is not valid UTF-8")),
+    }
+}
+
+fn respond(mut stream: &TcpStream, response: &Response) {
+    let _ = write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status,
+        reason(response.status),
+        response.body.len(),
+        response.body
+    );
+}
+
+fn serve_connection(stream: TcpStream, limits: &Limits, time: Duration) {
+    if let Ok(response) = read_request(&stream, limits, time) {
+        respond(&stream, &response);
+    }
+    // Closing with unread input resets the connection, which can lose the
+    // response before the client reads it, so what's left is read first,
+    // up to a point.
+    let _ = stream.shutdown(Shutdown::Write);
+    let rest = Timed {
+        stream: &stream,
+        until: Instant::now() + Duration::from_secs(1),
+    };
+    let _ = std::io::copy(&mut rest.take(MAX_HEADER_BYTES * 8), &mut std::io::sink());
+}
+
+// One of the `MAX_CONNECTIONS`, given back when dropped.
+struct Slot<'a>(&'a AtomicUsize);
+
+impl<'a> Slot<'a> {
+    fn take(open: &'a AtomicUsize, max: usize) -> Option<Self> {
+        open.fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+            (n < max).then_some(n + 1)
+        })
+        .ok()
+        .map(|_| Slot(open))
+    }
+}
+
+impl Drop for Slot<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+pub fn serve(addr: &str, limits: Limits) -> Result<(), Error> {
+    let listener = TcpListener::bind(addr).map_err(|e| Error::Io {
+        path: addr.to_string(),
+        comment: e.to_string(),
+    })?;
+    eprintln!("listening on http://{}", addr);
+    let open = AtomicUsize::new(0);
+    std::thread::scope(|scope| {
+        for stream in listener.incoming().flatten() {
+            let Some(slot) = Slot::take(&open, MAX_CONNECTIONS) else {
+                respond(&stream, &Response::error(503, "too many connections"));
+                continue;
+            };
+            let limits = &limits;
+            scope.spawn(move || {
+                let _slot = slot;
+                serve_connection(stream, limits, REQUEST_TIME);
+            });
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod server_tests {
+    use super::*;
+
+    #[test]
+    fn run_endpoint_executes_program() {
+        let response = handle(
+            "POST",
+            "/run",
+            "fun main 2 2 * print ret",
+            &Limits::default(),
+        );
+        assert_eq!(response.status, 200);
+        assert!(response.body.contains(r#""output":[4]"#));
+        assert!(response.body.contains(r#""errors":[]"#));
+    }
+
+    #[test]
+    fn run_endpoint_applies_limits() {
+        let limits = Limits {
+            max_steps: 5,
+            ..Limits::default()
+        };
+        let response = handle("POST", "/run", "fun main 1 while end ret", &limits);
+        assert!(response.body.contains("step limit of 5 exceeded"));
+    }
+
+    #[test]
+    fn check_endpoint_reports_errors() {
+        let response = handle("POST", "/check", "2 +", &Limits::default());
+        assert_eq!(response.status, 200);
+        assert!(response.body.starts_with(r#"{"ok":false,"errors":["#));
+        assert!(response.body.ends_with(r#""codes":["E0003"]}"#));
+
+        let response = handle("POST", "/check", "2 3 + print", &Limits::default());
+        assert_eq!(response.body, r#"{"ok":true,"errors":[],"codes":[]}"#);
+    }
+
+    #[test]
+    fn unknown_routes() {
+        assert_eq!(handle("GET", "/run", "", &Limits::default()).status, 405);
+        assert_eq!(handle("POST", "/nope", "", &Limits::default()).status, 404);
+    }
+
+    #[test]
+    fn serves_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handle = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            serve_connection(stream, &Limits::default(), REQUEST_TIME);
+        });
+        let mut client = TcpStream::connect(addr).unwrap();
+        let body = "fun main 7 print ret";
+        write!(
+            client,
+            "POST /run HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        )
+        .unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        drop(client);
+        handle.join().unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains(r#""output":[7]"#));
+    }
+
+    // Sends `request` in pieces `pause` apart and returns the response.
+    fn exchange(request: &[u8], pieces: usize, pause: Duration, time: Duration) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            serve_connection(stream, &Limits::default(), time);
+        });
+        let mut client = TcpStream::connect(addr).unwrap();
+        for piece in request.chunks(request.len().div_ceil(pieces)) {
+            // The server may have stopped listening already.
+            if client.write_all(piece).is_err() {
+                break;
+            }
+            std::thread::sleep(pause);
+        }
+        let mut response = String::new();
+        let _ = client.read_to_string(&mut response);
+        drop(client);
+        server.join().unwrap();
+        response
+    }
+
+    #[test]
+    fn oversized_headers_are_refused() {
+        let long = format!(
+            "POST /run HTTP/1.1\r\nX-Long: {}\r\n\r\n",
+            "a".repeat(20_000)
+        );
+        let response = exchange(long.as_bytes(), 1, Duration::ZERO, REQUEST_TIME);
+        assert!(response.starts_with("HTTP/1.1 431 "), "{}", response);
+
+        let many = format!("POST /run HTTP/1.1\r\n{}\r\n", "X: y\r\n".repeat(100));
+        let response = exchange(many.as_bytes(), 1, Duration::ZERO, REQUEST_TIME);
+        assert!(response.starts_with("HTTP/1.1 431 "), "{}", response);
+    }
+
+    #[test]
+    fn slow_clients_run_out_of_time() {
+        let request = b"POST /run HTTP/1.1\r\nContent-Length: 20\r\n\r\nfun main 7 print ret";
+        let started = Instant::now();
+        // Every read is quick, but the whole would take two seconds.
+        let response = exchange(
+            request,
+            40,
+            Duration::from_millis(50),
+            Duration::from_millis(300),
+        );
+        assert!(response.starts_with("HTTP/1.1 408 "), "{}", response);
+        assert!(started.elapsed() < Duration::from_secs(3));
+    }
+
+    #[test]
+    fn connections_are_counted() {
+        let open = AtomicUsize::new(0);
+        let first = Slot::take(&open, 2).unwrap();
+        let second = Slot::take(&open, 2).unwrap();
+        assert!(Slot::take(&open, 2).is_none());
+        drop(first);
+        assert!(Slot::take(&open, 2).is_some());
+        drop(second);
+        assert_eq!(open.load(Ordering::Acquire), 0);
+    }
+}
@@ -0,0 +1,350 @@
+// Where each instruction of a compiled program came from. `rorth build`
+// writes one next to `prog.rorthc` as `prog.rorthc.map`:
+//
+//   {"version":1,"checksum":123,"sources":["main.sm","lib/util.sm"],
+//    "instructions":[[0,1,10],[1,4,3],...]}
+//
+// `instructions` holds the source, line and column of each instruction in
+// order. Sources are relative to the map's folder when they are inside it.
+// `checksum` is an FNV-1a hash of the `.rorthc` it belongs to, so a map left
+// over from an older build is ignored instead of pointing at the wrong line.
+use std::path::{Path, PathBuf};
+
+use crate::common::Error;
+use crate::json::{self, Value};
+use crate::stack_machine::Program;
+use crate::tokenizer::Token;
+
+pub const VERSION: i64 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Origin {
+    pub source: usize,
+    pub line: usize,
+    pub pos: usize,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct SourceMap {
+    pub sources: Vec<PathBuf>,
+    pub instructions: Vec<Origin>,
+}
+
+// `prog.rorthc.map` for `prog.rorthc`.
+pub fn path_for(program: &Path) -> PathBuf {
+    let mut path = program.as_os_str().to_owned();
+    path.push(".map");
+    PathBuf::from(path)
+}
+
+fn checksum(bytes: &[u8]) -> i64 {
+    bytes.iter().fold(0x811c9dc5u32, |hash, byte| {
+        (hash ^ *byte as u32).wrapping_mul(0x01000193)
+    }) as i64
+}
+
+fn map_error(path: &Path, comment: &str) -> Error {
+    Error::Io {
+        path: path.display().to_string(),
+        comment: comment.to_string(),
+    }
+}
+
+// Which of `origins` the token at `index` belongs to.
+fn origin_of(origins: &[(usize, PathBuf)], index: usize) -> Option<usize> {
+    origins
+        .partition_point(|(start, _)| *start <= index)
+        .checked_sub(1)
+}
+
+// The file the tokens at `line`:`pos` came from, if there are any and they
+// all agree. For errors found before there is a program to map.
+pub fn token_source<'a>(
+    tokens: &[Token],
+    origins: &'a [(usize, PathBuf)],
+    line: usize,
+    pos: usize,
+) -> Option<&'a Path> {
+    let mut found = tokens
+        .iter()
+        .enumerate()
+        .filter(|(_, token)| (token.line, token.pos) == (line, pos))
+        .map(|(index, _)| origin_of(origins, index).map(|origin| &origins[origin].1));
+    let first = found.next()??;
+    if found.any(|path| path != Some(first)) {
+        return None;
+    }
+    Some(first)
+}
+
+impl SourceMap {
+    // `origins` says which file each run of `tokens` came from, as
+    // `Includer::origins` does. Instructions are made in the order of the
+    // tokens they come from, so each one is matched to the next token at
+    // its line and column.
+    pub fn new(program: &Program, tokens: &[Token], origins: &[(usize, PathBuf)]) -> Self {
+        let mut sources: Vec<PathBuf> = vec![];
+        let source_of: Vec<usize> = origins
+            .iter()
+            .map(
+                |(_, path)| match sources.iter().position(|source| source == path) {
+                    Some(index) => index,
+                    None => {
+                        sources.push(path.clone());
+                        sources.len() - 1
+                    }
+                },
+            )
+            .collect();
+        let mut next = 0;
+        let instructions = program
+            .instructions
+            .iter()
+            .map(|instruction| {
+                while next < tokens.len()
+                    && (tokens[next].line, tokens[next].pos) != (instruction.line, instruction.pos)
+                {
+                    next += 1;
+                }
+                let origin = origin_of(origins, next);
+                next += 1;
+                Origin {
+                    source: origin.map_or(0, |origin| source_of[origin]),
+                    line: instruction.line,
+                    pos: instruction.pos,
+                }
+            })
+            .collect();
+        Self {
+            sources,
+            instructions,
+        }
+    }
+
+    // The file an error at `line`:`pos` came from, if every instruction
+    // there agrees on it.
+    pub fn source_at(&self, line: usize, pos: usize) -> Option<&Path> {
+        let mut found = self
+            .instructions
+            .iter()
+            .filter(|origin| (origin.line, origin.pos) == (line, pos))
+            .map(|origin| origin.source);
+        let first = found.next()?;
+        if found.any(|source| source != first) {
+            return None;
+        }
+        self.sources.get(first).map(PathBuf::as_path)
+    }
+
+    pub fn to_json(&self, program: &[u8]) -> String {
+        json::object(&[
+            ("version", VERSION.to_string()),
+            ("checksum", checksum(program).to_string()),
+            (
+                "sources",
+                json::array(
+                    self.sources
+                        .iter()
+                        .map(|source| json::string(&source.to_string_lossy())),
+                ),
+            ),
+            (
+                "instructions",
+                json::array(
+                    self.instructions.iter().map(|origin| {
+                        format!("[{},{},{}]", origin.source, origin.line, origin.pos)
+                    }),
+                ),
+            ),
+        ])
+    }
+
+    // None unless `text` is a map of this version for `program`.
+    pub fn from_json(text: &str, program: &[u8]) -> Option<Self> {
+        let value = json::parse(text)?;
+        if value.get("version")?.as_number()? != VERSION
+            || value.get("checksum")?.as_number()? != checksum(program)
+        {
+            return None;
+        }
+        let sources = value
+            .get("sources")?
+            .as_array()?
+            .iter()
+            .map(|source| source.as_str().map(PathBuf::from))
+            .collect::<Option<Vec<_>>>()?;
+        let number = |value: &Value| usize::try_from(value.as_number()?).ok();
+        let instructions = value
+            .get("instructions")?
+            .as_array()?
+            .iter()
+            .map(|origin| match origin.as_array()? {
+                [source, line, pos] => Some(Origin {
+                    source: number(source).filter(|s| *s < sources.len())?,
+                    line: number(line)?,
+                    pos: number(pos)?,
+                }),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(Self {
+            sources,
+            instructions,
+        })
+    }
+
+    // Next to `program_path`, which holds `program`.
+    pub fn write(&self, program_path: &Path, program: &[u8]) -> Result<(), Error> {
+        let path = path_for(program_path);
+        let parent = program_path
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        let dir = std::fs::canonicalize(parent).map_err(|e| map_error(&path, &e.to_string()))?;
+        let sources = self
+            .sources
+            .iter()
+            .map(|source| {
+                let absolute = std::fs::canonicalize(source).unwrap_or_else(|_| source.clone());
+                absolute
+                    .strip_prefix(&dir)
+                    .map_or(absolute.clone(), Path::to_path_buf)
+            })
+            .collect();
+        let relative = Self {
+            sources,
+            instructions: self.instructions.clone(),
+        };
+        std::fs::write(&path, relative.to_json(program))
+            .map_err(|e| map_error(&path, &e.to_string()))
+    }
+
+    // The map next to `program_path`, with its sources found from there.
+    pub fn read(program_path: &Path, program: &[u8]) -> Result<Self, Error> {
+        let path = path_for(program_path);
+        let text = std::fs::read_to_string(&path).map_err(|e| map_error(&path, &e.to_string()))?;
+        let mut map = Self::from_json(&text, program)
+            .ok_or_else(|| map_error(&path, "not a source map for this program, rebuild it"))?;
+        let dir = program_path.parent().unwrap_or(Path::new(""));
+        for source in &mut map.sources {
+            *source = dir.join(&*source);
+        }
+        Ok(map)
+    }
+}
+
+#[cfg(test)]
+mod source_map_tests {
+    use super::*;
+    use crate::bytecode;
+    use crate::host::SourceLoader;
+    use crate::include::Includer;
+    use crate::parser::parse;
+
+    struct MapLoader(Vec<(&'static str, &'static str)>);
+
+    impl SourceLoader for MapLoader {
+        fn load(&self, path: &str) -> Result<String, Error> {
+            self.0
+                .iter()
+                .find(|(name, _)| *name == path)
+                .map(|(_, source)| source.to_string())
+                .ok_or(Error::Io {
+                    path: path.to_string(),
+                    comment: "not found".to_string(),
+                })
+        }
+    }
+
+    fn build() -> (Program, SourceMap) {
+        let loader = MapLoader(vec![
+            ("app/main.sm", "include \"sq.sm\"\nfun main 3 sq print ret"),
+            ("app/sq.sm", "fun sq dup * ret"),
+        ]);
+        let mut includer = Includer::new(&loader, vec![]);
+        let tokens = includer.load(Path::new("app/main.sm")).unwrap();
+        let origins = includer.origins().to_vec();
+        let program = parse(tokens.clone()).unwrap();
+        let map = SourceMap::new(&program, &tokens, &origins);
+        (program, map)
+    }
+
+    #[test]
+    fn maps_instructions_to_their_files() {
+        let (_, map) = build();
+        assert_eq!(
+            map.sources,
+            vec![PathBuf::from("app/main.sm"), PathBuf::from("app/sq.sm")]
+        );
+        // sq: dup * ret, then main: 3 sq print ret
+        let origins: Vec<(usize, usize, usize)> = map
+            .instructions
+            .iter()
+            .map(|o| (o.source, o.line, o.pos))
+            .collect();
+        assert_eq!(
+            origins,
+            vec![
+                (1, 1, 8),
+                (1, 1, 12),
+                (1, 1, 14),
+                (0, 2, 10),
+                (0, 2, 12),
+                (0, 2, 15),
+                (0, 2, 21),
+            ]
+        );
+        assert_eq!(map.source_at(1, 12), Some(Path::new("app/sq.sm")));
+        assert_eq!(map.source_at(2, 15), Some(Path::new("app/main.sm")));
+        assert_eq!(map.source_at(9, 9), None);
+    }
+
+    #[test]
+    fn tokens_know_their_files() {
+        let loader = MapLoader(vec![
+            ("app/main.sm", "include \"sq.sm\"\nfun main 3 sq print ret"),
+            ("app/sq.sm", "fun sq dup * ret\nfun cubed ret"),
+        ]);
+        let mut includer = Includer::new(&loader, vec![]);
+        let tokens = includer.load(Path::new("app/main.sm")).unwrap();
+        let origins = includer.origins();
+        let source = |line, pos| token_source(&tokens, origins, line, pos);
+        assert_eq!(source(1, 12), Some(Path::new("app/sq.sm")));
+        assert_eq!(source(2, 10), Some(Path::new("app/main.sm")));
+        // `fun` starts line 2 of both files.
+        assert_eq!(source(2, 1), None);
+        assert_eq!(source(7, 7), None);
+    }
+
+    #[test]
+    fn positions_in_two_files_are_ambiguous() {
+        let map = SourceMap {
+            sources: vec![PathBuf::from("a.sm"), PathBuf::from("b.sm")],
+            instructions: vec![
+                Origin {
+                    source: 0,
+                    line: 1,
+                    pos: 5,
+                },
+                Origin {
+                    source: 1,
+                    line: 1,
+                    pos: 5,
+                },
+            ],
+        };
+        assert_eq!(map.source_at(1, 5), None);
+    }
+
+    #[test]
+    fn json_round_trip_checks_the_program() {
+        let (program, map) = build();
+        let bytes = bytecode::encode(&program);
+        let text = map.to_json(&bytes);
+        assert_eq!(SourceMap::from_json(&text, &bytes), Some(map));
+        let mut other = bytes.clone();
+        other.push(0);
+        assert_eq!(SourceMap::from_json(&text, &other), None);
+        assert_eq!(SourceMap::from_json("{}", &bytes), None);
+    }
+}
@@ -1,10 +1,18 @@
-pub trait Stack<T: std::fmt::Debug> {
+use alloc::vec::Vec;
+
+pub trait Stack<T: core::fmt::Debug> {
     fn push(&mut self, item: T);
     fn pop(&mut self) -> Option<T>;
     fn peek(&self) -> Option<&T>;
     fn is_empty(&self) -> bool;
     fn size(&self) -> usize;
-    fn print(&self);
+    // Bottom of the stack first.
+    fn as_slice(&self) -> &[T];
+    // Writes the stack, bottom first, to any sink: a `String`, or stdout
+    // through an adapter where there is one.
+    fn print(&self, out: &mut dyn core::fmt::Write) -> core::fmt::Result {
+        writeln!(out, "{:?}", self.as_slice())
+    }
 }
 
 pub struct VecStack<T> {
@@ -17,7 +25,13 @@ impl<T> VecStack<T> {
     }
 }
 
-impl<T: std::fmt::Debug> Stack<T> for VecStack<T> {
+impl<T> Default for VecStack<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: core::fmt::Debug> Stack<T> for VecStack<T> {
     fn push(&mut self, item: T) {
         self.vec.push(item);
     }
@@ -38,8 +52,151 @@ impl<T: std::fmt::Debug> Stack<T> for VecStack<T> {
         self.vec.len()
     }
 
-    fn print(&self) {
-        println!("{:?}", self.vec);
+    fn as_slice(&self) -> &[T] {
+        &self.vec
+    }
+}
+
+// Values `CheckedStack` writes where nothing real should be read: `POISON`
+// into every slot a `pop` frees, `CANARY` under the bottom value.
+pub trait Poison {
+    const POISON: Self;
+    const CANARY: Self;
+}
+
+impl Poison for i32 {
+    const POISON: i32 = 0x5ead_beef;
+    const CANARY: i32 = 0x0ca7_a11f;
+}
+
+// Runs an embedder's `Stack` alongside a plain vector and checks its answers
+// against the vector, panicking at the first that differs: a `pop` that hands
+// back the wrong value or none, a `size` that is off by one, a `peek` that
+// still shows a popped value. Every `pop` pushes `POISON` into the slot it
+// freed and pops it again, so a stack that keeps showing a freed slot shows
+// the poison, and a `CANARY` under the bottom value catches one that loses
+// or overwrites it. Each check looks at the size and both ends only, so it
+// costs the same however deep the stack is. `Builder` wraps every stack in
+// one in debug builds; elsewhere:
+//
+//   let mut machine = StackMachine::new(CheckedStack::new(MyStack::default()));
+pub struct CheckedStack<S, T> {
+    inner: S,
+    model: Vec<T>,
+}
+
+impl<S: Stack<T>, T: core::fmt::Debug + Clone + PartialEq + Poison> CheckedStack<S, T> {
+    // `inner` may already hold values; they are taken as they are, with the
+    // canary put under them.
+    pub fn new(mut inner: S) -> Self {
+        let mut model: Vec<T> = core::iter::from_fn(|| inner.pop()).collect();
+        model.reverse();
+        inner.push(T::CANARY);
+        for item in &model {
+            inner.push(item.clone());
+        }
+        let checked = Self { inner, model };
+        checked.check("new");
+        checked
+    }
+
+    // The stack without the canary.
+    pub fn into_inner(mut self) -> S {
+        for _ in 0..self.model.len() {
+            self.inner.pop();
+        }
+        self.inner.pop();
+        for item in self.model {
+            self.inner.push(item);
+        }
+        self.inner
+    }
+
+    fn check(&self, after: &str) {
+        let expected = self.model.len();
+        let size = self.inner.size().wrapping_sub(1);
+        assert!(
+            size == expected,
+            "Stack::size is {} after {}, but {} values are on the stack",
+            size,
+            after,
+            expected
+        );
+        assert!(
+            !self.inner.is_empty(),
+            "Stack::is_empty is true after {} with {} values on the stack",
+            after,
+            expected
+        );
+        let slice = self.inner.as_slice();
+        assert!(
+            slice.len() == expected + 1 && slice[0] == T::CANARY,
+            "Stack::as_slice lost the value under the bottom one after {}: {:?} there",
+            after,
+            slice.first()
+        );
+        let canary = T::CANARY;
+        let top = self.model.last().unwrap_or(&canary);
+        assert!(
+            self.inner.peek() != Some(&T::POISON) || *top == T::POISON,
+            "Stack::peek shows a slot that was popped after {}",
+            after
+        );
+        assert!(
+            self.inner.peek() == Some(top) && slice.last() == Some(top),
+            "Stack::peek is {:?} after {}, but the top is {:?}",
+            self.inner.peek(),
+            after,
+            self.model.last()
+        );
+    }
+}
+
+impl<S: Stack<T>, T: core::fmt::Debug + Clone + PartialEq + Poison> Stack<T>
+    for CheckedStack<S, T>
+{
+    fn push(&mut self, item: T) {
+        self.model.push(item.clone());
+        self.inner.push(item);
+        self.check("push");
+    }
+
+    // Never reaches the canary: popping an empty stack is answered here.
+    fn pop(&mut self) -> Option<T> {
+        let expected = self.model.pop()?;
+        let item = self.inner.pop();
+        assert!(
+            item.as_ref() == Some(&expected),
+            "Stack::pop returned {:?}, but the top was {:?}",
+            item,
+            expected
+        );
+        self.inner.push(T::POISON);
+        let poison = self.inner.pop();
+        assert!(
+            poison.as_ref() == Some(&T::POISON),
+            "Stack::pop returned {:?} right after a push of {:?}",
+            poison,
+            T::POISON
+        );
+        self.check("pop");
+        item
+    }
+
+    fn peek(&self) -> Option<&T> {
+        self.model.last().and(self.inner.peek())
+    }
+
+    fn is_empty(&self) -> bool {
+        self.model.is_empty()
+    }
+
+    fn size(&self) -> usize {
+        self.model.len()
+    }
+
+    fn as_slice(&self) -> &[T] {
+        self.inner.as_slice().get(1..).unwrap_or_default()
     }
 }
 
@@ -66,4 +223,128 @@ mod vec_stack_tests {
         let mut stack = VecStack::<i32>::new();
         assert_eq!(stack.pop(), None);
     }
+
+    #[test]
+    fn prints_to_a_sink() {
+        let mut stack = VecStack::new();
+        stack.push(1);
+        stack.push(2);
+        let mut out = String::new();
+        stack.print(&mut out).unwrap();
+        assert_eq!(out, "[1, 2]\n");
+    }
+}
+
+#[cfg(test)]
+mod checked_stack_tests {
+    use super::*;
+    use crate::parser::parse;
+    use crate::stack_machine::StackMachine;
+    use crate::tokenizer::tokenize;
+
+    // Forgets to take the value off when the stack has more than one.
+    #[derive(Default)]
+    struct Sticky(Vec<i32>);
+
+    impl Stack<i32> for Sticky {
+        fn push(&mut self, item: i32) {
+            self.0.push(item);
+        }
+
+        fn pop(&mut self) -> Option<i32> {
+            match self.0.len() {
+                0 | 1 => self.0.pop(),
+                _ => self.0.last().copied(),
+            }
+        }
+
+        fn peek(&self) -> Option<&i32> {
+            self.0.last()
+        }
+
+        fn is_empty(&self) -> bool {
+            self.0.is_empty()
+        }
+
+        fn size(&self) -> usize {
+            self.0.len()
+        }
+
+        fn as_slice(&self) -> &[i32] {
+            &self.0
+        }
+    }
+
+    #[test]
+    fn a_working_stack_runs_as_usual() {
+        let program = parse(tokenize("fun main 1 2 + dup * print ret").unwrap()).unwrap();
+        let mut machine = StackMachine::new(CheckedStack::new(VecStack::new()));
+        assert_eq!(machine.execute(program), Ok(vec![9]));
+    }
+
+    #[test]
+    #[should_panic(expected = "Stack::size is 3 after pop, but 1 values are on the stack")]
+    fn a_pop_that_keeps_the_value_is_caught() {
+        let program = parse(tokenize("fun main 1 2 swap print print ret").unwrap()).unwrap();
+        let mut machine = StackMachine::new(CheckedStack::new(Sticky::default()));
+        let _ = machine.execute(program);
+    }
+
+    // Pops by moving its length back, but `peek` forgets to look at it.
+    #[derive(Default)]
+    struct OffByOne {
+        slots: Vec<i32>,
+        len: usize,
+    }
+
+    impl Stack<i32> for OffByOne {
+        fn push(&mut self, item: i32) {
+            self.slots.truncate(self.len);
+            self.slots.push(item);
+            self.len += 1;
+        }
+
+        fn pop(&mut self) -> Option<i32> {
+            self.len = self.len.checked_sub(1)?;
+            Some(self.slots[self.len])
+        }
+
+        fn peek(&self) -> Option<&i32> {
+            self.slots.last()
+        }
+
+        fn is_empty(&self) -> bool {
+            self.len == 0
+        }
+
+        fn size(&self) -> usize {
+            self.len
+        }
+
+        fn as_slice(&self) -> &[i32] {
+            &self.slots[..self.len]
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Stack::peek shows a slot that was popped after pop")]
+    fn reading_a_freed_slot_finds_the_poison() {
+        let program = parse(tokenize("fun main 1 2 + print ret").unwrap()).unwrap();
+        let mut machine = StackMachine::new(CheckedStack::new(OffByOne::default()));
+        let _ = machine.execute(program);
+    }
+
+    #[test]
+    fn values_already_there_stay_above_the_canary() {
+        let mut inner = VecStack::new();
+        inner.push(7);
+        let mut checked = CheckedStack::new(inner);
+        assert_eq!(checked.as_slice(), [7]);
+        checked.push(8);
+        assert_eq!(checked.pop(), Some(8));
+        assert_eq!(checked.pop(), Some(7));
+        assert_eq!(checked.pop(), None);
+        checked.push(9);
+        assert_eq!(checked.into_inner().as_slice(), [9]);
+    }
 }
@@ -1,30 +1,806 @@
-use std::collections::HashMap;
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
 
 use crate::{
+    builder::{Builder, Checked},
+    checker::{simple_effect, validate_program, Verifier},
+    clock::Clock,
     common::Error,
+    fixed,
+    heap::{GcStats, Heap},
+    interrupt,
     parser::{Instruction, InstructionType},
-    stack::Stack,
+    plugin,
+    replay::{Event, Source},
+    rng::{Rng, DETERMINISTIC_SEED},
+    stack::{Stack, VecStack},
+    stats::Stats,
+    syscall,
+    words::{self, Fault},
 };
 
+#[derive(Default)]
 pub struct Program {
     pub instructions: Vec<Instruction>,
-    pub functions: HashMap<String, usize>,
+    pub functions: BTreeMap<String, usize>,
+    // Module functions declared without `pub`.
+    pub private: BTreeSet<String>,
+    // Text of the `##` comments right before each function.
+    pub docs: BTreeMap<String, String>,
+    // The name the function starting at an index goes by in traces, stats
+    // and listings: the first of its names. See `index_symbols`.
+    pub symbols: BTreeMap<usize, String>,
+    // The variables `env` reads, by the number in its instruction.
+    pub env: Vec<String>,
+    // The text of each `asm` block, by the number in its instruction.
+    pub asm: Vec<String>,
 }
 
-pub struct StackMachine<T: Stack<i32>>(pub T);
+impl Program {
+    // Rebuilds `symbols` from `functions`.
+    pub fn index_symbols(&mut self) {
+        self.symbols.clear();
+        for (name, start) in &self.functions {
+            self.symbols.entry(*start).or_insert_with(|| name.clone());
+        }
+    }
+
+    // Each function by name with the instructions it spans, in the order
+    // they start in. One runs until the next one starts.
+    pub fn spans(&self) -> Vec<(&str, core::ops::Range<usize>)> {
+        let starts: Vec<(&usize, &String)> = self.symbols.iter().collect();
+        starts
+            .iter()
+            .enumerate()
+            .map(|(i, (start, name))| {
+                let end = starts
+                    .get(i + 1)
+                    .map_or(self.instructions.len(), |(next, _)| **next);
+                (name.as_str(), **start..end)
+            })
+            .collect()
+    }
+
+    pub fn symbol(&self, start: usize) -> Option<&str> {
+        self.symbols.get(&start).map(String::as_str)
+    }
+
+    // An instruction as listings show it: `call fib` rather than `call 17`,
+    // and `&fib` rather than `address 17`.
+    pub fn describe(&self, instruction: &InstructionType) -> String {
+        match instruction {
+            InstructionType::Call(start) => match self.symbol(*start) {
+                Some(name) => format!("call {}", name),
+                None => instruction.to_string(),
+            },
+            InstructionType::Address(start) => match self.symbol(*start) {
+                Some(name) => format!("&{}", name),
+                None => instruction.to_string(),
+            },
+            other => other.to_string(),
+        }
+    }
+}
+
+// Limits and capabilities of a machine. `None` means unlimited. Anything
+// that consumes resources or reaches outside the VM should be gated here so
+// untrusted programs can be run with `VmConfig::sandboxed()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VmConfig {
+    pub max_steps: Option<usize>,
+    pub max_stack: Option<usize>,
+    // Bytes held by the data stack, the call frames and the heap together.
+    pub max_memory: Option<usize>,
+    // Milliseconds each `call`, `start` or `resume` may run for, by the
+    // machine's clock: wall time, or steps with `virtual_clock`.
+    pub timeout: Option<usize>,
+    // Packs that write files when the program ends, such as `turtle`.
+    pub allow_fs: bool,
+    // `env`, unless the value comes from `replay`.
+    pub allow_env: bool,
+    pub allow_time: bool,
+    // `syscall0` … `syscall3`, which can do anything the process can.
+    pub allow_syscalls: bool,
+    // `None` seeds `rand` from the OS.
+    pub seed: Option<u64>,
+    pub virtual_clock: bool,
+    // Steps between calls to the heartbeat callback, if one is set.
+    pub heartbeat: Option<usize>,
+    // Stop with `Error::Interrupted` once `interrupt::INTERRUPTED` is set.
+    pub interruptible: bool,
+    // Count what runs into `StackMachine::stats`.
+    pub stats: bool,
+    // Check the stack against what the checker predicts after every step,
+    // see `checker::Verifier`.
+    pub verify: bool,
+    // Run `n +` and `n *` as one step, see `Immediate`. Off for `-O0`, and
+    // for `verify_opt` to check the two ways agree.
+    pub optimize: bool,
+    // Keep what `rand` and `time` return, for `StackMachine::take_recording`.
+    pub record: bool,
+    // What `rand` and `time` return instead, in order, from a recording.
+    pub replay: Option<Vec<Event>>,
+    pub on_error: ErrorPolicy,
+    pub division: Division,
+    // The function `run_main` starts at, when not `main`.
+    pub entry: Option<String>,
+}
+
+// What happens when an instruction fails with an error that has a code.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    // Stop the run with the error.
+    #[default]
+    Abort,
+    // Call the program's `on_error` word with the code pushed, then carry on
+    // after the failed instruction. Without an `on_error`, or when it fails
+    // itself, the run stops as with `Abort`. Errors inside `try` go to its
+    // `recover` under either policy.
+    Trap,
+}
+
+// How `/` and `divmod` round when the result isn't whole. Forths differ.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Division {
+    // Toward zero, so -7 3 divmod is -2 -1.
+    #[default]
+    Trunc,
+    // Toward negative infinity, so -7 3 divmod is -3 2. The remainder has
+    // the sign of the divisor.
+    Floor,
+}
+
+pub const ERROR_HANDLER: &str = "on_error";
+
+// `n +` and `n *` as the machine runs them when nothing would notice the
+// step in between: the top value changes in place, without pushing `n`.
+// Made at dispatch from the two instructions, so compiled programs,
+// listings and traces never see them.
+//
+// Constant pushes are not hoisted out of loops. To keep the value around
+// the loop would read it back with `dup` or `over`, which costs the step the
+// push did, so the loop goes round in as many steps as before, and the
+// `swap` and `drop` around it would change the step count that `verify_opt`
+// holds the two ways to.
+#[derive(Clone, Copy)]
+enum Immediate {
+    Add(i32),
+    Mul(i32),
+}
+
+impl Immediate {
+    fn decode(program: &Program, idx: usize) -> Option<Self> {
+        let InstructionType::Push(n) = program.instructions[idx].instruction_type else {
+            return None;
+        };
+        match program.instructions.get(idx + 1)?.instruction_type {
+            InstructionType::Add => Some(Immediate::Add(n)),
+            InstructionType::Mul => Some(Immediate::Mul(n)),
+            _ => None,
+        }
+    }
+
+    // None when it overflows, for the two instructions to say so as usual.
+    fn apply(self, top: i32) -> Option<i32> {
+        match self {
+            Immediate::Add(n) => top.checked_add(n),
+            Immediate::Mul(n) => top.checked_mul(n),
+        }
+    }
+}
+
+// Steps between looks at the wall clock for `VmConfig::timeout`, which costs
+// more than a step does.
+const TIMEOUT_CHECK_EVERY: usize = 1024;
+
+impl Default for VmConfig {
+    fn default() -> Self {
+        Self {
+            max_steps: None,
+            max_stack: None,
+            max_memory: None,
+            timeout: None,
+            allow_fs: true,
+            allow_env: true,
+            allow_time: true,
+            allow_syscalls: false,
+            seed: None,
+            virtual_clock: false,
+            heartbeat: None,
+            interruptible: false,
+            stats: false,
+            verify: false,
+            optimize: true,
+            record: false,
+            replay: None,
+            on_error: ErrorPolicy::Abort,
+            division: Division::Trunc,
+            entry: None,
+        }
+    }
+}
+
+impl VmConfig {
+    pub fn entry(&self) -> &str {
+        self.entry.as_deref().unwrap_or("main")
+    }
+
+    pub fn sandboxed() -> Self {
+        Self {
+            max_steps: Some(1_000_000),
+            max_stack: Some(4096),
+            max_memory: Some(1 << 20),
+            timeout: None,
+            allow_fs: false,
+            allow_env: false,
+            allow_time: false,
+            allow_syscalls: false,
+            seed: None,
+            virtual_clock: false,
+            heartbeat: None,
+            interruptible: false,
+            stats: false,
+            verify: false,
+            optimize: true,
+            record: false,
+            replay: None,
+            on_error: ErrorPolicy::Abort,
+            division: Division::Trunc,
+            entry: None,
+        }
+    }
+
+    // Fixed seed and a step-driven clock, so the same program always
+    // produces the same output.
+    pub fn deterministic(self) -> Self {
+        Self {
+            seed: Some(self.seed.unwrap_or(DETERMINISTIC_SEED)),
+            virtual_clock: true,
+            ..self
+        }
+    }
+}
+
+// How `print` writes numbers, switched by `hex`, `bin` and `dec`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Base {
+    #[default]
+    Dec,
+    Hex,
+    Bin,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Format {
+    base: Base,
+    width: usize,
+    // Printed by `fx.`, always in decimal.
+    fixed: bool,
+}
+
+impl Format {
+    fn render(&self, value: i32) -> String {
+        if self.fixed {
+            format!("{:>width$}", fixed::format(value), width = self.width)
+        } else {
+            format_number(value, self.base, self.width)
+        }
+    }
+}
+
+// Right aligned to `width`. Negative numbers get a sign rather than their
+// two's complement bits, so -255 in hex is "-ff".
+pub fn format_number(value: i32, base: Base, width: usize) -> String {
+    let sign = if value < 0 { "-" } else { "" };
+    let magnitude = value.unsigned_abs();
+    let digits = match base {
+        Base::Dec => magnitude.to_string(),
+        Base::Hex => format!("{:x}", magnitude),
+        Base::Bin => format!("{:b}", magnitude),
+    };
+    format!("{:>width$}", format!("{}{}", sign, digits), width = width)
+}
+
+// Where a running program is, as reported to the heartbeat callback.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Status {
+    pub steps: usize,
+    pub function: String,
+    pub depth: usize,
+    pub line: usize,
+}
+
+pub(crate) type Heartbeat = Box<dyn FnMut(&Status)>;
+
+// An instruction that just ran and the stack it left, for the trace callback.
+pub struct Step<'a> {
+    pub index: usize,
+    pub instruction: &'a Instruction,
+    pub stack: &'a [i32],
+    // `StackMachine::steps` with this one counted.
+    pub steps: usize,
+}
+
+pub(crate) type Trace = Box<dyn FnMut(&Step)>;
+
+// An instruction that failed, with the stack after a `try` or `on_error`
+// took over, or as it was if nothing did.
+pub(crate) type FailureTrace = Box<dyn FnMut(&Step, &Error)>;
+
+// Where `eprint` writes, one formatted value at a time.
+pub(crate) type ErrorSink = Box<dyn FnMut(&str)>;
+
+// Where `print` writes when the values shouldn't wait for the run to end.
+pub(crate) type OutputSink = Box<dyn FnMut(&str)>;
+
+// Where `start` and `resume` stop. A line breaks at its first instruction,
+// so a loop on one line stops once, not on every turn.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Breakpoint {
+    Line(usize),
+    InstructionIndex(usize),
+    FunctionEntry(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BreakpointId(usize);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Paused {
+    pub breakpoint: BreakpointId,
+    // The instruction that runs next.
+    pub index: usize,
+    pub function: String,
+    pub line: usize,
+    pub pos: usize,
+}
+
+// Where a run stopped with an error it didn't handle: the failing
+// instruction, the `call` of each frame it was in, outermost first, and the
+// data stack, bottom first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Crash {
+    pub index: usize,
+    pub calls: Vec<usize>,
+    pub stack: Vec<i32>,
+}
+
+// What a run of `main` left behind, from `StackMachine::run_main`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct RunResult {
+    pub printed: Vec<i32>,
+    // `printed` as text, in the base and width each value was printed with.
+    pub output: Vec<String>,
+    // 0 when `main` returned. Otherwise the error's code, see `Error::code`,
+    // or -1 for the errors a program can't handle.
+    pub exit_code: i32,
+    pub error: Option<Error>,
+    pub steps: usize,
+    // Bottom first.
+    pub final_stack: Vec<i32>,
+    // Set along with `error` when it came from running, not checking.
+    pub crash: Option<Crash>,
+}
+
+impl RunResult {
+    // The printed values, or the error, the way `execute` reports them.
+    pub fn into_result(self) -> Result<Vec<i32>, Error> {
+        match self.error {
+            Some(error) => Err(error),
+            None => Ok(self.printed),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RunState {
+    Finished,
+    Paused(Paused),
+}
+
+// A run that can stop and be picked up again.
+struct Thread {
+    idx: usize,
+    call_stack: Vec<usize>,
+    // Start of the running function for each frame.
+    frames: Vec<usize>,
+    // Set after a pause so the same breakpoint doesn't stop it again at once.
+    resumed: bool,
+    // Innermost `try` last.
+    handlers: Vec<Handler>,
+}
+
+// An entered `try`: where its `recover` is, and how deep the calls and the
+// stack were, to unwind to on an error.
+struct Handler {
+    recover: usize,
+    calls: usize,
+    depth: usize,
+}
+
+impl Thread {
+    fn at(program: &Program, name: &str) -> Result<Thread, Error> {
+        let idx = *(program.functions.get(name).ok_or(Error::FunctionNotFound {
+            name: name.to_string(),
+        })?);
+        Ok(Thread {
+            idx,
+            call_stack: vec![],
+            frames: vec![idx],
+            resumed: false,
+            handlers: vec![],
+        })
+    }
+}
+
+// Refuses a program with inline assembly before any of it runs, as only a
+// native build can run that.
+pub(crate) fn interpretable(program: &Program) -> Result<(), Error> {
+    match program
+        .instructions
+        .iter()
+        .find(|i| matches!(i.instruction_type, InstructionType::Asm(_)))
+    {
+        Some(i) => Err(Error::UnsupportedInstruction {
+            word: i.instruction_type.to_string(),
+            pos: i.pos,
+            line: i.line,
+        }),
+        None => Ok(()),
+    }
+}
+
+#[cfg(feature = "std")]
+fn default_error_sink(line: &str) {
+    eprintln!("{}", line);
+}
+
+// Nowhere to write to without `std` until `set_error_sink` says where.
+#[cfg(not(feature = "std"))]
+fn default_error_sink(_: &str) {}
+
+// Widest `print-padded` pads to, so a stray width can't eat the memory.
+pub const MAX_WIDTH: usize = 1024;
+
+// A return address and the start of the called function, counted as two
+// 8-byte words on every target so a memory limit stops a program at the
+// same call everywhere.
+const FRAME_BYTES: usize = 16;
+
+pub struct StackMachine<T: Stack<i32>> {
+    pub stack: T,
+    pub config: VmConfig,
+    pub steps: usize,
+    pub stats: Stats,
+    pub base: Base,
+    printed: Vec<i32>,
+    // How each printed value is written, in step with `printed`.
+    formats: Vec<Format>,
+    rng: Rng,
+    clock: Clock,
+    on_heartbeat: Option<Heartbeat>,
+    on_step: Option<Trace>,
+    on_failure: Option<FailureTrace>,
+    on_eprint: ErrorSink,
+    on_print: Option<OutputSink>,
+    breakpoints: Vec<(BreakpointId, Breakpoint)>,
+    next_breakpoint: usize,
+    thread: Option<Thread>,
+    // Where the last run failed, if it did.
+    crash: Option<Crash>,
+    packs: plugin::States,
+    recording: Vec<Event>,
+    // How far into `config.replay` the runs so far have read.
+    replayed: usize,
+    heap: Heap,
+}
+
+impl StackMachine<VecStack<i32>> {
+    // Starts a `Builder`, on a `VecStack` unless `Builder::stack` says
+    // otherwise.
+    pub fn builder() -> Builder<Checked<VecStack<i32>>> {
+        Builder::new()
+    }
+}
 
 impl<T: Stack<i32>> StackMachine<T> {
     pub fn new(stack: T) -> Self {
-        Self(stack)
+        Self::with_config(stack, VmConfig::default())
+    }
+
+    pub fn with_config(stack: T, config: VmConfig) -> Self {
+        let rng = match config.seed {
+            Some(seed) => Rng::new(seed),
+            None => Rng::from_entropy(),
+        };
+        let clock = if config.virtual_clock {
+            Clock::Virtual
+        } else {
+            Clock::wall()
+        };
+        Self {
+            stack,
+            config,
+            steps: 0,
+            stats: Stats::default(),
+            base: Base::Dec,
+            printed: vec![],
+            formats: vec![],
+            rng,
+            clock,
+            on_heartbeat: None,
+            on_step: None,
+            on_failure: None,
+            on_eprint: Box::new(default_error_sink),
+            on_print: None,
+            breakpoints: vec![],
+            next_breakpoint: 0,
+            thread: None,
+            crash: None,
+            packs: plugin::States::default(),
+            recording: vec![],
+            replayed: 0,
+            heap: Heap::default(),
+        }
+    }
+
+    // Called every `config.heartbeat` steps while a program runs.
+    pub fn set_heartbeat(&mut self, callback: impl FnMut(&Status) + 'static) {
+        self.on_heartbeat = Some(Box::new(callback));
+    }
+
+    // Replaces stderr as the destination of `eprint`.
+    pub fn set_error_sink(&mut self, callback: impl FnMut(&str) + 'static) {
+        self.on_eprint = Box::new(callback);
+    }
+
+    // Gets every printed value as it is printed, written in its base and
+    // width, instead of keeping it for `take_printed` and `take_output`.
+    pub fn set_output(&mut self, callback: impl FnMut(&str) + 'static) {
+        self.on_print = Some(Box::new(callback));
+    }
+
+    // Called after every instruction that runs without an error.
+    pub fn set_trace(&mut self, callback: impl FnMut(&Step) + 'static) {
+        self.on_step = Some(Box::new(callback));
+    }
+
+    // Called after every instruction that fails, caught or not.
+    pub fn set_failure_trace(&mut self, callback: impl FnMut(&Step, &Error) + 'static) {
+        self.on_failure = Some(Box::new(callback));
+    }
+
+    // Bookkeeping after an instruction ran.
+    fn stepped(&mut self, index: usize, instruction: &Instruction, call_depth: usize) {
+        if self.config.stats {
+            self.stats.count(
+                index,
+                &instruction.instruction_type,
+                self.stack.size(),
+                call_depth,
+            );
+        }
+        if let Some(callback) = self.on_step.as_mut() {
+            callback(&Step {
+                index,
+                instruction,
+                stack: self.stack.as_slice(),
+                steps: self.steps,
+            });
+        }
+    }
+
+    fn failed(&mut self, index: usize, instruction: &Instruction, error: &Error) {
+        if let Some(callback) = self.on_failure.as_mut() {
+            let step = Step {
+                index,
+                instruction,
+                stack: self.stack.as_slice(),
+                steps: self.steps,
+            };
+            callback(&step, error);
+        }
+    }
+
+    fn verify(
+        &self,
+        verifier: &mut Option<Verifier>,
+        index: usize,
+        instruction: &Instruction,
+        calls: usize,
+    ) -> Result<(), Error> {
+        match verifier {
+            Some(verifier) => verifier.step(index, instruction, calls, self.stack.size()),
+            None => Ok(()),
+        }
+    }
+
+    fn function_name(program: &Program, start: usize) -> String {
+        program.symbol(start).unwrap_or("?").to_string()
+    }
+
+    fn interrupted(&self, program: &Program, frames: &[usize], i: &Instruction) -> Error {
+        Error::Interrupted {
+            backtrace: frames
+                .iter()
+                .rev()
+                .map(|start| Self::function_name(program, *start))
+                .collect(),
+            depth: self.stack.size(),
+            pos: i.pos,
+            line: i.line,
+        }
+    }
+
+    // Where to go for an error under `ErrorPolicy::Trap`, and the code to
+    // pass along. A failing handler is not called again.
+    fn error_handler(
+        &self,
+        program: &Program,
+        frames: &[usize],
+        error: &Error,
+    ) -> Option<(usize, i32)> {
+        if self.config.on_error != ErrorPolicy::Trap {
+            return None;
+        }
+        let code = error.code()?;
+        let handler = *program.functions.get(ERROR_HANDLER)?;
+        (!frames.contains(&handler)).then_some((handler, code))
+    }
+
+    fn crashed(&mut self, error: Error, at: usize, call_stack: &[usize]) -> Error {
+        self.crash = Some(Crash {
+            index: at,
+            calls: call_stack.to_vec(),
+            stack: self.stack.as_slice().to_vec(),
+        });
+        error
+    }
+
+    fn beat(&mut self, program: &Program, function: usize, line: usize) {
+        let Some(callback) = self.on_heartbeat.as_mut() else {
+            return;
+        };
+        let function = Self::function_name(program, function);
+        callback(&Status {
+            steps: self.steps,
+            function,
+            depth: self.stack.size(),
+            line,
+        });
+    }
+
+    // Values printed so far. After a failed `execute` this holds the output
+    // produced before the error.
+    pub fn take_printed(&mut self) -> Vec<i32> {
+        self.formats.clear();
+        core::mem::take(&mut self.printed)
+    }
+
+    // Like `take_printed`, but written in the base and width each value was
+    // printed with.
+    pub fn take_output(&mut self) -> Vec<String> {
+        let formats = core::mem::take(&mut self.formats);
+        self.take_printed()
+            .into_iter()
+            .zip(formats)
+            .map(|(value, format)| format.render(value))
+            .collect()
+    }
+
+    fn print(&mut self, value: i32, width: usize) {
+        let format = Format {
+            base: self.base,
+            width,
+            fixed: false,
+        };
+        self.emit(value, format);
+    }
+
+    fn print_fixed(&mut self, value: i32) {
+        let format = Format {
+            base: Base::Dec,
+            width: 0,
+            fixed: true,
+        };
+        self.emit(value, format);
+    }
+
+    fn emit(&mut self, value: i32, format: Format) {
+        match self.on_print.as_mut() {
+            Some(callback) => callback(&format.render(value)),
+            None => {
+                self.printed.push(value);
+                self.formats.push(format);
+            }
+        }
+    }
+
+    fn tick(&mut self, i: &Instruction) -> Result<(), Error> {
+        self.steps += 1;
+        match self.config.max_steps {
+            Some(max_steps) if self.steps > max_steps => Err(Error::StepLimitExceeded {
+                limit: max_steps,
+                pos: i.pos,
+                line: i.line,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    fn check_stack_limit(&self, i: &Instruction) -> Result<(), Error> {
+        match self.config.max_stack {
+            Some(max_stack) if self.stack.size() > max_stack => Err(Error::StackOverflow {
+                limit: max_stack,
+                pos: i.pos,
+                line: i.line,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    // Bytes held by the stack, `frames` call frames and the heap.
+    fn memory_used(&self, frames: usize) -> usize {
+        (self.stack.size() + self.heap.cells()) * core::mem::size_of::<i32>() + frames * FRAME_BYTES
+    }
+
+    fn check_memory(&self, frames: usize, i: &Instruction) -> Result<(), Error> {
+        let Some(limit) = self.config.max_memory else {
+            return Ok(());
+        };
+        if self.memory_used(frames) > limit {
+            Err(Error::OutOfMemory {
+                limit,
+                pos: i.pos,
+                line: i.line,
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    // Whether the pushed value of an `Immediate` would have fit, and its
+    // second step stayed within the step limit.
+    fn room_for_immediate(&self, frames: usize) -> bool {
+        let size = self.stack.size();
+        let used = self.memory_used(frames) + core::mem::size_of::<i32>();
+        size > 0
+            && self.config.max_steps.is_none_or(|max| self.steps < max)
+            && self.config.max_stack.is_none_or(|max| size < max)
+            && self.config.max_memory.is_none_or(|limit| used <= limit)
     }
 
     fn push(&mut self, n: i32) {
-        self.0.push(n);
+        self.stack.push(n);
+    }
+
+    // Where a `call` goes, or the function an `invoke` takes off the stack.
+    // Anything but a function's `&` is put back for a handler to see.
+    fn callee(&mut self, program: &Program, instruction: &Instruction) -> Result<usize, Error> {
+        if let InstructionType::Call(start) = instruction.instruction_type {
+            return Ok(start);
+        }
+        let value = self.pop(instruction)?;
+        match usize::try_from(value) {
+            Ok(start) if program.symbols.contains_key(&start) => Ok(start),
+            _ => {
+                self.push(value);
+                Err(Error::NotAFunction {
+                    value,
+                    pos: instruction.pos,
+                    line: instruction.line,
+                })
+            }
+        }
     }
 
     fn pop(&mut self, i: &Instruction) -> Result<i32, Error> {
         let Instruction { pos, line, .. } = i;
-        self.0.pop().ok_or(Error::StackEmpty {
+        self.stack.pop().ok_or(Error::StackEmpty {
             pos: *pos,
             line: *line,
         })
@@ -32,174 +808,634 @@ impl<T: Stack<i32>> StackMachine<T> {
 
     fn peek(&mut self, i: &Instruction) -> Result<&i32, Error> {
         let Instruction { pos, line, .. } = i;
-        self.0.peek().ok_or(Error::StackEmpty {
+        self.stack.peek().ok_or(Error::StackEmpty {
             pos: *pos,
             line: *line,
         })
     }
 
-    fn add(&mut self, i: &Instruction) -> Result<(), Error> {
-        let a = self.pop(i)?;
-        let b = self.pop(i)?;
-        self.0.push(a + b);
-        Ok(())
+    fn fault(fault: Fault, instruction: &Instruction) -> Error {
+        let (pos, line) = (instruction.pos, instruction.line);
+        match fault {
+            Fault::DivisionByZero => Error::DivisionByZero { pos, line },
+            Fault::Overflow => Error::Overflow { pos, line },
+            Fault::Failed(comment) => Error::Plugin {
+                word: instruction.instruction_type.to_string(),
+                comment,
+                pos,
+                line,
+            },
+        }
     }
 
-    fn sub(&mut self, i: &Instruction) -> Result<(), Error> {
-        let a = self.pop(i)?;
-        let b = self.pop(i)?;
-        self.0.push(b - a);
-        Ok(())
+    // What is left on the heap once everything unreachable is freed.
+    pub fn gc_stats(&mut self) -> GcStats {
+        self.heap.collect(self.stack.as_slice());
+        self.heap.stats()
     }
 
-    fn mul(&mut self, i: &Instruction) -> Result<(), Error> {
-        let a = self.pop(i)?;
-        let b = self.pop(i)?;
-        self.0.push(a * b);
+    // The number is on top, the first of `count` arguments deepest.
+    fn syscall(&mut self, count: usize, instruction: &Instruction) -> Result<(), Error> {
+        if !self.config.allow_syscalls {
+            return Err(Error::CapabilityDenied {
+                capability: instruction.instruction_type.to_string(),
+                pos: instruction.pos,
+                line: instruction.line,
+            });
+        }
+        let number = self.pop(instruction)?;
+        let mut args = [0; 3];
+        for i in (0..count).rev() {
+            args[i] = self.pop(instruction)?;
+        }
+        let result =
+            syscall::call(number, args).map_err(|fault| Self::fault(fault, instruction))?;
+        self.push(result);
         Ok(())
     }
 
-    fn div(&mut self, i: &Instruction) -> Result<(), Error> {
-        let a = self.pop(i)?;
-        let b = self.pop(i)?;
-        self.0.push(b / a);
+    // `frames` is how many calls are live, which count against the limit.
+    fn alloc(&mut self, instruction: &Instruction, frames: usize) -> Result<(), Error> {
+        let (pos, line) = (instruction.pos, instruction.line);
+        let size = *self.peek(instruction)?;
+        let Ok(size) = usize::try_from(size) else {
+            return Err(Error::Heap {
+                comment: format!("can't allocate {} cells", size),
+                pos,
+                line,
+            });
+        };
+        let bytes = size.saturating_mul(core::mem::size_of::<i32>());
+        let over =
+            |machine: &Self, limit| machine.memory_used(frames).saturating_add(bytes) > limit;
+        if let Some(limit) = self.config.max_memory.filter(|limit| over(self, *limit)) {
+            self.heap.collect(self.stack.as_slice());
+            if over(self, limit) {
+                return Err(Error::OutOfMemory { limit, pos, line });
+            }
+        } else if self.heap.due() {
+            self.heap.collect(self.stack.as_slice());
+        }
+        let address = self.heap.alloc(size).ok_or_else(|| Error::Heap {
+            comment: "out of addresses for new blocks".to_string(),
+            pos,
+            line,
+        })?;
+        self.stack.pop();
+        self.push(address);
         Ok(())
     }
 
-    fn dup(&mut self, i: &Instruction) -> Result<(), Error> {
-        let n = self.pop(i)?;
-        self.push(n);
-        self.push(n);
-        Ok(())
+    fn no_memory_at(address: i32, instruction: &Instruction) -> Error {
+        Error::Heap {
+            comment: format!("no memory at address {}", address),
+            pos: instruction.pos,
+            line: instruction.line,
+        }
     }
 
-    fn swap(&mut self, i: &Instruction) -> Result<(), Error> {
-        let a = self.pop(i)?;
-        let b = self.pop(i)?;
-        self.push(a);
-        self.push(b);
-        Ok(())
+    // A value from outside the program: the next one in the replay, if
+    // there is one, or else from `fresh`. Recorded either way.
+    fn read(
+        &mut self,
+        source: Source,
+        instruction: &Instruction,
+        fresh: impl FnOnce(&mut Self) -> i32,
+    ) -> Result<i32, Error> {
+        let value = match &self.config.replay {
+            Some(events) => {
+                let comment = match events.get(self.replayed) {
+                    Some(event) if event.source == source => None,
+                    Some(event) => Some(format!(
+                        "the recording has `{}` here, not `{}`",
+                        event.source, source
+                    )),
+                    None => Some(format!("the recording has no more values for `{}`", source)),
+                };
+                if let Some(comment) = comment {
+                    return Err(Error::Replay {
+                        comment,
+                        pos: instruction.pos,
+                        line: instruction.line,
+                    });
+                }
+                self.replayed += 1;
+                events[self.replayed - 1].value
+            }
+            None => fresh(self),
+        };
+        if self.config.record {
+            self.recording.push(Event { source, value });
+        }
+        Ok(value)
     }
 
-    fn rot(&mut self, i: &Instruction) -> Result<(), Error> {
-        let a = self.pop(i)?;
-        let b = self.pop(i)?;
-        let c = self.pop(i)?;
-        self.push(b);
-        self.push(a);
-        self.push(c);
+    // `env "NAME"`: the variable as a number, or 0 when it is unset or
+    // isn't one. Without `std` there is no environment, so always 0.
+    fn env(
+        &mut self,
+        program: &Program,
+        index: usize,
+        instruction: &Instruction,
+    ) -> Result<(), Error> {
+        if self.config.replay.is_none() && !self.config.allow_env {
+            return Err(Error::CapabilityDenied {
+                capability: "env".to_string(),
+                pos: instruction.pos,
+                line: instruction.line,
+            });
+        }
+        let name = program.env.get(index).map_or("", String::as_str);
+        let value = self.read(Source::Env, instruction, |_| {
+            #[cfg(feature = "std")]
+            {
+                std::env::var(name)
+                    .ok()
+                    .and_then(|value| value.trim().parse().ok())
+                    .unwrap_or(0)
+            }
+            #[cfg(not(feature = "std"))]
+            {
+                let _ = name;
+                0
+            }
+        })?;
+        self.push(value);
         Ok(())
     }
 
-    fn over(&mut self, i: &Instruction) -> Result<(), Error> {
-        let a = self.pop(i)?;
-        let b = self.pop(i)?;
-        self.push(b);
-        self.push(a);
-        self.push(b);
-        Ok(())
+    // What `rand`, `time` and `env` returned so far, with `VmConfig::record`.
+    pub fn take_recording(&mut self) -> Vec<Event> {
+        core::mem::take(&mut self.recording)
     }
 
-    fn nip(&mut self, i: &Instruction) -> Result<(), Error> {
-        let x = self.pop(i)?;
-        self.pop(i)?;
-        self.push(x);
+    fn execute_linear(&mut self, instruction: &Instruction) -> Result<(), Error> {
+        use InstructionType::*;
+        // A failing instruction leaves the stack as it found it, so a handler
+        // sees the values that were there.
+        if let Some((takes, _)) = simple_effect(&instruction.instruction_type) {
+            if (self.stack.size() as i64) < takes {
+                return Err(Error::StackEmpty {
+                    pos: instruction.pos,
+                    line: instruction.line,
+                });
+            }
+        }
+        let eval = match (self.config.division, &instruction.instruction_type) {
+            (Division::Floor, Div) => Some(words::floor_div as words::Eval),
+            (Division::Floor, DivMod) => Some(words::floor_divmod as words::Eval),
+            (_, instruction) => words::op(instruction).and_then(|(_, op)| op.eval),
+        };
+        if let Some(eval) = eval {
+            return eval(&mut self.stack).map_err(|fault| Self::fault(fault, instruction));
+        }
+        match instruction.instruction_type {
+            Push(n) => self.push(n),
+            Print => {
+                let value = self.pop(instruction)?;
+                self.print(value, 0);
+            }
+            PrintPadded => {
+                let width = self.pop(instruction)?;
+                let value = self.pop(instruction)?;
+                self.print(value, (width.max(0) as usize).min(MAX_WIDTH));
+            }
+            FxPrint => {
+                let value = self.pop(instruction)?;
+                self.print_fixed(value);
+            }
+            Eprint => {
+                let value = self.pop(instruction)?;
+                (self.on_eprint)(&format_number(value, self.base, 0));
+            }
+            Native(id) => {
+                if !self.config.allow_fs && plugin::word(id).is_some_and(|w| w.pack.writes_files) {
+                    return Err(Error::CapabilityDenied {
+                        capability: instruction.instruction_type.to_string(),
+                        pos: instruction.pos,
+                        line: instruction.line,
+                    });
+                }
+                // Packs pop before they know whether they can go on, so the
+                // inputs are put back for a handler to see.
+                let takes = simple_effect(&instruction.instruction_type).map_or(0, |e| e.0);
+                let depth = self.stack.size() - takes as usize;
+                let inputs = self.stack.as_slice()[depth..].to_vec();
+                if let Err(fault) = self.packs.call(id, &mut self.stack) {
+                    while self.stack.size() > depth {
+                        self.stack.pop();
+                    }
+                    while self.stack.size() < depth {
+                        self.stack.push(0);
+                    }
+                    inputs.into_iter().for_each(|n| self.stack.push(n));
+                    return Err(Self::fault(fault, instruction));
+                }
+            }
+            Hex => self.base = Base::Hex,
+            Bin => self.base = Base::Bin,
+            Dec => self.base = Base::Dec,
+            Rand => {
+                let n = self.read(Source::Rand, instruction, |machine| machine.rng.next_i32())?;
+                self.push(n);
+            }
+            Time => {
+                if !self.clock.is_virtual() && !self.config.allow_time {
+                    return Err(Error::CapabilityDenied {
+                        capability: "time".to_string(),
+                        pos: instruction.pos,
+                        line: instruction.line,
+                    });
+                }
+                let now = self.read(Source::Time, instruction, |machine| {
+                    machine.clock.millis(machine.steps)
+                })?;
+                self.push(now);
+            }
+            // Only outside `run`, where no calls are live.
+            Alloc => self.alloc(instruction, 0)?,
+            Fetch => {
+                let address = *self.peek(instruction)?;
+                let value = self
+                    .heap
+                    .get(address)
+                    .ok_or_else(|| Self::no_memory_at(address, instruction))?;
+                self.stack.pop();
+                self.push(value);
+            }
+            // The machine has checked both values are there.
+            Store => {
+                let address = self.pop(instruction)?;
+                let value = self.pop(instruction)?;
+                if self.heap.set(address, value).is_none() {
+                    self.push(value);
+                    self.push(address);
+                    return Err(Self::no_memory_at(address, instruction));
+                }
+            }
+            GcStats => {
+                let stats = self.gc_stats();
+                self.push(stats.blocks as i32);
+                self.push(stats.cells as i32);
+            }
+            Address(start) => self.push(start as i32),
+            Syscall0 => self.syscall(0, instruction)?,
+            Syscall1 => self.syscall(1, instruction)?,
+            Syscall2 => self.syscall(2, instruction)?,
+            Syscall3 => self.syscall(3, instruction)?,
+            Pop | Add | Sub | Mul | Div | DivMod | AddWrap | SubWrap | MulWrap | AddSat
+            | SubSat | MulSat | Dup | Swap | Rot | Over | Nip | FxMul | FxDiv | IntToFx
+            | FxToInt => {
+                unreachable!("evaluated through the word table")
+            }
+            // `env` needs the program's names. `asm` is for native backends.
+            While(_) | EndWhile(_) | Loop(_) | Do(_) | EndDo(_) | If(_) | Else(_) | EndIf
+            | Try(_) | Recover(_) | EndTry | Ret | Call(_) | Invoke | Env(_) | Asm(_) => {
+                return Err(Error::UnsupportedInstruction {
+                    word: format!("{}", instruction.instruction_type),
+                    pos: instruction.pos,
+                    line: instruction.line,
+                });
+            }
+        }
         Ok(())
     }
 
+    // Runs straight-line code without building a `Program`. Jumps and calls
+    // need random access to the instruction list, so they are rejected.
+    pub fn execute_iter<I>(&mut self, instructions: I) -> Result<Vec<i32>, Error>
+    where
+        I: IntoIterator<Item = Instruction>,
+    {
+        for instruction in instructions {
+            self.tick(&instruction)?;
+            self.execute_linear(&instruction)?;
+            self.check_stack_limit(&instruction)?;
+            self.check_memory(0, &instruction)?;
+        }
+        Ok(self.take_printed())
+    }
+
+    // Kept for callers written before `run_main`; on an error the printed
+    // values stay behind for `take_printed`.
     pub fn execute(&mut self, program: Program) -> Result<Vec<i32>, Error> {
-        let mut result = vec![];
-        let mut idx = *(program
-            .functions
-            .get("main")
-            .ok_or(Error::FunctionNotFound {
-                name: "main".to_string(),
-            })?);
+        self.call(&program, "main")?;
+        Ok(self.take_printed())
+    }
+
+    // Runs `main`, or the config's `entry`, and lets the plugin packs
+    // finish, collecting everything the run produced. The program's jumps
+    // are checked first, as it may not have come from the parser.
+    pub fn run_main(&mut self, program: &Program) -> RunResult {
+        let before = self.steps;
+        let entry = self.config.entry().to_string();
+        let error = validate_program(program)
+            .and_then(|_| interpretable(program))
+            .and_then(|_| self.call(program, &entry))
+            .and_then(|_| self.finish_packs())
+            .err();
+        let formats = core::mem::take(&mut self.formats);
+        let printed = self.take_printed();
+        let output = printed
+            .iter()
+            .zip(formats)
+            .map(|(value, format)| format.render(*value))
+            .collect();
+        RunResult {
+            printed,
+            output,
+            exit_code: error.as_ref().map_or(0, |e| e.code().unwrap_or(-1)),
+            crash: error.as_ref().and_then(|_| self.crash.take()),
+            error,
+            steps: self.steps - before,
+            final_stack: self.stack.as_slice().to_vec(),
+        }
+    }
+
+    // Runs one function on the current stack, ignoring breakpoints. Printed
+    // values are kept until `take_printed`.
+    pub fn call(&mut self, program: &Program, name: &str) -> Result<(), Error> {
+        let thread = Thread::at(program, name)?;
+        self.run(program, thread, false).map(|_| ())
+    }
+
+    // Like `call`, but stops before any instruction a breakpoint matches.
+    pub fn start(&mut self, program: &Program, name: &str) -> Result<RunState, Error> {
+        let thread = Thread::at(program, name)?;
+        self.run(program, thread, true)
+    }
+
+    // Carries on after a pause, starting with the instruction it stopped at.
+    pub fn resume(&mut self, program: &Program) -> Result<RunState, Error> {
+        match self.thread.take() {
+            Some(thread) => self.run(program, thread, true),
+            None => Ok(RunState::Finished),
+        }
+    }
+
+    // Where the last `call`, `start` or `resume` stopped with an error, for
+    // showing more than the error itself.
+    pub fn take_crash(&mut self) -> Option<Crash> {
+        self.crash.take()
+    }
 
-        let mut call_stack = Vec::new();
+    // Lets the plugin packs that ran write out what they collected. Call it
+    // once the program is done.
+    pub fn finish_packs(&mut self) -> Result<(), Error> {
+        self.packs.finish()
+    }
+
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> BreakpointId {
+        let id = BreakpointId(self.next_breakpoint);
+        self.next_breakpoint += 1;
+        self.breakpoints.push((id, breakpoint));
+        id
+    }
+
+    pub fn remove_breakpoint(&mut self, id: BreakpointId) -> bool {
+        let before = self.breakpoints.len();
+        self.breakpoints.retain(|(b, _)| *b != id);
+        self.breakpoints.len() != before
+    }
+
+    fn breakpoint_at(&self, program: &Program, idx: usize) -> Option<BreakpointId> {
+        let is_start = |idx| program.functions.values().any(|start| *start == idx);
+        self.breakpoints
+            .iter()
+            .find(|(_, breakpoint)| match breakpoint {
+                Breakpoint::Line(line) => {
+                    program.instructions[idx].line == *line
+                        && (idx == 0
+                            || program.instructions[idx - 1].line != *line
+                            || is_start(idx))
+                }
+                Breakpoint::InstructionIndex(i) => *i == idx,
+                Breakpoint::FunctionEntry(name) => program.functions.get(name) == Some(&idx),
+            })
+            .map(|(id, _)| *id)
+    }
+
+    fn run(
+        &mut self,
+        program: &Program,
+        thread: Thread,
+        stop_at_breakpoints: bool,
+    ) -> Result<RunState, Error> {
+        let Thread {
+            mut idx,
+            mut call_stack,
+            mut frames,
+            mut resumed,
+            mut handlers,
+        } = thread;
+        self.crash = None;
+        let deadline = self.config.timeout.map(|timeout| {
+            (
+                timeout,
+                self.clock.elapsed(self.steps).saturating_add(timeout),
+            )
+        });
+        // Anything that looks at every step, or could stop between the two,
+        // needs them run one at a time.
+        let immediates = self.config.optimize
+            && self.on_step.is_none()
+            && !self.config.stats
+            && !self.config.verify
+            && self.config.heartbeat.is_none()
+            && (!stop_at_breakpoints || self.breakpoints.is_empty())
+            && (deadline.is_none() || !self.clock.is_virtual());
+        let mut verifier = self.config.verify.then(|| {
+            let mut verifier = Verifier::new(program);
+            verifier.start(call_stack.len(), (!resumed).then(|| self.stack.size()));
+            verifier
+        });
 
         while idx < program.instructions.len() {
             // stack.print();
             let instruction = &program.instructions[idx];
-            use InstructionType::*;
-            match instruction.instruction_type {
-                Push(n) => self.push(n),
-                Pop => {
-                    self.pop(instruction)?;
-                }
-                Add => {
-                    self.add(instruction)?;
-                }
-                Sub => {
-                    self.sub(instruction)?;
-                }
-                Mul => {
-                    self.mul(instruction)?;
+            let at = idx;
+            if !core::mem::take(&mut resumed) && stop_at_breakpoints {
+                if let Some(breakpoint) = self.breakpoint_at(program, idx) {
+                    let function = Self::function_name(program, *frames.last().unwrap_or(&0));
+                    self.thread = Some(Thread {
+                        idx,
+                        call_stack,
+                        frames,
+                        resumed: true,
+                        handlers,
+                    });
+                    return Ok(RunState::Paused(Paused {
+                        breakpoint,
+                        index: idx,
+                        function,
+                        line: instruction.line,
+                        pos: instruction.pos,
+                    }));
                 }
-                Div => {
-                    self.div(instruction)?;
-                }
-                Print => {
-                    result.push(self.pop(instruction)?);
-                }
-                Dup => {
-                    self.dup(instruction)?;
-                }
-                Swap => {
-                    self.swap(instruction)?;
-                }
-                Rot => {
-                    self.rot(instruction)?;
-                }
-                Over => {
-                    self.over(instruction)?;
+            }
+            self.tick(instruction)
+                .map_err(|e| self.crashed(e, at, &call_stack))?;
+            if self.config.interruptible && interrupt::take() {
+                let error = self.interrupted(program, &frames, instruction);
+                return Err(self.crashed(error, at, &call_stack));
+            }
+            if let Some((limit, deadline)) = deadline {
+                let look =
+                    self.clock.is_virtual() || self.steps.is_multiple_of(TIMEOUT_CHECK_EVERY);
+                if look && self.clock.elapsed(self.steps) > deadline {
+                    let error = Error::Timeout {
+                        limit,
+                        pos: instruction.pos,
+                        line: instruction.line,
+                    };
+                    return Err(self.crashed(error, at, &call_stack));
                 }
-                Nip => {
-                    self.nip(instruction)?;
+            }
+            if let Some(every) = self.config.heartbeat {
+                if self.steps.is_multiple_of(every) {
+                    let function = *frames.last().unwrap_or(&0);
+                    self.beat(program, function, instruction.line);
                 }
-                While(jmp_pos) => {
-                    let val = self.peek(instruction)?;
+            }
+            if let Some(result) = immediates
+                .then(|| Immediate::decode(program, idx))
+                .flatten()
+                .filter(|_| self.room_for_immediate(call_stack.len()))
+                .and_then(|immediate| {
+                    immediate.apply(self.stack.peek().copied().unwrap_or_default())
+                })
+            {
+                self.stack.pop();
+                self.push(result);
+                self.steps += 1;
+                idx += 2;
+                continue;
+            }
+            use InstructionType::*;
+            let outcome = match instruction.instruction_type {
+                While(jmp_pos) | If(jmp_pos) => self.peek(instruction).map(|val| {
                     if *val == 0 {
                         idx = jmp_pos;
                     }
-                }
-                EndWhile(jmp_pos) => {
-                    let val = self.peek(instruction)?;
+                }),
+                EndWhile(jmp_pos) => self.peek(instruction).map(|val| {
                     if *val != 0 {
                         idx = jmp_pos;
                     }
-                }
-                If(jmp_pos) => {
-                    let val = self.peek(instruction)?;
-                    if *val == 0 {
+                }),
+                Do(jmp_pos) => self.pop(instruction).map(|val| {
+                    if val == 0 {
                         idx = jmp_pos;
                     }
-                }
-                Else(jmp_pos) => {
+                }),
+                Else(jmp_pos) | EndDo(jmp_pos) => {
                     idx = jmp_pos;
+                    Ok(())
                 }
-                EndIf => {
-                    // do nothing?
+                Loop(_) | EndIf | EndTry => Ok(()),
+                Try(recover) => {
+                    handlers.push(Handler {
+                        recover,
+                        calls: call_stack.len(),
+                        depth: self.stack.size(),
+                    });
+                    Ok(())
                 }
-                Ret => match call_stack.pop() {
+                Recover(jmp_pos) => {
+                    handlers.pop();
+                    idx = jmp_pos;
+                    Ok(())
+                }
+                Ret => match call_stack.pop() {
                     Some(jmp_pos) => {
+                        self.stepped(idx, instruction, call_stack.len());
+                        self.verify(&mut verifier, at, instruction, call_stack.len() + 1)
+                            .map_err(|e| self.crashed(e, at, &call_stack))?;
+                        frames.pop();
+                        // A `ret` inside `try` leaves it.
+                        while handlers.last().is_some_and(|h| h.calls > call_stack.len()) {
+                            handlers.pop();
+                        }
                         idx = jmp_pos + 1;
                         continue;
                     }
                     None => {
                         // Assume that we're in main
-                        return Ok(result);
+                        self.stepped(idx, instruction, call_stack.len());
+                        self.verify(&mut verifier, at, instruction, 0)
+                            .map_err(|e| self.crashed(e, at, &call_stack))?;
+                        return Ok(RunState::Finished);
                     }
                 },
-                Call(jmp_pos) => {
-                    call_stack.push(idx);
-                    idx = jmp_pos;
-                    continue;
+                Call(_) | Invoke => match self.callee(program, instruction) {
+                    Ok(jmp_pos) => {
+                        call_stack.push(idx);
+                        frames.push(jmp_pos);
+                        self.check_memory(call_stack.len(), instruction)
+                            .map_err(|e| {
+                                self.crashed(e, at, &call_stack[..call_stack.len() - 1])
+                            })?;
+                        // Traces and stats see an `invoke` as the call it made.
+                        let call = Instruction {
+                            instruction_type: Call(jmp_pos),
+                            ..*instruction
+                        };
+                        self.stepped(idx, &call, call_stack.len());
+                        self.verify(&mut verifier, at, &call, call_stack.len() - 1)
+                            .map_err(|e| self.crashed(e, at, &call_stack))?;
+                        idx = jmp_pos;
+                        continue;
+                    }
+                    Err(error) => Err(error),
+                },
+                Alloc => self.alloc(instruction, call_stack.len()).and_then(|_| {
+                    self.check_stack_limit(instruction)?;
+                    self.check_memory(call_stack.len(), instruction)
+                }),
+                Env(index) => self.env(program, index, instruction).and_then(|_| {
+                    self.check_stack_limit(instruction)?;
+                    self.check_memory(call_stack.len(), instruction)
+                }),
+                _ => self.execute_linear(instruction).and_then(|_| {
+                    self.check_stack_limit(instruction)?;
+                    self.check_memory(call_stack.len(), instruction)
+                }),
+            };
+            if let Err(error) = outcome {
+                if let Some(code) = error.code() {
+                    if let Some(handler) = handlers.pop() {
+                        call_stack.truncate(handler.calls);
+                        frames.truncate(handler.calls + 1);
+                        while self.stack.size() > handler.depth {
+                            self.stack.pop();
+                        }
+                        self.push(code);
+                        self.failed(at, instruction, &error);
+                        idx = handler.recover + 1;
+                        continue;
+                    }
                 }
+                let Some((handler, code)) = self.error_handler(program, &frames, &error) else {
+                    self.failed(at, instruction, &error);
+                    return Err(self.crashed(error, at, &call_stack));
+                };
+                // Called as if the failed instruction were a call, so the
+                // handler returns to the one after it.
+                self.push(code);
+                self.failed(at, instruction, &error);
+                call_stack.push(at);
+                frames.push(handler);
+                self.check_memory(call_stack.len(), instruction)
+                    .map_err(|e| self.crashed(e, at, &call_stack))?;
+                idx = handler;
+                continue;
             }
+            self.stepped(at, instruction, call_stack.len());
+            self.verify(&mut verifier, at, instruction, call_stack.len())
+                .map_err(|e| self.crashed(e, at, &call_stack))?;
             idx += 1;
         }
-        Ok(result)
+        Ok(RunState::Finished)
     }
 }
 
@@ -239,11 +1475,12 @@ mod test_stack_machine {
     }
 
     fn to_program(instructions: Vec<Instruction>) -> Program {
-        let mut functions = HashMap::new();
+        let mut functions = BTreeMap::new();
         functions.insert("main".to_string(), 0);
         Program {
             instructions,
             functions,
+            ..Default::default()
         }
     }
 
@@ -705,7 +1942,7 @@ mod test_stack_machine {
         let mut machine = StackMachine::new(stack);
         let result = machine.execute(Program {
             instructions: program,
-            functions: HashMap::new(),
+            ..Default::default()
         });
         assert_eq!(
             result,
@@ -714,6 +1951,510 @@ mod test_stack_machine {
             })
         );
     }
+
+    #[test]
+    fn execute_iter_runs_straight_line_code() {
+        let instructions = vec![
+            Instruction {
+                instruction_type: InstructionType::Push(6),
+                pos: 1,
+                line: 1,
+            },
+            Instruction {
+                instruction_type: InstructionType::Push(7),
+                pos: 3,
+                line: 1,
+            },
+            Instruction {
+                instruction_type: InstructionType::Mul,
+                pos: 5,
+                line: 1,
+            },
+            Instruction {
+                instruction_type: InstructionType::Print,
+                pos: 7,
+                line: 1,
+            },
+        ];
+        let mut machine = StackMachine::new(VecStack::new());
+        let result = machine.execute_iter(instructions);
+        assert_eq!(result, Ok(vec![42]));
+    }
+
+    #[test]
+    fn execute_iter_keeps_stack_between_calls() {
+        let mut machine = StackMachine::new(VecStack::new());
+        let first = machine.execute_iter(vec![Instruction {
+            instruction_type: InstructionType::Push(5),
+            pos: 1,
+            line: 1,
+        }]);
+        assert_eq!(first, Ok(vec![]));
+        let second = machine.execute_iter(vec![Instruction {
+            instruction_type: InstructionType::Print,
+            pos: 1,
+            line: 2,
+        }]);
+        assert_eq!(second, Ok(vec![5]));
+    }
+
+    #[test]
+    fn execute_iter_rejects_jumps() {
+        let instructions = vec![
+            Instruction {
+                instruction_type: InstructionType::Push(1),
+                pos: 1,
+                line: 1,
+            },
+            Instruction {
+                instruction_type: InstructionType::While(3),
+                pos: 3,
+                line: 1,
+            },
+        ];
+        let mut machine = StackMachine::new(VecStack::new());
+        let result = machine.execute_iter(instructions);
+        assert_eq!(
+            result,
+            Err(Error::UnsupportedInstruction {
+                word: "while".to_string(),
+                pos: 3,
+                line: 1,
+            })
+        );
+    }
+
+    #[test]
+    fn with_config_limits_steps() {
+        let config = VmConfig {
+            max_steps: Some(2),
+            ..VmConfig::default()
+        };
+        let mut machine = StackMachine::with_config(VecStack::new(), config);
+        let result = machine.execute(to_program(vec![
+            Instruction {
+                instruction_type: InstructionType::Push(1),
+                pos: 1,
+                line: 1,
+            },
+            Instruction {
+                instruction_type: InstructionType::Push(2),
+                pos: 3,
+                line: 1,
+            },
+            Instruction {
+                instruction_type: InstructionType::Add,
+                pos: 5,
+                line: 1,
+            },
+        ]));
+        assert_eq!(
+            result,
+            Err(Error::StepLimitExceeded {
+                limit: 2,
+                pos: 5,
+                line: 1,
+            })
+        );
+    }
+
+    #[test]
+    fn with_config_limits_memory() {
+        use crate::parser::parse;
+        use crate::tokenizer::tokenize;
+
+        let config = VmConfig {
+            max_memory: Some(16),
+            ..VmConfig::default()
+        };
+        let program = |source| parse(tokenize(source).unwrap()).unwrap();
+        let mut machine = StackMachine::with_config(VecStack::new(), config.clone());
+        assert_eq!(
+            machine.execute(program("fun main 1 2 3 4 5 ret")),
+            Err(Error::OutOfMemory {
+                limit: 16,
+                pos: 18,
+                line: 1,
+            })
+        );
+
+        // Recursion that never grows the data stack still runs out.
+        let mut machine = StackMachine::with_config(VecStack::new(), config);
+        assert!(matches!(
+            machine.execute(program("fun down down ret\nfun main down ret")),
+            Err(Error::OutOfMemory {
+                limit: 16,
+                line: 1,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn call_frames_weigh_the_same_everywhere() {
+        use crate::parser::parse;
+        use crate::tokenizer::tokenize;
+
+        // Two calls under `main`: 32 bytes, whatever the target.
+        let source = "fun b ret\nfun a b ret\nfun main a ret";
+        let run = |limit| {
+            let config = VmConfig {
+                max_memory: Some(limit),
+                ..VmConfig::default()
+            };
+            StackMachine::with_config(VecStack::new(), config)
+                .execute(parse(tokenize(source).unwrap()).unwrap())
+        };
+        assert_eq!(run(32), Ok(vec![]));
+        assert!(matches!(run(31), Err(Error::OutOfMemory { limit: 31, .. })));
+    }
+
+    #[test]
+    fn allocating_in_recursion_counts_the_frames() {
+        use crate::parser::parse;
+        use crate::tokenizer::tokenize;
+
+        // Two calls deep, a 32 byte block fits next to the garbage one from
+        // `main` only if that is freed: 8 bytes of stack, 64 of heap and two
+        // 16 byte frames are past the limit.
+        let config = VmConfig {
+            max_memory: Some(90),
+            ..VmConfig::default()
+        };
+        let source = "fun deep if 1 - deep else 8 alloc pop end ret
+fun main 8 alloc pop 1 deep ret";
+        let mut machine = StackMachine::with_config(VecStack::new(), config);
+        let result = machine.run_main(&parse(tokenize(source).unwrap()).unwrap());
+        assert_eq!(result.error, None);
+        assert_eq!(result.final_stack, vec![0]);
+    }
+
+    #[test]
+    fn division_rounds_as_configured() {
+        use crate::parser::parse;
+        use crate::tokenizer::tokenize;
+
+        let program = parse(
+            tokenize(
+                "fun show divmod swap print print ret
+fun main
+  0 7 - 3 show  7 0 3 - show  0 7 - 0 3 - show  7 3 show
+  0 7 - 3 / print  6 3 / print
+ret",
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let run = |division| {
+            let config = VmConfig {
+                division,
+                ..VmConfig::default()
+            };
+            StackMachine::with_config(VecStack::new(), config)
+                .run_main(&program)
+                .output
+        };
+        assert_eq!(
+            run(Division::Trunc),
+            vec!["-2", "-1", "-2", "1", "2", "-1", "2", "1", "-2", "2"]
+        );
+        assert_eq!(
+            run(Division::Floor),
+            vec!["-3", "2", "-3", "-2", "2", "-1", "2", "1", "-3", "2"]
+        );
+
+        let mut machine = StackMachine::new(VecStack::new());
+        machine.set_error_sink(|_| {});
+        let result =
+            machine.run_main(&parse(tokenize("fun main 7 0 divmod ret").unwrap()).unwrap());
+        assert_eq!(
+            result.error,
+            Some(Error::DivisionByZero { pos: 14, line: 1 })
+        );
+        assert_eq!(result.final_stack, vec![7, 0]);
+    }
+
+    #[test]
+    fn memory_words_store_and_fetch() {
+        use crate::parser::parse;
+        use crate::tokenizer::tokenize;
+
+        let program = |source| parse(tokenize(source).unwrap()).unwrap();
+        let mut machine = StackMachine::new(VecStack::new());
+        let result = machine.run_main(&program(
+            "fun main 2 alloc dup 1 + 7 swap ! dup @ print 1 + @ print ret",
+        ));
+        assert_eq!(result.output, vec!["0", "7"]);
+
+        let mut machine = StackMachine::new(VecStack::new());
+        machine.set_error_sink(|_| {});
+        let result = machine.run_main(&program("fun main 3 5 ! ret"));
+        assert_eq!(
+            result.error.map(|e| e.to_string()),
+            Some("1:14: no memory at address 5".to_string())
+        );
+        assert_eq!(result.final_stack, vec![3, 5]);
+
+        let mut machine = StackMachine::new(VecStack::new());
+        let result = machine.run_main(&program("fun main try 0 1 - alloc recover print end ret"));
+        assert_eq!(result.output, vec!["6"]);
+    }
+
+    #[test]
+    fn unreachable_blocks_are_freed() {
+        use crate::parser::parse;
+        use crate::tokenizer::tokenize;
+
+        // Far more than the limit allows at once, so nothing may leak.
+        let config = VmConfig {
+            max_memory: Some(1 << 14),
+            ..VmConfig::default()
+        };
+        let mut machine = StackMachine::with_config(VecStack::new(), config);
+        let program = parse(
+            tokenize(
+                "fun main
+  1000 while 64 alloc dup dup ! pop 1 - end pop
+  gc-stats print print
+  8 alloc gc-stats print print
+ret",
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let result = machine.run_main(&program);
+        assert_eq!(result.error, None);
+        assert_eq!(result.output, vec!["0", "0", "8", "1"]);
+        let stats = machine.gc_stats();
+        assert_eq!((stats.blocks, stats.cells, stats.freed), (1, 8, 1000));
+
+        machine.stack.pop();
+        assert_eq!(machine.gc_stats().blocks, 0);
+    }
+
+    #[test]
+    fn with_config_limits_time() {
+        use crate::parser::parse;
+        use crate::tokenizer::tokenize;
+
+        let config = VmConfig {
+            timeout: Some(50),
+            ..VmConfig::default().deterministic()
+        };
+        let program =
+            parse(tokenize("fun spin 1 while end ret\nfun quick 1 1 + ret").unwrap()).unwrap();
+        let mut machine = StackMachine::with_config(VecStack::new(), config);
+        assert!(matches!(
+            machine.call(&program, "spin"),
+            Err(Error::Timeout {
+                limit: 50,
+                line: 1,
+                ..
+            })
+        ));
+        // On the virtual clock a step is a millisecond, so the cut-off is exact.
+        assert_eq!(machine.steps, 51);
+        // Each call gets the whole budget again.
+        assert_eq!(machine.call(&program, "quick"), Ok(()));
+    }
+
+    #[test]
+    fn with_config_limits_stack() {
+        let config = VmConfig {
+            max_stack: Some(1),
+            ..VmConfig::default()
+        };
+        let mut machine = StackMachine::with_config(VecStack::new(), config);
+        let result = machine.execute_iter(vec![
+            Instruction {
+                instruction_type: InstructionType::Push(1),
+                pos: 1,
+                line: 1,
+            },
+            Instruction {
+                instruction_type: InstructionType::Dup,
+                pos: 3,
+                line: 1,
+            },
+        ]);
+        assert_eq!(
+            result,
+            Err(Error::StackOverflow {
+                limit: 1,
+                pos: 3,
+                line: 1,
+            })
+        );
+    }
+
+    #[test]
+    fn deterministic_config_repeats_runs() {
+        let program = || {
+            vec![
+                Instruction {
+                    instruction_type: InstructionType::Rand,
+                    pos: 1,
+                    line: 1,
+                },
+                Instruction {
+                    instruction_type: InstructionType::Print,
+                    pos: 1,
+                    line: 1,
+                },
+                Instruction {
+                    instruction_type: InstructionType::Time,
+                    pos: 1,
+                    line: 1,
+                },
+                Instruction {
+                    instruction_type: InstructionType::Print,
+                    pos: 1,
+                    line: 1,
+                },
+            ]
+        };
+        let config = VmConfig::sandboxed().deterministic();
+        let mut first = StackMachine::with_config(VecStack::new(), config.clone());
+        let mut second = StackMachine::with_config(VecStack::new(), config);
+        let first = first.execute(to_program(program())).unwrap();
+        assert_eq!(first, second.execute(to_program(program())).unwrap());
+        assert_eq!(first[1], 3);
+    }
+
+    #[test]
+    fn recorded_runs_replay() {
+        use crate::parser::parse;
+        use crate::tokenizer::tokenize;
+        let program =
+            parse(tokenize("fun main rand print time print rand print ret").unwrap()).unwrap();
+        let config = VmConfig {
+            record: true,
+            ..VmConfig::default()
+        };
+        let mut machine = StackMachine::with_config(VecStack::new(), config);
+        let first = machine.run_main(&program);
+        let recording = machine.take_recording();
+        assert_eq!(recording.len(), 3);
+        assert_eq!(recording[1].source, Source::Time);
+
+        // A different seed, so only the recording can make the runs agree.
+        let config = VmConfig {
+            seed: Some(1),
+            replay: Some(recording),
+            ..VmConfig::default()
+        };
+        let mut machine = StackMachine::with_config(VecStack::new(), config);
+        assert_eq!(machine.run_main(&program).output, first.output);
+    }
+
+    #[test]
+    fn replay_must_match_the_run() {
+        use crate::parser::parse;
+        use crate::tokenizer::tokenize;
+        let program = parse(tokenize("fun main rand print time print ret").unwrap()).unwrap();
+        let run = |replay| {
+            let config = VmConfig {
+                replay: Some(replay),
+                ..VmConfig::default()
+            };
+            let mut machine = StackMachine::with_config(VecStack::new(), config);
+            machine.set_error_sink(|_| {});
+            machine.run_main(&program)
+        };
+        let rand = Event {
+            source: Source::Rand,
+            value: 4,
+        };
+        let result = run(vec![rand, rand]);
+        assert_eq!(result.output, vec!["4"]);
+        assert_eq!(
+            result.error,
+            Some(Error::Replay {
+                comment: "the recording has `rand` here, not `time`".to_string(),
+                pos: 21,
+                line: 1,
+            })
+        );
+        assert_eq!(
+            run(vec![]).error.map(|e| e.to_string()),
+            Some("1:10: replay: the recording has no more values for `rand`".to_string())
+        );
+    }
+
+    #[test]
+    fn time_needs_capability() {
+        let config = VmConfig::sandboxed();
+        let mut machine = StackMachine::with_config(VecStack::new(), config);
+        let result = machine.execute_iter(vec![Instruction {
+            instruction_type: InstructionType::Time,
+            pos: 4,
+            line: 2,
+        }]);
+        assert_eq!(
+            result,
+            Err(Error::CapabilityDenied {
+                capability: "time".to_string(),
+                pos: 4,
+                line: 2,
+            })
+        );
+    }
+
+    #[test]
+    fn env_needs_capability_and_is_recorded() {
+        use crate::parser::parse;
+        use crate::tokenizer::tokenize;
+        std::env::set_var("RORTH_TEST_ENV_COUNT", " 12 ");
+        std::env::set_var("RORTH_TEST_ENV_WORD", "twelve");
+        let program = parse(
+            tokenize(
+                "fun main
+  env \"RORTH_TEST_ENV_COUNT\" print
+  env \"RORTH_TEST_ENV_WORD\" print
+  env \"RORTH_TEST_ENV_UNSET\" print
+ret",
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(program.env.len(), 3);
+        assert!(matches!(
+            parse(tokenize("fun main env ret").unwrap()),
+            Err(Error::Parse { comment, .. }) if comment == "`env` expects a variable name in quotes"
+        ));
+        let run = |config| StackMachine::with_config(VecStack::new(), config).run_main(&program);
+        let config = VmConfig {
+            record: true,
+            ..VmConfig::default()
+        };
+        let mut machine = StackMachine::with_config(VecStack::new(), config);
+        assert_eq!(machine.run_main(&program).output, ["12", "0", "0"]);
+        let recording = machine.take_recording();
+        assert!(recording.iter().all(|event| event.source == Source::Env));
+
+        let denied = run(VmConfig::sandboxed());
+        assert_eq!(
+            denied.error,
+            Some(Error::CapabilityDenied {
+                capability: "env".to_string(),
+                pos: 3,
+                line: 2,
+            })
+        );
+        // A replay hands the values back without looking.
+        let replayed = run(VmConfig {
+            replay: Some(recording),
+            ..VmConfig::sandboxed()
+        });
+        assert_eq!(replayed.output, ["12", "0", "0"]);
+    }
+
+    #[test]
+    fn sandboxed_config_denies_capabilities() {
+        let config = VmConfig::sandboxed();
+        assert!(!config.allow_fs && !config.allow_env && !config.allow_time);
+        assert!(config.max_steps.is_some() && config.max_stack.is_some());
+    }
 }
 
 #[cfg(test)]
@@ -728,12 +2469,607 @@ mod test_basic_operations {
         let mut machine = StackMachine::new(stack);
         machine.push(1);
         machine.push(2);
-        let _ = machine.add(&Instruction {
+        let _ = machine.execute_linear(&Instruction {
             instruction_type: InstructionType::Add,
             pos: 1,
             line: 1,
         });
-        assert_eq!(*machine.0.peek().unwrap(), 3);
-        assert_eq!(machine.0.size(), 1)
+        assert_eq!(*machine.stack.peek().unwrap(), 3);
+        assert_eq!(machine.stack.size(), 1)
+    }
+
+    #[test]
+    fn heartbeat_reports_progress() {
+        use crate::parser::parse;
+        use crate::tokenizer::tokenize;
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let program =
+            parse(tokenize("fun count 1 - ret\nfun main 5 while count end ret").unwrap()).unwrap();
+        let config = VmConfig {
+            heartbeat: Some(4),
+            ..VmConfig::default()
+        };
+        let mut machine = StackMachine::with_config(VecStack::new(), config);
+        let beats = Rc::new(RefCell::new(vec![]));
+        let seen = beats.clone();
+        machine.set_heartbeat(move |status| seen.borrow_mut().push(status.clone()));
+        machine.execute(program).unwrap();
+
+        let beats = beats.borrow();
+        assert_eq!(beats.len(), machine.steps / 4);
+        assert_eq!(
+            beats[0],
+            Status {
+                steps: 4,
+                function: "count".to_string(),
+                depth: 1,
+                line: 1,
+            }
+        );
+        assert!(beats.iter().any(|b| b.function == "main"));
+    }
+
+    #[test]
+    fn breakpoints_pause_and_resume() {
+        use crate::parser::parse;
+        use crate::tokenizer::tokenize;
+
+        let program =
+            parse(tokenize("fun sq dup * ret\nfun main 3 sq print\n  4 sq print ret").unwrap())
+                .unwrap();
+        let mut machine = StackMachine::new(VecStack::new());
+        let entry = machine.add_breakpoint(Breakpoint::FunctionEntry("sq".to_string()));
+        let line = machine.add_breakpoint(Breakpoint::Line(3));
+        let paused = |breakpoint, index, function: &str, line, pos| {
+            Ok(RunState::Paused(Paused {
+                breakpoint,
+                index,
+                function: function.to_string(),
+                line,
+                pos,
+            }))
+        };
+
+        assert_eq!(
+            machine.start(&program, "main"),
+            paused(entry, 0, "sq", 1, 8)
+        );
+        assert_eq!(machine.stack.as_slice(), &[3]);
+        assert_eq!(machine.resume(&program), paused(line, 6, "main", 3, 3));
+        assert_eq!(machine.take_printed(), vec![9]);
+        assert_eq!(machine.resume(&program), paused(entry, 0, "sq", 1, 8));
+        assert_eq!(machine.stack.as_slice(), &[4]);
+
+        assert!(machine.remove_breakpoint(entry));
+        assert!(!machine.remove_breakpoint(entry));
+        assert_eq!(machine.resume(&program), Ok(RunState::Finished));
+        assert_eq!(machine.take_printed(), vec![16]);
+        assert_eq!(machine.resume(&program), Ok(RunState::Finished));
+
+        // `call` runs straight through.
+        machine.add_breakpoint(Breakpoint::InstructionIndex(0));
+        assert_eq!(machine.call(&program, "main"), Ok(()));
+        assert_eq!(machine.take_printed(), vec![9, 16]);
+    }
+
+    #[test]
+    fn interrupt_stops_with_a_backtrace() {
+        use crate::parser::parse;
+        use crate::tokenizer::tokenize;
+        use std::sync::atomic::Ordering;
+
+        let program =
+            parse(tokenize("fun spin 1 while end ret\nfun main 3 print 7 spin ret").unwrap())
+                .unwrap();
+        let config = VmConfig {
+            heartbeat: Some(50),
+            interruptible: true,
+            ..VmConfig::default()
+        };
+        let mut machine = StackMachine::with_config(VecStack::new(), config);
+        // Stands in for Ctrl-C arriving while `spin` runs.
+        machine.set_heartbeat(|_| interrupt::INTERRUPTED.store(true, Ordering::Relaxed));
+        let error = machine.execute(program).unwrap_err();
+        assert!(matches!(
+            &error,
+            Error::Interrupted { backtrace, depth: 2, line: 1, .. } if backtrace == &["spin", "main"]
+        ));
+        assert!(error
+            .to_string()
+            .ends_with("interrupted in `spin` (called from `main`) with 2 values on the stack"));
+        assert_eq!(machine.take_printed(), vec![3]);
+        assert!(!interrupt::take());
+    }
+
+    #[test]
+    fn output_formatting() {
+        use crate::parser::parse;
+        use crate::tokenizer::tokenize;
+
+        let program = parse(
+            tokenize("fun main 255 hex print 0 255 - print 5 bin 6 print-padded dec 42 print 7 0 1 - print-padded ret")
+                .unwrap(),
+        )
+        .unwrap();
+        let mut machine = StackMachine::new(VecStack::new());
+        machine.call(&program, "main").unwrap();
+        assert_eq!(machine.base, Base::Dec);
+        assert_eq!(
+            machine.take_output(),
+            vec!["ff", "-ff", "   101", "42", "7"]
+        );
+        assert_eq!(format_number(i32::MIN, Base::Hex, 0), "-80000000");
+    }
+
+    #[test]
+    fn immediates_run_like_the_two_instructions() {
+        use crate::parser::parse;
+        use crate::tokenizer::tokenize;
+
+        let source = "fun main 0 10 while 1 - swap 3 + 2 * swap end pop print ret";
+        let program = parse(tokenize(source).unwrap()).unwrap();
+        let run = |config: VmConfig| {
+            let mut machine = StackMachine::with_config(VecStack::new(), config);
+            let result = machine.call(&program, "main");
+            (
+                result,
+                machine.take_printed(),
+                machine.steps,
+                machine.stack.as_slice().to_vec(),
+            )
+        };
+        // Stats count every step, so they turn immediates off.
+        let counted = VmConfig {
+            stats: true,
+            ..VmConfig::default()
+        };
+        assert_eq!(run(VmConfig::default()), run(counted.clone()));
+
+        // Limits that land between the two stop in the same place.
+        for limit in [
+            VmConfig {
+                max_steps: Some(6),
+                ..VmConfig::default()
+            },
+            VmConfig {
+                max_stack: Some(2),
+                ..VmConfig::default()
+            },
+            VmConfig {
+                max_memory: Some(8),
+                ..VmConfig::default()
+            },
+        ] {
+            let with_stats = VmConfig {
+                stats: true,
+                ..limit.clone()
+            };
+            assert_eq!(run(limit), run(with_stats));
+        }
+        // On an empty stack `+` still fails.
+        let program = parse(tokenize("fun f 1 + ret").unwrap()).unwrap();
+        let mut machine = StackMachine::new(VecStack::new());
+        assert_eq!(
+            machine.call(&program, "f"),
+            Err(Error::StackEmpty { pos: 9, line: 1 })
+        );
+    }
+
+    #[test]
+    fn calls_are_named_by_their_symbols() {
+        use crate::parser::parse;
+        use crate::tokenizer::tokenize;
+
+        // `square` and `sq` start at the same place; the first name wins.
+        let source = "fun square fun sq dup * ret fun main 3 sq ret";
+        let mut program = parse(tokenize(source).unwrap()).unwrap();
+        assert_eq!(
+            program.symbols,
+            BTreeMap::from([(0, "sq".to_string()), (3, "main".to_string())])
+        );
+        assert_eq!(program.describe(&InstructionType::Call(0)), "call sq");
+        assert_eq!(program.describe(&InstructionType::Call(9)), "call 9");
+        assert_eq!(program.describe(&InstructionType::Dup), "dup");
+
+        program.functions.remove("sq");
+        program.index_symbols();
+        assert_eq!(program.symbol(0), Some("square"));
+    }
+
+    #[test]
+    fn crash_says_where_the_run_failed() {
+        use crate::parser::parse;
+        use crate::tokenizer::tokenize;
+
+        // 0: 0  1: /  2: ret  3: 5  4: 1  5: call 0  6: ret  7: call 3  8: ret
+        let source = "fun half 0 / ret fun inner 5 1 half ret fun main inner ret";
+        let program = parse(tokenize(source).unwrap()).unwrap();
+        let mut machine = StackMachine::new(VecStack::new());
+        assert!(machine.call(&program, "main").is_err());
+        assert_eq!(
+            machine.take_crash(),
+            Some(Crash {
+                index: 1,
+                calls: vec![7, 5],
+                stack: vec![5, 1, 0],
+            })
+        );
+        assert_eq!(machine.take_crash(), None);
+
+        // Nothing to show for an error the program handled.
+        let source = "fun main try 1 0 / recover pop end ret";
+        let program = parse(tokenize(source).unwrap()).unwrap();
+        let result = StackMachine::new(VecStack::new()).run_main(&program);
+        assert_eq!((result.error, result.crash), (None, None));
+    }
+
+    #[test]
+    fn run_main_starts_at_the_entry() {
+        use crate::parser::parse;
+        use crate::tokenizer::tokenize;
+
+        let program =
+            parse(tokenize("fun bench 2 print ret\nfun main 1 print ret").unwrap()).unwrap();
+        let config = VmConfig {
+            entry: Some("bench".to_string()),
+            ..VmConfig::default()
+        };
+        let mut machine = StackMachine::with_config(VecStack::new(), config);
+        let result = machine.run_main(&program);
+        assert_eq!((result.printed, result.error), (vec![2], None));
+    }
+
+    #[test]
+    fn invoke_calls_a_function_by_its_address() {
+        use crate::parser::parse;
+        use crate::tokenizer::tokenize;
+
+        let run = |source| {
+            let program = parse(tokenize(source).unwrap()).unwrap();
+            let result = StackMachine::new(VecStack::new()).run_main(&program);
+            (result.printed, result.error)
+        };
+        let table = "fun inc 1 + ret
+fun dbl 2 * ret
+fun main
+  5 &inc invoke print
+  2 alloc &inc over ! &dbl over 1 + !
+  10 over 1 + @ invoke print pop
+ret";
+        assert_eq!(run(table), (vec![6, 20], None));
+        assert_eq!(
+            run("fun main 99 invoke ret"),
+            (
+                vec![],
+                Some(Error::NotAFunction {
+                    value: 99,
+                    pos: 13,
+                    line: 1
+                })
+            )
+        );
+        assert_eq!(
+            run("fun main try 99 invoke recover print end ret"),
+            (vec![7], None)
+        );
+    }
+
+    #[test]
+    fn run_main_collects_the_outcome() {
+        use crate::parser::parse;
+        use crate::tokenizer::tokenize;
+
+        let program = parse(tokenize("fun main 7 hex 255 print 3 ret").unwrap()).unwrap();
+        let mut machine = StackMachine::new(VecStack::new());
+        assert_eq!(
+            machine.run_main(&program),
+            RunResult {
+                printed: vec![255],
+                output: vec!["ff".to_string()],
+                exit_code: 0,
+                error: None,
+                steps: 6,
+                final_stack: vec![7, 3],
+                crash: None,
+            }
+        );
+
+        let program = parse(tokenize("fun main 1 print 4 0 / ret").unwrap()).unwrap();
+        let mut machine = StackMachine::new(VecStack::new());
+        let result = machine.run_main(&program);
+        assert_eq!(result.printed, vec![1]);
+        assert_eq!(result.exit_code, 2);
+        assert_eq!(result.final_stack, vec![4, 0]);
+        assert_eq!(
+            result.crash,
+            Some(Crash {
+                index: 4,
+                calls: vec![],
+                stack: vec![4, 0],
+            })
+        );
+        assert_eq!(
+            result.into_result(),
+            Err(Error::DivisionByZero { pos: 22, line: 1 })
+        );
+    }
+
+    #[test]
+    fn arithmetic_stops_on_overflow_unless_asked_to_wrap() {
+        use crate::parser::parse;
+        use crate::tokenizer::tokenize;
+
+        let run = |source: &str| {
+            let program = parse(tokenize(source).unwrap()).unwrap();
+            StackMachine::new(VecStack::new()).run_main(&program)
+        };
+        let result =
+            run("fun main 2147483647 1 +w dup print 0 1 - *w print 1 5000 print-padded ret");
+        assert_eq!(result.printed, vec![i32::MIN, i32::MIN, 1]);
+        assert_eq!(result.output[2].len(), MAX_WIDTH);
+
+        let result = run("fun main 2147483647 1 + print ret");
+        assert_eq!(
+            result.into_result(),
+            Err(Error::Overflow { pos: 23, line: 1 })
+        );
+        // The fused `n +` says so just the same, with the values left alone.
+        let result = run("fun main 2147483647 1 + ret");
+        assert_eq!(result.final_stack, vec![i32::MAX, 1]);
+        let result = run("fun main 0 2147483647 - 1 - 0 1 - / ret");
+        assert_eq!(result.final_stack, vec![i32::MIN, -1]);
+        assert!(matches!(result.into_result(), Err(Error::Overflow { .. })));
+    }
+
+    #[test]
+    fn nested_jumps_land_where_they_should() {
+        use crate::checker::validate_program;
+        use crate::parser::parse;
+        use crate::tokenizer::tokenize;
+
+        let cases: &[(&str, &[i32])] = &[
+            ("fun main 1 if 0 if 1 print else 2 print end else 3 print end pop ret", &[2]),
+            ("fun main 0 if 1 print else 1 if 2 print else 3 print end pop end pop ret", &[2]),
+            (
+                "fun main 2 while dup if 10 print else 20 print end 1 - end pop ret",
+                &[10, 10],
+            ),
+            (
+                "fun main 2 while 2 while dup print 1 - end pop 1 - end pop ret",
+                &[2, 1, 2, 1],
+            ),
+            (
+                "fun main 0 if 1 print else 2 while dup print 1 - end pop end pop ret",
+                &[2, 1],
+            ),
+            (
+                "fun main 1 if 2 while 0 if 9 print else 8 print end pop 1 - end pop else end pop ret",
+                &[8, 8],
+            ),
+            (
+                "fun twice dup if 2 * else 1 + end ret
+fun main 3 while dup twice print 1 - end pop 0 twice print ret",
+                &[6, 4, 2, 1],
+            ),
+            (
+                "fun down dup if 1 - down else end ret
+fun main 3 down print 2 while down 7 print end print ret",
+                &[0, 7, 0],
+            ),
+            (
+                "fun main 2 while dup do 2 while dup do dup print 1 - end pop 1 - end pop ret",
+                &[2, 1, 2, 1],
+            ),
+            (
+                "fun main 2 while dup do 1 try 0 / recover pop pop 5 print end 1 - end pop ret",
+                &[5, 5],
+            ),
+            (
+                "fun main 1 if 2 while dup do try 1 0 / recover print end 1 - end else end pop ret",
+                &[2, 2],
+            ),
+            (
+                "fun pick if 1 else 2 end ret
+fun main 0 pick print 5 pick print pop pop ret",
+                &[2, 1],
+            ),
+        ];
+        for (source, printed) in cases {
+            let program = parse(tokenize(source).unwrap()).unwrap();
+            assert_eq!(validate_program(&program), Ok(()), "{}", source);
+            let mut machine = StackMachine::new(VecStack::new());
+            let result = machine.run_main(&program);
+            assert_eq!(result.error, None, "{}", source);
+            assert_eq!(result.printed, *printed, "{}", source);
+        }
+    }
+
+    #[test]
+    fn run_main_refuses_broken_jumps() {
+        use crate::parser::parse;
+        use crate::tokenizer::tokenize;
+
+        let mut program =
+            parse(tokenize("fun main 1 if 2 print else 3 print end ret").unwrap()).unwrap();
+        // 0: 1  1: if  2: 2  3: print  4: else  5: 3  6: print  7: end
+        // Off by one, the `else` would skip to the last `print`.
+        program.instructions[4].instruction_type = InstructionType::Else(6);
+        let mut machine = StackMachine::new(VecStack::new());
+        let result = machine.run_main(&program);
+        assert_eq!(result.printed, vec![]);
+        assert_eq!(result.exit_code, -1);
+        assert!(matches!(result.error, Some(Error::StaticCheck { .. })));
+    }
+
+    #[test]
+    fn asm_is_refused_before_anything_runs() {
+        use crate::parser::parse;
+        use crate::tokenizer::tokenize;
+
+        let program = parse(tokenize("fun main 1 print\n  asm \"nop\" end ret").unwrap()).unwrap();
+        let result = StackMachine::new(VecStack::new()).run_main(&program);
+        assert_eq!(result.printed, vec![]);
+        assert_eq!(
+            result.error,
+            Some(Error::UnsupportedInstruction {
+                word: "asm".to_string(),
+                pos: 3,
+                line: 2
+            })
+        );
+        let mut machine = StackMachine::new(VecStack::new());
+        assert!(matches!(
+            machine.call(&program, "main"),
+            Err(Error::UnsupportedInstruction { .. })
+        ));
+        assert_eq!(machine.take_printed(), vec![1]);
+    }
+
+    #[test]
+    fn do_loops_take_their_condition() {
+        use crate::parser::parse;
+        use crate::tokenizer::tokenize;
+
+        let program = parse(
+            tokenize("fun main 3 while dup do dup print 1 - end print 0 while dup do end ret")
+                .unwrap(),
+        )
+        .unwrap();
+        let mut machine = StackMachine::new(VecStack::new());
+        let result = machine.run_main(&program);
+        assert_eq!(result.printed, vec![3, 2, 1, 0]);
+        assert_eq!(result.final_stack, vec![0]);
+
+        let program = parse(tokenize("fun main while do end ret").unwrap()).unwrap();
+        let mut machine = StackMachine::new(VecStack::new());
+        assert_eq!(
+            machine.run_main(&program).error,
+            Some(Error::StackEmpty { pos: 16, line: 1 })
+        );
+    }
+
+    #[test]
+    fn fixed_point_words() {
+        use crate::parser::parse;
+        use crate::tokenizer::tokenize;
+
+        let program = parse(
+            tokenize(
+                "fun main 3 int>fx 2 int>fx fx/ dup fx. hex 3 int>fx fx* dup fx. fx>int print ret",
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let mut machine = StackMachine::new(VecStack::new());
+        let result = machine.run_main(&program);
+        assert_eq!(result.output, vec!["1.5", "4.5", "4"]);
+
+        let program = parse(tokenize("fun main 200 int>fx dup fx* ret").unwrap()).unwrap();
+        let mut machine = StackMachine::new(VecStack::new());
+        let result = machine.run_main(&program);
+        assert_eq!(result.exit_code, 5);
+        assert_eq!(result.final_stack, vec![200 << 16, 200 << 16]);
+        assert_eq!(
+            result.into_result(),
+            Err(Error::Overflow { pos: 25, line: 1 })
+        );
+    }
+
+    #[test]
+    fn eprint_goes_to_the_error_sink() {
+        use crate::parser::parse;
+        use crate::tokenizer::tokenize;
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let program =
+            parse(tokenize("fun main 1 eprint 2 print 10 hex eprint ret").unwrap()).unwrap();
+        let mut machine = StackMachine::new(VecStack::new());
+        let errors = Rc::new(RefCell::new(vec![]));
+        let sink = errors.clone();
+        machine.set_error_sink(move |line| sink.borrow_mut().push(line.to_string()));
+        machine.call(&program, "main").unwrap();
+        assert_eq!(*errors.borrow(), vec!["1", "a"]);
+        assert_eq!(machine.take_output(), vec!["2"]);
+    }
+
+    #[test]
+    fn errors_trap_to_on_error() {
+        use crate::parser::parse;
+        use crate::tokenizer::tokenize;
+
+        let source = "fun on_error print 0 ret
+fun main 7 0 / print print print + 9 print ret";
+        let config = VmConfig {
+            on_error: ErrorPolicy::Trap,
+            ..VmConfig::default()
+        };
+        let mut machine = StackMachine::with_config(VecStack::new(), config);
+        let program = parse(tokenize(source).unwrap()).unwrap();
+        machine.call(&program, "main").unwrap();
+        // The division leaves 7 0 where they were, under the handler's 0.
+        assert_eq!(machine.take_printed(), vec![2, 0, 0, 7, 1, 9]);
+        assert_eq!(machine.stack.as_slice(), &[0]);
+
+        let mut machine = StackMachine::new(VecStack::new());
+        assert_eq!(
+            machine.execute(parse(tokenize(source).unwrap()).unwrap()),
+            Err(Error::DivisionByZero { pos: 14, line: 2 })
+        );
+    }
+
+    #[test]
+    fn failing_handler_and_limits_are_not_trapped() {
+        use crate::parser::parse;
+        use crate::tokenizer::tokenize;
+
+        let config = VmConfig {
+            on_error: ErrorPolicy::Trap,
+            max_steps: Some(20),
+            ..VmConfig::default()
+        };
+        let mut machine = StackMachine::with_config(VecStack::new(), config.clone());
+        let program = parse(tokenize("fun on_error pop pop ret\nfun main + ret").unwrap()).unwrap();
+        assert_eq!(
+            machine.call(&program, "main"),
+            Err(Error::StackEmpty { pos: 18, line: 1 })
+        );
+
+        let mut machine = StackMachine::with_config(VecStack::new(), config);
+        let program =
+            parse(tokenize("fun on_error pop ret\nfun main 1 while end ret").unwrap()).unwrap();
+        assert!(matches!(
+            machine.call(&program, "main"),
+            Err(Error::StepLimitExceeded { .. })
+        ));
+    }
+
+    #[test]
+    fn try_comes_before_on_error_and_limits_are_not_caught() {
+        use crate::parser::parse;
+        use crate::tokenizer::tokenize;
+
+        let config = VmConfig {
+            on_error: ErrorPolicy::Trap,
+            max_steps: Some(30),
+            ..VmConfig::default()
+        };
+        let program = parse(
+            tokenize("fun on_error 100 + print ret\nfun main try + recover print end + ret")
+                .unwrap(),
+        )
+        .unwrap();
+        let mut machine = StackMachine::with_config(VecStack::new(), config.clone());
+        machine.call(&program, "main").unwrap();
+        assert_eq!(machine.take_printed(), vec![1, 101]);
+
+        let program = parse(tokenize("fun main try 1 while end recover end ret").unwrap()).unwrap();
+        let mut machine = StackMachine::with_config(VecStack::new(), config);
+        assert!(matches!(
+            machine.call(&program, "main"),
+            Err(Error::StepLimitExceeded { .. })
+        ));
     }
 }
@@ -0,0 +1,250 @@
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use crate::bytecode::{mnemonic, opcode, OPCODES};
+use crate::checker::depth_change;
+use crate::parser::InstructionType;
+use crate::stack_machine::Program;
+
+// Counters kept by the machine while `VmConfig::stats` is on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stats {
+    pub instructions: usize,
+    // Indexed by opcode number, see `bytecode::mnemonic`.
+    pub opcodes: [usize; OPCODES],
+    // By the index the function starts at.
+    pub calls: BTreeMap<usize, usize>,
+    // How often each instruction ran, by its index.
+    pub executed: Vec<usize>,
+    pub max_depth: usize,
+    pub max_call_depth: usize,
+}
+
+// Arrays this long have no derived `Default`.
+impl Default for Stats {
+    fn default() -> Self {
+        Self {
+            instructions: 0,
+            opcodes: [0; OPCODES],
+            calls: BTreeMap::new(),
+            executed: Vec::new(),
+            max_depth: 0,
+            max_call_depth: 0,
+        }
+    }
+}
+
+impl Stats {
+    pub fn count(
+        &mut self,
+        index: usize,
+        instruction: &InstructionType,
+        depth: usize,
+        call_depth: usize,
+    ) {
+        self.instructions += 1;
+        if self.executed.len() <= index {
+            self.executed.resize(index + 1, 0);
+        }
+        self.executed[index] += 1;
+        self.opcodes[opcode(instruction).0 as usize] += 1;
+        if let InstructionType::Call(start) = instruction {
+            *self.calls.entry(*start).or_default() += 1;
+        }
+        self.max_depth = self.max_depth.max(depth);
+        self.max_call_depth = self.max_call_depth.max(call_depth);
+    }
+}
+
+// Biggest counts first, ties by name.
+fn table(rows: &mut [(String, usize)]) -> String {
+    rows.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    let width = rows.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+    rows.iter()
+        .map(|(name, count)| format!("  {:<width$}  {}\n", name, count, width = width))
+        .collect()
+}
+
+pub fn report(stats: &Stats, program: &Program, elapsed: Duration) -> String {
+    let mut out = format!(
+        "instructions executed: {}\nwall time: {:.3} ms\nmax stack depth: {}\nmax call depth: {}\n",
+        stats.instructions,
+        elapsed.as_secs_f64() * 1000.0,
+        stats.max_depth,
+        stats.max_call_depth
+    );
+    let mut opcodes: Vec<(String, usize)> = stats
+        .opcodes
+        .iter()
+        .enumerate()
+        .filter(|(_, count)| **count > 0)
+        .filter_map(|(op, count)| Some((mnemonic(op as u8)?.to_string(), *count)))
+        .collect();
+    if !opcodes.is_empty() {
+        out.push_str("opcodes:\n");
+        out.push_str(&table(&mut opcodes));
+    }
+    let mut calls: Vec<(String, usize)> = stats
+        .calls
+        .iter()
+        .map(|(start, count)| {
+            let name = program
+                .symbol(*start)
+                .map_or_else(|| format!("@{}", start), String::from);
+            (name, *count)
+        })
+        .collect();
+    if !calls.is_empty() {
+        out.push_str("calls:\n");
+        out.push_str(&table(&mut calls));
+    }
+    out
+}
+
+// How big a program is, without running it: what `rorth stats` prints.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Size {
+    pub instructions: usize,
+    // Name, instructions and deepest nesting of each function, in the
+    // order they start in.
+    pub functions: Vec<(String, usize, usize)>,
+}
+
+// How many `while`, `if` and `try` blocks are open around each
+// instruction, at most.
+fn nesting(instructions: &[InstructionType]) -> usize {
+    let (mut depth, mut deepest) = (0isize, 0);
+    for instruction in instructions {
+        depth += depth_change(instruction);
+        deepest = deepest.max(depth);
+    }
+    deepest as usize
+}
+
+pub fn size(program: &Program) -> Size {
+    let types: Vec<InstructionType> = program
+        .instructions
+        .iter()
+        .map(|instruction| instruction.instruction_type)
+        .collect();
+    let functions = program
+        .spans()
+        .into_iter()
+        .map(|(name, span)| (name.to_string(), span.len(), nesting(&types[span])))
+        .collect();
+    Size {
+        instructions: types.len(),
+        functions,
+    }
+}
+
+pub fn size_report(size: &Size) -> String {
+    let mut out = format!(
+        "instructions: {}\nfunctions: {}\n",
+        size.instructions,
+        size.functions.len()
+    );
+    // The first one wins a tie.
+    let longest = size.functions.iter().rev().max_by_key(|f| f.1);
+    if let Some((name, instructions, _)) = longest {
+        out.push_str(&format!(
+            "longest function: {} ({} instructions)\n",
+            name, instructions
+        ));
+    }
+    let deepest = size.functions.iter().rev().max_by_key(|f| f.2);
+    if let Some((name, _, nesting)) = deepest.filter(|f| f.2 > 0) {
+        out.push_str(&format!("deepest nesting: {} (in {})\n", nesting, name));
+    }
+    let mut rows: Vec<(String, usize)> = size
+        .functions
+        .iter()
+        .map(|(name, instructions, _)| (name.clone(), *instructions))
+        .collect();
+    if !rows.is_empty() {
+        out.push_str("instructions by function:\n");
+        out.push_str(&table(&mut rows));
+    }
+    out
+}
+
+#[cfg(test)]
+mod stats_tests {
+    use super::*;
+    use crate::parser::parse;
+    use crate::stack::VecStack;
+    use crate::stack_machine::{StackMachine, VmConfig};
+    use crate::tokenizer::tokenize;
+
+    fn program() -> Program {
+        parse(tokenize("fun sq dup * ret\nfun main 2 sq sq 3 sq + print ret").unwrap()).unwrap()
+    }
+
+    #[test]
+    fn counts_a_run() {
+        let config = VmConfig {
+            stats: true,
+            ..VmConfig::default()
+        };
+        let mut machine = StackMachine::with_config(VecStack::new(), config);
+        machine.execute(program()).unwrap();
+        let stats = &machine.stats;
+        assert_eq!(stats.instructions, machine.steps);
+        assert_eq!(stats.max_depth, 3);
+        assert_eq!(stats.max_call_depth, 1);
+        // `dup *` ran once for each of the three calls.
+        assert_eq!(stats.executed[..2], [3, 3]);
+        assert_eq!(stats.executed.iter().sum::<usize>(), 17);
+
+        assert_eq!(
+            report(stats, &program(), Duration::from_micros(1500)),
+            "instructions executed: 17
+wall time: 1.500 ms
+max stack depth: 3
+max call depth: 1
+opcodes:
+  ret    4
+  *      3
+  call   3
+  dup    3
+  push   2
+  +      1
+  print  1
+calls:
+  sq  3
+"
+        );
+    }
+
+    #[test]
+    fn sizes_a_program() {
+        let source = "fun sq dup * ret
+fun count 3 while dup 2 divmod pop if dup print else end 1 - end pop ret
+fun main count 2 sq print ret";
+        let size = size(&parse(tokenize(source).unwrap()).unwrap());
+        assert_eq!(size.functions[1].2, 2);
+        assert_eq!(
+            size_report(&size),
+            "instructions: 24
+functions: 3
+longest function: count (16 instructions)
+deepest nesting: 2 (in count)
+instructions by function:
+  count  16
+  main   5
+  sq     3
+"
+        );
+    }
+
+    #[test]
+    fn off_by_default() {
+        let program = parse(tokenize("fun main 1 print ret").unwrap()).unwrap();
+        let mut machine = StackMachine::new(VecStack::new());
+        machine.execute(program).unwrap();
+        assert_eq!(machine.stats, Stats::default());
+    }
+}
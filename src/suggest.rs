@@ -0,0 +1,71 @@
+// "Did you mean" for names that don't resolve.
+use alloc::vec;
+use alloc::vec::Vec;
+
+// The edits that turn `a` into `b`, one character inserted, deleted or
+// replaced at a time. Two neighbours swapped count as one edit, as that is
+// the typo people make most.
+pub fn distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    // Three rows of the table: two back, the previous one and this one.
+    let mut before: Vec<usize> = (0..=b.len()).collect();
+    let mut previous = before.clone();
+    let mut row = vec![0; b.len() + 1];
+    for i in 1..=a.len() {
+        row[0] = i;
+        for j in 1..=b.len() {
+            let replace = previous[j - 1] + usize::from(a[i - 1] != b[j - 1]);
+            row[j] = replace.min(previous[j] + 1).min(row[j - 1] + 1);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                row[j] = row[j].min(before[j - 2] + 1);
+            }
+        }
+        core::mem::swap(&mut before, &mut previous);
+        core::mem::swap(&mut previous, &mut row);
+    }
+    previous[b.len()]
+}
+
+// The candidate nearest to `name`, when it is near enough to be what was
+// meant: one edit for every three characters, and at least one, but never
+// every character. The first of equally near ones wins.
+pub fn closest<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    let length = name.chars().count();
+    let limit = (length / 3).max(1).min(length.saturating_sub(1));
+    let mut best: Option<(usize, &str)> = None;
+    for candidate in candidates {
+        let distance = distance(name, candidate);
+        if distance <= limit && best.is_none_or(|(nearest, _)| distance < nearest) {
+            best = Some((distance, candidate));
+        }
+    }
+    best.map(|(_, candidate)| candidate)
+}
+
+#[cfg(test)]
+mod suggest_tests {
+    use super::*;
+
+    #[test]
+    fn swaps_count_once() {
+        assert_eq!(distance("print", "print"), 0);
+        assert_eq!(distance("pritn", "print"), 1);
+        assert_eq!(distance("prnt", "print"), 1);
+        assert_eq!(distance("dupp", "dup"), 1);
+        assert_eq!(distance("swap", "over"), 4);
+        assert_eq!(distance("", "rot"), 3);
+    }
+
+    #[test]
+    fn only_near_names_are_suggested() {
+        let words = ["dup", "print", "eprint", "square"];
+        assert_eq!(closest("pritn", words), Some("print"));
+        assert_eq!(closest("eprnt", words), Some("eprint"));
+        assert_eq!(closest("sqaure", words), Some("square"));
+        assert_eq!(closest("du", words), Some("dup"));
+        assert_eq!(closest("xyz", words), None);
+        assert_eq!(closest("nope", words), None);
+        assert_eq!(closest("a", ["b", "ab"]), None);
+    }
+}
@@ -0,0 +1,737 @@
+// `rorth symexec`: runs a function on values it doesn't know, following
+// every way `if` and the loops can go, and looks for inputs that make a word
+// fail or take more values than there are. The inputs are the values the
+// function takes from the stack and whatever `rand` and `time` return.
+// Paths are cut short after a number of steps, and a finding is only
+// reported with inputs found to trigger it, so this finds bugs rather than
+// proving there are none.
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::checker::infer_stack_effects;
+use crate::common::Error;
+use crate::parser::{Instruction, InstructionType};
+use crate::rng::{Rng, DETERMINISTIC_SEED};
+use crate::stack::{Stack, VecStack};
+use crate::stack_machine::Program;
+use crate::words::{self, Fault, Op};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    // Unknown values on the stack when the function starts. By default as
+    // many as `checker::infer_stack_effects` says it takes, or none.
+    pub inputs: Option<usize>,
+    // Steps along one path before it is cut short.
+    pub max_steps: usize,
+    // Paths followed in all.
+    pub max_paths: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            inputs: None,
+            max_steps: 1000,
+            max_paths: 10_000,
+        }
+    }
+}
+
+// A failure and inputs that lead to it, by name, in the order the program
+// met them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub error: Error,
+    pub inputs: Vec<(String, i32)>,
+}
+
+impl Finding {
+    pub fn inputs(&self) -> String {
+        if self.inputs.is_empty() {
+            return "whatever the inputs".to_string();
+        }
+        let inputs: Vec<String> = self
+            .inputs
+            .iter()
+            .map(|(name, value)| format!("{} = {}", name, value))
+            .collect();
+        format!("when {}", inputs.join(", "))
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Report {
+    // One per instruction and kind of failure, the first way found.
+    pub findings: Vec<Finding>,
+    pub paths: usize,
+    // Paths that ran out of steps, or reached a word from a plugin.
+    pub cut_short: usize,
+}
+
+// An index into `Explorer::values`. Values only ever refer to earlier ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct Value(usize);
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Const(i32),
+    Input(usize),
+    // A word with an `eval` applied to earlier values.
+    Word(InstructionType, Vec<Value>),
+}
+
+// What a path knows about its values.
+#[derive(Debug, Clone, Copy)]
+enum Fact {
+    Zero(Value),
+    NonZero(Value),
+    Runs(Value),
+    Fails(Value),
+}
+
+#[derive(Clone)]
+struct Handler {
+    recover: usize,
+    calls: usize,
+    depth: usize,
+}
+
+#[derive(Clone)]
+struct Path {
+    idx: usize,
+    stack: Vec<Value>,
+    calls: Vec<usize>,
+    handlers: Vec<Handler>,
+    facts: Vec<Fact>,
+    // The inputs this path has met, and values for every input that meet
+    // its facts.
+    inputs: Vec<usize>,
+    witness: Vec<i32>,
+    steps: usize,
+}
+
+enum Outcome {
+    Next,
+    Done,
+    Cut,
+}
+
+// Assignments tried on top of the interesting values before giving up on
+// a set of facts.
+const RANDOM_TRIES: usize = 200;
+const SEARCH_BUDGET: usize = 20_000;
+
+struct Explorer<'a> {
+    program: &'a Program,
+    limits: Limits,
+    values: Vec<Expr>,
+    names: Vec<String>,
+    // Values worth trying for an input: small ones, the edges, and the
+    // constants in the program and their neighbours.
+    pool: Vec<i32>,
+    rng: Rng,
+    queue: Vec<Path>,
+    seen: BTreeSet<(usize, String)>,
+    report: Report,
+}
+
+pub fn explore(program: &Program, function: &str, limits: Limits) -> Result<Report, Error> {
+    let start = *program
+        .functions
+        .get(function)
+        .ok_or_else(|| Error::FunctionNotFound {
+            name: function.to_string(),
+        })?;
+    let inputs = limits.inputs.unwrap_or_else(|| {
+        infer_stack_effects(program)
+            .get(function)
+            .map_or(0, |effect| effect.inputs)
+    });
+    let mut explorer = Explorer::new(program, limits);
+    let stack = (1..=inputs)
+        .map(|n| explorer.input(format!("x{}", n)))
+        .collect();
+    explorer.queue.push(Path {
+        idx: start,
+        stack,
+        calls: vec![],
+        handlers: vec![],
+        facts: vec![],
+        inputs: (0..inputs).collect(),
+        witness: vec![0; inputs],
+        steps: 0,
+    });
+    explorer.run();
+    Ok(explorer.report)
+}
+
+impl<'a> Explorer<'a> {
+    fn new(program: &'a Program, limits: Limits) -> Self {
+        let mut pool = vec![0, 1, -1, 2, -2, i32::MAX, i32::MIN];
+        for instruction in &program.instructions {
+            if let InstructionType::Push(n) = instruction.instruction_type {
+                for value in [n, n.wrapping_add(1), n.wrapping_sub(1), n.wrapping_neg()] {
+                    if !pool.contains(&value) {
+                        pool.push(value);
+                    }
+                }
+            }
+        }
+        Self {
+            program,
+            limits,
+            values: vec![],
+            names: vec![],
+            pool,
+            rng: Rng::new(DETERMINISTIC_SEED),
+            queue: vec![],
+            seen: BTreeSet::new(),
+            report: Report::default(),
+        }
+    }
+
+    fn run(&mut self) {
+        while let Some(mut path) = self.queue.pop() {
+            self.report.paths += 1;
+            loop {
+                if path.steps >= self.limits.max_steps {
+                    self.report.cut_short += 1;
+                    break;
+                }
+                path.steps += 1;
+                match self.step(&mut path) {
+                    Outcome::Next => {}
+                    Outcome::Done => break,
+                    Outcome::Cut => {
+                        self.report.cut_short += 1;
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    fn value(&mut self, expr: Expr) -> Value {
+        self.values.push(expr);
+        Value(self.values.len() - 1)
+    }
+
+    fn constant(&mut self, n: i32) -> Value {
+        self.value(Expr::Const(n))
+    }
+
+    fn input(&mut self, name: String) -> Value {
+        self.names.push(name);
+        self.value(Expr::Input(self.names.len() - 1))
+    }
+
+    fn known(&self, value: Value) -> Option<i32> {
+        match self.values[value.0] {
+            Expr::Const(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    // Another path to follow, if there are paths left.
+    fn fork(&mut self, path: Path) {
+        if self.report.paths + self.queue.len() < self.limits.max_paths {
+            self.queue.push(path);
+        }
+    }
+
+    fn step(&mut self, path: &mut Path) -> Outcome {
+        let Some(instruction) = self.program.instructions.get(path.idx) else {
+            return Outcome::Done;
+        };
+        use InstructionType::*;
+        match instruction.instruction_type {
+            While(jump) | If(jump) => {
+                let Some(&top) = path.stack.last() else {
+                    return self.empty(path, instruction);
+                };
+                self.branch(path, top, jump + 1, path.idx + 1)
+            }
+            EndWhile(jump) => {
+                let Some(&top) = path.stack.last() else {
+                    return self.empty(path, instruction);
+                };
+                self.branch(path, top, path.idx + 1, jump + 1)
+            }
+            Do(jump) => {
+                let Some(top) = path.stack.pop() else {
+                    return self.empty(path, instruction);
+                };
+                self.branch(path, top, jump + 1, path.idx + 1)
+            }
+            Else(jump) | EndDo(jump) | Recover(jump) => {
+                if let Recover(_) = instruction.instruction_type {
+                    path.handlers.pop();
+                }
+                path.idx = jump + 1;
+                Outcome::Next
+            }
+            Loop(_) | EndIf | EndTry | Hex | Bin | Dec => {
+                path.idx += 1;
+                Outcome::Next
+            }
+            Try(recover) => {
+                path.handlers.push(Handler {
+                    recover,
+                    calls: path.calls.len(),
+                    depth: path.stack.len(),
+                });
+                path.idx += 1;
+                Outcome::Next
+            }
+            Call(start) => {
+                path.calls.push(path.idx);
+                path.idx = start;
+                Outcome::Next
+            }
+            // Followed when the function is known; the machine checks the
+            // rest.
+            Invoke => {
+                let start = path.stack.last().and_then(|v| self.known(*v));
+                match start.and_then(|n| usize::try_from(n).ok()) {
+                    Some(start) if self.program.symbols.contains_key(&start) => {
+                        path.stack.pop();
+                        path.calls.push(path.idx);
+                        path.idx = start;
+                        Outcome::Next
+                    }
+                    _ => Outcome::Cut,
+                }
+            }
+            Ret => {
+                let Some(call) = path.calls.pop() else {
+                    return Outcome::Done;
+                };
+                while path
+                    .handlers
+                    .last()
+                    .is_some_and(|h| h.calls > path.calls.len())
+                {
+                    path.handlers.pop();
+                }
+                path.idx = call + 1;
+                Outcome::Next
+            }
+            Push(n) => {
+                let value = self.constant(n);
+                path.stack.push(value);
+                path.idx += 1;
+                Outcome::Next
+            }
+            Address(start) => {
+                let value = self.constant(start as i32);
+                path.stack.push(value);
+                path.idx += 1;
+                Outcome::Next
+            }
+            Rand | Time | Env(_) => {
+                let name = format!(
+                    "{}@{}:{}",
+                    instruction.instruction_type, instruction.line, instruction.pos
+                );
+                let value = self.input(name);
+                path.inputs.push(self.names.len() - 1);
+                path.stack.push(value);
+                path.idx += 1;
+                Outcome::Next
+            }
+            Print | Eprint | FxPrint | PrintPadded => {
+                let takes = if instruction.instruction_type == PrintPadded {
+                    2
+                } else {
+                    1
+                };
+                if path.stack.len() < takes {
+                    return self.empty(path, instruction);
+                }
+                path.stack.truncate(path.stack.len() - takes);
+                path.idx += 1;
+                Outcome::Next
+            }
+            // What a pack does is up to the pack.
+            Native(_) => Outcome::Cut,
+            _ => self.word(path, instruction),
+        }
+    }
+
+    // Words from the table that only work on the stack: the ones that move
+    // values around do so here, the others make a new value.
+    fn word(&mut self, path: &mut Path, instruction: &Instruction) -> Outcome {
+        use InstructionType::*;
+        let Some((_, op)) = words::op(&instruction.instruction_type) else {
+            return Outcome::Cut;
+        };
+        let Some(effect) = op.effect else {
+            return Outcome::Cut;
+        };
+        if path.stack.len() < effect.inputs {
+            return self.empty(path, instruction);
+        }
+        let inputs = path.stack.split_off(path.stack.len() - effect.inputs);
+        let moved = match (instruction.instruction_type, inputs.as_slice()) {
+            (Pop, [_]) => Some(vec![]),
+            (Dup, [a]) => Some(vec![*a, *a]),
+            (Swap, [a, b]) => Some(vec![*b, *a]),
+            (Rot, [a, b, c]) => Some(vec![*b, *c, *a]),
+            (Over, [a, b]) => Some(vec![*a, *b, *a]),
+            (Nip, [_, b]) => Some(vec![*b]),
+            _ => None,
+        };
+        if let Some(moved) = moved {
+            path.stack.extend(moved);
+            path.idx += 1;
+            return Outcome::Next;
+        }
+        if effect.outputs != 1 || op.eval.is_none() {
+            return Outcome::Cut;
+        }
+        let known: Option<Vec<i32>> = inputs.iter().map(|v| self.known(*v)).collect();
+        if let Some(known) = known {
+            return match apply(op, &known) {
+                Ok(n) => {
+                    let value = self.constant(n);
+                    path.stack.push(value);
+                    path.idx += 1;
+                    Outcome::Next
+                }
+                Err(fault) => {
+                    let error = fault_error(fault, instruction);
+                    path.stack.extend(inputs);
+                    self.fail(path, error)
+                }
+            };
+        }
+        let value = self.value(Expr::Word(instruction.instruction_type, inputs.clone()));
+        let mut failing = path.clone();
+        if self.assume(&mut failing, Fact::Fails(value)) {
+            failing.stack.extend(inputs);
+            let fault = self
+                .evaluate(value, &failing.witness, &mut BTreeMap::new())
+                .expect_err("the witness makes it fail");
+            let error = fault_error(fault, instruction);
+            if let Outcome::Next = self.fail(&mut failing, error) {
+                self.fork(failing);
+            }
+        }
+        if !self.assume(path, Fact::Runs(value)) {
+            return Outcome::Done;
+        }
+        path.stack.push(value);
+        path.idx += 1;
+        Outcome::Next
+    }
+
+    // Goes on at `zero` or `nonzero` depending on `value`, or both ways
+    // when either could happen.
+    fn branch(&mut self, path: &mut Path, value: Value, zero: usize, nonzero: usize) -> Outcome {
+        if let Some(n) = self.known(value) {
+            path.idx = if n == 0 { zero } else { nonzero };
+            return Outcome::Next;
+        }
+        let mut other = path.clone();
+        if self.assume(&mut other, Fact::Zero(value)) {
+            other.idx = zero;
+            self.fork(other);
+        }
+        if !self.assume(path, Fact::NonZero(value)) {
+            return Outcome::Done;
+        }
+        path.idx = nonzero;
+        Outcome::Next
+    }
+
+    fn empty(&mut self, path: &mut Path, instruction: &Instruction) -> Outcome {
+        let error = Error::StackEmpty {
+            pos: instruction.pos,
+            line: instruction.line,
+        };
+        self.fail(path, error)
+    }
+
+    // An error on this path: caught by the innermost `try`, if there is
+    // one, and a finding if not.
+    fn fail(&mut self, path: &mut Path, error: Error) -> Outcome {
+        if let Some(handler) = path.handlers.pop() {
+            path.calls.truncate(handler.calls);
+            path.stack.truncate(handler.depth);
+            let code = self.constant(error.code().unwrap_or_default());
+            path.stack.push(code);
+            path.idx = handler.recover + 1;
+            return Outcome::Next;
+        }
+        let (line, pos) = error.position().unwrap_or_default();
+        let key = (line << 16 | pos, error.to_string());
+        if self.seen.insert(key) {
+            let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+            let inputs = path
+                .inputs
+                .iter()
+                .map(|&input| {
+                    let name = self.names[input].as_str();
+                    let count = counts.entry(name).or_default();
+                    *count += 1;
+                    let name = match *count {
+                        1 => name.to_string(),
+                        n => format!("{}#{}", name, n),
+                    };
+                    (name, path.witness.get(input).copied().unwrap_or_default())
+                })
+                .collect();
+            self.report.findings.push(Finding { error, inputs });
+        }
+        Outcome::Done
+    }
+
+    // Adds `fact` to what `path` knows, when some inputs make all of it
+    // hold, and keeps those inputs as its witness.
+    fn assume(&mut self, path: &mut Path, fact: Fact) -> bool {
+        path.facts.push(fact);
+        path.witness.resize(self.names.len(), 0);
+        if self.holds(&path.facts, &path.witness) {
+            return true;
+        }
+        if let Some(witness) = self.nearby(&path.facts, &path.inputs, &path.witness, fact) {
+            path.witness = witness;
+            return true;
+        }
+        match self.solve(&path.facts, &path.inputs) {
+            Some(witness) => {
+                path.witness = witness;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn holds(&self, facts: &[Fact], assignment: &[i32]) -> bool {
+        let mut memo = BTreeMap::new();
+        facts.iter().all(|fact| match *fact {
+            Fact::Zero(v) => self.evaluate(v, assignment, &mut memo) == Ok(0),
+            Fact::NonZero(v) => matches!(self.evaluate(v, assignment, &mut memo), Ok(n) if n != 0),
+            Fact::Runs(v) => self.evaluate(v, assignment, &mut memo).is_ok(),
+            Fact::Fails(v) => self.evaluate(v, assignment, &mut memo).is_err(),
+        })
+    }
+
+    // The witness with one input changed: to each interesting value, or to
+    // where the new fact's value crosses zero if it moves in a straight
+    // line with that input, as a counter does.
+    fn nearby(
+        &self,
+        facts: &[Fact],
+        inputs: &[usize],
+        witness: &[i32],
+        fact: Fact,
+    ) -> Option<Vec<i32>> {
+        let mut assignment = witness.to_vec();
+        for input in inputs {
+            let root = match fact {
+                Fact::Zero(value) => self.root(value, *input, &mut assignment),
+                _ => None,
+            };
+            for candidate in root.iter().chain(&self.pool) {
+                assignment[*input] = *candidate;
+                if self.holds(facts, &assignment) {
+                    return Some(assignment);
+                }
+            }
+            assignment[*input] = witness[*input];
+        }
+        None
+    }
+
+    // Where `value` is zero, if it is `a * input + b` for whole `a` and `b`.
+    fn root(&self, value: Value, input: usize, assignment: &mut [i32]) -> Option<i32> {
+        let mut at = |x: i32| {
+            assignment[input] = x;
+            self.evaluate(value, assignment, &mut BTreeMap::new()).ok()
+        };
+        let (b, a) = (at(0)?, at(1)?.checked_sub(at(0)?)?);
+        if a == 0 || b.checked_rem(a)? != 0 {
+            return None;
+        }
+        let x = b.checked_neg()?.checked_div(a)?;
+        (at(x)? == 0).then_some(x)
+    }
+
+    // Inputs that make every fact hold: first the interesting values in
+    // every combination, as far as the budget goes, then random ones.
+    fn solve(&mut self, facts: &[Fact], inputs: &[usize]) -> Option<Vec<i32>> {
+        let mut assignment = vec![0; self.names.len()];
+        let mut digits = vec![0; inputs.len()];
+        for _ in 0..SEARCH_BUDGET {
+            for (digit, input) in digits.iter().zip(inputs) {
+                assignment[*input] = self.pool[*digit];
+            }
+            if self.holds(facts, &assignment) {
+                return Some(assignment);
+            }
+            // The next combination, or on to random ones after the last.
+            let Some(i) = digits.iter().position(|d| d + 1 < self.pool.len()) else {
+                break;
+            };
+            digits[i] += 1;
+            digits[..i].iter_mut().for_each(|d| *d = 0);
+        }
+        for _ in 0..RANDOM_TRIES {
+            for input in inputs {
+                let pick = self.rng.next_u64() as usize % (self.pool.len() * 2);
+                assignment[*input] = match self.pool.get(pick) {
+                    Some(n) => *n,
+                    None => self.rng.next_i32(),
+                };
+            }
+            if self.holds(facts, &assignment) {
+                return Some(assignment);
+            }
+        }
+        None
+    }
+
+    fn evaluate(
+        &self,
+        value: Value,
+        assignment: &[i32],
+        memo: &mut BTreeMap<Value, Result<i32, Fault>>,
+    ) -> Result<i32, Fault> {
+        if let Some(known) = memo.get(&value) {
+            return known.clone();
+        }
+        let result = match &self.values[value.0] {
+            Expr::Const(n) => Ok(*n),
+            Expr::Input(i) => Ok(assignment.get(*i).copied().unwrap_or_default()),
+            Expr::Word(instruction, inputs) => {
+                let (_, op) = words::op(instruction).expect("only words from the table");
+                inputs
+                    .iter()
+                    .map(|v| self.evaluate(*v, assignment, memo))
+                    .collect::<Result<Vec<i32>, Fault>>()
+                    .and_then(|inputs| apply(op, &inputs))
+            }
+        };
+        memo.insert(value, result.clone());
+        result
+    }
+}
+
+fn apply(op: &Op, inputs: &[i32]) -> Result<i32, Fault> {
+    let eval = op.eval.expect("only words with an eval");
+    let mut stack = VecStack::new();
+    inputs.iter().for_each(|n| stack.push(*n));
+    eval(&mut stack)?;
+    Ok(stack.pop().unwrap_or_default())
+}
+
+fn fault_error(fault: Fault, instruction: &Instruction) -> Error {
+    let (pos, line) = (instruction.pos, instruction.line);
+    match fault {
+        Fault::DivisionByZero => Error::DivisionByZero { pos, line },
+        Fault::Overflow => Error::Overflow { pos, line },
+        Fault::Failed(comment) => Error::Plugin {
+            word: instruction.instruction_type.to_string(),
+            comment,
+            pos,
+            line,
+        },
+    }
+}
+
+#[cfg(test)]
+mod symexec_tests {
+    use super::*;
+    use crate::parser::parse;
+    use crate::tokenizer::tokenize;
+
+    fn explored(source: &str, function: &str, limits: Limits) -> Report {
+        let program = parse(tokenize(source).unwrap()).unwrap();
+        explore(&program, function, limits).unwrap()
+    }
+
+    fn findings(source: &str, function: &str) -> Vec<String> {
+        explored(source, function, Limits::default())
+            .findings
+            .iter()
+            .map(|f| format!("{} {}", f.error, f.inputs()))
+            .collect()
+    }
+
+    #[test]
+    fn finds_inputs_that_divide_by_zero() {
+        assert_eq!(
+            findings("fun f 10 swap 3 - / ret", "f"),
+            vec![
+                "1:17: arithmetic overflow when x1 = -2147483648",
+                "1:19: division by zero when x1 = 3"
+            ]
+        );
+        assert_eq!(
+            findings("fun f 10 swap 3 - if / else pop pop 0 end ret", "f"),
+            vec!["1:17: arithmetic overflow when x1 = -2147483648"]
+        );
+    }
+
+    #[test]
+    fn finds_paths_that_run_out_of_values() {
+        // Both branches take the one value there is.
+        let limits = Limits {
+            inputs: Some(1),
+            ..Limits::default()
+        };
+        let report = explored("fun f if print else pop end print ret", "f", limits);
+        assert_eq!(
+            report.findings,
+            vec![Finding {
+                error: Error::StackEmpty { pos: 29, line: 1 },
+                inputs: vec![("x1".to_string(), 1)],
+            }]
+        );
+        assert_eq!(report.paths, 2);
+    }
+
+    #[test]
+    fn random_values_are_inputs_too() {
+        assert_eq!(
+            findings("fun main 100 rand 7 - / print ret", "main"),
+            vec![
+                "1:21: arithmetic overflow when rand@1:14 = -2147483648",
+                "1:23: division by zero when rand@1:14 = 7"
+            ]
+        );
+    }
+
+    #[test]
+    fn errors_inside_try_are_handled() {
+        assert_eq!(
+            findings("fun f try 1 swap / recover pop 0 end ret", "f"),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn loops_are_followed_until_the_steps_run_out() {
+        let limits = Limits {
+            inputs: Some(1),
+            max_steps: 50,
+            ..Limits::default()
+        };
+        let report = explored("fun f while 1 - end ret", "f", limits);
+        // Out after every count the steps allow, and on into negatives,
+        // where the smallest one can't go any lower.
+        assert!(report.paths > 10);
+        assert!(report.cut_short > 0);
+        assert_eq!(
+            report.findings,
+            vec![Finding {
+                error: Error::Overflow { pos: 15, line: 1 },
+                inputs: vec![("x1".to_string(), i32::MIN)],
+            }]
+        );
+    }
+}
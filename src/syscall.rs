@@ -0,0 +1,79 @@
+// `syscall0` … `syscall3`: Linux system calls straight from a program, like
+// Porth has. The arguments are numbers, as everything is; an address from
+// `alloc` means nothing to the kernel, so calls that take a buffer can't be
+// made. The result is what the kernel returned, a negative error number
+// when the call failed. Numbers are the host architecture's: 39 is `getpid`
+// on x86_64 but `umount2` on aarch64.
+use crate::words::Fault;
+
+#[cfg(all(target_os = "linux", feature = "std"))]
+mod linux {
+    use std::os::raw::{c_int, c_long};
+
+    extern "C" {
+        fn syscall(number: c_long, ...) -> c_long;
+        // Both glibc and musl keep errno here.
+        fn __errno_location() -> *mut c_int;
+    }
+
+    pub fn call(number: i32, args: [i32; 3]) -> i64 {
+        let [a, b, c] = args.map(c_long::from);
+        // libc hands back -1 and sets errno; the kernel's own convention of
+        // returning the negated error number is what programs expect. errno
+        // is cleared first, so a call that really returns -1 leaves it at 0
+        // and keeps its result.
+        let (result, errno) = unsafe {
+            *__errno_location() = 0;
+            let result = syscall(c_long::from(number), a, b, c);
+            (result, *__errno_location())
+        };
+        match (result, errno) {
+            (-1, errno) if errno != 0 => -i64::from(errno),
+            (result, _) => result,
+        }
+    }
+}
+
+// Makes system call `number`. Unused arguments are 0.
+pub fn call(number: i32, args: [i32; 3]) -> Result<i32, Fault> {
+    #[cfg(all(target_os = "linux", feature = "std"))]
+    {
+        let result = linux::call(number, args);
+        i32::try_from(result).map_err(|_| Fault::Overflow)
+    }
+    #[cfg(not(all(target_os = "linux", feature = "std")))]
+    {
+        let _ = (number, args);
+        Err(Fault::Failed("system calls only work on Linux".into()))
+    }
+}
+
+// System call numbers differ between architectures.
+#[cfg(all(
+    test,
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+mod syscall_tests {
+    use super::*;
+
+    #[cfg(target_arch = "x86_64")]
+    const GETPID: i32 = 39;
+    #[cfg(target_arch = "x86_64")]
+    const CLOSE: i32 = 3;
+    #[cfg(target_arch = "aarch64")]
+    const GETPID: i32 = 172;
+    #[cfg(target_arch = "aarch64")]
+    const CLOSE: i32 = 57;
+
+    #[test]
+    fn calls_reach_the_kernel() {
+        assert_eq!(call(GETPID, [0; 3]), Ok(std::process::id() as i32));
+    }
+
+    #[test]
+    fn failures_are_negative_error_numbers() {
+        // EBADF
+        assert_eq!(call(CLOSE, [-1, 0, 0]), Ok(-9));
+    }
+}
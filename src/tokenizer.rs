@@ -1,20 +1,41 @@
-use std::fmt::Display;
+use alloc::borrow::Cow;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::Display;
+use core::iter::Peekable;
+use core::str::CharIndices;
 
+use crate::catalog::text;
 use crate::common;
+use crate::line_index::LineIndex;
+use crate::words;
 
-#[derive(PartialEq, Eq, Debug)]
-pub enum TokenType {
+// Names, doc comments and strings borrow from the source they were read
+// from; `into_owned` makes tokens that outlive it.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum TokenType<'src> {
     Num(i32),
     Pop,
     Add,
     Sub,
     Mul,
     Div,
+    DivMod,
+    AddWrap,
+    SubWrap,
+    MulWrap,
+    AddSat,
+    SubSat,
+    MulSat,
     Print,
     While,
+    Do,
     End,
     If,
     Else,
+    Try,
+    Recover,
     Fun,
     Ret,
     // Stack operations
@@ -23,62 +44,161 @@ pub enum TokenType {
     Rot,
     Over,
     Nip,
-    Identifier(String),
+    Rand,
+    Time,
+    // Number formatting
+    Hex,
+    Bin,
+    Dec,
+    PrintPadded,
+    Eprint,
+    // Q16.16 fixed point
+    FxMul,
+    FxDiv,
+    FxPrint,
+    IntToFx,
+    FxToInt,
+    // Memory
+    Struct,
+    Enum,
+    Alloc,
+    Fetch,
+    Store,
+    GcStats,
+    Syscall0,
+    Syscall1,
+    Syscall2,
+    Syscall3,
+    Invoke,
+    Include,
+    Env,
+    Asm,
+    Module,
+    Import,
+    Pub,
+    Doc(Cow<'src, str>),
+    Str(Cow<'src, str>),
+    Identifier(Cow<'src, str>),
+    // `&name`, the function `name` as a value.
+    Address(Cow<'src, str>),
+    // `#pragma edition <year>`: the words after it, up to the end of the
+    // file, mean what they mean in that edition.
+    Edition(u16),
 }
 
-impl Display for TokenType {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{}",
-            match self {
-                TokenType::While => "while".into(),
-                TokenType::End => "end".into(),
-                TokenType::Num(n) => n.to_string(),
-                TokenType::Pop => "pop".into(),
-                TokenType::Add => "+".into(),
-                TokenType::Sub => "-".into(),
-                TokenType::Mul => "*".into(),
-                TokenType::Div => "/".into(),
-                TokenType::Print => "print".into(),
-                TokenType::Dup => "dup".into(),
-                TokenType::Swap => "swap".into(),
-                TokenType::Rot => "rot".into(),
-                TokenType::Over => "over".into(),
-                TokenType::Nip => "nip".into(),
-                TokenType::If => "if".into(),
-                TokenType::Else => "else".into(),
-                TokenType::Identifier(s) => s.clone(),
-                TokenType::Fun => "function".into(),
-                TokenType::Ret => "ret".into(),
-            }
-        )
+impl TokenType<'_> {
+    pub fn into_owned(self) -> TokenType<'static> {
+        use TokenType::*;
+        match self {
+            Doc(text) => Doc(Cow::Owned(text.into_owned())),
+            Str(text) => Str(Cow::Owned(text.into_owned())),
+            Identifier(name) => Identifier(Cow::Owned(name.into_owned())),
+            Address(name) => Address(Cow::Owned(name.into_owned())),
+            Edition(year) => Edition(year),
+            Num(n) => Num(n),
+            Pop => Pop,
+            Add => Add,
+            Sub => Sub,
+            Mul => Mul,
+            Div => Div,
+            DivMod => DivMod,
+            AddWrap => AddWrap,
+            SubWrap => SubWrap,
+            MulWrap => MulWrap,
+            AddSat => AddSat,
+            SubSat => SubSat,
+            MulSat => MulSat,
+            Print => Print,
+            While => While,
+            Do => Do,
+            End => End,
+            If => If,
+            Else => Else,
+            Try => Try,
+            Recover => Recover,
+            Fun => Fun,
+            Ret => Ret,
+            Dup => Dup,
+            Swap => Swap,
+            Rot => Rot,
+            Over => Over,
+            Nip => Nip,
+            Rand => Rand,
+            Time => Time,
+            Hex => Hex,
+            Bin => Bin,
+            Dec => Dec,
+            PrintPadded => PrintPadded,
+            Eprint => Eprint,
+            FxMul => FxMul,
+            FxDiv => FxDiv,
+            FxPrint => FxPrint,
+            IntToFx => IntToFx,
+            FxToInt => FxToInt,
+            Struct => Struct,
+            Enum => Enum,
+            Alloc => Alloc,
+            Fetch => Fetch,
+            Store => Store,
+            GcStats => GcStats,
+            Syscall0 => Syscall0,
+            Syscall1 => Syscall1,
+            Syscall2 => Syscall2,
+            Syscall3 => Syscall3,
+            Invoke => Invoke,
+            Include => Include,
+            Env => Env,
+            Asm => Asm,
+            Module => Module,
+            Import => Import,
+            Pub => Pub,
+        }
+    }
+}
+
+impl Display for TokenType<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            TokenType::Num(n) => write!(f, "{}", n),
+            TokenType::Doc(s) => write!(f, "## {}", s),
+            TokenType::Str(s) => write!(f, "\"{}\"", s),
+            TokenType::Identifier(s) => write!(f, "{}", s),
+            TokenType::Address(s) => write!(f, "&{}", s),
+            TokenType::Edition(year) => write!(f, "#pragma edition {}", year),
+            word => match words::by_token(word) {
+                Some(word) => write!(f, "{}", word.name),
+                None => write!(f, "{:?}", word),
+            },
+        }
     }
 }
 
-#[derive(PartialEq, Eq, Debug)]
-pub struct Token {
-    pub token_type: TokenType,
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Token<'src> {
+    pub token_type: TokenType<'src>,
     pub pos: usize,
     pub line: usize,
 }
 
-fn identifier(input: &str) -> TokenType {
-    match input {
-        "print" => TokenType::Print,
-        "pop" => TokenType::Pop,
-        "while" => TokenType::While,
-        "end" => TokenType::End,
-        "dup" => TokenType::Dup,
-        "swap" => TokenType::Swap,
-        "rot" => TokenType::Rot,
-        "over" => TokenType::Over,
-        "nip" => TokenType::Nip,
-        "if" => TokenType::If,
-        "else" => TokenType::Else,
-        "fun" => TokenType::Fun,
-        "ret" => TokenType::Ret,
-        _ => TokenType::Identifier(input.to_string()),
+impl Token<'_> {
+    pub fn into_owned(self) -> Token<'static> {
+        Token {
+            token_type: self.token_type.into_owned(),
+            pos: self.pos,
+            line: self.line,
+        }
+    }
+}
+
+// Owned copies of `tokens`, for keeping them after their source is gone.
+pub fn into_owned(tokens: Vec<Token>) -> Vec<Token<'static>> {
+    tokens.into_iter().map(Token::into_owned).collect()
+}
+
+fn identifier(input: &str) -> TokenType<'_> {
+    match words::by_name(input) {
+        Some(word) => word.token.clone(),
+        None => TokenType::Identifier(Cow::Borrowed(input)),
     }
 }
 
@@ -86,116 +206,221 @@ fn is_identifier_char(c: &char) -> bool {
     c.is_ascii_alphanumeric() || *c == '_'
 }
 
+// Names may be qualified with the module they live in, as in `math.square`.
+fn is_name_char(c: &char) -> bool {
+    is_identifier_char(c) || *c == '.'
+}
+
+fn punctuated_word(input: &str, start: usize) -> Option<&'static str> {
+    words::reserved().find(|word| {
+        !word.chars().all(|c| is_name_char(&c))
+            && input[start..].starts_with(word)
+            && !input[start + word.len()..]
+                .chars()
+                .next()
+                .is_some_and(|c| is_name_char(&c) || c == '-')
+    })
+}
+
+// `+w`, `*s` and the other arithmetic words spelled with a symbol first.
+fn operator_word(input: &str, start: usize) -> Option<&'static str> {
+    words::BUILTINS.iter().map(|word| word.name).find(|word| {
+        word.len() > 1
+            && word.starts_with(['+', '-', '*'])
+            && input[start..].starts_with(word)
+            && !input[start + word.len()..]
+                .chars()
+                .next()
+                .is_some_and(|c| is_name_char(&c))
+    })
+}
+
 fn is_numeric_char(c: &char) -> bool {
-    c.is_numeric()
+    c.is_ascii_digit()
 }
 
 fn is_not_newline(c: &char) -> bool {
     *c != '\n'
 }
-macro_rules! collect_while {
-    ($idx:expr, $pos:expr, $chars:expr, $cond:expr) => {{
-        let mut buf = String::new();
-        buf.push(*$chars.get($idx).unwrap());
-        while let Some(c) = $chars.get($idx + 1) {
-            if $cond(c) {
-                buf.push(*c);
-                $idx += 1;
-                $pos += 1;
-            } else {
-                break;
-            }
+
+fn is_string_char(c: &char) -> bool {
+    *c != '"' && *c != '\n'
+}
+
+// Advances past the characters matching `cond` and returns the byte offset
+// just after the last one.
+fn take_while(chars: &mut Peekable<CharIndices>, input: &str, cond: fn(&char) -> bool) -> usize {
+    while let Some((_, c)) = chars.peek() {
+        if !cond(c) {
+            break;
         }
-        buf
-    }};
+        chars.next();
+    }
+    chars.peek().map_or(input.len(), |(offset, _)| *offset)
+}
+
+// The editions of the language this build reads. A file can say which one
+// it is written for with `#pragma edition 2026`; being a comment, the line
+// means nothing to a rorth from before editions. When the meaning of a word
+// changes, the change gets a new edition here and the old one stays.
+pub const EDITIONS: &[u16] = &[2024, 2026];
+
+// The edition of a file without the pragma.
+pub const FIRST_EDITION: u16 = 2024;
+
+// The edition a `#pragma edition` line asks for, given the text after its
+// `#`, or what is wrong with it. Other comments, other pragmas included, are
+// left alone.
+fn pragma(comment: &str) -> Option<Result<u16, String>> {
+    let mut words = comment.split_whitespace();
+    if words.next() != Some("pragma") || words.next() != Some("edition") {
+        return None;
+    }
+    Some(match (words.next(), words.next()) {
+        (Some(edition), None) => edition
+            .parse()
+            .ok()
+            .filter(|year| EDITIONS.contains(year))
+            .ok_or_else(|| {
+                let known: Vec<String> = EDITIONS.iter().map(|year| year.to_string()).collect();
+                text(
+                    "E0002.edition",
+                    &[("edition", &edition), ("known", &known.join(", "))],
+                )
+            }),
+        _ => Err(text("E0002.pragma", &[])),
+    })
 }
 
-pub fn tokenize(input: &str) -> Result<Vec<Token>, common::Error> {
+pub fn tokenize(input: &str) -> Result<Vec<Token<'_>>, common::Error> {
+    tokenize_recovering(input).map_err(|mut errors| errors.remove(0))
+}
+
+// Skips unknown characters instead of stopping at the first one, so every
+// problem in the input is reported at once.
+pub fn tokenize_recovering(input: &str) -> Result<Vec<Token<'_>>, Vec<common::Error>> {
+    let (tokens, errors) = tokenize_all(input);
+    if errors.is_empty() {
+        Ok(tokens)
+    } else {
+        Err(errors)
+    }
+}
+
+// The tokens around the errors too. No token spans lines, so a run of whole
+// lines tokenizes the same on its own as in the middle of a file.
+pub(crate) fn tokenize_all(input: &str) -> (Vec<Token<'_>>, Vec<common::Error>) {
+    // Editors saving UTF-8 with a byte order mark don't show it, so columns
+    // are counted as if it wasn't there.
+    let input = input.strip_prefix('\u{feff}').unwrap_or(input);
     let mut tokens = Vec::new();
+    let mut errors = Vec::new();
 
-    let mut line = 1;
-    let mut pos = 0;
-    let mut idx = 0;
-    let chars: Vec<char> = input.chars().collect();
+    let index = LineIndex::new(input);
+    let mut chars = input.char_indices().peekable();
+    let mut push = |token_type, offset| {
+        let (line, pos) = index.line_col(input, offset);
+        tokens.push(Token {
+            token_type,
+            pos,
+            line,
+        });
+    };
 
-    while let Some(c) = chars.get(idx) {
+    while let Some((start, c)) = chars.next() {
         use TokenType::*;
-        pos += 1;
+        if let Some(word) = operator_word(input, start) {
+            while chars.next_if(|(i, _)| *i < start + word.len()).is_some() {}
+            push(identifier(word), start);
+            continue;
+        }
         match c {
-            ' ' => {}
-            '\n' => {
-                line += 1;
-                pos = 0;
-            }
-            '\r' => {
-                pos = 0;
-            }
-            '\t' => {}
-            '+' => {
-                tokens.push(Token {
-                    token_type: Add,
-                    pos,
-                    line,
-                });
+            ' ' | '\t' | '\r' | '\n' => {}
+            '+' => push(Add, start),
+            '-' => push(Sub, start),
+            '*' => push(Mul, start),
+            '/' => push(Div, start),
+            '@' => push(Fetch, start),
+            '!' => push(Store, start),
+            // Also covers a `#!/usr/bin/env rorth` line at the top of a script.
+            '#' if chars.next_if(|(_, c)| *c == '#').is_some() => {
+                let end = take_while(&mut chars, input, is_not_newline);
+                push(Doc(Cow::Borrowed(input[start + 2..end].trim())), start);
             }
-            '-' => {
-                tokens.push(Token {
-                    token_type: Sub,
-                    pos,
-                    line,
-                });
-            }
-            '*' => {
-                tokens.push(Token {
-                    token_type: Mul,
-                    pos,
-                    line,
-                });
+            '#' => {
+                let end = take_while(&mut chars, input, is_not_newline);
+                match pragma(&input[start + 1..end]) {
+                    Some(Ok(year)) => push(Edition(year), start),
+                    Some(Err(comment)) => {
+                        let (line, pos) = index.line_col(input, start);
+                        errors.push(common::Error::Parse {
+                            word: input[start..end].trim_end().to_string(),
+                            pos,
+                            line,
+                            comment,
+                        })
+                    }
+                    None => {}
+                }
             }
-            '/' => {
-                tokens.push(Token {
-                    token_type: Div,
-                    pos,
-                    line,
-                });
+            '&' if chars.peek().is_some_and(|(_, c)| is_identifier_char(c)) => {
+                let end = take_while(&mut chars, input, is_name_char);
+                push(Address(Cow::Borrowed(&input[start + 1..end])), start);
             }
-            '#' => {
-                collect_while!(idx, pos, chars, is_not_newline);
-                idx += 1;
-                pos = 0;
-                line += 1;
+            '"' => {
+                let end = take_while(&mut chars, input, is_string_char);
+                if chars.next_if(|(_, c)| *c == '"').is_some() {
+                    push(Str(Cow::Borrowed(&input[start + 1..end])), start);
+                } else {
+                    let (line, pos) = index.line_col(input, start);
+                    errors.push(common::Error::UnknownToken {
+                        word: input[start..end].to_string(),
+                        pos,
+                        line,
+                    })
+                }
             }
-            c if is_numeric_char(c) => {
-                let buf = collect_while!(idx, pos, chars, is_numeric_char);
-                tokens.push(Token {
-                    token_type: Num(buf.parse::<i32>().unwrap()),
-                    pos: pos - buf.len() + 1,
-                    line,
-                });
+            c if is_numeric_char(&c) => {
+                let end = take_while(&mut chars, input, is_numeric_char);
+                match input[start..end].parse::<i32>() {
+                    Ok(n) => push(Num(n), start),
+                    Err(_) => {
+                        let (line, pos) = index.line_col(input, start);
+                        errors.push(common::Error::Parse {
+                            word: input[start..end].to_string(),
+                            pos,
+                            line,
+                            comment: format!("`{}` does not fit in 32 bits", &input[start..end]),
+                        })
+                    }
+                }
             }
-            c if is_identifier_char(c) => {
-                let buf = collect_while!(idx, pos, chars, is_identifier_char);
-                let tok_begin_pos = pos - buf.len() + 1;
-                let token_type = identifier(&buf);
-
-                tokens.push(Token {
-                    token_type,
-                    pos: tok_begin_pos,
-                    line,
-                });
+            c if is_identifier_char(&c) => {
+                let mut end = take_while(&mut chars, input, is_name_char);
+                // `-`, `*`, `/` and `>` are not part of names, except in
+                // reserved words like `print-padded` and `fx*`.
+                if let Some(word) = punctuated_word(input, start) {
+                    while chars.next_if(|(i, _)| *i < start + word.len()).is_some() {}
+                    end = start + word.len();
+                } else if let Some((at, _)) = chars.next_if(|(_, c)| *c == '@' || *c == '!') {
+                    // A name may end in one, like the field words `Point.x@`
+                    // and `Point.x!`.
+                    end = at + 1;
+                }
+                push(identifier(&input[start..end]), start);
             }
             _ => {
-                return Err(common::Error::UnknownToken {
+                let (line, pos) = index.line_col(input, start);
+                errors.push(common::Error::UnknownToken {
                     word: c.to_string(),
                     pos,
                     line,
                 })
             }
         }
-        idx += 1;
     }
 
-    Ok(tokens)
+    (tokens, errors)
 }
 
 #[cfg(test)]
@@ -497,6 +722,253 @@ mod tokenizer_tests {
         );
     }
 
+    #[test]
+    fn recovering_collects_all_unknown_symbols() {
+        let input = "1 ^ 2\n  $ ~";
+        let tokens = tokenize_recovering(input);
+        assert_eq!(
+            tokens,
+            Err(vec![
+                common::Error::UnknownToken {
+                    word: "^".to_string(),
+                    pos: 3,
+                    line: 1
+                },
+                common::Error::UnknownToken {
+                    word: "$".to_string(),
+                    pos: 3,
+                    line: 2
+                },
+                common::Error::UnknownToken {
+                    word: "~".to_string(),
+                    pos: 5,
+                    line: 2
+                },
+            ])
+        );
+        assert_eq!(
+            tokenize(input),
+            Err(common::Error::UnknownToken {
+                word: "^".to_string(),
+                pos: 3,
+                line: 1
+            })
+        );
+    }
+
+    #[test]
+    fn recovering_returns_tokens_for_valid_input() {
+        assert_eq!(
+            tokenize_recovering("print"),
+            Ok(vec![Token {
+                token_type: TokenType::Print,
+                pos: 1,
+                line: 1,
+            }])
+        );
+    }
+
+    #[test]
+    fn tabs_are_one_column() {
+        assert_eq!(
+            tokenize("\t\t+"),
+            Ok(vec![Token {
+                token_type: TokenType::Add,
+                pos: 3,
+                line: 1,
+            }])
+        );
+    }
+
+    #[test]
+    fn crlf_line_endings() {
+        assert_eq!(
+            tokenize("1\r\n  dup\r\n"),
+            Ok(vec![
+                Token {
+                    token_type: TokenType::Num(1),
+                    pos: 1,
+                    line: 1,
+                },
+                Token {
+                    token_type: TokenType::Dup,
+                    pos: 3,
+                    line: 2,
+                }
+            ])
+        );
+    }
+
+    #[test]
+    fn shebang_line_is_a_comment() {
+        assert_eq!(
+            tokenize("#!/usr/bin/env -S rorth --deterministic\nfun main ret"),
+            Ok(vec![
+                Token {
+                    token_type: TokenType::Fun,
+                    pos: 1,
+                    line: 2,
+                },
+                Token {
+                    token_type: TokenType::Identifier("main".into()),
+                    pos: 5,
+                    line: 2,
+                },
+                Token {
+                    token_type: TokenType::Ret,
+                    pos: 10,
+                    line: 2,
+                }
+            ])
+        );
+    }
+
+    #[test]
+    fn shebang_line_without_newline() {
+        assert_eq!(tokenize("#!/usr/bin/env rorth"), Ok(vec![]));
+    }
+
+    #[test]
+    fn byte_order_mark_is_skipped() {
+        assert_eq!(
+            tokenize("\u{feff}fun main\r\n  ret\r\n"),
+            Ok(vec![
+                Token {
+                    token_type: TokenType::Fun,
+                    pos: 1,
+                    line: 1,
+                },
+                Token {
+                    token_type: TokenType::Identifier("main".into()),
+                    pos: 5,
+                    line: 1,
+                },
+                Token {
+                    token_type: TokenType::Ret,
+                    pos: 3,
+                    line: 2,
+                }
+            ])
+        );
+    }
+
+    #[test]
+    fn byte_order_mark_after_start_is_unknown() {
+        assert_eq!(
+            tokenize("1\r\n\u{feff}"),
+            Err(common::Error::UnknownToken {
+                word: "\u{feff}".to_string(),
+                pos: 1,
+                line: 2,
+            })
+        );
+    }
+
+    #[test]
+    fn crlf_errors_have_correct_positions() {
+        assert_eq!(
+            tokenize_recovering("1\r\n ^\r\n\r\n  ^"),
+            Err(vec![
+                common::Error::UnknownToken {
+                    word: "^".to_string(),
+                    pos: 2,
+                    line: 2,
+                },
+                common::Error::UnknownToken {
+                    word: "^".to_string(),
+                    pos: 3,
+                    line: 4,
+                }
+            ])
+        );
+    }
+
+    #[test]
+    fn lone_carriage_return_does_not_reset_column() {
+        assert_eq!(
+            tokenize("1\r+"),
+            Ok(vec![
+                Token {
+                    token_type: TokenType::Num(1),
+                    pos: 1,
+                    line: 1,
+                },
+                Token {
+                    token_type: TokenType::Add,
+                    pos: 3,
+                    line: 1,
+                }
+            ])
+        );
+    }
+
+    #[test]
+    fn comment_at_end_of_file_without_newline() {
+        assert_eq!(
+            tokenize("1 # one"),
+            Ok(vec![Token {
+                token_type: TokenType::Num(1),
+                pos: 1,
+                line: 1,
+            }])
+        );
+    }
+
+    #[test]
+    fn comments_after_words_and_between_lines() {
+        assert_eq!(
+            tokenize("1 # one\n  # two\n\n 2 # three\n+"),
+            Ok(vec![
+                Token {
+                    token_type: TokenType::Num(1),
+                    pos: 1,
+                    line: 1,
+                },
+                Token {
+                    token_type: TokenType::Num(2),
+                    pos: 2,
+                    line: 4,
+                },
+                Token {
+                    token_type: TokenType::Add,
+                    pos: 1,
+                    line: 5,
+                }
+            ])
+        );
+    }
+
+    #[test]
+    fn comment_followed_by_crlf() {
+        assert_eq!(
+            tokenize("# one\r\n\t+ # two\r\n-"),
+            Ok(vec![
+                Token {
+                    token_type: TokenType::Add,
+                    pos: 2,
+                    line: 2,
+                },
+                Token {
+                    token_type: TokenType::Sub,
+                    pos: 1,
+                    line: 3,
+                }
+            ])
+        );
+    }
+
+    #[test]
+    fn columns_after_multibyte_characters() {
+        assert_eq!(
+            tokenize("# é\nfoo é"),
+            Err(common::Error::UnknownToken {
+                word: "é".to_string(),
+                pos: 5,
+                line: 2,
+            })
+        );
+    }
+
     #[test]
     fn while_end() {
         let input = "while end";
@@ -603,6 +1075,12 @@ mod test_identifier {
         assert_eq!(identifier("nip"), (TokenType::Nip))
     }
 
+    #[test]
+    fn test_rand_time() {
+        assert_eq!(identifier("rand"), (TokenType::Rand));
+        assert_eq!(identifier("time"), (TokenType::Time));
+    }
+
     #[test]
     fn test_if_else() {
         assert_eq!(identifier("if"), (TokenType::If));
@@ -613,7 +1091,7 @@ mod test_identifier {
     fn test_anything() {
         assert_eq!(
             identifier("anything"),
-            (TokenType::Identifier("anything".to_string()))
+            (TokenType::Identifier("anything".into()))
         );
     }
 
@@ -626,4 +1104,222 @@ mod test_identifier {
     fn test_ret() {
         assert_eq!(identifier("ret"), (TokenType::Ret));
     }
+
+    #[test]
+    fn include_and_strings() {
+        assert_eq!(
+            tokenize("include \"lib/math.sm\"\n"),
+            Ok(vec![
+                Token {
+                    token_type: TokenType::Include,
+                    pos: 1,
+                    line: 1,
+                },
+                Token {
+                    token_type: TokenType::Str("lib/math.sm".into()),
+                    pos: 9,
+                    line: 1,
+                },
+            ])
+        );
+        assert_eq!(
+            tokenize("include \"oops\n1"),
+            Err(common::Error::UnknownToken {
+                word: "\"oops".to_string(),
+                pos: 9,
+                line: 1,
+            })
+        );
+    }
+
+    #[test]
+    fn qualified_names() {
+        let tokens: Vec<TokenType> = tokenize("module math import math math.square")
+            .unwrap()
+            .into_iter()
+            .map(|t| t.token_type)
+            .collect();
+        assert_eq!(
+            tokens,
+            vec![
+                TokenType::Module,
+                TokenType::Identifier("math".into()),
+                TokenType::Import,
+                TokenType::Identifier("math".into()),
+                TokenType::Identifier("math.square".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn doc_comments() {
+        assert_eq!(
+            tokenize("# plain\n## Squares it.\r\n##\nfun"),
+            Ok(vec![
+                Token {
+                    token_type: TokenType::Doc("Squares it.".into()),
+                    pos: 1,
+                    line: 2,
+                },
+                Token {
+                    token_type: TokenType::Doc("".into()),
+                    pos: 1,
+                    line: 3,
+                },
+                Token {
+                    token_type: TokenType::Fun,
+                    pos: 1,
+                    line: 4,
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn words_are_all_reserved() {
+        for word in words::reserved() {
+            assert!(
+                !matches!(identifier(word), TokenType::Identifier(_)),
+                "{}",
+                word
+            );
+        }
+    }
+
+    #[test]
+    fn edition_pragmas() {
+        assert_eq!(
+            tokenize("#pragma edition 2026\n# pragmatic\n#pragma once\n1"),
+            Ok(vec![
+                Token {
+                    token_type: TokenType::Edition(2026),
+                    pos: 1,
+                    line: 1,
+                },
+                Token {
+                    token_type: TokenType::Num(1),
+                    pos: 1,
+                    line: 4,
+                }
+            ])
+        );
+        let comments: Vec<String> = [
+            "#pragma edition 2031",
+            "#pragma edition",
+            "#pragma edition 2024 2026",
+        ]
+        .iter()
+        .map(|source| tokenize(source).unwrap_err().to_string())
+        .collect();
+        assert_eq!(
+            comments,
+            vec![
+                "1:1: This rorth doesn't know edition 2031; it reads 2024, 2026",
+                "1:1: Expected `#pragma edition <year>`",
+                "1:1: Expected `#pragma edition <year>`",
+            ]
+        );
+    }
+
+    #[test]
+    fn hyphenated_words() {
+        let tokens: Vec<TokenType> = tokenize("x print-padded print-paddedx print-3")
+            .unwrap()
+            .into_iter()
+            .map(|t| t.token_type)
+            .collect();
+        assert_eq!(
+            tokens,
+            vec![
+                TokenType::Identifier("x".into()),
+                TokenType::PrintPadded,
+                TokenType::Print,
+                TokenType::Sub,
+                TokenType::Identifier("paddedx".into()),
+                TokenType::Print,
+                TokenType::Sub,
+                TokenType::Num(3),
+            ]
+        );
+    }
+
+    #[test]
+    fn punctuated_words() {
+        let tokens: Vec<TokenType> =
+            tokenize("fx* fx/ fx. int>fx fx>int fx*2 fx gc-stats a@ b! @ !")
+                .unwrap()
+                .into_iter()
+                .map(|t| t.token_type)
+                .collect();
+        assert_eq!(
+            tokens,
+            vec![
+                TokenType::FxMul,
+                TokenType::FxDiv,
+                TokenType::FxPrint,
+                TokenType::IntToFx,
+                TokenType::FxToInt,
+                TokenType::Identifier("fx".into()),
+                TokenType::Mul,
+                TokenType::Num(2),
+                TokenType::Identifier("fx".into()),
+                TokenType::GcStats,
+                TokenType::Identifier("a@".into()),
+                TokenType::Identifier("b!".into()),
+                TokenType::Fetch,
+                TokenType::Store,
+            ]
+        );
+    }
+
+    #[test]
+    fn arithmetic_variants() {
+        let tokens: Vec<TokenType> = tokenize("1 2 +w -s *s +x -w_ *w\n+s")
+            .unwrap()
+            .into_iter()
+            .map(|t| t.token_type)
+            .collect();
+        assert_eq!(
+            tokens,
+            vec![
+                TokenType::Num(1),
+                TokenType::Num(2),
+                TokenType::AddWrap,
+                TokenType::SubSat,
+                TokenType::MulSat,
+                TokenType::Add,
+                TokenType::Identifier("x".into()),
+                TokenType::Sub,
+                TokenType::Identifier("w_".into()),
+                TokenType::MulWrap,
+                TokenType::AddSat,
+            ]
+        );
+    }
+
+    #[test]
+    fn numbers_must_fit() {
+        assert_eq!(
+            tokenize("2147483647 2147483648").map_err(|e| e.to_string()),
+            Err("1:12: `2147483648` does not fit in 32 bits".to_string())
+        );
+    }
+
+    #[test]
+    fn names_borrow_from_the_source() {
+        let source = String::from("fun sq dup * ret \"hi\"");
+        let tokens = tokenize(&source).unwrap();
+        assert!(matches!(
+            &tokens[1].token_type,
+            TokenType::Identifier(Cow::Borrowed("sq"))
+        ));
+        assert!(matches!(
+            &tokens[5].token_type,
+            TokenType::Str(Cow::Borrowed("hi"))
+        ));
+        let owned = into_owned(tokens);
+        drop(source);
+        assert_eq!(owned[1].token_type, TokenType::Identifier("sq".into()));
+        assert_eq!(owned[5].token_type, TokenType::Str("hi".into()));
+    }
 }
@@ -0,0 +1,185 @@
+// Turtle graphics, loaded with `--plugin turtle`. The turtle starts in the
+// middle facing up with its pen down; the lines it draws are written to
+// `turtle.svg` once the program ends.
+use std::fmt::Write;
+
+use crate::checker::StackEffect;
+use crate::common::Error;
+use crate::plugin::{Native, Pack, PackState};
+use crate::stack::Stack;
+use crate::words::Fault;
+
+pub const OUTPUT: &str = "turtle.svg";
+const MARGIN: f64 = 10.0;
+
+pub static PACK: Pack = Pack {
+    name: "turtle",
+    description: "draws lines into turtle.svg",
+    words: &[
+        Native {
+            name: "forward",
+            effect: StackEffect {
+                inputs: 1,
+                outputs: 0,
+            },
+            description: "moves the turtle that many steps, drawing when the pen is down",
+        },
+        Native {
+            name: "turn",
+            effect: StackEffect {
+                inputs: 1,
+                outputs: 0,
+            },
+            description: "turns the turtle clockwise by that many degrees",
+        },
+        Native {
+            name: "pen",
+            effect: StackEffect {
+                inputs: 1,
+                outputs: 0,
+            },
+            description: "lifts the pen on 0 and puts it down otherwise",
+        },
+    ],
+    writes_files: true,
+    start: || Box::new(Turtle::default()),
+};
+
+type Point = (f64, f64);
+
+pub struct Turtle {
+    at: Point,
+    // Degrees clockwise from up.
+    heading: i64,
+    pen: bool,
+    lines: Vec<(Point, Point)>,
+}
+
+impl Default for Turtle {
+    fn default() -> Self {
+        Self {
+            at: (0.0, 0.0),
+            heading: 0,
+            pen: true,
+            lines: vec![],
+        }
+    }
+}
+
+impl Turtle {
+    fn forward(&mut self, steps: i32) {
+        let radians = (self.heading as f64).to_radians();
+        let from = self.at;
+        // Up is negative y in SVG.
+        let to = (
+            from.0 + steps as f64 * radians.sin(),
+            from.1 - steps as f64 * radians.cos(),
+        );
+        if self.pen {
+            self.lines.push((from, to));
+        }
+        self.at = to;
+    }
+
+    // The drawing, cropped to the lines with a margin around them.
+    pub fn svg(&self) -> String {
+        let points = self.lines.iter().flat_map(|(a, b)| [*a, *b]);
+        let (mut left, mut top, mut right, mut bottom) = (0.0f64, 0.0f64, 0.0f64, 0.0f64);
+        for (x, y) in points {
+            left = left.min(x);
+            top = top.min(y);
+            right = right.max(x);
+            bottom = bottom.max(y);
+        }
+        let mut out = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"{} {} {} {}\">\n",
+            number(left - MARGIN),
+            number(top - MARGIN),
+            number(right - left + 2.0 * MARGIN),
+            number(bottom - top + 2.0 * MARGIN)
+        );
+        for ((x1, y1), (x2, y2)) in &self.lines {
+            let _ = writeln!(
+                out,
+                "  <line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"black\"/>",
+                number(*x1),
+                number(*y1),
+                number(*x2),
+                number(*y2)
+            );
+        }
+        out.push_str("</svg>\n");
+        out
+    }
+}
+
+// Two decimals at most, and no `-0`.
+fn number(n: f64) -> String {
+    let rounded = (n * 100.0).round() / 100.0;
+    format!("{}", rounded + 0.0)
+}
+
+impl PackState for Turtle {
+    fn call(&mut self, word: usize, stack: &mut dyn Stack<i32>) -> Result<(), Fault> {
+        let n = stack.pop().unwrap_or_default();
+        match word {
+            0 => self.forward(n),
+            1 => self.heading = (self.heading + n as i64).rem_euclid(360),
+            _ => self.pen = n != 0,
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        std::fs::write(OUTPUT, self.svg()).map_err(|e| Error::Io {
+            path: OUTPUT.to_string(),
+            comment: e.to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod turtle_tests {
+    use super::*;
+    use crate::stack::VecStack;
+
+    fn draw(words: &[(usize, i32)]) -> Turtle {
+        let mut turtle = Turtle::default();
+        let mut stack = VecStack::new();
+        for (word, n) in words {
+            stack.push(*n);
+            turtle.call(*word, &mut stack).unwrap();
+        }
+        turtle
+    }
+
+    #[test]
+    fn squares_and_gaps() {
+        let turtle = draw(&[(0, 10), (1, 90), (2, 0), (0, 10), (1, 90), (2, 1), (0, 10)]);
+        assert_eq!(
+            turtle.svg(),
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"-10 -20 30 30\">
+  <line x1=\"0\" y1=\"0\" x2=\"0\" y2=\"-10\" stroke=\"black\"/>
+  <line x1=\"10\" y1=\"-10\" x2=\"10\" y2=\"0\" stroke=\"black\"/>
+</svg>
+"
+        );
+    }
+
+    #[test]
+    fn turns_wrap_around() {
+        let turtle = draw(&[(1, -90), (1, 720), (0, 5)]);
+        assert_eq!(turtle.heading, 270);
+        assert!(turtle
+            .svg()
+            .contains("x1=\"0\" y1=\"0\" x2=\"-5\" y2=\"0\""));
+    }
+
+    #[test]
+    fn nothing_drawn() {
+        assert_eq!(
+            Turtle::default().svg(),
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"-10 -10 20 20\">\n</svg>\n"
+        );
+    }
+}
@@ -0,0 +1,96 @@
+// Runs `main` twice, as usual and with `VmConfig::optimize` off, and checks
+// that nothing outside the machine can tell the runs apart: the same output,
+// final stack, steps and error. `rorth verify-opt` does it for a program
+// that is suspected of running wrong; the corpus test does it for every
+// input there.
+use alloc::format;
+use alloc::string::String;
+use core::fmt::{Debug, Display};
+
+use crate::stack::VecStack;
+use crate::stack_machine::{Program, RunResult, StackMachine, VmConfig};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Difference {
+    pub what: &'static str,
+    pub optimized: String,
+    pub plain: String,
+}
+
+impl Display for Difference {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{} differ: {} with optimizations, {} without",
+            self.what, self.optimized, self.plain
+        )
+    }
+}
+
+// The optimized run when the two agree. `rand` and `time` are made
+// deterministic, or the runs would differ anyway.
+pub fn compare(program: &Program, config: VmConfig) -> Result<RunResult, Difference> {
+    let config = config.deterministic();
+    let run = |optimize| {
+        let config = VmConfig {
+            optimize,
+            ..config.clone()
+        };
+        let mut machine = StackMachine::with_config(VecStack::new(), config);
+        // `eprint` once is enough.
+        if !optimize {
+            machine.set_error_sink(|_| {});
+        }
+        machine.run_main(program)
+    };
+    let (optimized, plain) = (run(true), run(false));
+    same("outputs", &optimized.output, &plain.output)?;
+    same("final stacks", &optimized.final_stack, &plain.final_stack)?;
+    same("errors", &optimized.error, &plain.error)?;
+    same("steps", &optimized.steps, &plain.steps)?;
+    Ok(optimized)
+}
+
+fn same<T: Debug + PartialEq>(
+    what: &'static str,
+    optimized: &T,
+    plain: &T,
+) -> Result<(), Difference> {
+    if optimized == plain {
+        return Ok(());
+    }
+    Err(Difference {
+        what,
+        optimized: format!("{:?}", optimized),
+        plain: format!("{:?}", plain),
+    })
+}
+
+#[cfg(test)]
+mod verify_opt_tests {
+    use super::*;
+    use crate::parser::parse;
+    use crate::tokenizer::tokenize;
+
+    #[test]
+    fn immediates_are_checked_against_plain_steps() {
+        let source = "fun main 0 5 while 1 - swap 3 + 2 * swap end pop print ret";
+        let program = parse(tokenize(source).unwrap()).unwrap();
+        let result = compare(&program, VmConfig::default()).unwrap();
+        assert_eq!(result.output, ["186"]);
+        let limited = VmConfig {
+            max_steps: Some(9),
+            ..VmConfig::default()
+        };
+        assert!(compare(&program, limited).unwrap().error.is_some());
+    }
+
+    #[test]
+    fn differences_say_which_part() {
+        assert_eq!(same("steps", &1, &1), Ok(()));
+        assert_eq!(
+            same("outputs", &[1], &[2]).unwrap_err().to_string(),
+            "outputs differ: [1] with optimizations, [2] without"
+        );
+    }
+}
@@ -0,0 +1,253 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::common::Error;
+use crate::json;
+use crate::stack::{Stack, VecStack};
+use crate::stack_machine::{Breakpoint, Program, RunState, StackMachine, VmConfig};
+
+// The stack at one moment of a run, and what just ran or is about to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub line: usize,
+    pub pos: usize,
+    pub label: String,
+    pub stack: Vec<i32>,
+}
+
+pub struct Recording {
+    pub frames: Vec<Frame>,
+    pub printed: Vec<String>,
+    pub result: Result<(), Error>,
+}
+
+// Runs `main`, or the config's entry, and keeps a frame after every instruction, or, when `lines`
+// is not empty, only when execution reaches the start of one of them.
+pub fn record(program: &Program, config: VmConfig, lines: &[usize]) -> Recording {
+    let entry = config.entry().to_string();
+    let mut machine = StackMachine::with_config(VecStack::new(), config);
+    let frames = Rc::new(RefCell::new(vec![]));
+    let result = if lines.is_empty() {
+        let seen = frames.clone();
+        // The trace outlives `program`; naming calls needs only its symbols.
+        let names = Program {
+            symbols: program.symbols.clone(),
+            ..Program::default()
+        };
+        machine.set_trace(move |step| {
+            seen.borrow_mut().push(Frame {
+                line: step.instruction.line,
+                pos: step.instruction.pos,
+                label: format!(
+                    "after `{}`",
+                    names.describe(&step.instruction.instruction_type)
+                ),
+                stack: step.stack.to_vec(),
+            })
+        });
+        machine.call(program, &entry)
+    } else {
+        for line in lines {
+            machine.add_breakpoint(Breakpoint::Line(*line));
+        }
+        let mut state = machine.start(program, &entry);
+        while let Ok(RunState::Paused(paused)) = &state {
+            frames.borrow_mut().push(Frame {
+                line: paused.line,
+                pos: paused.pos,
+                label: format!(
+                    "before `{}` in `{}`",
+                    program.describe(&program.instructions[paused.index].instruction_type),
+                    paused.function
+                ),
+                stack: machine.stack.as_slice().to_vec(),
+            });
+            state = machine.resume(program);
+        }
+        state.map(|_| ())
+    };
+    let frames = frames.take();
+    Recording {
+        frames,
+        printed: machine.take_output(),
+        result,
+    }
+}
+
+// The stack drawn top down, one boxed cell per value.
+pub fn diagram(stack: &[i32]) -> String {
+    if stack.is_empty() {
+        return "  (empty)\n".to_string();
+    }
+    let width = stack.iter().map(|v| v.to_string().len()).max().unwrap_or(1);
+    let border = format!("  +{}+\n", "-".repeat(width + 2));
+    let mut out = border.clone();
+    for (i, value) in stack.iter().rev().enumerate() {
+        let top = if i == 0 { "  <- top" } else { "" };
+        out.push_str(&format!("  | {:>width$} |{}\n", value, top, width = width));
+        out.push_str(&border);
+    }
+    out
+}
+
+pub fn text(frame: &Frame) -> String {
+    format!(
+        "{}:{} {}\n{}",
+        frame.line,
+        frame.pos,
+        frame.label,
+        diagram(&frame.stack)
+    )
+}
+
+// A page that steps through the frames, with buttons and arrow keys.
+pub fn html(frames: &[Frame], title: &str) -> String {
+    let data = json::array(frames.iter().map(|frame| {
+        json::object(&[
+            ("line", frame.line.to_string()),
+            ("pos", frame.pos.to_string()),
+            ("label", json::string(&frame.label)),
+            (
+                "stack",
+                json::array(frame.stack.iter().map(|v| v.to_string())),
+            ),
+        ])
+    }))
+    // Keeps a `</script>` in the data from ending the script early.
+    .replace('<', "\\u003c");
+    let title = title
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;");
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head><meta charset="utf-8"><title>{title}</title>
+<style>
+body {{ font-family: monospace; margin: 2em; }}
+#stack {{ display: inline-flex; flex-direction: column; min-width: 6em; margin: 1em 0; }}
+.cell {{ border: 1px solid #444; padding: 0.3em 1em; text-align: right; }}
+.cell:first-child {{ background: #ffe9a8; }}
+</style>
+</head>
+<body>
+<h1>{title}</h1>
+<p><button id="prev">&larr;</button> <button id="play">play</button> <button id="next">&rarr;</button> <span id="where"></span></p>
+<div id="stack"></div>
+<script>
+const frames = {data};
+let at = 0, timer = null;
+function show() {{
+  const frame = frames[at];
+  if (!frame) {{ document.getElementById("where").textContent = "no steps"; return; }}
+  document.getElementById("where").textContent =
+    (at + 1) + "/" + frames.length + "  " + frame.line + ":" + frame.pos + " " + frame.label;
+  const stack = document.getElementById("stack");
+  stack.innerHTML = "";
+  if (frame.stack.length === 0) stack.textContent = "(empty)";
+  for (const value of frame.stack.slice().reverse()) {{
+    const cell = document.createElement("div");
+    cell.className = "cell";
+    cell.textContent = value;
+    stack.appendChild(cell);
+  }}
+}}
+function go(by) {{ at = Math.max(0, Math.min(frames.length - 1, at + by)); show(); }}
+document.getElementById("prev").onclick = () => go(-1);
+document.getElementById("next").onclick = () => go(1);
+document.getElementById("play").onclick = () => {{
+  if (timer) {{ clearInterval(timer); timer = null; return; }}
+  timer = setInterval(() => {{ if (at + 1 >= frames.length) {{ clearInterval(timer); timer = null; }} else go(1); }}, 500);
+}};
+document.onkeydown = (e) => {{ if (e.key === "ArrowLeft") go(-1); if (e.key === "ArrowRight") go(1); }};
+show();
+</script>
+</body>
+</html>
+"#
+    )
+}
+
+#[cfg(test)]
+mod viz_tests {
+    use super::*;
+    use crate::parser::parse;
+    use crate::tokenizer::tokenize;
+
+    fn program(source: &str) -> Program {
+        parse(tokenize(source).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn diagram_puts_the_top_first() {
+        assert_eq!(
+            diagram(&[1, -20]),
+            "  +-----+
+  | -20 |  <- top
+  +-----+
+  |   1 |
+  +-----+
+"
+        );
+        assert_eq!(diagram(&[]), "  (empty)\n");
+    }
+
+    #[test]
+    fn records_every_step() {
+        let recording = record(
+            &program("fun main 1 2 swap print ret"),
+            VmConfig::default(),
+            &[],
+        );
+        assert_eq!(recording.result, Ok(()));
+        assert_eq!(recording.printed, vec!["1"]);
+        let stacks: Vec<Vec<i32>> = recording.frames.iter().map(|f| f.stack.clone()).collect();
+        assert_eq!(
+            stacks,
+            vec![vec![1], vec![1, 2], vec![2, 1], vec![2], vec![2]]
+        );
+        assert_eq!(
+            text(&recording.frames[2]),
+            "1:14 after `swap`\n  +---+\n  | 1 |  <- top\n  +---+\n  | 2 |\n  +---+\n"
+        );
+    }
+
+    #[test]
+    fn records_at_breakpoints_and_keeps_errors() {
+        let recording = record(
+            &program("fun sq dup * ret\nfun main\n  3 sq\n  sq print\n  + ret"),
+            VmConfig::default(),
+            &[1, 4],
+        );
+        let frames: Vec<(usize, &str, Vec<i32>)> = recording
+            .frames
+            .iter()
+            .map(|f| (f.line, f.label.as_str(), f.stack.clone()))
+            .collect();
+        assert_eq!(
+            frames,
+            vec![
+                (1, "before `dup` in `sq`", vec![3]),
+                (4, "before `call sq` in `main`", vec![9]),
+                (1, "before `dup` in `sq`", vec![9]),
+            ]
+        );
+        assert_eq!(recording.printed, vec!["81"]);
+        assert_eq!(recording.result, Err(Error::StackEmpty { pos: 3, line: 5 }));
+    }
+
+    #[test]
+    fn html_embeds_the_frames() {
+        let frames = vec![Frame {
+            line: 1,
+            pos: 2,
+            label: "after `</script>`".to_string(),
+            stack: vec![4, 5],
+        }];
+        let page = html(&frames, "a<b");
+        assert!(page.contains("<title>a&lt;b</title>"));
+        assert!(page.contains(
+            r#"const frames = [{"line":1,"pos":2,"label":"after `\u003c/script>`","stack":[4,5]}];"#
+        ));
+    }
+}
@@ -0,0 +1,24 @@
+use wasm_bindgen::prelude::*;
+
+use crate::host::run_source;
+use crate::playground;
+use crate::stack_machine::VmConfig;
+
+#[wasm_bindgen]
+pub fn run(source: &str) -> String {
+    let mut out = Vec::new();
+    let result = run_source(source, VmConfig::sandboxed(), &mut out);
+    let mut text = String::from_utf8_lossy(&out).into_owned();
+    // Written as the CLI and the playground write them.
+    if let Err(e) = result {
+        for e in e.into_vec() {
+            text.push_str(&format!("error[{}]: {}\n", e.id(), e));
+        }
+    }
+    text
+}
+
+#[wasm_bindgen]
+pub fn evaluate(source: &str) -> String {
+    playground::evaluate(source, &playground::Limits::default()).to_json()
+}
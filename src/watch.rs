@@ -0,0 +1,166 @@
+// Notices when files change, for `rorth check --watch`. Files are polled:
+// each one's modification time and length are compared with what they were
+// last time, so a save is seen within one interval on any platform, and a
+// file that is deleted, or appears, counts as a change too.
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime};
+
+use crate::interrupt;
+
+pub const INTERVAL: Duration = Duration::from_millis(250);
+
+// None for a file that can't be read right now.
+type Stamp = Option<(SystemTime, u64)>;
+
+fn stamp(path: &Path) -> Stamp {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+pub struct Watcher {
+    files: Vec<(PathBuf, Stamp)>,
+}
+
+impl Watcher {
+    pub fn new(paths: impl IntoIterator<Item = PathBuf>) -> Self {
+        let mut files: Vec<(PathBuf, Stamp)> = vec![];
+        for path in paths {
+            if files.iter().all(|(seen, _)| *seen != path) {
+                let stamp = stamp(&path);
+                files.push((path, stamp));
+            }
+        }
+        Self { files }
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    // The files that changed since the last call, or since `new`.
+    pub fn changed(&mut self) -> Vec<PathBuf> {
+        let mut changed = vec![];
+        for (path, last) in &mut self.files {
+            let now = stamp(path);
+            if now != *last {
+                *last = now;
+                changed.push(path.clone());
+            }
+        }
+        changed
+    }
+
+    // Blocks until a file changes, checking every `interval`. None when
+    // Ctrl-C is pressed first.
+    pub fn wait(&mut self, interval: Duration) -> Option<Vec<PathBuf>> {
+        loop {
+            if interrupt::take() {
+                return None;
+            }
+            let changed = self.changed();
+            if !changed.is_empty() {
+                return Some(changed);
+            }
+            std::thread::sleep(interval);
+        }
+    }
+
+    // Runs `run` while a thread polls the files every `interval`, and sets
+    // `interrupt::INTERRUPTED` at the first change, so an `interruptible`
+    // machine inside `run` stops where it is. Also returns the files that
+    // changed, empty when none did before `run` was done.
+    pub fn interrupt_on_change<T>(
+        &mut self,
+        interval: Duration,
+        run: impl FnOnce() -> T,
+    ) -> (T, Vec<PathBuf>) {
+        let finished = AtomicBool::new(false);
+        std::thread::scope(|scope| {
+            let poller = scope.spawn(|| {
+                while !finished.load(Ordering::Relaxed) {
+                    let changed = self.changed();
+                    if !changed.is_empty() {
+                        interrupt::INTERRUPTED.store(true, Ordering::Relaxed);
+                        return changed;
+                    }
+                    std::thread::park_timeout(interval);
+                }
+                vec![]
+            });
+            let result = run();
+            finished.store(true, Ordering::Relaxed);
+            poller.thread().unpark();
+            let changed = poller.join().expect("polling doesn't panic");
+            // A change seen after `run` was done doesn't stop the next one.
+            if !changed.is_empty() {
+                interrupt::take();
+            }
+            (result, changed)
+        })
+    }
+}
+
+#[cfg(test)]
+mod watch_tests {
+    use super::*;
+
+    #[test]
+    fn edits_deletions_and_new_files_are_changes() {
+        let dir = std::env::temp_dir().join(format!("rorth-watch-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let main = dir.join("main.sm");
+        let lib = dir.join("lib.sm");
+        std::fs::write(&main, "fun main ret").unwrap();
+
+        let mut watcher = Watcher::new([main.clone(), lib.clone(), main.clone()]);
+        assert_eq!(watcher.len(), 2);
+        assert!(watcher.changed().is_empty());
+
+        // A longer file, so the change shows even where timestamps are
+        // coarser than the time the test takes.
+        std::fs::write(&main, "fun main 1 print ret").unwrap();
+        assert_eq!(watcher.changed(), vec![main.clone()]);
+        assert!(watcher.changed().is_empty());
+
+        std::fs::write(&lib, "fun f ret").unwrap();
+        assert_eq!(watcher.changed(), vec![lib]);
+
+        std::fs::remove_file(&main).unwrap();
+        assert_eq!(watcher.changed(), vec![main]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_change_interrupts_the_run() {
+        let dir = std::env::temp_dir().join(format!("rorth-watch-run-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let main = dir.join("main.sm");
+        std::fs::write(&main, "fun main ret").unwrap();
+
+        let mut watcher = Watcher::new([main.clone()]);
+        let (spins, changed) = watcher.interrupt_on_change(Duration::from_millis(5), || {
+            std::fs::write(&main, "fun main 1 print ret").unwrap();
+            // Stands in for a machine running until it is interrupted.
+            let mut spins = 0;
+            while !interrupt::take() {
+                spins += 1;
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            spins
+        });
+        assert!(spins > 0);
+        assert_eq!(changed, vec![main.clone()]);
+
+        let (_, changed) = watcher.interrupt_on_change(INTERVAL, || ());
+        assert!(changed.is_empty());
+        assert!(!interrupt::take());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
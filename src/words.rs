@@ -0,0 +1,960 @@
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::mem::discriminant;
+#[cfg(feature = "std")]
+use core::mem::Discriminant;
+#[cfg(feature = "std")]
+use std::collections::HashMap;
+#[cfg(feature = "std")]
+use std::sync::OnceLock;
+
+use crate::checker::StackEffect;
+use crate::fixed;
+use crate::parser::InstructionType;
+use crate::plugin;
+use crate::stack::Stack;
+use crate::tokenizer::TokenType;
+
+// A built-in word. The tokenizer, the parser, the checker, the machine and
+// the bytecode all read this table, and `rorth words` prints it. Adding a
+// word means adding a row here, plus a match arm in the machine when it does
+// more than move values around.
+pub struct Word {
+    pub name: &'static str,
+    pub token: TokenType<'static>,
+    // For words the parser turns into a single instruction.
+    pub op: Option<Op>,
+    pub description: &'static str,
+}
+
+pub struct Op {
+    pub instruction: InstructionType,
+    // Its number in compiled programs. Never reuse or renumber one, see
+    // `bytecode::VERSION`.
+    pub opcode: u8,
+    // For ops that always take and leave the same number of values.
+    pub effect: Option<StackEffect>,
+    // For ops that only work on the stack. The machine makes sure the
+    // effect's inputs are there before calling it.
+    pub eval: Option<Eval>,
+}
+
+pub type Eval = fn(&mut dyn Stack<i32>) -> Result<(), Fault>;
+
+// What an `Eval` or a plugin word can fail with. The machine adds the
+// position.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fault {
+    DivisionByZero,
+    Overflow,
+    Failed(String),
+}
+
+const fn effect(inputs: usize, outputs: usize) -> Option<StackEffect> {
+    Some(StackEffect { inputs, outputs })
+}
+
+// Works on the stack alone.
+const fn pure(
+    name: &'static str,
+    token: TokenType<'static>,
+    (instruction, opcode): (InstructionType, u8),
+    (inputs, outputs): (usize, usize),
+    eval: Eval,
+    description: &'static str,
+) -> Word {
+    Word {
+        name,
+        token,
+        op: Some(Op {
+            instruction,
+            opcode,
+            effect: effect(inputs, outputs),
+            eval: Some(eval),
+        }),
+        description,
+    }
+}
+
+// Needs the machine: output, the clock, the random generator or the frames.
+const fn machine(
+    name: &'static str,
+    token: TokenType<'static>,
+    (instruction, opcode): (InstructionType, u8),
+    effect: Option<StackEffect>,
+    description: &'static str,
+) -> Word {
+    Word {
+        name,
+        token,
+        op: Some(Op {
+            instruction,
+            opcode,
+            effect,
+            eval: None,
+        }),
+        description,
+    }
+}
+
+// Structure the parser handles itself.
+const fn syntax(name: &'static str, token: TokenType<'static>, description: &'static str) -> Word {
+    Word {
+        name,
+        token,
+        op: None,
+        description,
+    }
+}
+
+fn pop(stack: &mut dyn Stack<i32>) -> i32 {
+    stack
+        .pop()
+        .expect("the machine checks an op's inputs are there")
+}
+
+// `b a f` for the top two values, put back as they were when the result
+// doesn't fit, so a handler still sees them.
+fn checked(stack: &mut dyn Stack<i32>, f: fn(i32, i32) -> Option<i32>) -> Result<(), Fault> {
+    let a = pop(stack);
+    let b = pop(stack);
+    match f(b, a) {
+        Some(n) => stack.push(n),
+        None => {
+            stack.push(b);
+            stack.push(a);
+            return Err(Fault::Overflow);
+        }
+    }
+    Ok(())
+}
+
+fn add(stack: &mut dyn Stack<i32>) -> Result<(), Fault> {
+    checked(stack, i32::checked_add)
+}
+
+fn sub(stack: &mut dyn Stack<i32>) -> Result<(), Fault> {
+    checked(stack, i32::checked_sub)
+}
+
+fn mul(stack: &mut dyn Stack<i32>) -> Result<(), Fault> {
+    checked(stack, i32::checked_mul)
+}
+
+// Wrapping around instead, so `2147483647 1 +w` is -2147483648.
+fn add_wrap(stack: &mut dyn Stack<i32>) -> Result<(), Fault> {
+    let a = pop(stack);
+    let b = pop(stack);
+    stack.push(b.wrapping_add(a));
+    Ok(())
+}
+
+fn sub_wrap(stack: &mut dyn Stack<i32>) -> Result<(), Fault> {
+    let a = pop(stack);
+    let b = pop(stack);
+    stack.push(b.wrapping_sub(a));
+    Ok(())
+}
+
+fn mul_wrap(stack: &mut dyn Stack<i32>) -> Result<(), Fault> {
+    let a = pop(stack);
+    let b = pop(stack);
+    stack.push(b.wrapping_mul(a));
+    Ok(())
+}
+
+// Clamped to `i32::MIN` and `i32::MAX` instead.
+fn add_sat(stack: &mut dyn Stack<i32>) -> Result<(), Fault> {
+    let a = pop(stack);
+    let b = pop(stack);
+    stack.push(b.saturating_add(a));
+    Ok(())
+}
+
+fn sub_sat(stack: &mut dyn Stack<i32>) -> Result<(), Fault> {
+    let a = pop(stack);
+    let b = pop(stack);
+    stack.push(b.saturating_sub(a));
+    Ok(())
+}
+
+fn mul_sat(stack: &mut dyn Stack<i32>) -> Result<(), Fault> {
+    let a = pop(stack);
+    let b = pop(stack);
+    stack.push(b.saturating_mul(a));
+    Ok(())
+}
+
+// Checked before popping, so a handler still sees both values. The one
+// quotient that doesn't fit, -2147483648 / -1, is an overflow.
+fn divisor(stack: &dyn Stack<i32>) -> Result<(), Fault> {
+    let below = stack
+        .as_slice()
+        .len()
+        .checked_sub(2)
+        .map(|i| stack.as_slice()[i]);
+    match (below, stack.peek()) {
+        (_, Some(0)) => Err(Fault::DivisionByZero),
+        (Some(i32::MIN), Some(-1)) => Err(Fault::Overflow),
+        _ => Ok(()),
+    }
+}
+
+fn div(stack: &mut dyn Stack<i32>) -> Result<(), Fault> {
+    divisor(stack)?;
+    let a = pop(stack);
+    let b = pop(stack);
+    stack.push(b / a);
+    Ok(())
+}
+
+fn divmod(stack: &mut dyn Stack<i32>) -> Result<(), Fault> {
+    divisor(stack)?;
+    let a = pop(stack);
+    let b = pop(stack);
+    stack.push(b / a);
+    stack.push(b % a);
+    Ok(())
+}
+
+// Rounding toward negative infinity instead, for `Division::Floor`. The
+// remainder takes the sign of the divisor.
+fn floored(b: i32, a: i32) -> (i32, i32) {
+    let (q, r) = (b / a, b % a);
+    if r != 0 && (r < 0) != (a < 0) {
+        (q - 1, r + a)
+    } else {
+        (q, r)
+    }
+}
+
+pub fn floor_div(stack: &mut dyn Stack<i32>) -> Result<(), Fault> {
+    divisor(stack)?;
+    let a = pop(stack);
+    let b = pop(stack);
+    stack.push(floored(b, a).0);
+    Ok(())
+}
+
+pub fn floor_divmod(stack: &mut dyn Stack<i32>) -> Result<(), Fault> {
+    divisor(stack)?;
+    let a = pop(stack);
+    let b = pop(stack);
+    let (q, r) = floored(b, a);
+    stack.push(q);
+    stack.push(r);
+    Ok(())
+}
+
+fn drop(stack: &mut dyn Stack<i32>) -> Result<(), Fault> {
+    pop(stack);
+    Ok(())
+}
+
+fn dup(stack: &mut dyn Stack<i32>) -> Result<(), Fault> {
+    let a = pop(stack);
+    stack.push(a);
+    stack.push(a);
+    Ok(())
+}
+
+fn swap(stack: &mut dyn Stack<i32>) -> Result<(), Fault> {
+    let a = pop(stack);
+    let b = pop(stack);
+    stack.push(a);
+    stack.push(b);
+    Ok(())
+}
+
+fn rot(stack: &mut dyn Stack<i32>) -> Result<(), Fault> {
+    let a = pop(stack);
+    let b = pop(stack);
+    let c = pop(stack);
+    stack.push(b);
+    stack.push(a);
+    stack.push(c);
+    Ok(())
+}
+
+fn over(stack: &mut dyn Stack<i32>) -> Result<(), Fault> {
+    let a = pop(stack);
+    let b = pop(stack);
+    stack.push(b);
+    stack.push(a);
+    stack.push(b);
+    Ok(())
+}
+
+fn nip(stack: &mut dyn Stack<i32>) -> Result<(), Fault> {
+    let a = pop(stack);
+    pop(stack);
+    stack.push(a);
+    Ok(())
+}
+
+// Overflow puts the inputs back, so a handler still sees them.
+fn fx_mul(stack: &mut dyn Stack<i32>) -> Result<(), Fault> {
+    let a = pop(stack);
+    let b = pop(stack);
+    match fixed::mul(b, a) {
+        Some(product) => stack.push(product),
+        None => {
+            stack.push(b);
+            stack.push(a);
+            return Err(Fault::Overflow);
+        }
+    }
+    Ok(())
+}
+
+fn fx_div(stack: &mut dyn Stack<i32>) -> Result<(), Fault> {
+    if stack.peek() == Some(&0) {
+        return Err(Fault::DivisionByZero);
+    }
+    let a = pop(stack);
+    let b = pop(stack);
+    match fixed::div(b, a) {
+        Some(quotient) => stack.push(quotient),
+        None => {
+            stack.push(b);
+            stack.push(a);
+            return Err(Fault::Overflow);
+        }
+    }
+    Ok(())
+}
+
+fn int_to_fx(stack: &mut dyn Stack<i32>) -> Result<(), Fault> {
+    let n = pop(stack);
+    match fixed::from_int(n) {
+        Some(x) => stack.push(x),
+        None => {
+            stack.push(n);
+            return Err(Fault::Overflow);
+        }
+    }
+    Ok(())
+}
+
+fn fx_to_int(stack: &mut dyn Stack<i32>) -> Result<(), Fault> {
+    let x = pop(stack);
+    stack.push(fixed::to_int(x));
+    Ok(())
+}
+
+pub const BUILTINS: &[Word] = &[
+    pure(
+        "+",
+        TokenType::Add,
+        (InstructionType::Add, 2),
+        (2, 1),
+        add,
+        "adds the top two values",
+    ),
+    pure(
+        "-",
+        TokenType::Sub,
+        (InstructionType::Sub, 3),
+        (2, 1),
+        sub,
+        "subtracts the top value from the one below it",
+    ),
+    pure(
+        "*",
+        TokenType::Mul,
+        (InstructionType::Mul, 4),
+        (2, 1),
+        mul,
+        "multiplies the top two values",
+    ),
+    pure(
+        "/",
+        TokenType::Div,
+        (InstructionType::Div, 5),
+        (2, 1),
+        div,
+        "divides the second value by the top one, rounding toward zero unless `--division=floor`",
+    ),
+    pure(
+        "divmod",
+        TokenType::DivMod,
+        (InstructionType::DivMod, 46),
+        (2, 2),
+        divmod,
+        "divides the second value by the top one and pushes the quotient, then the remainder",
+    ),
+    pure(
+        "+w",
+        TokenType::AddWrap,
+        (InstructionType::AddWrap, 47),
+        (2, 1),
+        add_wrap,
+        "adds the top two values, wrapping around on overflow where `+` stops with an error",
+    ),
+    pure(
+        "-w",
+        TokenType::SubWrap,
+        (InstructionType::SubWrap, 48),
+        (2, 1),
+        sub_wrap,
+        "subtracts the top value from the one below it, wrapping around on overflow where `-` stops with an error",
+    ),
+    pure(
+        "*w",
+        TokenType::MulWrap,
+        (InstructionType::MulWrap, 49),
+        (2, 1),
+        mul_wrap,
+        "multiplies the top two values, wrapping around on overflow where `*` stops with an error",
+    ),
+    pure(
+        "+s",
+        TokenType::AddSat,
+        (InstructionType::AddSat, 50),
+        (2, 1),
+        add_sat,
+        "adds the top two values, stopping at the smallest or largest number",
+    ),
+    pure(
+        "-s",
+        TokenType::SubSat,
+        (InstructionType::SubSat, 51),
+        (2, 1),
+        sub_sat,
+        "subtracts the top value from the one below it, stopping at the smallest or largest number",
+    ),
+    pure(
+        "*s",
+        TokenType::MulSat,
+        (InstructionType::MulSat, 52),
+        (2, 1),
+        mul_sat,
+        "multiplies the top two values, stopping at the smallest or largest number",
+    ),
+    machine(
+        "print",
+        TokenType::Print,
+        (InstructionType::Print, 6),
+        effect(1, 0),
+        "prints the top value",
+    ),
+    machine(
+        "print-padded",
+        TokenType::PrintPadded,
+        (InstructionType::PrintPadded, 24),
+        effect(2, 0),
+        "prints the second value right aligned to the width on top",
+    ),
+    machine(
+        "eprint",
+        TokenType::Eprint,
+        (InstructionType::Eprint, 25),
+        effect(1, 0),
+        "prints the top value to stderr",
+    ),
+    machine(
+        "hex",
+        TokenType::Hex,
+        (InstructionType::Hex, 21),
+        effect(0, 0),
+        "prints numbers in hexadecimal from now on",
+    ),
+    machine(
+        "bin",
+        TokenType::Bin,
+        (InstructionType::Bin, 22),
+        effect(0, 0),
+        "prints numbers in binary from now on",
+    ),
+    machine(
+        "dec",
+        TokenType::Dec,
+        (InstructionType::Dec, 23),
+        effect(0, 0),
+        "prints numbers in decimal from now on",
+    ),
+    pure(
+        "pop",
+        TokenType::Pop,
+        (InstructionType::Pop, 1),
+        (1, 0),
+        drop,
+        "drops the top value",
+    ),
+    pure(
+        "dup",
+        TokenType::Dup,
+        (InstructionType::Dup, 12),
+        (1, 2),
+        dup,
+        "copies the top value",
+    ),
+    pure(
+        "swap",
+        TokenType::Swap,
+        (InstructionType::Swap, 13),
+        (2, 2),
+        swap,
+        "swaps the top two values",
+    ),
+    pure(
+        "rot",
+        TokenType::Rot,
+        (InstructionType::Rot, 14),
+        (3, 3),
+        rot,
+        "moves the third value to the top",
+    ),
+    pure(
+        "over",
+        TokenType::Over,
+        (InstructionType::Over, 15),
+        (2, 3),
+        over,
+        "copies the second value to the top",
+    ),
+    pure(
+        "nip",
+        TokenType::Nip,
+        (InstructionType::Nip, 16),
+        (2, 1),
+        nip,
+        "drops the second value",
+    ),
+    pure(
+        "int>fx",
+        TokenType::IntToFx,
+        (InstructionType::IntToFx, 33),
+        (1, 1),
+        int_to_fx,
+        "turns the top value into a Q16.16 fixed-point number",
+    ),
+    pure(
+        "fx>int",
+        TokenType::FxToInt,
+        (InstructionType::FxToInt, 34),
+        (1, 1),
+        fx_to_int,
+        "turns the fixed-point number on top into an integer, rounding toward zero",
+    ),
+    pure(
+        "fx*",
+        TokenType::FxMul,
+        (InstructionType::FxMul, 30),
+        (2, 1),
+        fx_mul,
+        "multiplies the top two fixed-point numbers",
+    ),
+    pure(
+        "fx/",
+        TokenType::FxDiv,
+        (InstructionType::FxDiv, 31),
+        (2, 1),
+        fx_div,
+        "divides the second fixed-point number by the top one",
+    ),
+    machine(
+        "fx.",
+        TokenType::FxPrint,
+        (InstructionType::FxPrint, 32),
+        effect(1, 0),
+        "prints the fixed-point number on top in decimal",
+    ),
+    machine(
+        "rand",
+        TokenType::Rand,
+        (InstructionType::Rand, 17),
+        effect(0, 1),
+        "pushes a random number",
+    ),
+    machine(
+        "time",
+        TokenType::Time,
+        (InstructionType::Time, 18),
+        effect(0, 1),
+        "pushes the time in milliseconds",
+    ),
+    machine(
+        "alloc",
+        TokenType::Alloc,
+        (InstructionType::Alloc, 38),
+        effect(1, 1),
+        "pushes the address of a new block of that many cells, all 0",
+    ),
+    machine(
+        "@",
+        TokenType::Fetch,
+        (InstructionType::Fetch, 39),
+        effect(1, 1),
+        "pushes the value in the cell at the address on top",
+    ),
+    machine(
+        "!",
+        TokenType::Store,
+        (InstructionType::Store, 40),
+        effect(2, 0),
+        "stores the second value in the cell at the address on top",
+    ),
+    machine(
+        "gc-stats",
+        TokenType::GcStats,
+        (InstructionType::GcStats, 41),
+        effect(0, 2),
+        "frees unreachable blocks and pushes how many blocks and cells are left",
+    ),
+    machine(
+        "syscall0",
+        TokenType::Syscall0,
+        (InstructionType::Syscall0, 42),
+        effect(1, 1),
+        "makes the Linux system call numbered on top with no arguments below it and pushes the result",
+    ),
+    machine(
+        "syscall1",
+        TokenType::Syscall1,
+        (InstructionType::Syscall1, 43),
+        effect(2, 1),
+        "makes the Linux system call numbered on top with one argument below it and pushes the result",
+    ),
+    machine(
+        "syscall2",
+        TokenType::Syscall2,
+        (InstructionType::Syscall2, 44),
+        effect(3, 1),
+        "makes the Linux system call numbered on top with two arguments below it and pushes the result",
+    ),
+    machine(
+        "syscall3",
+        TokenType::Syscall3,
+        (InstructionType::Syscall3, 45),
+        effect(4, 1),
+        "makes the Linux system call numbered on top with three arguments below it and pushes the result",
+    ),
+    machine(
+        "invoke",
+        TokenType::Invoke,
+        (InstructionType::Invoke, 54),
+        None,
+        "calls the function whose `&name` is on top",
+    ),
+    syntax(
+        "while",
+        TokenType::While,
+        "runs the block up to `end` for as long as the top value is not 0, or the condition up to `do`",
+    ),
+    syntax(
+        "do",
+        TokenType::Do,
+        "in `while … do … end`, takes the condition and leaves the loop when it is 0",
+    ),
+    syntax(
+        "if",
+        TokenType::If,
+        "runs the block up to `else` when the top value is not 0",
+    ),
+    syntax(
+        "else",
+        TokenType::Else,
+        "starts the block `if` runs when the top value is 0",
+    ),
+    syntax(
+        "try",
+        TokenType::Try,
+        "runs the block up to `recover`, catching runtime errors",
+    ),
+    syntax(
+        "recover",
+        TokenType::Recover,
+        "starts the block run with an error code when the `try` block fails",
+    ),
+    syntax(
+        "end",
+        TokenType::End,
+        "closes a `while`, `if`, `try`, `module`, `struct` or `enum`",
+    ),
+    syntax(
+        "fun",
+        TokenType::Fun,
+        "starts a function: `fun name ... ret`",
+    ),
+    machine(
+        "ret",
+        TokenType::Ret,
+        (InstructionType::Ret, 20),
+        None,
+        "returns from the function",
+    ),
+    syntax(
+        "module",
+        TokenType::Module,
+        "starts a module: `module name ... end`",
+    ),
+    syntax(
+        "struct",
+        TokenType::Struct,
+        "names the cells of a block: `struct Point x y end`",
+    ),
+    syntax(
+        "enum",
+        TokenType::Enum,
+        "numbers names from 0: `enum Color red green blue end`",
+    ),
+    syntax(
+        "pub",
+        TokenType::Pub,
+        "makes the next function visible outside its module",
+    ),
+    syntax(
+        "import",
+        TokenType::Import,
+        "makes a module's functions callable by their short names",
+    ),
+    syntax(
+        "include",
+        TokenType::Include,
+        "pulls in another file once: `include \"file.sm\"`",
+    ),
+    syntax(
+        "env",
+        TokenType::Env,
+        "pushes an environment variable as a number: `env \"COUNT\"`",
+    ),
+    syntax(
+        "asm",
+        TokenType::Asm,
+        "inline assembly for a native backend: `asm \"...\" end`; refused when interpreting",
+    ),
+];
+
+// A word that used to be spelled differently. Renaming a word means
+// changing its row above and adding one here, which `rorth fix` reads to
+// bring older programs up to date. Rows stay for good.
+pub struct Renamed {
+    pub old: &'static str,
+    pub new: &'static str,
+    // The release the new spelling came in.
+    pub since: &'static str,
+}
+
+// No word has been renamed yet.
+pub const RENAMED: &[Renamed] = &[];
+
+pub fn by_name(name: &str) -> Option<&'static Word> {
+    BUILTINS.iter().find(|word| word.name == name)
+}
+
+pub fn by_token(token: &TokenType) -> Option<&'static Word> {
+    BUILTINS.iter().find(|word| word.token == *token)
+}
+
+// The machine looks up every instruction it runs, so with `std` this one is
+// indexed. Without it there is no `OnceLock` to build the index in.
+#[cfg(feature = "std")]
+pub fn op(instruction: &InstructionType) -> Option<(&'static str, &'static Op)> {
+    type Index = HashMap<Discriminant<InstructionType>, (&'static str, &'static Op)>;
+    static INDEX: OnceLock<Index> = OnceLock::new();
+    INDEX
+        .get_or_init(|| {
+            ops()
+                .map(|(name, op)| (discriminant(&op.instruction), (name, op)))
+                .collect()
+        })
+        .get(&discriminant(instruction))
+        .copied()
+}
+
+#[cfg(not(feature = "std"))]
+pub fn op(instruction: &InstructionType) -> Option<(&'static str, &'static Op)> {
+    ops().find(|(_, op)| discriminant(&op.instruction) == discriminant(instruction))
+}
+
+fn ops() -> impl Iterator<Item = (&'static str, &'static Op)> {
+    BUILTINS
+        .iter()
+        .filter_map(|word| Some((word.name, word.op.as_ref()?)))
+}
+
+pub fn by_opcode(opcode: u8) -> Option<(&'static str, &'static Op)> {
+    ops().find(|(_, op)| op.opcode == opcode)
+}
+
+// The words that read like names, as opposed to `+` and friends. These are
+// the ones a function can't be called.
+pub fn reserved() -> impl Iterator<Item = &'static str> {
+    BUILTINS
+        .iter()
+        .map(|word| word.name)
+        .filter(|name| name.starts_with(|c: char| c.is_ascii_alphabetic()))
+}
+
+// One line per word, for `rorth words`.
+pub fn listing() -> String {
+    let builtins = BUILTINS.iter().map(|word| {
+        let effect = word.op.as_ref().and_then(|op| op.effect);
+        (word.name, effect, word.description)
+    });
+    let natives = plugin::natives()
+        .into_iter()
+        .map(|word| (word.name, Some(word.effect), word.description));
+    let rows: Vec<(&str, String, &str)> = builtins
+        .chain(natives)
+        .map(|(name, effect, description)| {
+            let effect = effect.map_or(String::new(), |e| e.to_string());
+            (name, effect, description)
+        })
+        .collect();
+    let name_width = rows.iter().map(|row| row.0.len()).max().unwrap_or(0);
+    let effect_width = rows.iter().map(|row| row.1.len()).max().unwrap_or(0);
+    rows.iter()
+        .map(|(name, effect, description)| {
+            format!(
+                "{:<name_width$}  {:<effect_width$}  {}\n",
+                name,
+                effect,
+                description,
+                name_width = name_width,
+                effect_width = effect_width
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod words_tests {
+    use super::*;
+    use crate::common::Error;
+    use crate::parser::Instruction;
+    use crate::stack::{Stack, VecStack};
+    use crate::stack_machine::{StackMachine, VmConfig};
+    use crate::tokenizer::tokenize;
+
+    #[test]
+    fn every_word_tokenizes_to_its_token() {
+        for word in BUILTINS {
+            let tokens = tokenize(word.name).unwrap();
+            assert_eq!(tokens.len(), 1, "{}", word.name);
+            assert_eq!(tokens[0].token_type, word.token, "{}", word.name);
+            assert!(by_token(&word.token).is_some_and(|w| w.name == word.name));
+        }
+    }
+
+    // `getpid`, the one system call the table test makes, as numbers differ
+    // between architectures. Elsewhere the system call words are skipped.
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    const GETPID: Option<i32> = Some(39);
+    #[cfg(all(target_os = "linux", target_arch = "aarch64"))]
+    const GETPID: Option<i32> = Some(172);
+    #[cfg(not(all(
+        target_os = "linux",
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    )))]
+    const GETPID: Option<i32> = None;
+
+    // The machine takes and leaves what the table says.
+    #[test]
+    fn effects_match_the_machine() {
+        for word in BUILTINS {
+            let Some(Op {
+                instruction,
+                effect: Some(effect),
+                ..
+            }) = word.op
+            else {
+                continue;
+            };
+            // The inputs are 1s, but the memory words get a cell's address
+            // and the system calls get the number of `getpid`, which ignores
+            // its arguments.
+            let address = matches!(instruction, InstructionType::Fetch | InstructionType::Store);
+            let syscall = matches!(
+                instruction,
+                InstructionType::Syscall0
+                    | InstructionType::Syscall1
+                    | InstructionType::Syscall2
+                    | InstructionType::Syscall3
+            );
+            if syscall && GETPID.is_none() {
+                continue;
+            }
+            let run = |depth: usize| {
+                let config = VmConfig {
+                    allow_syscalls: true,
+                    ..VmConfig::default()
+                };
+                let mut machine = StackMachine::with_config(VecStack::new(), config);
+                machine.set_error_sink(|_| {});
+                for i in 0..depth {
+                    match GETPID {
+                        Some(number) if syscall && i + 1 == depth => machine.stack.push(number),
+                        _ => machine.stack.push(1),
+                    }
+                    if address && i + 1 == depth {
+                        let alloc = Instruction {
+                            instruction_type: InstructionType::Alloc,
+                            pos: 1,
+                            line: 1,
+                        };
+                        machine.execute_iter([alloc]).unwrap();
+                    }
+                }
+                let result = machine.execute_iter([Instruction {
+                    instruction_type: instruction,
+                    pos: 1,
+                    line: 1,
+                }]);
+                result.map(|_| machine.stack.size())
+            };
+            assert_eq!(run(effect.inputs), Ok(effect.outputs), "{}", word.name);
+            if effect.inputs > 0 {
+                assert_eq!(
+                    run(effect.inputs - 1),
+                    Err(Error::StackEmpty { pos: 1, line: 1 }),
+                    "{}",
+                    word.name
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn ops_are_indexed_by_instruction_and_opcode() {
+        let mut opcodes = vec![];
+        for word in BUILTINS {
+            let Some(entry) = &word.op else { continue };
+            assert_eq!(
+                op(&entry.instruction).map(|(name, _)| name),
+                Some(word.name)
+            );
+            assert_eq!(
+                by_opcode(entry.opcode).map(|(name, _)| name),
+                Some(word.name)
+            );
+            opcodes.push(entry.opcode);
+        }
+        let count = opcodes.len();
+        opcodes.sort();
+        opcodes.dedup();
+        assert_eq!(opcodes.len(), count);
+    }
+
+    #[test]
+    fn listing_lines_up() {
+        // Other tests load plugins, which add lines at the end.
+        let listing: String = listing()
+            .lines()
+            .take(BUILTINS.len())
+            .map(|line| format!("{}\n", line))
+            .collect();
+        assert!(listing.starts_with("+             ( 2 -- 1 )  adds the top two values\n"));
+        assert!(listing.contains("\nwhile                     runs the block up to `end`"));
+        assert_eq!(listing.lines().count(), BUILTINS.len());
+    }
+
+    #[test]
+    fn listing_includes_loaded_plugins() {
+        plugin::register(&plugin::plugin_tests::TALLY).unwrap();
+        let listing = listing();
+        assert!(listing.contains("\ntallyadd      ( 1 -- 0 )  adds the top value to the total\n"));
+    }
+}
@@ -0,0 +1,619 @@
+// Programs as x86-64 assembly for Linux, written by `rorth build --target
+// x86_64-linux` into `.s` files for the GNU assembler:
+//
+//   as prog.s -o prog.o && ld prog.o -o prog
+//
+// The result needs no libc. Values sit in a fixed array, `rbx` pointing at
+// the first free slot, `r12` at the bottom and `r13` at the end; calls are
+// native calls, checked against `r14` so a runaway recursion stops with an
+// error instead of a crash. Every instruction gets the label `.L<index>`,
+// so jumps go where the interpreter's would. Errors are written to stderr
+// as the interpreter words them and exit with its status.
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use crate::checker::{divisor_ranges, validate_program, Range};
+use crate::common::Error;
+use crate::parser::{Instruction, InstructionType};
+use crate::stack_machine::Program;
+
+pub const EXTENSION: &str = "s";
+// Values the stack holds before a push fails with a stack limit error.
+pub const STACK_VALUES: usize = 1 << 20;
+// Bytes of the native stack calls may use before failing with a memory
+// limit error; well within Linux's default of 8 MiB.
+pub const CALL_BYTES: usize = 1 << 20;
+
+const RUNTIME: &str = "
+rorth_fail:
+    mov r15d, edi
+    mov eax, 1
+    mov edi, 2
+    syscall
+    mov edi, r15d
+rorth_exit:
+    mov eax, 231
+    syscall
+
+# eax in the current base, then a newline, to the file descriptor in edi.
+rorth_print:
+    lea rsi, [rip + rorth_text_end]
+    mov byte ptr [rsi - 1], 10
+    lea r8, [rsi - 1]
+    mov r9d, eax
+    test eax, eax
+    jns rorth_print_digits
+    neg eax
+rorth_print_digits:
+    mov ecx, dword ptr [rip + rorth_base]
+    lea r10, [rip + rorth_digits]
+rorth_print_digit:
+    xor edx, edx
+    div ecx
+    mov dl, byte ptr [r10 + rdx]
+    dec r8
+    mov byte ptr [r8], dl
+    test eax, eax
+    jnz rorth_print_digit
+    test r9d, r9d
+    jns rorth_print_write
+    dec r8
+    mov byte ptr [r8], 45
+rorth_print_write:
+    mov rdx, rsi
+    sub rdx, r8
+    mov rsi, r8
+    mov eax, 1
+    syscall
+    ret
+";
+
+pub fn assembly(program: &Program) -> Result<String, Error> {
+    validate_program(program)?;
+    let main = *program
+        .functions
+        .get("main")
+        .ok_or_else(|| Error::FunctionNotFound {
+            name: "main".to_string(),
+        })?;
+    let mut asm = Asm {
+        text: String::new(),
+        failures: Vec::new(),
+        divisors: divisor_ranges(program),
+        inline: &program.asm,
+    };
+    asm.text
+        .push_str("    .intel_syntax noprefix\n    .text\n    .globl _start\n");
+    asm.text.push_str("_start:\n");
+    asm.op("lea r12, [rip + rorth_stack]");
+    asm.op("mov rbx, r12");
+    asm.op(format!("lea r13, [r12 + {}]", STACK_VALUES * 4));
+    asm.op(format!("lea r14, [rsp - {}]", CALL_BYTES));
+    asm.op(format!("call .L{}", main));
+    asm.op("xor edi, edi");
+    asm.op("jmp rorth_exit");
+    for (index, instruction) in program.instructions.iter().enumerate() {
+        asm.text.push_str(&format!(".L{}:\n", index));
+        asm.instruction(index, instruction)?;
+    }
+    // Running off the end finishes the run, as it does in the interpreter.
+    asm.text
+        .push_str(&format!(".L{}:\n", program.instructions.len()));
+    asm.op("xor edi, edi");
+    asm.op("jmp rorth_exit");
+    for (n, (label, error)) in asm.failures.iter().enumerate() {
+        asm.text.push_str(&format!("{}:\n", label));
+        asm.text
+            .push_str(&format!("    lea rsi, [rip + .Lmessage{}]\n", n));
+        asm.text
+            .push_str(&format!("    mov edx, {}\n", error.to_string().len() + 1));
+        asm.text
+            .push_str(&format!("    mov edi, {}\n", error.exit_status()));
+        asm.text.push_str("    jmp rorth_fail\n");
+    }
+    asm.text.push_str(RUNTIME);
+    asm.text.push_str("\n    .section .rodata\n");
+    asm.text
+        .push_str("rorth_digits:\n    .ascii \"0123456789abcdef\"\n");
+    for (n, (_, error)) in asm.failures.iter().enumerate() {
+        let message = format!("{}\n", error);
+        asm.text.push_str(&format!(
+            ".Lmessage{}:\n    .ascii \"{}\"\n",
+            n,
+            escaped(&message)
+        ));
+    }
+    asm.text
+        .push_str("\n    .data\nrorth_base:\n    .long 10\n");
+    asm.text.push_str("\n    .bss\n");
+    asm.text
+        .push_str(&format!("rorth_stack:\n    .skip {}\n", STACK_VALUES * 4));
+    // A sign, 32 binary digits and the newline.
+    asm.text
+        .push_str("rorth_text:\n    .skip 40\nrorth_text_end:\n");
+    asm.text
+        .push_str("\n    .section .note.GNU-stack,\"\",@progbits\n");
+    Ok(asm.text)
+}
+
+// For `.ascii`: quotes, backslashes and anything unprintable as octal.
+fn escaped(text: &str) -> String {
+    text.bytes()
+        .map(|byte| match byte {
+            b'"' | b'\\' => format!("\\{}", byte as char),
+            b' '..=b'~' => (byte as char).to_string(),
+            _ => format!("\\{:03o}", byte),
+        })
+        .collect()
+}
+
+struct Asm<'a> {
+    text: String,
+    // Where each error is raised from, and the error.
+    failures: Vec<(String, Error)>,
+    divisors: alloc::collections::BTreeMap<usize, Range>,
+    // The text of the program's `asm` blocks.
+    inline: &'a [String],
+}
+
+impl Asm<'_> {
+    fn op(&mut self, op: impl AsRef<str>) {
+        self.text.push_str("    ");
+        self.text.push_str(op.as_ref());
+        self.text.push('\n');
+    }
+
+    // A label that fails the run with `error`.
+    fn failure(&mut self, error: Error) -> String {
+        let label = format!(".Lfail{}", self.failures.len());
+        self.failures.push((label.clone(), error));
+        label
+    }
+
+    // At least `n` values on the stack.
+    fn takes(&mut self, n: usize, i: &Instruction) {
+        if n == 0 {
+            return;
+        }
+        let fail = self.failure(Error::StackEmpty {
+            pos: i.pos,
+            line: i.line,
+        });
+        self.op(format!("lea rax, [r12 + {}]", n * 4));
+        self.op("cmp rbx, rax");
+        self.op(format!("jb {}", fail));
+    }
+
+    // Room for one more value.
+    fn grows(&mut self, i: &Instruction) {
+        let fail = self.failure(Error::StackOverflow {
+            limit: STACK_VALUES,
+            pos: i.pos,
+            line: i.line,
+        });
+        self.op("cmp rbx, r13");
+        self.op(format!("jae {}", fail));
+    }
+
+    fn instruction(&mut self, index: usize, i: &Instruction) -> Result<(), Error> {
+        use InstructionType::*;
+        match i.instruction_type {
+            Push(n) => {
+                self.grows(i);
+                self.op(format!("mov dword ptr [rbx], {}", n));
+                self.op("add rbx, 4");
+            }
+            Pop => {
+                self.takes(1, i);
+                self.op("sub rbx, 4");
+            }
+            Add | Sub | Mul | AddWrap | SubWrap | MulWrap => {
+                self.takes(2, i);
+                let op = match i.instruction_type {
+                    Add | AddWrap => "add",
+                    Sub | SubWrap => "sub",
+                    _ => "imul",
+                };
+                self.op("mov eax, dword ptr [rbx - 8]");
+                self.op(format!("{} eax, dword ptr [rbx - 4]", op));
+                if matches!(i.instruction_type, Add | Sub | Mul) {
+                    let fail = self.failure(Error::Overflow {
+                        pos: i.pos,
+                        line: i.line,
+                    });
+                    self.op(format!("jo {}", fail));
+                }
+                self.op("mov dword ptr [rbx - 8], eax");
+                self.op("sub rbx, 4");
+            }
+            // Worked out in 64 bits, where it can't overflow, then clamped.
+            AddSat | SubSat | MulSat => {
+                self.takes(2, i);
+                let op = match i.instruction_type {
+                    AddSat => "add",
+                    SubSat => "sub",
+                    _ => "imul",
+                };
+                self.op("movsxd rax, dword ptr [rbx - 8]");
+                self.op("movsxd rcx, dword ptr [rbx - 4]");
+                self.op(format!("{} rax, rcx", op));
+                self.op(format!("mov rcx, {}", i32::MAX));
+                self.op("cmp rax, rcx");
+                self.op("cmovg rax, rcx");
+                self.op(format!("mov rcx, {}", i32::MIN));
+                self.op("cmp rax, rcx");
+                self.op("cmovl rax, rcx");
+                self.op("mov dword ptr [rbx - 8], eax");
+                self.op("sub rbx, 4");
+            }
+            Div | DivMod => {
+                self.takes(2, i);
+                self.op("mov ecx, dword ptr [rbx - 4]");
+                let divisor = self.divisors.get(&index).copied().unwrap_or(Range::ANY);
+                if divisor.contains(0) {
+                    let fail = self.failure(Error::DivisionByZero {
+                        pos: i.pos,
+                        line: i.line,
+                    });
+                    self.op("test ecx, ecx");
+                    self.op(format!("jz {}", fail));
+                }
+                if divisor.contains(-1) {
+                    let fail = self.failure(Error::Overflow {
+                        pos: i.pos,
+                        line: i.line,
+                    });
+                    self.op("cmp ecx, -1");
+                    self.op(format!("jne .L{}_divide", index));
+                    self.op(format!("cmp dword ptr [rbx - 8], {}", i32::MIN));
+                    self.op(format!("je {}", fail));
+                    self.text.push_str(&format!(".L{}_divide:\n", index));
+                }
+                self.op("mov eax, dword ptr [rbx - 8]");
+                self.op("cdq");
+                self.op("idiv ecx");
+                self.op("mov dword ptr [rbx - 8], eax");
+                if i.instruction_type == Div {
+                    self.op("sub rbx, 4");
+                } else {
+                    self.op("mov dword ptr [rbx - 4], edx");
+                }
+            }
+            Print | Eprint => {
+                self.takes(1, i);
+                self.op("sub rbx, 4");
+                self.op("mov eax, dword ptr [rbx]");
+                self.op(format!(
+                    "mov edi, {}",
+                    if i.instruction_type == Print { 1 } else { 2 }
+                ));
+                self.op("call rorth_print");
+            }
+            Hex | Bin | Dec => {
+                let base = match i.instruction_type {
+                    Hex => 16,
+                    Bin => 2,
+                    _ => 10,
+                };
+                self.op(format!("mov dword ptr [rip + rorth_base], {}", base));
+            }
+            Dup | Over => {
+                let from = if i.instruction_type == Dup { 4 } else { 8 };
+                self.takes(from / 4, i);
+                self.grows(i);
+                self.op(format!("mov eax, dword ptr [rbx - {}]", from));
+                self.op("mov dword ptr [rbx], eax");
+                self.op("add rbx, 4");
+            }
+            Swap => {
+                self.takes(2, i);
+                self.op("mov eax, dword ptr [rbx - 4]");
+                self.op("mov ecx, dword ptr [rbx - 8]");
+                self.op("mov dword ptr [rbx - 8], eax");
+                self.op("mov dword ptr [rbx - 4], ecx");
+            }
+            Rot => {
+                self.takes(3, i);
+                self.op("mov eax, dword ptr [rbx - 12]");
+                self.op("mov ecx, dword ptr [rbx - 8]");
+                self.op("mov edx, dword ptr [rbx - 4]");
+                self.op("mov dword ptr [rbx - 12], ecx");
+                self.op("mov dword ptr [rbx - 8], edx");
+                self.op("mov dword ptr [rbx - 4], eax");
+            }
+            Nip => {
+                self.takes(2, i);
+                self.op("mov eax, dword ptr [rbx - 4]");
+                self.op("mov dword ptr [rbx - 8], eax");
+                self.op("sub rbx, 4");
+            }
+            // Jumps land after their target, as the interpreter's do.
+            While(target) | If(target) | EndWhile(target) => {
+                self.takes(1, i);
+                self.op("cmp dword ptr [rbx - 4], 0");
+                let jump = if matches!(i.instruction_type, EndWhile(_)) {
+                    "jne"
+                } else {
+                    "je"
+                };
+                self.op(format!("{} .L{}", jump, target + 1));
+            }
+            Do(target) => {
+                self.takes(1, i);
+                self.op("sub rbx, 4");
+                self.op("cmp dword ptr [rbx], 0");
+                self.op(format!("je .L{}", target + 1));
+            }
+            Else(target) | EndDo(target) => self.op(format!("jmp .L{}", target + 1)),
+            Loop(_) | EndIf => {}
+            Call(start) => {
+                let fail = self.failure(Error::OutOfMemory {
+                    limit: CALL_BYTES,
+                    pos: i.pos,
+                    line: i.line,
+                });
+                self.op("cmp rsp, r14");
+                self.op(format!("jbe {}", fail));
+                self.op(format!("call .L{}", start));
+            }
+            Ret => self.op("ret"),
+            // Pasted in as it is, between the instructions around it.
+            Asm(text) => {
+                self.text.push_str(&self.inline[text]);
+                if !self.text.ends_with('\n') {
+                    self.text.push('\n');
+                }
+            }
+            // The number on top, the first argument deepest. Arguments are
+            // sign-extended, as the interpreter's are, and a result that
+            // doesn't fit in 32 bits fails the way it does there.
+            Syscall0 | Syscall1 | Syscall2 | Syscall3 => {
+                let count = match i.instruction_type {
+                    Syscall0 => 0,
+                    Syscall1 => 1,
+                    Syscall2 => 2,
+                    _ => 3,
+                };
+                self.takes(count + 1, i);
+                self.op("movsxd rax, dword ptr [rbx - 4]");
+                for (n, register) in ["rdi", "rsi", "rdx"].iter().enumerate().take(count) {
+                    self.op(format!(
+                        "movsxd {}, dword ptr [rbx - {}]",
+                        register,
+                        (count + 1 - n) * 4
+                    ));
+                }
+                self.op("syscall");
+                let fail = self.failure(Error::Overflow {
+                    pos: i.pos,
+                    line: i.line,
+                });
+                self.op("movsxd rcx, eax");
+                self.op("cmp rcx, rax");
+                self.op(format!("jne {}", fail));
+                self.op(format!("mov dword ptr [rbx - {}], eax", (count + 1) * 4));
+                if count > 0 {
+                    self.op(format!("sub rbx, {}", count * 4));
+                }
+            }
+            Try(_) | Recover(_) | EndTry | Rand | Time | Address(_) | Invoke | Env(_)
+            | Native(_) | PrintPadded | FxMul | FxDiv | FxPrint | IntToFx | FxToInt | Alloc
+            | Fetch | Store | GcStats => {
+                return Err(Error::UnsupportedInstruction {
+                    word: i.instruction_type.to_string(),
+                    pos: i.pos,
+                    line: i.line,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod x86_64_tests {
+    use super::*;
+    use crate::parser::parse;
+    use crate::tokenizer::tokenize;
+
+    fn program(source: &str) -> Program {
+        parse(tokenize(source).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn what_it_cant_build_is_refused() {
+        assert_eq!(
+            assembly(&program("fun main\n  1 2 try + recover pop end ret")),
+            Err(Error::UnsupportedInstruction {
+                word: "try".to_string(),
+                pos: 7,
+                line: 2
+            })
+        );
+        assert_eq!(
+            assembly(&program("fun start 1 print ret")),
+            Err(Error::FunctionNotFound {
+                name: "main".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn asm_is_pasted_in() {
+        let asm = assembly(&program(
+            "fun main\n  asm \"  mov eax, 1\" end\n  asm \"nop\" end ret",
+        ))
+        .unwrap();
+        assert!(asm.contains(".L0:\n  mov eax, 1\n.L1:\nnop\n.L2:\n"));
+    }
+
+    #[test]
+    fn divisors_known_not_to_be_zero_go_unchecked() {
+        let checks = |source| {
+            assembly(&program(source))
+                .unwrap()
+                .matches("test ecx")
+                .count()
+        };
+        assert_eq!(checks("fun main 10 2 / print ret"), 0);
+        assert_eq!(
+            checks("fun half 10 swap / ret\nfun main 2 half print ret"),
+            1
+        );
+    }
+
+    // Builds a program into `dir` with the GNU assembler and linker, or
+    // None where they aren't there.
+    #[cfg(all(feature = "std", target_arch = "x86_64", target_os = "linux"))]
+    fn build(dir: &std::path::Path, name: &str, program: &Program) -> Option<std::path::PathBuf> {
+        use std::process::Command;
+
+        let asm = dir.join(format!("{}.s", name));
+        let exe = dir.join(name);
+        std::fs::write(&asm, assembly(program).unwrap()).unwrap();
+        let built = Command::new("as")
+            .arg(&asm)
+            .arg("-o")
+            .arg(exe.with_extension("o"))
+            .status()
+            .and_then(|_| {
+                Command::new("ld")
+                    .arg(exe.with_extension("o"))
+                    .arg("-o")
+                    .arg(&exe)
+                    .status()
+            });
+        built.is_ok_and(|status| status.success()).then_some(exe)
+    }
+
+    // Runs each program natively and through the interpreter, where the
+    // GNU assembler and linker are there to build it.
+    #[cfg(all(feature = "std", target_arch = "x86_64", target_os = "linux"))]
+    #[test]
+    fn runs_as_the_interpreter_does() {
+        use crate::stack::VecStack;
+        use crate::stack_machine::StackMachine;
+        use std::process::Command;
+
+        let dir = std::env::temp_dir().join(format!("rorth-x86_64-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let programs = [
+            "fun fact dup 1 - dup if fact * else pop end ret\nfun main 5 fact print ret",
+            "fun main 3 while dup do dup print 1 - end pop 10 while 1 - end print ret",
+            "fun main hex 0 255 - print bin 5 print dec 0 7 - 2 divmod print print ret",
+            "fun main 1 2 3 rot print print print 4 5 over nip swap print print ret",
+            "fun main 2147483647 1 +s print 0 2147483647 - 9 -s print 65536 dup *s print ret",
+            "fun main 2147483647 1 +w print 65536 dup *w print 1 print 2147483647 1 + ret",
+            "fun main 0 2147483647 - 1 - 0 1 - / ret",
+            "fun main 1 print 7 0 divmod ret",
+            "fun main 1 eprint 2 print ret",
+        ];
+        for (n, source) in programs.iter().enumerate() {
+            let program = program(source);
+            let Some(exe) = build(&dir, &n.to_string(), &program) else {
+                std::fs::remove_dir_all(&dir).unwrap();
+                return;
+            };
+            let native = Command::new(&exe).output().unwrap();
+            let mut machine = StackMachine::new(VecStack::new());
+            let result = machine.run_main(&program);
+            let output: String = result
+                .output
+                .iter()
+                .map(|line| format!("{}\n", line))
+                .collect();
+            let status = result.error.as_ref().map_or(0, |e| e.exit_status() as i32);
+            let error = result.error.map_or(String::new(), |e| format!("{}\n", e));
+            assert_eq!(
+                String::from_utf8_lossy(&native.stdout),
+                output,
+                "{}",
+                source
+            );
+            assert_eq!(native.status.code(), Some(status), "{}", source);
+            if source.contains("eprint") {
+                assert_eq!(String::from_utf8_lossy(&native.stderr), "1\n", "{}", source);
+            } else {
+                assert_eq!(String::from_utf8_lossy(&native.stderr), error, "{}", source);
+            }
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // System calls the kernel answers the same for the test and for the
+    // program it starts, and the ones it doesn't.
+    #[cfg(all(feature = "std", target_arch = "x86_64", target_os = "linux"))]
+    #[test]
+    fn makes_system_calls_as_the_interpreter_does() {
+        use crate::stack::VecStack;
+        use crate::stack_machine::{StackMachine, VmConfig};
+        use std::process::Command;
+
+        let dir = std::env::temp_dir().join(format!("rorth-syscall-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // `sched_yield`, then `close`, `dup2` and `lseek` on a bad file.
+        let source = "fun main 24 syscall0 print 0 1 - 3 syscall1 print \
+                      0 1 - dup 33 syscall2 print 0 1 - 0 0 8 syscall3 print ret";
+        let Some(exe) = build(&dir, "calls", &program(source)) else {
+            std::fs::remove_dir_all(&dir).unwrap();
+            return;
+        };
+        let native = Command::new(&exe).output().unwrap();
+        let config = VmConfig {
+            allow_syscalls: true,
+            ..VmConfig::default()
+        };
+        let result = StackMachine::with_config(VecStack::new(), config).run_main(&program(source));
+        assert_eq!(result.output, ["0", "-9", "-9", "-9"]);
+        let output: String = result
+            .output
+            .iter()
+            .map(|line| format!("{}\n", line))
+            .collect();
+        assert_eq!(String::from_utf8_lossy(&native.stdout), output);
+        assert_eq!(native.status.code(), Some(0));
+        // `getpid`, with the arguments below the number left alone.
+        let exe = build(
+            &dir,
+            "pid",
+            &program("fun main 0 0 0 39 syscall0 print print ret"),
+        )
+        .unwrap();
+        let child = Command::new(&exe)
+            .stdout(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        let pid = child.id();
+        let native = child.wait_with_output().unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&native.stdout),
+            format!("{}\n0\n", pid)
+        );
+        // `exit`, before the last `print`.
+        let exe = build(
+            &dir,
+            "exit",
+            &program("fun main 1 print 3 60 syscall1 2 print ret"),
+        )
+        .unwrap();
+        let native = Command::new(&exe).output().unwrap();
+        assert_eq!(String::from_utf8_lossy(&native.stdout), "1\n");
+        assert_eq!(native.status.code(), Some(3));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // An `asm` block pushing a number the way `push` does.
+    #[cfg(all(feature = "std", target_arch = "x86_64", target_os = "linux"))]
+    #[test]
+    fn asm_runs_natively() {
+        let dir = std::env::temp_dir().join(format!("rorth-asm-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let source = "fun main asm \"mov dword ptr [rbx], 42; add rbx, 4\" end print ret";
+        if let Some(exe) = build(&dir, "asm", &program(source)) {
+            let native = std::process::Command::new(&exe).output().unwrap();
+            assert_eq!(String::from_utf8_lossy(&native.stdout), "42\n");
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,83 @@
+// Inputs that once crashed or hung rorth, cut down to a few tokens. Every file
+// in `tests/corpus/` goes through the whole pipeline with a step budget; it
+// may fail with an error at any stage, but nothing may panic or run forever,
+// and it must run the same with and without optimizations. A file that starts
+// with `# expect: <message>` must stop with that error.
+// A new crash gets a new file here along with its fix.
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::Path;
+
+use stack_machine_bez::bytecode;
+use stack_machine_bez::checker::{
+    check_loop_conditions, check_stack_safety, infer_stack_effects, peek_loops,
+    unused_private_functions, validate_program,
+};
+use stack_machine_bez::parser::parse_recovering;
+use stack_machine_bez::stack::VecStack;
+use stack_machine_bez::stack_machine::{ErrorPolicy, StackMachine, VmConfig};
+use stack_machine_bez::tokenizer::tokenize_recovering;
+use stack_machine_bez::verify_opt;
+
+fn config(on_error: ErrorPolicy) -> VmConfig {
+    VmConfig {
+        max_steps: Some(100_000),
+        max_stack: Some(10_000),
+        max_memory: Some(1 << 20),
+        on_error,
+        ..VmConfig::default().deterministic()
+    }
+}
+
+fn run_through_everything(source: &str) {
+    let Ok(tokens) = tokenize_recovering(source) else {
+        return;
+    };
+    let Ok(program) = parse_recovering(tokens) else {
+        return;
+    };
+    let _ = check_stack_safety(&program.instructions);
+    let _ = validate_program(&program);
+    let _ = infer_stack_effects(&program);
+    let _ = check_loop_conditions(&program);
+    let _ = peek_loops(&program);
+    let _ = unused_private_functions(&program);
+    let _ = bytecode::disassemble(&program);
+    let decoded = bytecode::decode(&bytecode::encode(&program)).expect("own bytecode reads back");
+    assert_eq!(decoded.instructions, program.instructions);
+    let expected = source
+        .lines()
+        .next()
+        .and_then(|line| line.strip_prefix("# expect: "));
+    for on_error in [ErrorPolicy::Abort, ErrorPolicy::Trap] {
+        let mut machine = StackMachine::with_config(VecStack::new(), config(on_error));
+        let result = machine.run_main(&program);
+        if let (Some(expected), ErrorPolicy::Abort) = (expected, on_error) {
+            let error = result.error.as_ref().map(|e| e.message());
+            assert_eq!(error.as_deref(), Some(expected));
+        }
+        if let Err(difference) = verify_opt::compare(&program, config(on_error)) {
+            panic!("{}", difference);
+        }
+    }
+}
+
+#[test]
+fn corpus_runs_without_panics() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus");
+    let mut paths: Vec<_> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "sm"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty(), "no inputs in {}", dir.display());
+    let panicked: Vec<String> = paths
+        .iter()
+        .filter(|path| {
+            let source = std::fs::read_to_string(path).unwrap();
+            catch_unwind(AssertUnwindSafe(|| run_through_everything(&source))).is_err()
+        })
+        .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+        .collect();
+    assert!(panicked.is_empty(), "panicked on {:?}", panicked);
+}